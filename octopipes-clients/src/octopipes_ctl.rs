@@ -0,0 +1,226 @@
+//! # Octopipes-Clients
+//!
+//! `octopipes-ctl` provides a simple binary to administer a running Octopipes Server through its admin socket.
+
+//
+//   Octopipes-Clients
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+extern crate getopts;
+
+use getopts::Options;
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::exit;
+use std::time::Duration;
+
+const DEFAULT_ADMIN_SOCKET: &str = "/tmp/octopipes/admin.sock";
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!(
+        "Usage: {} [options] COMMAND\n\nCommands:\n    clients                 List the subscribed clients with their groups\n    groups                  List the groups with their subscribers\n    subscriptions CLIENT    Show the groups a client is subscribed to\n    kick CLIENT...          Forcibly unsubscribe clients\n    stats                   Dump the server statistics\n    queues                  Show the number of messages queued for each client\n    loglevel LEVEL          Change the server's log level (0: NONE, 1: DEBUG, 2: INFO, 3: WARN, 4: ERROR)\n    reload                  Reload the server configuration",
+        program
+    );
+    print!("{}", opts.usage(&brief));
+}
+
+/// ### send_request
+///
+/// Send a request to the admin socket and return the reply lines (the status line included)
+fn send_request(
+    stream: &mut UnixStream,
+    request: &str,
+    json: bool,
+) -> std::io::Result<Vec<String>> {
+    let request: String = match json {
+        true => format!("json {}\n", request),
+        false => format!("{}\n", request),
+    };
+    stream.write_all(request.as_bytes())?;
+    let mut reader: BufReader<&UnixStream> = BufReader::new(stream);
+    let mut lines: Vec<String> = Vec::new();
+    loop {
+        let mut line: String = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        let line: &str = line.trim_end_matches('\n');
+        //JSON replies are a single line; text replies end with an empty line
+        if json {
+            lines.push(String::from(line));
+            break;
+        }
+        if line.is_empty() {
+            break;
+        }
+        lines.push(String::from(line));
+    }
+    Ok(lines)
+}
+
+/// ### print_table
+///
+/// Print the body of a list reply (key value1,value2) as a table
+fn print_table(header: (&str, &str), body: &[String]) {
+    let rows: Vec<(&str, &str)> = body
+        .iter()
+        .map(|line| match line.find(' ') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => (line.as_str(), ""),
+        })
+        .collect();
+    let width: usize = rows
+        .iter()
+        .map(|(key, _)| key.len())
+        .chain(std::iter::once(header.0.len()))
+        .max()
+        .unwrap_or(0);
+    println!("{:width$}  {}", header.0, header.1, width = width);
+    for (key, values) in rows.iter() {
+        println!(
+            "{:width$}  {}",
+            key,
+            values.replace(',', ", "),
+            width = width
+        );
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program: String = args[0].clone();
+    //Get opts
+    let mut opts = Options::new();
+    opts.optopt(
+        "s",
+        "socket",
+        "Specify the admin socket of the server",
+        "<ADMIN_SOCKET>",
+    );
+    opts.optflag("j", "json", "Print replies as JSON");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => panic!("{}", f),
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        return;
+    }
+    let socket: String = match matches.opt_str("s") {
+        Some(socket) => socket,
+        None => String::from(DEFAULT_ADMIN_SOCKET),
+    };
+    let json: bool = matches.opt_present("j");
+    //Get command and prepare requests
+    let requests: Vec<String> = match matches
+        .free
+        .iter()
+        .map(|arg| arg.as_str())
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        ["clients"] => vec![String::from("list")],
+        ["groups"] => vec![String::from("groups")],
+        ["subscriptions", client] => vec![format!("subscriptions {}", client)],
+        ["kick", clients @ ..] if !clients.is_empty() => clients
+            .iter()
+            .map(|client| format!("kick {}", client))
+            .collect(),
+        ["stats"] => vec![String::from("stats")],
+        ["queues"] => vec![String::from("queues")],
+        ["loglevel", level] => vec![format!("loglevel {}", level)],
+        ["reload"] => vec![String::from("reload")],
+        _ => {
+            println!("A valid COMMAND must be specified");
+            print_usage(&program, opts);
+            exit(255);
+        }
+    };
+    //Options OK!
+    //Connect to server
+    let mut stream: UnixStream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(error) => {
+            println!("Could not connect to {}: {}", socket, error);
+            exit(1);
+        }
+    };
+    if stream.set_read_timeout(Some(ADMIN_TIMEOUT)).is_err() {
+        println!("Could not set timeout on {}", socket);
+        exit(1);
+    }
+    let mut exit_code: i32 = 0;
+    for request in requests.iter() {
+        let reply: Vec<String> = match send_request(&mut stream, request, json) {
+            Ok(reply) => reply,
+            Err(error) => {
+                println!("Could not communicate with server: {}", error);
+                exit(1);
+            }
+        };
+        if json {
+            //Print reply as is
+            if reply[0].starts_with("{\"status\":\"error\"") {
+                exit_code = 1;
+            }
+            println!("{}", reply[0]);
+            continue;
+        }
+        let status: &str = reply[0].as_str();
+        if status != "OK" {
+            println!("{}", status.strip_prefix("ERR ").unwrap_or(status));
+            exit_code = 1;
+            continue;
+        }
+        let body: &[String] = &reply[1..];
+        match request.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["list"] => print_table(("CLIENT", "GROUPS"), body),
+            ["groups"] => print_table(("GROUP", "CLIENTS"), body),
+            ["queues"] => print_table(("CLIENT", "QUEUED"), body),
+            ["subscriptions", _] => {
+                for group in body.iter() {
+                    println!("{}", group);
+                }
+            }
+            ["stats"] => {
+                for line in body.iter() {
+                    if let Some(index) = line.find(' ') {
+                        println!(
+                            "{:24}{}",
+                            format!("{}:", &line[..index]),
+                            &line[index + 1..]
+                        );
+                    }
+                }
+            }
+            ["kick", client] => println!("Client '{}' has been kicked", client),
+            ["loglevel", level] => println!("Log level set to {}", level),
+            ["reload"] => println!("Configuration reload scheduled"),
+            _ => {}
+        }
+    }
+    //Exit
+    exit(exit_code);
+}
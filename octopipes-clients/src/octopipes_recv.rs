@@ -45,9 +45,6 @@ fn print_usage(program: &String, opts: Options) {
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program: String = args[0].clone();
-    let cap_path: String;
-    let clid: String;
-    let verbose: bool;
     let message_amount: u32;
     let mut groups: Vec<String>;
     let mut exit_code: i32 = 0;
@@ -69,16 +66,14 @@ fn main() {
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
+        Err(f) => panic!("{}", f),
     };
     if matches.opt_present("h") {
         print_usage(&program, opts);
         return;
     }
-    match matches.opt_str("c") {
-        Some(cap) => {
-            cap_path = cap;
-        }
+    let cap_path: String = match matches.opt_str("c") {
+        Some(cap) => cap,
         None => {
             println!("CAP path must be specified");
             print_usage(&program, opts);
@@ -100,22 +95,15 @@ fn main() {
             message_amount = 0;
         }
     };
-    if matches.opt_present("v") {
-        verbose = true;
-    } else {
-        verbose = false;
-    }
-    match matches.opt_str("C") {
-        Some(client_id) => {
-            clid = client_id;
-        }
+    let verbose: bool = matches.opt_present("v");
+    let clid: String = match matches.opt_str("C") {
+        Some(client_id) => client_id,
         None => {
             //Generate a random client id
             let rng = thread_rng();
-            clid = rng
-                .sample_iter(rand::distributions::Alphanumeric)
+            rng.sample_iter(rand::distributions::Alphanumeric)
                 .take(16)
-                .collect::<String>();
+                .collect::<String>()
         }
     };
     //Get groups
@@ -133,7 +121,7 @@ fn main() {
     //Set CTRL+C handler
     let (tx_channel, rx_channel) = mpsc::channel();
     ctrlc::set_handler(move || {
-        if let Err(..) = tx_channel.send(1) {
+        if tx_channel.send(1).is_err() {
             panic!("Could not send CTRL-C");
         }
     })
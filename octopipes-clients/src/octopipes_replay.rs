@@ -0,0 +1,493 @@
+//! # Octopipes-Clients
+//!
+//! `octopipes-replay` provides a simple binary to publish again the messages recorded in the capture files of an Octopipes Server.
+
+//
+//   Octopipes-Clients
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+extern crate ctrlc;
+extern crate getopts;
+extern crate rustypipes;
+
+use getopts::Options;
+use rustypipes::{OctopipesClient, OctopipesOptions, OctopipesProtocolVersion};
+use std::collections::HashMap;
+use std::env;
+use std::process::exit;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+//Capture format, see docs/capture.md
+const CAPTURE_HEADER: &[u8; 8] = b"OCTOCAP\x01";
+//Retain option, which can't be sent through rustypipes
+const OPTION_RET: u8 = 0b0000_1000;
+
+/// ### Record
+///
+/// A message recorded in a capture file
+struct Record {
+    //Microseconds since the Unix epoch
+    timestamp: u64,
+    origin: String,
+    remote: String,
+    ttl: u8,
+    options: u8,
+    data: Vec<u8>,
+}
+
+/// ### Mapping
+///
+/// A remote remapping; when both the remotes end with '#', the group and all its subgroups are remapped
+#[derive(Debug, PartialEq)]
+struct Mapping {
+    from: String,
+    to: String,
+}
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options] FILES", program);
+    print!("{}", opts.usage(&brief));
+}
+
+/// ### read_capture
+///
+/// Read the records in a capture file; a truncated record at the end of the file is ignored
+fn read_capture(path: &str) -> Result<Vec<Record>, String> {
+    let data: Vec<u8> = std::fs::read(path).map_err(|err| err.to_string())?;
+    if data.len() < CAPTURE_HEADER.len() || &data[..CAPTURE_HEADER.len()] != CAPTURE_HEADER {
+        return Err(String::from("not a capture file"));
+    }
+    let mut records: Vec<Record> = Vec::new();
+    let mut offset: usize = CAPTURE_HEADER.len();
+    while let Some((record, size)) = decode_record(&data[offset..]) {
+        records.push(record);
+        offset += size;
+    }
+    Ok(records)
+}
+
+/// ### decode_record
+///
+/// Decode the record at the beginning of data, returning it with its size
+fn decode_record(data: &[u8]) -> Option<(Record, usize)> {
+    let mut offset: usize = 0;
+    let mut take = |size: usize| -> Option<&[u8]> {
+        let field: &[u8] = data.get(offset..offset.checked_add(size)?)?;
+        offset += size;
+        Some(field)
+    };
+    let timestamp: u64 = be_u64(take(8)?);
+    let _version: u8 = take(1)?[0];
+    let origin_size: usize = take(1)?[0] as usize;
+    let origin: String = String::from_utf8_lossy(take(origin_size)?).to_string();
+    let remote_size: usize = take(1)?[0] as usize;
+    let remote: String = String::from_utf8_lossy(take(remote_size)?).to_string();
+    let ttl: u8 = take(1)?[0];
+    let options: u8 = take(1)?[0];
+    let data_size: u64 = be_u64(take(8)?);
+    if data_size > usize::MAX as u64 {
+        return None;
+    }
+    let payload: Vec<u8> = take(data_size as usize)?.to_vec();
+    Some((
+        Record {
+            timestamp,
+            origin,
+            remote,
+            ttl,
+            options,
+            data: payload,
+        },
+        offset,
+    ))
+}
+
+/// ### be_u64
+///
+/// Decode a big endian u64 from 8 bytes
+fn be_u64(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value: u64, byte: &u8| (value << 8) | *byte as u64)
+}
+
+/// ### parse_mapping
+///
+/// Parse a remote remapping as FROM=TO; '#' can only be the last level, on both sides
+fn parse_mapping(mapping: &str) -> Option<Mapping> {
+    let index: usize = mapping.find('=')?;
+    let (from, to) = (&mapping[..index], &mapping[index + 1..]);
+    let is_valid = |remote: &str| -> bool {
+        !remote.is_empty()
+            && !remote.trim_end_matches('#').contains('#')
+            && (remote == "#" || !remote.ends_with('#') || remote.ends_with("/#"))
+    };
+    if !is_valid(from) || !is_valid(to) || from.ends_with('#') != to.ends_with('#') {
+        return None;
+    }
+    Some(Mapping {
+        from: String::from(from),
+        to: String::from(to),
+    })
+}
+
+/// ### remap
+///
+/// Apply the first matching remapping to the remote. As in the subscriptions, '#' matches zero or more levels,
+/// so 'sensors/#' remaps 'sensors' too
+fn remap(mappings: &[Mapping], remote: &str) -> String {
+    for mapping in mappings.iter() {
+        if !mapping.from.ends_with('#') {
+            if mapping.from == remote {
+                return mapping.to.clone();
+            }
+            continue;
+        }
+        let from: &str = mapping.from.trim_end_matches('#').trim_end_matches('/');
+        let to: &str = mapping.to.trim_end_matches('#').trim_end_matches('/');
+        //Levels below the remapped group
+        let levels: &str = match (from, remote.strip_prefix(from)) {
+            ("", _) => remote,
+            (_, Some("")) => "",
+            (_, Some(levels)) if levels.starts_with('/') => &levels[1..],
+            _ => continue,
+        };
+        match (to, levels) {
+            //The remote would be empty
+            ("", "") => continue,
+            ("", levels) => return String::from(levels),
+            (to, "") => return String::from(to),
+            (to, levels) => return format!("{}/{}", to, levels),
+        }
+    }
+    String::from(remote)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program: String = args[0].clone();
+    let mut exit_code: i32 = 0;
+    //Get opts
+    let mut opts = Options::new();
+    opts.optopt("c", "cap-path", "Specify CAP path", "<CAP_PATH>");
+    opts.optopt(
+        "C",
+        "clid",
+        "Send all the messages from this client id, instead of their origin",
+        "<CLIENT_ID>",
+    );
+    opts.optmulti(
+        "m",
+        "map",
+        "Remap a remote (FROM=TO; 'sensors/#=lab/sensors/#' remaps 'sensors' and all its subgroups)",
+        "<FROM=TO>",
+    );
+    opts.optopt(
+        "s",
+        "speed",
+        "Replay speed, relative to the original timing (default: 1)",
+        "<FACTOR>",
+    );
+    opts.optflag("f", "fast", "Send the messages as fast as possible");
+    opts.optflag(
+        "v",
+        "verbose",
+        "Verbose mode prints messages as {ORIGIN} > {REMOTE} {SIZE}",
+    );
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => panic!("{}", f),
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        return;
+    }
+    let cap_path: String = match matches.opt_str("c") {
+        Some(cap) => cap,
+        None => {
+            println!("CAP path must be specified");
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    let clid: Option<String> = matches.opt_str("C");
+    let mut mappings: Vec<Mapping> = Vec::new();
+    for mapping in matches.opt_strs("m").iter() {
+        match parse_mapping(mapping) {
+            Some(mapping) => mappings.push(mapping),
+            None => {
+                println!("Invalid remapping '{}'", mapping);
+                exit(1);
+            }
+        }
+    }
+    let speed: f64 = match matches.opt_str("s") {
+        Some(speed_str) => match speed_str.parse::<f64>() {
+            Ok(speed) if speed > 0.0 && speed.is_finite() => speed,
+            _ => {
+                println!("Speed must be a positive number");
+                exit(1);
+            }
+        },
+        None => 1.0,
+    };
+    let fast: bool = matches.opt_present("f");
+    let verbose: bool = matches.opt_present("v");
+    if matches.free.is_empty() {
+        println!("FILES must be specified");
+        print_usage(&program, opts);
+        return;
+    }
+    //Read captures, in the provided order
+    let mut records: Vec<Record> = Vec::new();
+    for file in matches.free.iter() {
+        match read_capture(file) {
+            Ok(file_records) => records.extend(file_records),
+            Err(error) => {
+                println!("Could not read capture {}: {}", file, error);
+                exit(1);
+            }
+        }
+    }
+    //Options OK!
+    //Set CTRL+C handler
+    let (tx_channel, rx_channel) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        if tx_channel.send(1).is_err() {
+            panic!("Could not send CTRL-C");
+        }
+    })
+    .expect("Error setting Ctrl-C handler");
+    //Clients are subscribed when they send their first message
+    let mut clients: HashMap<String, OctopipesClient> = HashMap::new();
+    let mut sent: usize = 0;
+    let mut skipped: usize = 0;
+    let started: Instant = Instant::now();
+    let first_timestamp: u64 = records.first().map(|record| record.timestamp).unwrap_or(0);
+    for record in records.into_iter() {
+        //Wait for the message time (or for CTRL+C)
+        if !fast {
+            let offset: Duration =
+                Duration::from_micros(record.timestamp.saturating_sub(first_timestamp))
+                    .div_f64(speed);
+            let elapsed: Duration = started.elapsed();
+            if offset > elapsed {
+                match rx_channel.recv_timeout(offset - elapsed) {
+                    Ok(..) => break,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        panic!("CTRL+C thread is dead");
+                    }
+                }
+            }
+        }
+        match rx_channel.try_recv() {
+            Ok(..) => break,
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => {
+                panic!("CTRL+C thread is dead");
+            }
+        }
+        //Messages sent by the server can't be sent by a client
+        if record.origin.is_empty() {
+            skipped += 1;
+            continue;
+        }
+        //Replaying a retained message without the option would change the server state differently
+        if record.options & OPTION_RET != 0 {
+            println!(
+                "Skipping retained message from {} to {}: the retain option can't be sent",
+                record.origin, record.remote
+            );
+            skipped += 1;
+            continue;
+        }
+        let origin: String = match clid.as_ref() {
+            Some(clid) => clid.clone(),
+            None => record.origin.clone(),
+        };
+        let remote: String = remap(&mappings, &record.remote);
+        if !clients.contains_key(&origin) {
+            let mut client: OctopipesClient = OctopipesClient::new(
+                origin.clone(),
+                cap_path.clone(),
+                OctopipesProtocolVersion::Version1,
+            );
+            if let Err(error) = client.subscribe(&vec![]) {
+                println!(
+                    "Could not subscribe {} to Octopipes Server: {}",
+                    origin, error
+                );
+                exit_code = 1;
+                break;
+            }
+            clients.insert(origin.clone(), client);
+        }
+        let client: &OctopipesClient = clients.get(&origin).unwrap();
+        if verbose {
+            println!("{} > {} {}", origin, remote, record.data.len());
+        }
+        let size: usize = record.data.len();
+        match client.send_ex(
+            &remote,
+            record.data,
+            record.ttl,
+            OctopipesOptions::from_bits_truncate(record.options),
+        ) {
+            Ok(..) => sent += 1,
+            Err(error) => {
+                println!("Could not send {} bytes to {}: {}", size, remote, error);
+                exit_code = 1;
+            }
+        }
+    }
+    //Unsubscribe
+    for (origin, client) in clients.iter_mut() {
+        if let Err(error) = client.unsubscribe() {
+            println!("Could not unsubscribe {} from server: {}", origin, error);
+            exit_code = 1;
+        }
+    }
+    println!("Replayed {} messages ({} skipped)", sent, skipped);
+    //Exit
+    exit(exit_code);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //Capture shared with the tests of the server capture module
+    const SAMPLE_CAPTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../docs/capture-sample.cap");
+
+    #[test]
+    fn test_replay_read_capture() {
+        let records: Vec<Record> = read_capture(SAMPLE_CAPTURE).unwrap();
+        //The truncated record at the end is ignored
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].timestamp, 1600000000000000);
+        assert_eq!(records[0].origin, String::from("foo"));
+        assert_eq!(records[0].remote, String::from("sensors/kitchen"));
+        assert_eq!(records[0].ttl, 5);
+        assert_eq!(records[0].options, 0);
+        assert_eq!(records[0].data, b"21.5".to_vec());
+        assert_eq!(records[1].timestamp, 1600000000250000);
+        assert_eq!(records[1].origin, String::new());
+        assert_eq!(records[1].remote, String::from("$SYS/server"));
+        assert_eq!(records[1].ttl, 0);
+        assert_eq!(records[1].data, vec![0x00, 0xff]);
+        assert_eq!(records[2].timestamp, 1600000001000000);
+        assert_eq!(records[2].origin, String::from("bar"));
+        assert_eq!(records[2].remote, String::from("status"));
+        assert_eq!(records[2].ttl, 10);
+        assert_eq!(records[2].options, OPTION_RET);
+        assert_eq!(records[2].data, b"up".to_vec());
+        //Not a capture
+        assert!(read_capture(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).is_err());
+    }
+
+    #[test]
+    fn test_replay_decode_record() {
+        let data: Vec<u8> = std::fs::read(SAMPLE_CAPTURE).unwrap();
+        let (record, size) = decode_record(&data[CAPTURE_HEADER.len()..]).unwrap();
+        //21 bytes, plus origin, remote and payload
+        assert_eq!(size, 21 + 3 + 15 + 4);
+        assert_eq!(record.remote, String::from("sensors/kitchen"));
+        //Truncated records
+        assert!(
+            decode_record(&data[CAPTURE_HEADER.len()..CAPTURE_HEADER.len() + size - 1]).is_none()
+        );
+        assert!(decode_record(&data[CAPTURE_HEADER.len()..CAPTURE_HEADER.len() + 20]).is_none());
+        assert!(decode_record(&[]).is_none());
+        //Payload size is bigger than the record
+        let mut data: Vec<u8> = vec![0; 21];
+        data[13..21].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(decode_record(&data).is_none());
+    }
+
+    #[test]
+    fn test_replay_parse_mapping() {
+        assert_eq!(
+            parse_mapping("foo=bar"),
+            Some(Mapping {
+                from: String::from("foo"),
+                to: String::from("bar")
+            })
+        );
+        assert_eq!(
+            parse_mapping("sensors/#=lab/sensors/#"),
+            Some(Mapping {
+                from: String::from("sensors/#"),
+                to: String::from("lab/sensors/#")
+            })
+        );
+        assert!(parse_mapping("#=lab/#").is_some());
+        assert!(parse_mapping("sensors/#=#").is_some());
+        //Only the first '=' separates the remotes
+        assert_eq!(parse_mapping("a=b=c").unwrap().to, String::from("b=c"));
+        assert!(parse_mapping("foo").is_none());
+        assert!(parse_mapping("=bar").is_none());
+        assert!(parse_mapping("foo=").is_none());
+        assert!(parse_mapping("sensors/#=lab").is_none());
+        assert!(parse_mapping("sensors=lab/#").is_none());
+        assert!(parse_mapping("sensors#=lab#").is_none());
+        assert!(parse_mapping("sensors/#/temp=lab/#").is_none());
+    }
+
+    #[test]
+    fn test_replay_remap() {
+        let mappings: Vec<Mapping> = vec![
+            parse_mapping("alarms=lab/alarms").unwrap(),
+            parse_mapping("sensors/#=lab/sensors/#").unwrap(),
+            parse_mapping("tmp/#=#").unwrap(),
+        ];
+        assert_eq!(remap(&mappings, "alarms"), String::from("lab/alarms"));
+        assert_eq!(remap(&mappings, "alarms/fire"), String::from("alarms/fire"));
+        assert_eq!(
+            remap(&mappings, "sensors/kitchen/temp"),
+            String::from("lab/sensors/kitchen/temp")
+        );
+        //'#' matches zero levels too
+        assert_eq!(remap(&mappings, "sensors"), String::from("lab/sensors"));
+        assert_eq!(remap(&mappings, "sensorsfoo"), String::from("sensorsfoo"));
+        assert_eq!(remap(&mappings, "tmp/foo/bar"), String::from("foo/bar"));
+        //Remote would be empty
+        assert_eq!(remap(&mappings, "tmp"), String::from("tmp"));
+        assert_eq!(remap(&mappings, "other"), String::from("other"));
+        //All the remotes
+        let mappings: Vec<Mapping> = vec![parse_mapping("#=lab/#").unwrap()];
+        assert_eq!(remap(&mappings, "foo"), String::from("lab/foo"));
+        //First matching remapping is applied
+        let mappings: Vec<Mapping> = vec![
+            parse_mapping("sensors/kitchen=kitchen").unwrap(),
+            parse_mapping("sensors/#=lab/#").unwrap(),
+        ];
+        assert_eq!(remap(&mappings, "sensors/kitchen"), String::from("kitchen"));
+        assert_eq!(
+            remap(&mappings, "sensors/garage"),
+            String::from("lab/garage")
+        );
+    }
+}
@@ -43,10 +43,6 @@ fn print_usage(program: &String, opts: Options) {
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program: String = args[0].clone();
-    let cap_path: String;
-    let clid: String;
-    let payload: String;
-    let remote: String;
     let mut exit_code: i32 = 0;
     //Get opts
     let mut opts = Options::new();
@@ -57,53 +53,44 @@ fn main() {
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
+        Err(f) => panic!("{}", f),
     };
     if matches.opt_present("h") {
         print_usage(&program, opts);
         return;
     }
-    match matches.opt_str("c") {
-        Some(cap) => {
-            cap_path = cap;
-        }
+    let cap_path: String = match matches.opt_str("c") {
+        Some(cap) => cap,
         None => {
             println!("CAP path must be specified");
             print_usage(&program, opts);
             return;
         }
     };
-    match matches.opt_str("r") {
-        Some(remote_group) => {
-            remote = remote_group;
-        }
+    let remote: String = match matches.opt_str("r") {
+        Some(remote_group) => remote_group,
         None => {
             println!("remote must be specified");
             print_usage(&program, opts);
             return;
         }
     };
-    match matches.opt_str("p") {
-        Some(data) => {
-            payload = data;
-        }
+    let payload: String = match matches.opt_str("p") {
+        Some(data) => data,
         None => {
             println!("payload must be specified");
             print_usage(&program, opts);
             return;
         }
     };
-    match matches.opt_str("C") {
-        Some(client_id) => {
-            clid = client_id;
-        }
+    let clid: String = match matches.opt_str("C") {
+        Some(client_id) => client_id,
         None => {
             //Generate a random client id
             let rng = thread_rng();
-            clid = rng
-                .sample_iter(rand::distributions::Alphanumeric)
+            rng.sample_iter(rand::distributions::Alphanumeric)
                 .take(16)
-                .collect::<String>();
+                .collect::<String>()
         }
     };
     //Options OK!
@@ -117,7 +104,7 @@ fn main() {
     //Send data
     let mut data: Vec<u8> = Vec::with_capacity(payload.len());
     for ch in payload.as_bytes() {
-        data.push(*ch as u8);
+        data.push(*ch);
     }
     if let Err(error) = client.send(&remote, data) {
         println!("Could not send data to {}: {}", remote, error);
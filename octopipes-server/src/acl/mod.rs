@@ -0,0 +1,244 @@
+//! ### acl
+//!
+//! `acl` is the module which provides the access control lists checked on subscriptions and sends
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use std::fmt;
+use std::str::FromStr;
+
+/// ### Acl
+///
+/// `Acl` is an access control list. The first rule matching a client decides what the client is allowed to do;
+/// if no rule matches, the default policy is applied
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub struct Acl {
+    pub default: AclPolicy,
+    pub rules: Vec<AclRule>,
+}
+
+/// ### AclRule
+///
+/// `AclRule` grants permissions to the clients whose name matches its pattern; a rule without pattern matches every client.
+/// Rules can't match the user of a client, since it can't be established.
+/// Each permission is a list of patterns; None means the operation isn't restricted
+#[derive(Clone, Default, PartialEq, fmt::Debug)]
+pub struct AclRule {
+    pub client: Option<String>,
+    pub names: Option<Vec<String>>,
+    pub subscribe: Option<Vec<String>>,
+    pub send: Option<Vec<String>>,
+}
+
+/// ### AclPolicy
+///
+/// `AclPolicy` describes what is allowed to the clients which don't match any rule
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum AclPolicy {
+    Allow,
+    Deny,
+}
+
+/// ### Access
+///
+/// `Access` describes an operation checked against the ACL
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub enum Access {
+    /// Claim the client name
+    Claim,
+    /// Subscribe to a group
+    Subscribe(String),
+    /// Send a message to a remote
+    Send(String),
+}
+
+impl Acl {
+    /// ### new
+    ///
+    /// Instantiates a new Acl
+    pub fn new(default: AclPolicy, rules: Vec<AclRule>) -> Acl {
+        Acl { default, rules }
+    }
+
+    /// ### allows
+    ///
+    /// `allows` returns whether the client is allowed to perform the operation
+    pub fn allows(&self, client: &str, access: &Access) -> bool {
+        match self.rules.iter().find(|rule| rule.matches(client)) {
+            Some(rule) => rule.allows(client, access),
+            None => self.default == AclPolicy::Allow,
+        }
+    }
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        Acl::new(AclPolicy::Allow, Vec::new())
+    }
+}
+
+impl AclRule {
+    /// ### matches
+    ///
+    /// `matches` returns whether the rule applies to the client
+    fn matches(&self, client: &str) -> bool {
+        match &self.client {
+            Some(pattern) => pattern_match(pattern, client),
+            None => true,
+        }
+    }
+
+    /// ### allows
+    ///
+    /// `allows` returns whether the rule grants the operation to the client
+    fn allows(&self, client: &str, access: &Access) -> bool {
+        let (patterns, name): (&Option<Vec<String>>, &str) = match access {
+            Access::Claim => (&self.names, client),
+            Access::Subscribe(group) => (&self.subscribe, group.as_str()),
+            Access::Send(remote) => (&self.send, remote.as_str()),
+        };
+        match patterns {
+            Some(patterns) => patterns.iter().any(|pattern| pattern_match(pattern, name)),
+            None => true,
+        }
+    }
+}
+
+/// ### pattern_match
+///
+/// `pattern_match` returns whether the name matches the pattern, where '*' matches any sequence of characters
+/// and '?' matches any single character
+pub fn pattern_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let mut p: usize = 0;
+    let mut n: usize = 0;
+    //Position of the last '*' in the pattern and of the character it has been matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            //Let the '*' match one more character
+            star = Some((star_p, star_n + 1));
+            p = star_p + 1;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl FromStr for AclPolicy {
+    type Err = ();
+
+    fn from_str(policy: &str) -> Result<AclPolicy, ()> {
+        match policy {
+            "allow" => Ok(AclPolicy::Allow),
+            "deny" => Ok(AclPolicy::Deny),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for AclPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AclPolicy::Allow => write!(f, "allow"),
+            AclPolicy::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Claim => write!(f, "claim the name"),
+            Access::Subscribe(group) => write!(f, "subscribe to '{}'", group),
+            Access::Send(remote) => write!(f, "send to '{}'", remote),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_acl_pattern_match() {
+        assert!(pattern_match("foo", "foo"));
+        assert!(!pattern_match("foo", "foobar"));
+        assert!(pattern_match("foo*", "foobar"));
+        assert!(pattern_match("foo*", "foo"));
+        assert!(pattern_match("*bar", "foobar"));
+        assert!(pattern_match("f*o*r", "foobar"));
+        assert!(!pattern_match("f*o*z", "foobar"));
+        assert!(pattern_match("sensor-?", "sensor-1"));
+        assert!(!pattern_match("sensor-?", "sensor-10"));
+        assert!(pattern_match("*", ""));
+        assert!(!pattern_match("?", ""));
+    }
+
+    #[test]
+    fn test_acl_allows() {
+        let acl: Acl = Acl::new(
+            AclPolicy::Deny,
+            vec![
+                AclRule {
+                    client: Some(String::from("logger")),
+                    ..AclRule::default()
+                },
+                AclRule {
+                    client: Some(String::from("sensor-*")),
+                    names: Some(vec![String::from("sensor-?")]),
+                    subscribe: Some(vec![String::from("sensors")]),
+                    send: Some(vec![String::from("collector"), String::from("BROADCAST")]),
+                },
+            ],
+        );
+        //Rule without permissions doesn't restrict anything
+        assert!(acl.allows("logger", &Access::Claim));
+        assert!(acl.allows("logger", &Access::Send(String::from("any"))));
+        //Rule matching by name
+        assert!(acl.allows("sensor-1", &Access::Claim));
+        assert!(!acl.allows("sensor-10", &Access::Claim));
+        assert!(acl.allows("sensor-1", &Access::Subscribe(String::from("sensors"))));
+        assert!(!acl.allows("sensor-1", &Access::Subscribe(String::from("alarms"))));
+        assert!(acl.allows("sensor-1", &Access::Send(String::from("BROADCAST"))));
+        assert!(!acl.allows("sensor-1", &Access::Send(String::from("logger"))));
+        //Clients matching no rule fall back to the default policy
+        assert!(!acl.allows("collector", &Access::Claim));
+        //Default ACL allows everything
+        let acl: Acl = Acl::default();
+        assert!(acl.allows("foo", &Access::Subscribe(String::from("bar"))));
+        assert_eq!("deny".parse::<AclPolicy>(), Ok(AclPolicy::Deny));
+        assert!("none".parse::<AclPolicy>().is_err());
+    }
+}
@@ -0,0 +1,697 @@
+//! ### admin
+//!
+//! `admin` is the module which implements the admin control socket of the server.
+//! The admin socket is a Unix domain socket with a line-based protocol: each request is a line,
+//! each reply starts with a status line (`OK` or `ERR <message>`) and ends with an empty line.
+//! If the request is prefixed with `json`, the reply is a single line JSON object instead.
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::{Duration, Instant};
+
+//Only the user running the server can administer it
+const SOCKET_MODE: u32 = 0o600;
+//Requests longer than this are refused and the connection is closed
+const MAX_REQUEST_SIZE: usize = 4096;
+//Connections whose replies pile up beyond this, since the peer isn't reading them, are closed
+const MAX_PENDING_REPLIES: usize = 1048576;
+//Connections beyond this are closed as soon as they're accepted
+const MAX_CONNECTIONS: usize = 16;
+//Connections which neither send requests nor read replies for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// ### AdminCommand
+///
+/// `AdminCommand` is a command which can be requested through the admin socket
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub enum AdminCommand {
+    ListClients,
+    ListGroups,
+    Subscriptions(String),
+    Kick(String),
+    Stats,
+    Queues,
+    LogLevel(usize),
+    Reload,
+}
+
+/// ### AdminFormat
+///
+/// `AdminFormat` is the format of the reply
+#[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
+pub enum AdminFormat {
+    Text,
+    Json,
+}
+
+/// ### AdminRequest
+///
+/// `AdminRequest` is a request received on the admin socket
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct AdminRequest {
+    pub command: AdminCommand,
+    pub format: AdminFormat,
+}
+
+/// ### AdminReply
+///
+/// `AdminReply` is the outcome of an admin command
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub enum AdminReply {
+    /// Subscribed clients with their groups
+    Clients(Vec<(String, Vec<String>)>),
+    /// Groups with their subscribers
+    Groups(Vec<(String, Vec<String>)>),
+    /// Subscriptions of a client
+    Subscriptions(String, Vec<String>),
+    /// Client has been forcibly unsubscribed
+    Kicked(String),
+    /// Server counters
+    Stats(Vec<(&'static str, u64)>),
+    /// Amount of messages queued for each client
+    Queues(Vec<(String, u64)>),
+    /// Log level has been changed
+    LogLevel(usize),
+    /// Configuration reload has been scheduled
+    Reload,
+    /// Command failed
+    Error(String),
+}
+
+/// ### AdminSocket
+///
+/// `AdminSocket` is the listener of the admin socket with its connections, each one identified by a token
+pub struct AdminSocket {
+    path: String,
+    listener: UnixListener,
+    connections: HashMap<u64, AdminConnection>,
+}
+
+/// ### AdminConnection
+///
+/// `AdminConnection` is a client connected to the admin socket
+struct AdminConnection {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    //Replies which haven't been written yet
+    out: Vec<u8>,
+    last_activity: Instant,
+}
+
+impl AdminRequest {
+    /// ### parse
+    ///
+    /// `parse` parses a request line. In case of error, the reason is returned
+    pub fn parse(line: &str) -> Result<AdminRequest, String> {
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        let format: AdminFormat = match tokens.first() {
+            Some(&"json") => {
+                tokens.remove(0);
+                AdminFormat::Json
+            }
+            _ => AdminFormat::Text,
+        };
+        let command: AdminCommand = match tokens.as_slice() {
+            ["list"] => AdminCommand::ListClients,
+            ["groups"] => AdminCommand::ListGroups,
+            ["subscriptions", client] => AdminCommand::Subscriptions(String::from(*client)),
+            ["kick", client] => AdminCommand::Kick(String::from(*client)),
+            ["stats"] => AdminCommand::Stats,
+            ["queues"] => AdminCommand::Queues,
+            ["reload"] => AdminCommand::Reload,
+            ["loglevel", level] => match level.parse::<usize>() {
+                Ok(level) if level <= 4 => AdminCommand::LogLevel(level),
+                _ => return Err(format!("Invalid log level '{}'", level)),
+            },
+            [] => return Err(String::from("Empty request")),
+            [command, ..] => return Err(format!("Unknown command '{}'", command)),
+        };
+        Ok(AdminRequest { command, format })
+    }
+}
+
+impl AdminReply {
+    /// ### encode
+    ///
+    /// `encode` encodes the reply in the provided format
+    pub fn encode(&self, format: AdminFormat) -> String {
+        match format {
+            AdminFormat::Text => self.encode_text(),
+            AdminFormat::Json => self.encode_json(),
+        }
+    }
+
+    /// ### encode_text
+    ///
+    /// `encode_text` encodes the reply as status line, body lines and an empty line
+    fn encode_text(&self) -> String {
+        let mut out: String = String::new();
+        match self {
+            AdminReply::Clients(entries) | AdminReply::Groups(entries) => {
+                out.push_str("OK\n");
+                for (key, values) in entries.iter() {
+                    let _ = writeln!(out, "{} {}", key, values.join(","));
+                }
+            }
+            AdminReply::Subscriptions(_, groups) => {
+                out.push_str("OK\n");
+                for group in groups.iter() {
+                    let _ = writeln!(out, "{}", group);
+                }
+            }
+            AdminReply::Kicked(_) | AdminReply::LogLevel(_) | AdminReply::Reload => {
+                out.push_str("OK\n")
+            }
+            AdminReply::Stats(counters) => {
+                out.push_str("OK\n");
+                for (name, value) in counters.iter() {
+                    let _ = writeln!(out, "{} {}", name, value);
+                }
+            }
+            AdminReply::Queues(queues) => {
+                out.push_str("OK\n");
+                for (client, depth) in queues.iter() {
+                    let _ = writeln!(out, "{} {}", client, depth);
+                }
+            }
+            AdminReply::Error(message) => {
+                let _ = writeln!(out, "ERR {}", message);
+            }
+        }
+        out.push('\n');
+        out
+    }
+
+    /// ### encode_json
+    ///
+    /// `encode_json` encodes the reply as a single line JSON object
+    fn encode_json(&self) -> String {
+        let body: String = match self {
+            AdminReply::Clients(entries) => format!(
+                "\"clients\":[{}]",
+                entries
+                    .iter()
+                    .map(|(client, groups)| format!(
+                        "{{\"id\":{},\"groups\":{}}}",
+                        json_string(client),
+                        json_string_array(groups)
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+            AdminReply::Groups(entries) => format!(
+                "\"groups\":[{}]",
+                entries
+                    .iter()
+                    .map(|(group, clients)| format!(
+                        "{{\"group\":{},\"clients\":{}}}",
+                        json_string(group),
+                        json_string_array(clients)
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+            AdminReply::Subscriptions(client, groups) => format!(
+                "\"client\":{},\"groups\":{}",
+                json_string(client),
+                json_string_array(groups)
+            ),
+            AdminReply::Kicked(client) => format!("\"client\":{}", json_string(client)),
+            AdminReply::Stats(counters) => format!(
+                "\"stats\":{{{}}}",
+                counters
+                    .iter()
+                    .map(|(name, value)| format!("{}:{}", json_string(name), value))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+            AdminReply::Queues(queues) => format!(
+                "\"queues\":[{}]",
+                queues
+                    .iter()
+                    .map(|(client, depth)| format!(
+                        "{{\"id\":{},\"depth\":{}}}",
+                        json_string(client),
+                        depth
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+            AdminReply::LogLevel(level) => format!("\"log_level\":{}", level),
+            AdminReply::Reload => String::from("\"reload\":\"scheduled\""),
+            AdminReply::Error(message) => {
+                return format!(
+                    "{{\"status\":\"error\",\"message\":{}}}\n",
+                    json_string(message)
+                )
+            }
+        };
+        format!("{{\"status\":\"ok\",{}}}\n", body)
+    }
+}
+
+impl AdminSocket {
+    /// ### bind
+    ///
+    /// `bind` creates the admin socket in the provided path. A stale socket left in the path is replaced
+    pub fn bind(path: &str) -> std::io::Result<AdminSocket> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(std::io::Error::from(ErrorKind::AlreadyExists));
+            }
+            //A server is already listening on this socket
+            if UnixStream::connect(path).is_ok() {
+                return Err(std::io::Error::from(ErrorKind::AddrInUse));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener: UnixListener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(SOCKET_MODE))?;
+        Ok(AdminSocket {
+            path: String::from(path),
+            listener,
+            connections: HashMap::new(),
+        })
+    }
+
+    /// ### accept
+    ///
+    /// `accept` accepts a pending connection, if any. The connection must then be added with `add_connection`.
+    /// Once the maximum amount of connections has been reached, the new connections are closed right away
+    pub fn accept(&mut self) -> Option<UnixStream> {
+        loop {
+            match self.listener.accept() {
+                Ok(_) if self.connections.len() >= MAX_CONNECTIONS => continue,
+                Ok((stream, _)) => {
+                    return match stream.set_nonblocking(true) {
+                        Ok(_) => Some(stream),
+                        Err(_) => None,
+                    }
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// ### add_connection
+    ///
+    /// `add_connection` starts tracking a connection with the provided token
+    pub fn add_connection(&mut self, token: u64, stream: UnixStream) {
+        self.connections.insert(
+            token,
+            AdminConnection {
+                stream,
+                buffer: Vec::new(),
+                out: Vec::new(),
+                last_activity: Instant::now(),
+            },
+        );
+    }
+
+    /// ### has_connection
+    ///
+    /// `has_connection` returns whether the token belongs to an admin connection
+    pub fn has_connection(&self, token: u64) -> bool {
+        self.connections.contains_key(&token)
+    }
+
+    /// ### connection_fd
+    ///
+    /// `connection_fd` returns the file descriptor of the connection
+    pub fn connection_fd(&self, token: u64) -> Option<RawFd> {
+        self.connections
+            .get(&token)
+            .map(|connection| connection.stream.as_raw_fd())
+    }
+
+    /// ### read
+    ///
+    /// `read` reads the available data on the connection and returns the complete request lines.
+    /// If the connection has been closed by the peer, None is returned; the connection must then be closed
+    pub fn read(&mut self, token: u64) -> Option<Vec<String>> {
+        let connection: &mut AdminConnection = self.connections.get_mut(&token)?;
+        let mut closed: bool = false;
+        let mut chunk: [u8; 1024] = [0; 1024];
+        loop {
+            match connection.stream.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(bytes) => {
+                    connection.buffer.extend_from_slice(&chunk[..bytes]);
+                    connection.last_activity = Instant::now();
+                }
+                Err(error) => match error.kind() {
+                    ErrorKind::WouldBlock => break,
+                    ErrorKind::Interrupted => continue,
+                    _ => {
+                        closed = true;
+                        break;
+                    }
+                },
+            }
+        }
+        let mut lines: Vec<String> = Vec::new();
+        while let Some(index) = connection.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = connection.buffer.drain(..=index).collect();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        if connection.buffer.len() > MAX_REQUEST_SIZE {
+            closed = true;
+        }
+        match closed && lines.is_empty() {
+            true => None,
+            false => Some(lines),
+        }
+    }
+
+    /// ### reply
+    ///
+    /// `reply` queues the reply on the connection and writes as much as possible of the queued replies, without blocking.
+    /// Returns whether part of the replies is still queued; if so, the rest must be written with `flush` once the connection is writable.
+    /// If the peer doesn't read its replies, an error is returned; the connection must then be closed
+    pub fn reply(&mut self, token: u64, reply: &str) -> std::io::Result<bool> {
+        let connection: &mut AdminConnection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return Err(std::io::Error::from(ErrorKind::NotConnected)),
+        };
+        if connection.out.len() + reply.len() > MAX_PENDING_REPLIES {
+            return Err(std::io::Error::from(ErrorKind::WouldBlock));
+        }
+        connection.out.extend_from_slice(reply.as_bytes());
+        self.flush(token)
+    }
+
+    /// ### flush
+    ///
+    /// `flush` writes the queued replies on the connection, until it can't take more data.
+    /// Returns whether part of the replies is still queued
+    pub fn flush(&mut self, token: u64) -> std::io::Result<bool> {
+        let connection: &mut AdminConnection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return Err(std::io::Error::from(ErrorKind::NotConnected)),
+        };
+        while !connection.out.is_empty() {
+            match connection.stream.write(&connection.out) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero)),
+                Ok(bytes) => {
+                    connection.out.drain(..bytes);
+                    connection.last_activity = Instant::now();
+                }
+                Err(error) => match error.kind() {
+                    ErrorKind::WouldBlock => break,
+                    ErrorKind::Interrupted => continue,
+                    _ => return Err(error),
+                },
+            }
+        }
+        Ok(!connection.out.is_empty())
+    }
+
+    /// ### idle
+    ///
+    /// `idle` returns the tokens of the connections which have been idle for too long; they must be closed
+    pub fn idle(&self) -> Vec<u64> {
+        self.connections
+            .iter()
+            .filter(|(_, connection)| connection.last_activity.elapsed() >= IDLE_TIMEOUT)
+            .map(|(token, _)| *token)
+            .collect()
+    }
+
+    /// ### next_idle_check
+    ///
+    /// `next_idle_check` returns when the first connection will have been idle for too long, if any
+    pub fn next_idle_check(&self) -> Option<Instant> {
+        self.connections
+            .values()
+            .map(|connection| connection.last_activity + IDLE_TIMEOUT)
+            .min()
+    }
+
+    /// ### close
+    ///
+    /// `close` closes the connection
+    pub fn close(&mut self, token: u64) {
+        self.connections.remove(&token);
+    }
+
+    /// ### tokens
+    ///
+    /// `tokens` returns the tokens of all the connections
+    pub fn tokens(&self) -> Vec<u64> {
+        self.connections.keys().cloned().collect()
+    }
+}
+
+impl AsRawFd for AdminSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for AdminSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// ### json_string
+///
+/// `json_string` encodes a string as a JSON string
+pub fn json_string(value: &str) -> String {
+    let mut out: String = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// ### json_string_array
+///
+/// `json_string_array` encodes a list of strings as a JSON array
+pub fn json_string_array(values: &[String]) -> String {
+    format!(
+        "[{}]",
+        values
+            .iter()
+            .map(|value| json_string(value))
+            .collect::<Vec<String>>()
+            .join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_admin_parse_request() {
+        assert_eq!(
+            AdminRequest::parse("list").unwrap(),
+            AdminRequest {
+                command: AdminCommand::ListClients,
+                format: AdminFormat::Text
+            }
+        );
+        assert_eq!(
+            AdminRequest::parse("json kick foo").unwrap(),
+            AdminRequest {
+                command: AdminCommand::Kick(String::from("foo")),
+                format: AdminFormat::Json
+            }
+        );
+        assert_eq!(
+            AdminRequest::parse("  subscriptions   bar ")
+                .unwrap()
+                .command,
+            AdminCommand::Subscriptions(String::from("bar"))
+        );
+        assert_eq!(
+            AdminRequest::parse("groups").unwrap().command,
+            AdminCommand::ListGroups
+        );
+        assert_eq!(
+            AdminRequest::parse("stats").unwrap().command,
+            AdminCommand::Stats
+        );
+        assert_eq!(
+            AdminRequest::parse("queues").unwrap().command,
+            AdminCommand::Queues
+        );
+        assert_eq!(
+            AdminRequest::parse("reload").unwrap().command,
+            AdminCommand::Reload
+        );
+        assert_eq!(
+            AdminRequest::parse("loglevel 3").unwrap().command,
+            AdminCommand::LogLevel(3)
+        );
+        assert!(AdminRequest::parse("loglevel 5").is_err());
+        assert!(AdminRequest::parse("loglevel debug").is_err());
+        assert!(AdminRequest::parse("kick").is_err());
+        assert!(AdminRequest::parse("reboot").is_err());
+        assert!(AdminRequest::parse("").is_err());
+    }
+
+    #[test]
+    fn test_admin_encode_reply() {
+        let reply: AdminReply = AdminReply::Clients(vec![
+            (
+                String::from("bar"),
+                vec![String::from("BROADCAST"), String::from("bar")],
+            ),
+            (String::from("foo"), vec![String::from("foo")]),
+        ]);
+        assert_eq!(
+            reply.encode(AdminFormat::Text),
+            "OK\nbar BROADCAST,bar\nfoo foo\n\n"
+        );
+        assert_eq!(
+            reply.encode(AdminFormat::Json),
+            "{\"status\":\"ok\",\"clients\":[{\"id\":\"bar\",\"groups\":[\"BROADCAST\",\"bar\"]},{\"id\":\"foo\",\"groups\":[\"foo\"]}]}\n"
+        );
+        let reply: AdminReply = AdminReply::Stats(vec![("clients", 2), ("messages_routed", 10)]);
+        assert_eq!(
+            reply.encode(AdminFormat::Text),
+            "OK\nclients 2\nmessages_routed 10\n\n"
+        );
+        assert_eq!(
+            reply.encode(AdminFormat::Json),
+            "{\"status\":\"ok\",\"stats\":{\"clients\":2,\"messages_routed\":10}}\n"
+        );
+        let reply: AdminReply = AdminReply::Queues(vec![(String::from("foo"), 3)]);
+        assert_eq!(reply.encode(AdminFormat::Text), "OK\nfoo 3\n\n");
+        assert_eq!(
+            reply.encode(AdminFormat::Json),
+            "{\"status\":\"ok\",\"queues\":[{\"id\":\"foo\",\"depth\":3}]}\n"
+        );
+        let reply: AdminReply = AdminReply::Error(String::from("Client \"baz\" not found"));
+        assert_eq!(
+            reply.encode(AdminFormat::Text),
+            "ERR Client \"baz\" not found\n\n"
+        );
+        assert_eq!(
+            reply.encode(AdminFormat::Json),
+            "{\"status\":\"error\",\"message\":\"Client \\\"baz\\\" not found\"}\n"
+        );
+        assert_eq!(json_string("a\tb\u{1}"), "\"a\\tb\\u0001\"");
+    }
+
+    #[test]
+    fn test_admin_socket() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("admin.sock").display().to_string();
+        let mut admin: AdminSocket = AdminSocket::bind(&path).unwrap();
+        //Socket already in use
+        assert!(AdminSocket::bind(&path).is_err());
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            SOCKET_MODE
+        );
+        //Connection used to probe the socket by the second bind
+        assert!(admin.accept().is_some());
+        assert!(admin.accept().is_none());
+        let mut client: UnixStream = UnixStream::connect(&path).unwrap();
+        let stream: UnixStream = admin.accept().unwrap();
+        admin.add_connection(32, stream);
+        assert!(admin.has_connection(32));
+        assert!(admin.connection_fd(32).is_some());
+        //Partial request
+        client.write_all(b"json li").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(admin.read(32).unwrap().is_empty());
+        client.write_all(b"st\nstats\n").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(
+            admin.read(32).unwrap(),
+            vec![String::from("json list"), String::from("stats")]
+        );
+        assert!(!admin.reply(32, "OK\n\n").unwrap());
+        let mut buffer: [u8; 4] = [0; 4];
+        client.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"OK\n\n");
+        //Replies which can't be written are queued, until the peer reads them
+        let reply: String = "x".repeat(MAX_PENDING_REPLIES / 4);
+        let mut pending: bool = false;
+        while !pending {
+            pending = admin.reply(32, &reply).unwrap();
+        }
+        assert!(admin.reply(32, &reply).is_ok());
+        assert!(admin.reply(32, &reply.repeat(4)).is_err());
+        let mut received: [u8; 1024] = [0; 1024];
+        client.read_exact(&mut received).unwrap();
+        assert!(admin.flush(32).unwrap());
+        assert!(admin.idle().is_empty());
+        assert!(admin.next_idle_check().unwrap() > Instant::now());
+        //Peer closed
+        drop(client);
+        assert!(admin.read(32).is_none());
+        admin.close(32);
+        assert!(!admin.has_connection(32));
+        //Connections beyond the limit are closed
+        let clients: Vec<UnixStream> = (0..MAX_CONNECTIONS + 1)
+            .map(|_| UnixStream::connect(&path).unwrap())
+            .collect();
+        for token in 0..MAX_CONNECTIONS {
+            let stream: UnixStream = admin.accept().unwrap();
+            admin.add_connection(token as u64, stream);
+        }
+        assert!(admin.accept().is_none());
+        let mut buffer: [u8; 1] = [0; 1];
+        assert_eq!((&clients[MAX_CONNECTIONS]).read(&mut buffer).unwrap(), 0);
+        for token in 0..MAX_CONNECTIONS {
+            admin.close(token as u64);
+        }
+        //Socket is removed on drop
+        drop(admin);
+        assert!(fs::metadata(&path).is_err());
+        //A stale socket is replaced
+        let stale: UnixListener = UnixListener::bind(&path).unwrap();
+        drop(stale);
+        assert!(AdminSocket::bind(&path).is_ok());
+    }
+}
@@ -0,0 +1,542 @@
+//! ### capture
+//!
+//! `capture` is the module which records the routed messages to a capture file, which can be replayed later.
+//! The format of the capture file is described in `docs/capture.md`
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::acl;
+use crate::groups;
+
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//Every capture file starts with the magic and the version of the format
+pub const CAPTURE_MAGIC: &[u8; 7] = b"OCTOCAP";
+pub const CAPTURE_FORMAT_VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 8;
+//Size of a record without origin, remote and payload
+const RECORD_MINIMUM_SIZE: usize = 21;
+
+/// ### CaptureRecord
+///
+/// `CaptureRecord` is a message routed by the server, as it is recorded in the capture file
+#[derive(Clone, PartialEq, std::fmt::Debug)]
+pub struct CaptureRecord {
+    //Microseconds since the Unix epoch
+    pub timestamp: u64,
+    pub version: u8,
+    //Empty if the message has been sent by the server
+    pub origin: String,
+    pub remote: String,
+    pub ttl: u8,
+    pub options: u8,
+    pub data: Vec<u8>,
+}
+
+/// ### CaptureFilter
+///
+/// `CaptureFilter` tells which messages are recorded: the origin must match one of the client patterns
+/// ('*' and '?' wildcards) and the remote one of the group filters ('+' and '#' wildcards). An empty list matches everything
+#[derive(Clone, Default, PartialEq, std::fmt::Debug)]
+pub struct CaptureFilter {
+    pub clients: Vec<String>,
+    pub groups: Vec<String>,
+}
+
+/// ### Capture
+///
+/// `Capture` is an open capture file, which is rotated once it exceeds the maximum size
+pub struct Capture {
+    path: String,
+    writer: BufWriter<File>,
+    size: u64,
+    //Maximum size of the capture file; 0 means unlimited
+    max_size: u64,
+    //Rotated capture files which are kept
+    max_files: usize,
+}
+
+impl CaptureRecord {
+    /// ### now
+    ///
+    /// `now` instantiates a new record, with the current time as timestamp
+    pub fn now(
+        version: u8,
+        origin: &str,
+        remote: &str,
+        ttl: u8,
+        options: u8,
+        data: Vec<u8>,
+    ) -> CaptureRecord {
+        let timestamp: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_micros() as u64)
+            .unwrap_or(0);
+        CaptureRecord {
+            timestamp,
+            version,
+            origin: String::from(origin),
+            remote: String::from(remote),
+            ttl,
+            options,
+            data,
+        }
+    }
+
+    /// ### encode
+    ///
+    /// `encode` encodes the record for the capture file
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data_out: Vec<u8> = Vec::with_capacity(
+            RECORD_MINIMUM_SIZE + self.origin.len() + self.remote.len() + self.data.len(),
+        );
+        data_out.extend_from_slice(&self.timestamp.to_be_bytes());
+        data_out.push(self.version);
+        data_out.push(self.origin.len() as u8);
+        data_out.extend_from_slice(self.origin.as_bytes());
+        data_out.push(self.remote.len() as u8);
+        data_out.extend_from_slice(self.remote.as_bytes());
+        data_out.push(self.ttl);
+        data_out.push(self.options);
+        data_out.extend_from_slice(&(self.data.len() as u64).to_be_bytes());
+        data_out.extend_from_slice(&self.data);
+        data_out
+    }
+
+    /// ### decode
+    ///
+    /// `decode` decodes the first record in the buffer, returning it with the amount of bytes it took.
+    /// None is returned if the buffer doesn't contain an entire record
+    pub fn decode(data: &[u8]) -> Option<(CaptureRecord, usize)> {
+        let mut offset: usize = 0;
+        let mut take = |size: usize| -> Option<&[u8]> {
+            let field: &[u8] = data.get(offset..offset.checked_add(size)?)?;
+            offset += size;
+            Some(field)
+        };
+        let mut timestamp: [u8; 8] = [0; 8];
+        timestamp.copy_from_slice(take(8)?);
+        let version: u8 = take(1)?[0];
+        let origin_size: usize = take(1)?[0] as usize;
+        let origin: String = String::from_utf8_lossy(take(origin_size)?).to_string();
+        let remote_size: usize = take(1)?[0] as usize;
+        let remote: String = String::from_utf8_lossy(take(remote_size)?).to_string();
+        let ttl: u8 = take(1)?[0];
+        let options: u8 = take(1)?[0];
+        let mut data_size: [u8; 8] = [0; 8];
+        data_size.copy_from_slice(take(8)?);
+        let data_size: usize = usize::try_from(u64::from_be_bytes(data_size)).ok()?;
+        let payload: Vec<u8> = take(data_size)?.to_vec();
+        Some((
+            CaptureRecord {
+                timestamp: u64::from_be_bytes(timestamp),
+                version,
+                origin,
+                remote,
+                ttl,
+                options,
+                data: payload,
+            },
+            offset,
+        ))
+    }
+}
+
+impl CaptureFilter {
+    /// ### matches
+    ///
+    /// `matches` returns whether a message sent by origin to remote has to be recorded
+    pub fn matches(&self, origin: &str, remote: &str) -> bool {
+        (self.clients.is_empty()
+            || self
+                .clients
+                .iter()
+                .any(|pattern| acl::pattern_match(pattern, origin)))
+            && (self.groups.is_empty()
+                || self
+                    .groups
+                    .iter()
+                    .any(|filter| groups::filter_matches(filter, remote)))
+    }
+}
+
+impl Capture {
+    /// ### open
+    ///
+    /// `open` opens the capture file, creating it if it doesn't exist; records are appended to an existing capture file.
+    /// Files which are not capture files are refused, while a truncated record at the end of the file is discarded
+    pub fn open(path: &str) -> std::io::Result<Capture> {
+        let mut file: File = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut size: u64 = file.metadata()?.len();
+        if size == 0 {
+            file.write_all(&header())?;
+            size = HEADER_SIZE;
+        } else {
+            let mut data: Vec<u8> = Vec::with_capacity(size as usize);
+            file.read_to_end(&mut data)?;
+            if data.len() < HEADER_SIZE as usize || data[..HEADER_SIZE as usize] != header() {
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+            //Find the end of the last complete record
+            let mut offset: usize = HEADER_SIZE as usize;
+            while let Some((_, record_size)) = CaptureRecord::decode(&data[offset..]) {
+                offset += record_size;
+            }
+            if offset < data.len() {
+                file.set_len(offset as u64)?;
+            }
+            size = offset as u64;
+        }
+        Ok(Capture {
+            path: String::from(path),
+            writer: BufWriter::new(file),
+            size,
+            max_size: 0,
+            max_files: 0,
+        })
+    }
+
+    /// ### get_path
+    ///
+    /// `get_path` returns the path of the capture file
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// ### set_path
+    ///
+    /// `set_path` changes the path of the open capture file, which is used once it's rotated (e.g. after a chroot)
+    pub fn set_path(&mut self, path: String) {
+        self.path = path;
+    }
+
+    /// ### set_rotation
+    ///
+    /// `set_rotation` sets the maximum size of the capture file (0 means unlimited) and how many rotated files are kept
+    pub fn set_rotation(&mut self, max_size: u64, max_files: usize) {
+        self.max_size = max_size;
+        self.max_files = max_files;
+    }
+
+    /// ### record
+    ///
+    /// `record` appends the record to the capture file, rotating it first if the record would make it exceed the maximum size.
+    /// Records are buffered until the capture is flushed
+    pub fn record(&mut self, record: &CaptureRecord) -> std::io::Result<()> {
+        let data: Vec<u8> = record.encode();
+        //A record bigger than the maximum size is written anyway, in its own file
+        if self.max_size > 0
+            && self.size > HEADER_SIZE
+            && self.size + data.len() as u64 > self.max_size
+        {
+            self.rotate()?;
+        }
+        self.writer.write_all(&data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// ### flush
+    ///
+    /// `flush` writes the buffered records to the capture file
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// ### rotate
+    ///
+    /// `rotate` renames the capture file appending '.1' to it (shifting the older ones, while the oldest is discarded)
+    /// and starts a new capture file
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        match self.max_files {
+            0 => fs::remove_file(&self.path)?,
+            max_files => {
+                for index in (1..max_files).rev() {
+                    let rotated: String = format!("{}.{}", self.path, index);
+                    if fs::metadata(&rotated).is_ok() {
+                        fs::rename(&rotated, format!("{}.{}", self.path, index + 1))?;
+                    }
+                }
+                fs::rename(&self.path, format!("{}.1", self.path))?;
+            }
+        }
+        let mut file: File = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)?;
+        file.write_all(&header())?;
+        self.writer = BufWriter::new(file);
+        self.size = HEADER_SIZE;
+        Ok(())
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// ### read_file
+///
+/// `read_file` reads all the records in a capture file; a truncated record at the end of the file is ignored
+pub fn read_file(path: &str) -> std::io::Result<Vec<CaptureRecord>> {
+    let data: Vec<u8> = fs::read(path)?;
+    if data.len() < HEADER_SIZE as usize || data[..HEADER_SIZE as usize] != header() {
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    let mut records: Vec<CaptureRecord> = Vec::new();
+    let mut offset: usize = HEADER_SIZE as usize;
+    while let Some((record, size)) = CaptureRecord::decode(&data[offset..]) {
+        records.push(record);
+        offset += size;
+    }
+    Ok(records)
+}
+
+/// ### header
+///
+/// `header` returns the header of the capture files
+fn header() -> [u8; HEADER_SIZE as usize] {
+    let mut header: [u8; HEADER_SIZE as usize] = [0; HEADER_SIZE as usize];
+    header[..CAPTURE_MAGIC.len()].copy_from_slice(CAPTURE_MAGIC);
+    header[CAPTURE_MAGIC.len()] = CAPTURE_FORMAT_VERSION;
+    header
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol;
+
+    #[test]
+    fn test_capture_record() {
+        let record: CaptureRecord = CaptureRecord {
+            timestamp: 1600000000123456,
+            version: 1,
+            origin: String::from("foo"),
+            remote: String::from("sensors/temp"),
+            ttl: 5,
+            options: 0x04,
+            data: vec![0x48, 0x49],
+        };
+        let data: Vec<u8> = record.encode();
+        assert_eq!(data.len(), 21 + 3 + 12 + 2);
+        assert_eq!(&data[..8], &1600000000123456u64.to_be_bytes());
+        assert_eq!(&data[8..13], &[0x01, 0x03, b'f', b'o', b'o']);
+        assert_eq!(
+            CaptureRecord::decode(&data),
+            Some((record.clone(), data.len()))
+        );
+        //Incomplete record
+        assert_eq!(CaptureRecord::decode(&data[..data.len() - 1]), None);
+        assert_eq!(CaptureRecord::decode(&[]), None);
+        //Current timestamp
+        let record: CaptureRecord =
+            CaptureRecord::now(1, "", "$SYS/server/started", 0, 0, Vec::new());
+        assert!(record.timestamp > 1600000000000000);
+        assert!(record.origin.is_empty());
+    }
+
+    #[test]
+    fn test_capture_sample() {
+        //Capture shared with the tests of octopipes-replay, which has its own decoder
+        let path: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../docs/capture-sample.cap");
+        let records: Vec<CaptureRecord> = read_file(path).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                CaptureRecord {
+                    timestamp: 1600000000000000,
+                    version: 1,
+                    origin: String::from("foo"),
+                    remote: String::from("sensors/kitchen"),
+                    ttl: 5,
+                    options: 0,
+                    data: b"21.5".to_vec(),
+                },
+                CaptureRecord {
+                    timestamp: 1600000000250000,
+                    version: 1,
+                    origin: String::new(),
+                    remote: String::from("$SYS/server"),
+                    ttl: 0,
+                    options: 0,
+                    data: vec![0x00, 0xff],
+                },
+                CaptureRecord {
+                    timestamp: 1600000001000000,
+                    version: 1,
+                    origin: String::from("bar"),
+                    remote: String::from("status"),
+                    ttl: 10,
+                    options: protocol::OPTION_RET,
+                    data: b"up".to_vec(),
+                },
+            ]
+        );
+        //Records are encoded as in the sample, which ends with a truncated record
+        let data: Vec<u8> = fs::read(path).unwrap();
+        let encoded: Vec<u8> = [
+            header().to_vec(),
+            records.iter().flat_map(|record| record.encode()).collect(),
+        ]
+        .concat();
+        assert!(data.len() > encoded.len());
+        assert_eq!(&data[..encoded.len()], encoded.as_slice());
+    }
+
+    #[test]
+    fn test_capture_filter() {
+        assert!(CaptureFilter::default().matches("foo", "bar"));
+        let filter: CaptureFilter = CaptureFilter {
+            clients: vec![String::from("sensor-*")],
+            groups: vec![String::from("sensors/#"), String::from("collector")],
+        };
+        assert!(filter.matches("sensor-1", "sensors/kitchen/temp"));
+        assert!(filter.matches("sensor-1", "collector"));
+        assert!(!filter.matches("logger", "collector"));
+        assert!(!filter.matches("sensor-1", "BROADCAST"));
+        let filter: CaptureFilter = CaptureFilter {
+            clients: Vec::new(),
+            groups: vec![String::from("#")],
+        };
+        assert!(filter.matches("", "BROADCAST"));
+        //System groups must be filtered explicitly
+        assert!(!filter.matches("", "$SYS/server/started"));
+    }
+
+    #[test]
+    fn test_capture_file() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("traffic.cap").display().to_string();
+        let record = |data: &[u8]| -> CaptureRecord {
+            CaptureRecord::now(1, "foo", "bar", 5, 0, data.to_vec())
+        };
+        let mut capture: Capture = Capture::open(&path).unwrap();
+        assert_eq!(capture.get_path(), path.as_str());
+        assert!(capture.record(&record(b"one")).is_ok());
+        assert!(capture.record(&record(b"two")).is_ok());
+        assert!(capture.flush().is_ok());
+        drop(capture);
+        //Records are appended to an existing capture
+        let mut capture: Capture = Capture::open(&path).unwrap();
+        assert!(capture.record(&record(b"three")).is_ok());
+        drop(capture);
+        let records: Vec<CaptureRecord> = read_file(&path).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.data.clone())
+                .collect::<Vec<Vec<u8>>>(),
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+        assert!(records[0].timestamp <= records[2].timestamp);
+        //A truncated record is ignored, and discarded when the capture is opened again
+        let mut truncated: Vec<u8> = record(b"four").encode();
+        truncated.truncate(10);
+        let mut file: File = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&truncated).unwrap();
+        drop(file);
+        assert_eq!(read_file(&path).unwrap().len(), 3);
+        let mut capture: Capture = Capture::open(&path).unwrap();
+        assert!(capture.record(&record(b"five")).is_ok());
+        drop(capture);
+        let records: Vec<CaptureRecord> = read_file(&path).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].data, b"five".to_vec());
+        //Files which aren't captures are refused
+        let other: String = tmpdir.path().join("other.txt").display().to_string();
+        fs::write(&other, "hello world").unwrap();
+        assert_eq!(
+            Capture::open(&other).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(read_file(&other).is_err());
+    }
+
+    #[test]
+    fn test_capture_rotation() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("traffic.cap").display().to_string();
+        let record = |data: &[u8]| -> CaptureRecord {
+            CaptureRecord::now(1, "foo", "bar", 5, 0, data.to_vec())
+        };
+        //Each record takes 21 + 3 + 3 + 10 bytes: two records fit in a file
+        let mut capture: Capture = Capture::open(&path).unwrap();
+        capture.set_rotation(HEADER_SIZE + 2 * 37, 2);
+        for data in [
+            b"0000000000",
+            b"1111111111",
+            b"2222222222",
+            b"3333333333",
+            b"4444444444",
+            b"5555555555",
+            b"6666666666",
+        ]
+        .iter()
+        {
+            assert!(capture.record(&record(*data)).is_ok());
+        }
+        drop(capture);
+        let payloads = |path: &str| -> Vec<Vec<u8>> {
+            read_file(path)
+                .unwrap()
+                .into_iter()
+                .map(|record| record.data)
+                .collect()
+        };
+        assert_eq!(payloads(&path), vec![b"6666666666".to_vec()]);
+        assert_eq!(
+            payloads(&format!("{}.1", path)),
+            vec![b"4444444444".to_vec(), b"5555555555".to_vec()]
+        );
+        assert_eq!(
+            payloads(&format!("{}.2", path)),
+            vec![b"2222222222".to_vec(), b"3333333333".to_vec()]
+        );
+        //The oldest file has been discarded
+        assert!(fs::metadata(format!("{}.3", path)).is_err());
+        //Without rotated files, the capture starts over
+        let mut capture: Capture = Capture::open(&path).unwrap();
+        capture.set_rotation(HEADER_SIZE + 37, 0);
+        assert!(capture.record(&record(b"7777777777")).is_ok());
+        drop(capture);
+        assert_eq!(payloads(&path), vec![b"7777777777".to_vec()]);
+        assert_eq!(
+            payloads(&format!("{}.1", path)),
+            vec![b"4444444444".to_vec(), b"5555555555".to_vec()]
+        );
+    }
+}
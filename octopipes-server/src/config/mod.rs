@@ -27,14 +27,134 @@
 
 extern crate yaml_rust;
 
+use crate::acl::{Acl, AclPolicy, AclRule};
+use crate::pipes::Ownership;
+use crate::process;
+use crate::groups;
+use crate::server::{OverflowPolicy, SelectionPolicy};
+use crate::store;
+
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::net::SocketAddr;
 use yaml_rust::{Yaml, YamlLoader};
 
+//Defaults
+pub const DEFAULT_LOG_LEVEL: usize = 2;
+pub const DEFAULT_LOG_FILE: &str = "/var/log/octopipes/octopipes.log";
+pub const DEFAULT_LOG_STDOUT: bool = true;
+pub const DEFAULT_CAP_PATH: &str = "/tmp/octopipes/cap.pipe";
+pub const DEFAULT_CLIENT_DIR: &str = "/tmp/octopipes/clients/";
+pub const DEFAULT_PROTOCOL_VERSION: u8 = 1;
+pub const DEFAULT_TTL: u64 = 5;
+pub const DEFAULT_ADMIN_ENABLED: bool = true;
+pub const DEFAULT_ADMIN_SOCKET: &str = "/tmp/octopipes/admin.sock";
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: u64 = 2000;
+pub const DEFAULT_DEAD_CLIENT_CHECK_INTERVAL: u64 = 0;
+pub const DEFAULT_MAX_MISSED_DELIVERIES: u64 = 0;
+pub const DEFAULT_HEARTBEAT_INTERVAL: u64 = 5000;
+pub const DEFAULT_HEARTBEAT_MISSED_BEATS: u64 = 3;
+pub const DEFAULT_QUEUE_SIZE: u64 = 1024;
+pub const DEFAULT_OVERFLOW_POLICY: OverflowPolicy = OverflowPolicy::DropOldest;
+pub const DEFAULT_QUEUE_GROUP_POLICY: SelectionPolicy = SelectionPolicy::RoundRobin;
+pub const DEFAULT_DATA_DIR: &str = "/var/lib/octopipes/";
+pub const DEFAULT_DURABLE_MAX_SIZE: u64 = 16777216;
+pub const DEFAULT_DURABLE_MAX_AGE: u64 = 86400;
+pub const DEFAULT_METRICS_INTERVAL: u64 = 15000;
+pub const DEFAULT_STATUS_INTERVAL: u64 = 5000;
+pub const DEFAULT_CAPTURE_MAX_SIZE: u64 = 67108864;
+pub const DEFAULT_CAPTURE_MAX_FILES: u64 = 4;
+
+//Environment variables
+pub const ENV_CONFIG_FILE: &str = "OCTOPIPES_CONFIG";
+pub const ENV_LOG_LEVEL: &str = "OCTOPIPES_LOG_LEVEL";
+pub const ENV_LOG_FILE: &str = "OCTOPIPES_LOG_FILE";
+pub const ENV_LOG_STDOUT: &str = "OCTOPIPES_LOG_STDOUT";
+pub const ENV_CAP_PATH: &str = "OCTOPIPES_CAP_PATH";
+pub const ENV_CLIENT_DIR: &str = "OCTOPIPES_CLIENT_DIR";
+pub const ENV_CAP_MODE: &str = "OCTOPIPES_CAP_MODE";
+pub const ENV_CAP_OWNER: &str = "OCTOPIPES_CAP_OWNER";
+pub const ENV_CAP_GROUP: &str = "OCTOPIPES_CAP_GROUP";
+pub const ENV_CLIENT_MODE: &str = "OCTOPIPES_CLIENT_MODE";
+pub const ENV_CLIENT_OWNER: &str = "OCTOPIPES_CLIENT_OWNER";
+pub const ENV_CLIENT_GROUP: &str = "OCTOPIPES_CLIENT_GROUP";
+pub const ENV_CLIENT_DIR_MODE: &str = "OCTOPIPES_CLIENT_DIR_MODE";
+pub const ENV_PROTOCOL_VERSION: &str = "OCTOPIPES_PROTOCOL_VERSION";
+pub const ENV_DEFAULT_TTL: &str = "OCTOPIPES_DEFAULT_TTL";
+pub const ENV_PID_FILE: &str = "OCTOPIPES_PIDFILE";
+pub const ENV_ADMIN_SOCKET: &str = "OCTOPIPES_ADMIN_SOCKET";
+pub const ENV_SHUTDOWN_GRACE_PERIOD: &str = "OCTOPIPES_SHUTDOWN_GRACE_PERIOD";
+pub const ENV_DEAD_CLIENT_CHECK_INTERVAL: &str = "OCTOPIPES_DEAD_CLIENT_CHECK_INTERVAL";
+pub const ENV_MAX_MISSED_DELIVERIES: &str = "OCTOPIPES_MAX_MISSED_DELIVERIES";
+pub const ENV_HEARTBEAT_INTERVAL: &str = "OCTOPIPES_HEARTBEAT_INTERVAL";
+pub const ENV_HEARTBEAT_MISSED_BEATS: &str = "OCTOPIPES_HEARTBEAT_MISSED_BEATS";
+pub const ENV_QUEUE_SIZE: &str = "OCTOPIPES_QUEUE_SIZE";
+pub const ENV_OVERFLOW_POLICY: &str = "OCTOPIPES_OVERFLOW_POLICY";
+pub const ENV_QUEUE_GROUP_POLICY: &str = "OCTOPIPES_QUEUE_GROUP_POLICY";
+pub const ENV_USER: &str = "OCTOPIPES_USER";
+pub const ENV_GROUP: &str = "OCTOPIPES_GROUP";
+pub const ENV_CHROOT: &str = "OCTOPIPES_CHROOT";
+pub const ENV_DATA_DIR: &str = "OCTOPIPES_DATA_DIR";
+pub const ENV_DEAD_LETTER_GROUP: &str = "OCTOPIPES_DEAD_LETTER_GROUP";
+pub const ENV_SYSTEM_EVENTS: &str = "OCTOPIPES_SYSTEM_EVENTS";
+pub const ENV_METRICS_LISTEN: &str = "OCTOPIPES_METRICS_LISTEN";
+pub const ENV_METRICS_FILE: &str = "OCTOPIPES_METRICS_FILE";
+pub const ENV_STATUS_FILE: &str = "OCTOPIPES_STATUS_FILE";
+pub const ENV_STATUS_INTERVAL: &str = "OCTOPIPES_STATUS_INTERVAL";
+pub const ENV_CAPTURE_FILE: &str = "OCTOPIPES_CAPTURE_FILE";
+
+//Settings which can be set in the configuration file, with their section and key in the YAML document
+const FILE_SETTINGS: [(&str, &str, &str); 34] = [
+    ("log-level", "logging", "log_level"),
+    ("log-file", "logging", "log_file"),
+    ("log-stdout", "logging", "stdout"),
+    ("cap-pipe", "pipes", "cap_path"),
+    ("client-dir", "pipes", "client_dir"),
+    ("cap-mode", "pipes", "cap_mode"),
+    ("cap-owner", "pipes", "cap_owner"),
+    ("cap-group", "pipes", "cap_group"),
+    ("client-mode", "pipes", "client_mode"),
+    ("client-owner", "pipes", "client_owner"),
+    ("client-group", "pipes", "client_group"),
+    ("client-dir-mode", "pipes", "client_dir_mode"),
+    ("protocol-version", "protocol", "version"),
+    ("default-ttl", "protocol", "default_ttl"),
+    ("admin-socket", "admin", "socket"),
+    ("shutdown-grace-period", "server", "shutdown_grace_period"),
+    ("dead-client-check-interval", "server", "dead_client_check_interval"),
+    ("max-missed-deliveries", "server", "max_missed_deliveries"),
+    ("heartbeat-interval", "server", "heartbeat_interval"),
+    ("heartbeat-missed-beats", "server", "heartbeat_missed_beats"),
+    ("queue-size", "server", "queue_size"),
+    ("overflow-policy", "server", "overflow_policy"),
+    ("queue-group-policy", "server", "queue_group_policy"),
+    ("user", "server", "user"),
+    ("group", "server", "group"),
+    ("chroot", "server", "chroot"),
+    ("data-dir", "durable", "data_dir"),
+    ("dead-letter-group", "server", "dead_letter_group"),
+    ("system-events", "server", "system_events"),
+    ("metrics-listen", "metrics", "listen"),
+    ("metrics-file", "metrics", "file"),
+    ("status-file", "status", "file"),
+    ("status-interval", "status", "interval"),
+    ("capture-file", "capture", "file"),
+];
+
 //Types
 pub struct Config {
     pub log_config: LogConfig,
     pub pipes_config: PipesConfig,
     pub protocol_config: ProtocolConfig,
+    pub admin_config: AdminConfig,
+    pub server_config: ServerConfig,
+    pub acl_config: Acl,
+    pub queue_groups: BTreeMap<String, SelectionPolicy>,
+    pub retained_groups: Vec<String>,
+    pub durable_config: DurableConfig,
+    pub metrics_config: MetricsConfig,
+    pub status_config: StatusConfig,
+    pub capture_config: CaptureConfig,
 }
 
 pub struct LogConfig {
@@ -46,10 +166,65 @@ pub struct LogConfig {
 pub struct PipesConfig {
     pub cap_path: String,
     pub client_dir: String,
+    pub cap_mode: Option<u32>,
+    pub cap_owner: Option<u32>,
+    pub cap_group: Option<u32>,
+    pub client_mode: Option<u32>,
+    pub client_owner: Option<u32>,
+    pub client_group: Option<u32>,
+    pub client_dir_mode: Option<u32>,
 }
 
 pub struct ProtocolConfig {
     pub version: u8,
+    pub default_ttl: u64,
+}
+
+pub struct AdminConfig {
+    pub enabled: bool,
+    pub socket: String,
+}
+
+pub struct DurableConfig {
+    pub data_dir: String,
+    pub clients: Vec<String>,
+    pub max_size: u64,
+    pub max_age: u64,
+}
+
+pub struct MetricsConfig {
+    pub listen: Option<String>,
+    pub file: Option<String>,
+    pub interval: u64,
+}
+
+pub struct StatusConfig {
+    pub file: Option<String>,
+    pub interval: u64,
+}
+
+pub struct CaptureConfig {
+    pub file: Option<String>,
+    pub clients: Vec<String>,
+    pub groups: Vec<String>,
+    pub max_size: u64,
+    pub max_files: u64,
+}
+
+pub struct ServerConfig {
+    pub shutdown_grace_period: u64,
+    pub dead_client_check_interval: u64,
+    pub max_missed_deliveries: u64,
+    pub heartbeat_interval: u64,
+    pub heartbeat_missed_beats: u64,
+    pub queue_size: u64,
+    pub overflow_policy: OverflowPolicy,
+    pub queue_group_policy: SelectionPolicy,
+    pub user: Option<u32>,
+    pub group: Option<u32>,
+    pub chroot: bool,
+    pub dead_letter_group: Option<String>,
+    pub system_events: bool,
 }
 
 #[derive(Copy, Clone, PartialEq, fmt::Debug)]
@@ -57,6 +232,7 @@ pub enum ConfigErrorCode {
     NoSuchFileOrDirectory,
     CouldNotReadFile,
     YamlSyntaxError,
+    InvalidValue,
 }
 
 pub struct ConfigError {
@@ -64,114 +240,350 @@ pub struct ConfigError {
     pub message: String,
 }
 
+/// ### SettingSource
+///
+/// `SettingSource` describes the layer an effective setting has been taken from
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub enum SettingSource {
+    Default,
+    File,
+    Env(&'static str),
+    Cli,
+}
+
+/// ### CliOverrides
+///
+/// `CliOverrides` contains the raw values provided through the command line options
+#[derive(Default)]
+pub struct CliOverrides {
+    pub config_file: Option<String>,
+    pub cap_path: Option<String>,
+    pub client_dir: Option<String>,
+    pub log_level: Option<String>,
+    pub log_file: Option<String>,
+    pub pid_file: Option<String>,
+}
+
+/// ### Settings
+///
+/// `Settings` is the effective server configuration, resolved merging (from the lowest to the highest priority)
+/// defaults, the YAML configuration file, the `OCTOPIPES_*` environment variables and the CLI options
+pub struct Settings {
+    pub config: Config,
+    pub config_file: Option<String>,
+    pub pid_file: Option<String>,
+    sources: HashMap<&'static str, SettingSource>,
+}
+
 //Implementation
 impl Config {
     /// ### parse_config
     ///
     /// `parse_config` parse a YAML configuration file and return a Config struct
     pub fn parse_config(config_file: String) -> Result<Config, ConfigError> {
+        Config::from_yaml(&Config::load_yaml(&config_file)?)
+    }
+
+    /// ### load_yaml
+    ///
+    /// `load_yaml` reads a YAML configuration file and returns its first document
+    fn load_yaml(config_file: &str) -> Result<Yaml, ConfigError> {
         let config_str: String;
         //Read configuration file
-        match std::fs::read_to_string(config_file.clone()) {
+        match std::fs::read_to_string(config_file) {
             Ok(config) => config_str = config,
             Err(err) => {
                 match err.kind() {
                     std::io::ErrorKind::NotFound => {
                         return Err(ConfigError {
                             code: ConfigErrorCode::NoSuchFileOrDirectory,
-                            message: String::from(["No such file or directory: ", config_file.as_str()].join(" ")),
+                            message: ["No such file or directory: ", config_file].join(" "),
                         })
                     },
                     _ => {
                         return Err(ConfigError {
                             code: ConfigErrorCode::CouldNotReadFile,
-                            message: String::from(["Could not read file ", config_file.as_str()].join(" ")),
+                            message: ["Could not read file ", config_file].join(" "),
                         })
                     }
                 }
-                
             }
         };
         //Parse YAML file
-        let yaml_docs: Vec<Yaml>;
+        let mut yaml_docs: Vec<Yaml>;
         match YamlLoader::load_from_str(config_str.as_str()) {
             Ok(doc) => yaml_docs = doc,
             Err(_) => {
                 return Err(ConfigError {
                     code: ConfigErrorCode::YamlSyntaxError,
-                    message: String::from(["Could not parse file", config_file.as_str()].join(" ")),
+                    message: ["Could not parse file", config_file].join(" "),
                 })
             }
         };
         //Check there is at least one document
-        if yaml_docs.len() == 0 {
+        if yaml_docs.is_empty() {
             return Err(ConfigError {
                 code: ConfigErrorCode::YamlSyntaxError,
                 message: String::from("File does not contain any YAML document"),
             });
         };
-        let yaml_doc: &Yaml = &yaml_docs[0];
+        Ok(yaml_docs.swap_remove(0))
+    }
+
+    /// ### from_yaml
+    ///
+    /// `from_yaml` gets a Config from a YAML document; missing sections and keys are taken from defaults
+    fn from_yaml(yaml_doc: &Yaml) -> Result<Config, ConfigError> {
         //Look for keys and get configuration parts
         let logging_config_yaml = &yaml_doc["logging"];
         let pipes_config_yaml = &yaml_doc["pipes"];
         let protocol_config_yaml = &yaml_doc["protocol"];
-        let logging_config: LogConfig = match LogConfig::parse_log_config(logging_config_yaml) {
-            Ok(config) => config,
-            Err(err) => return Err(err),
-        };
-        let pipes_config: PipesConfig = match PipesConfig::parse_pipes_config(pipes_config_yaml) {
-            Ok(config) => config,
-            Err(err) => return Err(err),
-        };
+        let admin_config_yaml = &yaml_doc["admin"];
+        let server_config_yaml = &yaml_doc["server"];
+        let acl_config_yaml = &yaml_doc["acl"];
+        let queue_groups_yaml = &yaml_doc["queue_groups"];
+        let retained_groups_yaml = &yaml_doc["retained_groups"];
+        let durable_config_yaml = &yaml_doc["durable"];
+        let metrics_config_yaml = &yaml_doc["metrics"];
+        let status_config_yaml = &yaml_doc["status"];
+        let capture_config_yaml = &yaml_doc["capture"];
+        let log_config: LogConfig = LogConfig::parse_log_config(logging_config_yaml)?;
+        let pipes_config: PipesConfig = PipesConfig::parse_pipes_config(pipes_config_yaml)?;
         let protocol_config: ProtocolConfig =
-            match ProtocolConfig::parse_protocol_config(protocol_config_yaml) {
-                Ok(config) => config,
-                Err(err) => return Err(err),
-            };
+            ProtocolConfig::parse_protocol_config(protocol_config_yaml)?;
+        let admin_config: AdminConfig = AdminConfig::parse_admin_config(admin_config_yaml)?;
+        let server_config: ServerConfig = ServerConfig::parse_server_config(server_config_yaml)?;
+        let acl_config: Acl = parse_acl_config(acl_config_yaml)?;
+        let queue_groups: BTreeMap<String, SelectionPolicy> = parse_queue_groups(queue_groups_yaml)?;
+        let retained_groups: Vec<String> = parse_retained_groups(retained_groups_yaml)?;
+        let durable_config: DurableConfig = DurableConfig::parse_durable_config(durable_config_yaml)?;
+        let metrics_config: MetricsConfig = MetricsConfig::parse_metrics_config(metrics_config_yaml)?;
+        let status_config: StatusConfig = StatusConfig::parse_status_config(status_config_yaml)?;
+        let capture_config: CaptureConfig = CaptureConfig::parse_capture_config(capture_config_yaml)?;
         Ok(Config {
-            log_config: logging_config,
-            pipes_config: pipes_config,
-            protocol_config: protocol_config,
+            log_config,
+            pipes_config,
+            protocol_config,
+            admin_config,
+            server_config,
+            acl_config,
+            queue_groups,
+            retained_groups,
+            durable_config,
+            metrics_config,
+            status_config,
+            capture_config,
         })
     }
+
+    /// ### reload
+    ///
+    /// `reload` applies the reloadable settings (logging, server, ACL, queue groups, retained groups, durable clients, metrics and status intervals,
+    /// capture filters and rotation) of the provided configuration.
+    /// The keys of the settings which have changed, but can't be applied without restarting the server, are returned
+    pub fn reload(&mut self, config: Config) -> Vec<&'static str> {
+        let mut restart_required: Vec<&'static str> = Vec::new();
+        if config.pipes_config.cap_path != self.pipes_config.cap_path {
+            restart_required.push("cap-pipe");
+        }
+        if config.pipes_config.client_dir != self.pipes_config.client_dir {
+            restart_required.push("client-dir");
+        }
+        if config.pipes_config.ownership() != self.pipes_config.ownership() {
+            restart_required.push("pipes-ownership");
+        }
+        if config.protocol_config.version != self.protocol_config.version {
+            restart_required.push("protocol-version");
+        }
+        if config.admin_config.enabled != self.admin_config.enabled {
+            restart_required.push("admin-enabled");
+        }
+        if config.admin_config.socket != self.admin_config.socket {
+            restart_required.push("admin-socket");
+        }
+        if config.server_config.user != self.server_config.user {
+            restart_required.push("user");
+        }
+        if config.server_config.group != self.server_config.group {
+            restart_required.push("group");
+        }
+        if config.server_config.chroot != self.server_config.chroot {
+            restart_required.push("chroot");
+        }
+        if config.durable_config.data_dir != self.durable_config.data_dir {
+            restart_required.push("data-dir");
+        }
+        if config.metrics_config.listen != self.metrics_config.listen {
+            restart_required.push("metrics-listen");
+        }
+        if config.metrics_config.file != self.metrics_config.file {
+            restart_required.push("metrics-file");
+        }
+        if config.status_config.file != self.status_config.file {
+            restart_required.push("status-file");
+        }
+        if config.capture_config.file != self.capture_config.file {
+            restart_required.push("capture-file");
+        }
+        self.log_config = config.log_config;
+        self.protocol_config.default_ttl = config.protocol_config.default_ttl;
+        //Privileges have already been dropped
+        let (user, group, chroot): (Option<u32>, Option<u32>, bool) = (
+            self.server_config.user,
+            self.server_config.group,
+            self.server_config.chroot,
+        );
+        self.server_config = config.server_config;
+        self.server_config.user = user;
+        self.server_config.group = group;
+        self.server_config.chroot = chroot;
+        self.acl_config = config.acl_config;
+        self.queue_groups = config.queue_groups;
+        self.retained_groups = config.retained_groups;
+        let data_dir: String = std::mem::take(&mut self.durable_config.data_dir);
+        self.durable_config = config.durable_config;
+        self.durable_config.data_dir = data_dir;
+        self.metrics_config.interval = config.metrics_config.interval;
+        self.status_config.interval = config.status_config.interval;
+        let capture_file: Option<String> = self.capture_config.file.take();
+        self.capture_config = config.capture_config;
+        self.capture_config.file = capture_file;
+        restart_required
+    }
+
+    /// ### privilege_conflicts
+    ///
+    /// `privilege_conflicts` returns the keys of the settings which can't work once the server runs as `uid` and `gid`, or in a chroot:
+    /// an unprivileged server can't give the client pipes to another user, and it can't look into the processes of the other users,
+    /// while in a chroot `/proc` isn't available at all
+    pub fn privilege_conflicts(&self, uid: Option<u32>, gid: Option<u32>, chroot: bool) -> Vec<&'static str> {
+        let mut conflicts: Vec<&'static str> = Vec::new();
+        let unprivileged: bool = uid.is_some_and(|uid| uid != 0);
+        if unprivileged {
+            if self.pipes_config.client_owner.is_some_and(|owner| Some(owner) != uid) {
+                conflicts.push("client-owner");
+            }
+            if self.pipes_config.client_group.is_some_and(|group| Some(group) != gid) {
+                conflicts.push("client-group");
+            }
+        }
+        if (unprivileged || chroot) && self.server_config.dead_client_check_interval > 0 {
+            conflicts.push("dead-client-check-interval");
+        }
+        conflicts
+    }
+
+    /// ### effective
+    ///
+    /// `effective` returns the value of each setting, formatted as a string and identified by its key
+    pub fn effective(&self) -> Vec<(&'static str, String)> {
+        let optional = |value: Option<String>| -> String { value.unwrap_or_else(|| String::from("unset")) };
+        let mode = |mode: Option<u32>| -> String { optional(mode.map(|mode| format!("{:04o}", mode))) };
+        let id = |id: Option<u32>| -> String { optional(id.map(|id| id.to_string())) };
+        vec![
+            ("log-level", self.log_config.log_level.to_string()),
+            ("log-file", self.log_config.log_file.clone()),
+            ("log-stdout", self.log_config.stdout.to_string()),
+            ("cap-pipe", self.pipes_config.cap_path.clone()),
+            ("client-dir", self.pipes_config.client_dir.clone()),
+            ("cap-mode", mode(self.pipes_config.cap_mode)),
+            ("cap-owner", id(self.pipes_config.cap_owner)),
+            ("cap-group", id(self.pipes_config.cap_group)),
+            ("client-mode", mode(self.pipes_config.client_mode)),
+            ("client-owner", id(self.pipes_config.client_owner)),
+            ("client-group", id(self.pipes_config.client_group)),
+            ("client-dir-mode", mode(self.pipes_config.client_dir_mode)),
+            ("protocol-version", self.protocol_config.version.to_string()),
+            ("default-ttl", self.protocol_config.default_ttl.to_string()),
+            ("admin-enabled", self.admin_config.enabled.to_string()),
+            ("admin-socket", self.admin_config.socket.clone()),
+            ("shutdown-grace-period", self.server_config.shutdown_grace_period.to_string()),
+            ("dead-client-check-interval", self.server_config.dead_client_check_interval.to_string()),
+            ("max-missed-deliveries", self.server_config.max_missed_deliveries.to_string()),
+            ("heartbeat-interval", self.server_config.heartbeat_interval.to_string()),
+            ("heartbeat-missed-beats", self.server_config.heartbeat_missed_beats.to_string()),
+            ("queue-size", self.server_config.queue_size.to_string()),
+            ("overflow-policy", self.server_config.overflow_policy.to_string()),
+            ("queue-group-policy", self.server_config.queue_group_policy.to_string()),
+            ("user", id(self.server_config.user)),
+            ("group", id(self.server_config.group)),
+            ("chroot", self.server_config.chroot.to_string()),
+            ("dead-letter-group", optional(self.server_config.dead_letter_group.clone())),
+            ("system-events", self.server_config.system_events.to_string()),
+            ("acl-default", self.acl_config.default.to_string()),
+            ("acl-rules", self.acl_config.rules.len().to_string()),
+            ("queue-groups", self.queue_groups.iter().map(|(group, policy)| format!("{}:{}", group, policy)).collect::<Vec<String>>().join(",")),
+            ("retained-groups", self.retained_groups.join(",")),
+            ("data-dir", self.durable_config.data_dir.clone()),
+            ("durable-clients", self.durable_config.clients.join(",")),
+            ("durable-max-size", self.durable_config.max_size.to_string()),
+            ("durable-max-age", self.durable_config.max_age.to_string()),
+            ("metrics-listen", optional(self.metrics_config.listen.clone())),
+            ("metrics-file", optional(self.metrics_config.file.clone())),
+            ("metrics-interval", self.metrics_config.interval.to_string()),
+            ("status-file", optional(self.status_config.file.clone())),
+            ("status-interval", self.status_config.interval.to_string()),
+            ("capture-file", optional(self.capture_config.file.clone())),
+            ("capture-clients", self.capture_config.clients.join(",")),
+            ("capture-groups", self.capture_config.groups.join(",")),
+            ("capture-max-size", self.capture_config.max_size.to_string()),
+            ("capture-max-files", self.capture_config.max_files.to_string()),
+        ]
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            log_config: LogConfig {
+                log_level: DEFAULT_LOG_LEVEL,
+                log_file: String::from(DEFAULT_LOG_FILE),
+                stdout: DEFAULT_LOG_STDOUT,
+            },
+            pipes_config: PipesConfig {
+                cap_path: String::from(DEFAULT_CAP_PATH),
+                client_dir: String::from(DEFAULT_CLIENT_DIR),
+                cap_mode: None,
+                cap_owner: None,
+                cap_group: None,
+                client_mode: None,
+                client_owner: None,
+                client_group: None,
+                client_dir_mode: None,
+            },
+            protocol_config: ProtocolConfig {
+                version: DEFAULT_PROTOCOL_VERSION,
+                default_ttl: DEFAULT_TTL,
+            },
+            admin_config: AdminConfig::default(),
+            server_config: ServerConfig::default(),
+            acl_config: Acl::default(),
+            queue_groups: BTreeMap::new(),
+            retained_groups: Vec::new(),
+            durable_config: DurableConfig::default(),
+            metrics_config: MetricsConfig::default(),
+            status_config: StatusConfig::default(),
+            capture_config: CaptureConfig::default(),
+        }
+    }
 }
 
 impl LogConfig {
     /// ### parse_log_config
     ///
-    /// `parse_log_config` parse a YAML document and get LogConfig
+    /// `parse_log_config` parse a YAML document and get LogConfig.
+    /// The 'logging' section is optional, as its keys; missing values are taken from defaults
     fn parse_log_config(config_doc: &Yaml) -> Result<LogConfig, ConfigError> {
-        let log_level: usize = match config_doc["log_level"].as_i64() {
+        let log_level: usize = match optional_u64(config_doc, "log_level", "logging")? {
             Some(value) => value as usize,
-            None => {
-                return Err(ConfigError {
-                    code: ConfigErrorCode::YamlSyntaxError,
-                    message: String::from("Could not find 'log_level' in 'logging'"),
-                })
-            }
-        };
-        let log_file: String = match config_doc["log_file"].as_str() {
-            Some(value) => String::from(value),
-            None => {
-                return Err(ConfigError {
-                    code: ConfigErrorCode::YamlSyntaxError,
-                    message: String::from("Could not find 'log_file' in 'logging'"),
-                })
-            }
-        };
-        let stdout: bool = match config_doc["stdout"].as_bool() {
-            Some(value) => value,
-            None => {
-                return Err(ConfigError {
-                    code: ConfigErrorCode::YamlSyntaxError,
-                    message: String::from("Could not find 'stdout' in 'logging'"),
-                })
-            }
+            None => DEFAULT_LOG_LEVEL,
         };
+        let log_file: String = optional_str(config_doc, "log_file", "logging")?.unwrap_or_else(|| String::from(DEFAULT_LOG_FILE));
+        let stdout: bool = optional_bool(config_doc, "stdout", "logging")?.unwrap_or(DEFAULT_LOG_STDOUT);
         Ok(LogConfig {
-            log_level: log_level,
-            log_file: log_file,
-            stdout: stdout,
+            log_level,
+            log_file,
+            stdout,
         })
     }
 }
@@ -179,50 +591,910 @@ impl LogConfig {
 impl PipesConfig {
     /// ### parse_pipes_config
     ///
-    /// `parse_pipes_config` parse a YAML document and get PipesConfig
+    /// `parse_pipes_config` parse a YAML document and get PipesConfig.
+    /// The 'pipes' section is optional, as its keys; missing values are taken from defaults
     fn parse_pipes_config(config_doc: &Yaml) -> Result<PipesConfig, ConfigError> {
-        let cap_path: String = match config_doc["cap_path"].as_str() {
-            Some(value) => String::from(value),
-            None => {
-                return Err(ConfigError {
-                    code: ConfigErrorCode::YamlSyntaxError,
-                    message: String::from("Could not find 'cap_path' in 'pipes'"),
-                })
+        let cap_path: String = optional_str(config_doc, "cap_path", "pipes")?.unwrap_or_else(|| String::from(DEFAULT_CAP_PATH));
+        let client_dir: String = optional_str(config_doc, "client_dir", "pipes")?.unwrap_or_else(|| String::from(DEFAULT_CLIENT_DIR));
+        let mode = |key: &str| -> Result<Option<u32>, ConfigError> {
+            match optional_str(config_doc, key, "pipes")? {
+                Some(mode) => parse_mode(key, &mode).map(Some),
+                None => Ok(None),
             }
         };
-        let client_dir: String = match config_doc["client_dir"].as_str() {
-            Some(value) => String::from(value),
-            None => {
-                return Err(ConfigError {
-                    code: ConfigErrorCode::YamlSyntaxError,
-                    message: String::from("Could not find 'client_dir' in 'pipes'"),
-                })
+        let owner = |key: &str| -> Result<Option<u32>, ConfigError> {
+            match optional_str(config_doc, key, "pipes")? {
+                Some(user) => parse_user(key, &user).map(Some),
+                None => Ok(None),
+            }
+        };
+        let group = |key: &str| -> Result<Option<u32>, ConfigError> {
+            match optional_str(config_doc, key, "pipes")? {
+                Some(group) => parse_group(key, &group).map(Some),
+                None => Ok(None),
             }
         };
+        //The user of the subscribing client can't be established, so the pipes can't be given to it
+        if optional_bool(config_doc, "peer_owner", "pipes")? == Some(true) {
+            return Err(ConfigError {
+                code: ConfigErrorCode::InvalidValue,
+                message: String::from("'peer_owner' in 'pipes' is not supported: the user of the subscribing client can't be established"),
+            });
+        }
         Ok(PipesConfig {
-            cap_path: cap_path,
-            client_dir: client_dir,
+            cap_path,
+            client_dir,
+            cap_mode: mode("cap_mode")?,
+            cap_owner: owner("cap_owner")?,
+            cap_group: group("cap_group")?,
+            client_mode: mode("client_mode")?,
+            client_owner: owner("client_owner")?,
+            client_group: group("client_group")?,
+            client_dir_mode: mode("client_dir_mode")?,
         })
     }
+
+    /// ### ownership
+    ///
+    /// `ownership` returns the settings describing the ownership of the pipes, which can't be changed while running:
+    /// (cap, client pipes, client directory)
+    pub fn ownership(&self) -> (Ownership, Ownership, Ownership) {
+        (
+            Ownership {
+                mode: self.cap_mode,
+                owner: self.cap_owner,
+                group: self.cap_group,
+            },
+            Ownership {
+                mode: self.client_mode,
+                owner: self.client_owner,
+                group: self.client_group,
+            },
+            Ownership {
+                mode: self.client_dir_mode,
+                owner: self.client_owner,
+                group: self.client_group,
+            },
+        )
+    }
 }
 
 impl ProtocolConfig {
     /// ### parse_protocol_config
     ///
-    /// `parse_protocol_config` parse a YAML document and get ProtocolConfig
+    /// `parse_protocol_config` parse a YAML document and get ProtocolConfig.
+    /// The 'protocol' section is optional, as its keys; missing values are taken from defaults
     fn parse_protocol_config(config_doc: &Yaml) -> Result<ProtocolConfig, ConfigError> {
-        let protocol_version: u8 = match config_doc["version"].as_i64() {
+        let protocol_version: u8 = match optional_u64(config_doc, "version", "protocol")? {
             Some(value) => value as u8,
-            None => {
+            None => DEFAULT_PROTOCOL_VERSION,
+        };
+        let default_ttl: u64 =
+            optional_u64(config_doc, "default_ttl", "protocol")?.unwrap_or(DEFAULT_TTL);
+        Ok(ProtocolConfig {
+            version: protocol_version,
+            default_ttl,
+        })
+    }
+}
+
+impl AdminConfig {
+    /// ### parse_admin_config
+    ///
+    /// `parse_admin_config` parse a YAML document and get AdminConfig.
+    /// The 'admin' section is optional, as its keys; missing values are taken from defaults
+    fn parse_admin_config(config_doc: &Yaml) -> Result<AdminConfig, ConfigError> {
+        let mut admin_config: AdminConfig = AdminConfig::default();
+        if config_doc.is_badvalue() {
+            return Ok(admin_config);
+        }
+        if let Some(enabled) = optional_bool(config_doc, "enabled", "admin")? {
+            admin_config.enabled = enabled;
+        }
+        if let Some(socket) = optional_str(config_doc, "socket", "admin")? {
+            admin_config.socket = socket;
+        }
+        Ok(admin_config)
+    }
+}
+
+impl DurableConfig {
+    /// ### parse_durable_config
+    ///
+    /// `parse_durable_config` parse a YAML document and get DurableConfig.
+    /// The 'durable' section is optional, as its keys; missing values are taken from defaults
+    fn parse_durable_config(config_doc: &Yaml) -> Result<DurableConfig, ConfigError> {
+        let mut durable_config: DurableConfig = DurableConfig::default();
+        if config_doc.is_badvalue() {
+            return Ok(durable_config);
+        }
+        if let Some(data_dir) = optional_str(config_doc, "data_dir", "durable")? {
+            durable_config.data_dir = data_dir;
+        }
+        if let Some(clients) = optional_str_list(config_doc, "clients", "durable")? {
+            if let Some(client) = clients.iter().find(|client| !store::is_valid_client(client) || client.contains(['+', '#'])) {
+                return Err(ConfigError {
+                    code: ConfigErrorCode::InvalidValue,
+                    message: format!("Invalid client '{}' in 'clients' in 'durable'", client),
+                });
+            }
+            durable_config.clients = clients;
+        }
+        if let Some(max_size) = optional_u64(config_doc, "max_size", "durable")? {
+            durable_config.max_size = max_size;
+        }
+        if let Some(max_age) = optional_u64(config_doc, "max_age", "durable")? {
+            durable_config.max_age = max_age;
+        }
+        Ok(durable_config)
+    }
+}
+
+impl MetricsConfig {
+    /// ### parse_metrics_config
+    ///
+    /// `parse_metrics_config` parse a YAML document and get MetricsConfig.
+    /// The 'metrics' section is optional, as its keys; missing values are taken from defaults
+    fn parse_metrics_config(config_doc: &Yaml) -> Result<MetricsConfig, ConfigError> {
+        let mut metrics_config: MetricsConfig = MetricsConfig::default();
+        if config_doc.is_badvalue() {
+            return Ok(metrics_config);
+        }
+        if let Some(listen) = optional_str(config_doc, "listen", "metrics")? {
+            metrics_config.listen = parse_metrics_listen("'listen' in 'metrics'", &listen)?;
+        }
+        if let Some(file) = optional_str(config_doc, "file", "metrics")? {
+            metrics_config.file = Some(file).filter(|file| !file.is_empty());
+        }
+        if let Some(interval) = optional_u64(config_doc, "interval", "metrics")? {
+            metrics_config.interval = interval;
+        }
+        Ok(metrics_config)
+    }
+}
+
+impl StatusConfig {
+    /// ### parse_status_config
+    ///
+    /// `parse_status_config` parse a YAML document and get StatusConfig.
+    /// The 'status' section is optional, as its keys; missing values are taken from defaults
+    fn parse_status_config(config_doc: &Yaml) -> Result<StatusConfig, ConfigError> {
+        let mut status_config: StatusConfig = StatusConfig::default();
+        if config_doc.is_badvalue() {
+            return Ok(status_config);
+        }
+        if let Some(file) = optional_str(config_doc, "file", "status")? {
+            status_config.file = Some(file).filter(|file| !file.is_empty());
+        }
+        if let Some(interval) = optional_u64(config_doc, "interval", "status")? {
+            status_config.interval = interval;
+        }
+        Ok(status_config)
+    }
+}
+
+impl CaptureConfig {
+    /// ### parse_capture_config
+    ///
+    /// `parse_capture_config` parse a YAML document and get CaptureConfig.
+    /// The 'capture' section is optional, as its keys; missing values are taken from defaults
+    fn parse_capture_config(config_doc: &Yaml) -> Result<CaptureConfig, ConfigError> {
+        let mut capture_config: CaptureConfig = CaptureConfig::default();
+        if config_doc.is_badvalue() {
+            return Ok(capture_config);
+        }
+        if let Some(file) = optional_str(config_doc, "file", "capture")? {
+            capture_config.file = Some(file).filter(|file| !file.is_empty());
+        }
+        if let Some(clients) = optional_str_list(config_doc, "clients", "capture")? {
+            capture_config.clients = clients;
+        }
+        if let Some(filters) = optional_str_list(config_doc, "groups", "capture")? {
+            if let Some(filter) = filters.iter().find(|filter| groups::queue_group(filter).is_some() || !groups::is_valid_group(filter)) {
+                return Err(ConfigError {
+                    code: ConfigErrorCode::InvalidValue,
+                    message: format!("Invalid group '{}' in 'groups' in 'capture'", filter),
+                });
+            }
+            capture_config.groups = filters;
+        }
+        if let Some(max_size) = optional_u64(config_doc, "max_size", "capture")? {
+            capture_config.max_size = max_size;
+        }
+        if let Some(max_files) = optional_u64(config_doc, "max_files", "capture")? {
+            capture_config.max_files = max_files;
+        }
+        Ok(capture_config)
+    }
+}
+
+impl ServerConfig {
+    /// ### parse_server_config
+    ///
+    /// `parse_server_config` parse a YAML document and get ServerConfig.
+    /// The 'server' section is optional, as its keys; missing values are taken from defaults
+    fn parse_server_config(config_doc: &Yaml) -> Result<ServerConfig, ConfigError> {
+        let mut server_config: ServerConfig = ServerConfig::default();
+        if config_doc.is_badvalue() {
+            return Ok(server_config);
+        }
+        if let Some(grace_period) = optional_u64(config_doc, "shutdown_grace_period", "server")? {
+            server_config.shutdown_grace_period = grace_period;
+        }
+        if let Some(interval) = optional_u64(config_doc, "dead_client_check_interval", "server")? {
+            server_config.dead_client_check_interval = interval;
+        }
+        if let Some(max_missed) = optional_u64(config_doc, "max_missed_deliveries", "server")? {
+            server_config.max_missed_deliveries = max_missed;
+        }
+        if let Some(interval) = optional_u64(config_doc, "heartbeat_interval", "server")? {
+            server_config.heartbeat_interval = interval;
+        }
+        if let Some(missed_beats) = optional_u64(config_doc, "heartbeat_missed_beats", "server")? {
+            server_config.heartbeat_missed_beats = missed_beats;
+        }
+        if let Some(queue_size) = optional_u64(config_doc, "queue_size", "server")? {
+            server_config.queue_size = queue_size;
+        }
+        if let Some(policy) = optional_str(config_doc, "overflow_policy", "server")? {
+            server_config.overflow_policy = match policy.parse::<OverflowPolicy>() {
+                Ok(policy) => policy,
+                Err(_) => {
+                    return Err(ConfigError {
+                        code: ConfigErrorCode::YamlSyntaxError,
+                        message: format!(
+                            "'overflow_policy' in 'server' must be one of block, drop-oldest, drop-newest, disconnect (found '{}')",
+                            policy
+                        ),
+                    })
+                }
+            };
+        }
+        if let Some(policy) = optional_str(config_doc, "queue_group_policy", "server")? {
+            server_config.queue_group_policy = parse_selection_policy("'queue_group_policy' in 'server'", &policy)?;
+        }
+        if let Some(user) = optional_str(config_doc, "user", "server")? {
+            server_config.user = Some(parse_user("user", &user)?);
+        }
+        if let Some(group) = optional_str(config_doc, "group", "server")? {
+            server_config.group = Some(parse_group("group", &group)?);
+        }
+        if let Some(chroot) = optional_bool(config_doc, "chroot", "server")? {
+            server_config.chroot = chroot;
+        }
+        if let Some(group) = optional_str(config_doc, "dead_letter_group", "server")? {
+            server_config.dead_letter_group = parse_dead_letter_group("'dead_letter_group' in 'server'", &group)?;
+        }
+        if let Some(system_events) = optional_bool(config_doc, "system_events", "server")? {
+            server_config.system_events = system_events;
+        }
+        Ok(server_config)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            dead_client_check_interval: DEFAULT_DEAD_CLIENT_CHECK_INTERVAL,
+            max_missed_deliveries: DEFAULT_MAX_MISSED_DELIVERIES,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            heartbeat_missed_beats: DEFAULT_HEARTBEAT_MISSED_BEATS,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            overflow_policy: DEFAULT_OVERFLOW_POLICY,
+            queue_group_policy: DEFAULT_QUEUE_GROUP_POLICY,
+            user: None,
+            group: None,
+            chroot: false,
+            dead_letter_group: None,
+            system_events: false,
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> AdminConfig {
+        AdminConfig {
+            enabled: DEFAULT_ADMIN_ENABLED,
+            socket: String::from(DEFAULT_ADMIN_SOCKET),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig {
+            listen: None,
+            file: None,
+            interval: DEFAULT_METRICS_INTERVAL,
+        }
+    }
+}
+
+impl Default for StatusConfig {
+    fn default() -> StatusConfig {
+        StatusConfig {
+            file: None,
+            interval: DEFAULT_STATUS_INTERVAL,
+        }
+    }
+}
+
+impl Default for CaptureConfig {
+    fn default() -> CaptureConfig {
+        CaptureConfig {
+            file: None,
+            clients: Vec::new(),
+            groups: Vec::new(),
+            max_size: DEFAULT_CAPTURE_MAX_SIZE,
+            max_files: DEFAULT_CAPTURE_MAX_FILES,
+        }
+    }
+}
+
+impl Default for DurableConfig {
+    fn default() -> DurableConfig {
+        DurableConfig {
+            data_dir: String::from(DEFAULT_DATA_DIR),
+            clients: Vec::new(),
+            max_size: DEFAULT_DURABLE_MAX_SIZE,
+            max_age: DEFAULT_DURABLE_MAX_AGE,
+        }
+    }
+}
+
+impl Settings {
+    /// ### resolve
+    ///
+    /// `resolve` builds the effective settings merging defaults, the configuration file,
+    /// the provided environment and the CLI overrides. CLI has the highest priority, then environment,
+    /// then configuration file and eventually the defaults.
+    pub fn resolve(
+        cli: &CliOverrides,
+        env: &HashMap<String, String>,
+    ) -> Result<Settings, ConfigError> {
+        let mut sources: HashMap<&'static str, SettingSource> = HashMap::new();
+        //Configuration file
+        let (config_file, config_file_source): (Option<String>, SettingSource) =
+            match (&cli.config_file, env.get(ENV_CONFIG_FILE)) {
+                (Some(file), _) => (Some(file.clone()), SettingSource::Cli),
+                (None, Some(file)) => (Some(file.clone()), SettingSource::Env(ENV_CONFIG_FILE)),
+                (None, None) => (None, SettingSource::Default),
+            };
+        sources.insert("config-file", config_file_source);
+        //Base layer: configuration file or defaults
+        let yaml_doc: Option<Yaml> = match &config_file {
+            Some(file) => Some(Config::load_yaml(file)?),
+            None => None,
+        };
+        let mut config: Config = match &yaml_doc {
+            Some(yaml_doc) => Config::from_yaml(yaml_doc)?,
+            None => Config::default(),
+        };
+        //Only the keys set in the configuration file come from it
+        for (key, section, yaml_key) in FILE_SETTINGS.iter() {
+            let source: SettingSource = match &yaml_doc {
+                Some(yaml_doc) if !yaml_doc[*section][*yaml_key].is_badvalue() => SettingSource::File,
+                _ => SettingSource::Default,
+            };
+            sources.insert(key, source);
+        }
+        //Environment layer
+        if let Some(value) = env.get(ENV_LOG_LEVEL) {
+            config.log_config.log_level = parse_value(ENV_LOG_LEVEL, value)?;
+            sources.insert("log-level", SettingSource::Env(ENV_LOG_LEVEL));
+        }
+        if let Some(value) = env.get(ENV_LOG_FILE) {
+            config.log_config.log_file = value.clone();
+            sources.insert("log-file", SettingSource::Env(ENV_LOG_FILE));
+        }
+        if let Some(value) = env.get(ENV_LOG_STDOUT) {
+            config.log_config.stdout = parse_bool(ENV_LOG_STDOUT, value)?;
+            sources.insert("log-stdout", SettingSource::Env(ENV_LOG_STDOUT));
+        }
+        if let Some(value) = env.get(ENV_CAP_PATH) {
+            config.pipes_config.cap_path = value.clone();
+            sources.insert("cap-pipe", SettingSource::Env(ENV_CAP_PATH));
+        }
+        if let Some(value) = env.get(ENV_CLIENT_DIR) {
+            config.pipes_config.client_dir = value.clone();
+            sources.insert("client-dir", SettingSource::Env(ENV_CLIENT_DIR));
+        }
+        if let Some(value) = env.get(ENV_CAP_MODE) {
+            config.pipes_config.cap_mode = Some(parse_mode(ENV_CAP_MODE, value)?);
+            sources.insert("cap-mode", SettingSource::Env(ENV_CAP_MODE));
+        }
+        if let Some(value) = env.get(ENV_CAP_OWNER) {
+            config.pipes_config.cap_owner = Some(parse_user(ENV_CAP_OWNER, value)?);
+            sources.insert("cap-owner", SettingSource::Env(ENV_CAP_OWNER));
+        }
+        if let Some(value) = env.get(ENV_CAP_GROUP) {
+            config.pipes_config.cap_group = Some(parse_group(ENV_CAP_GROUP, value)?);
+            sources.insert("cap-group", SettingSource::Env(ENV_CAP_GROUP));
+        }
+        if let Some(value) = env.get(ENV_CLIENT_MODE) {
+            config.pipes_config.client_mode = Some(parse_mode(ENV_CLIENT_MODE, value)?);
+            sources.insert("client-mode", SettingSource::Env(ENV_CLIENT_MODE));
+        }
+        if let Some(value) = env.get(ENV_CLIENT_OWNER) {
+            config.pipes_config.client_owner = Some(parse_user(ENV_CLIENT_OWNER, value)?);
+            sources.insert("client-owner", SettingSource::Env(ENV_CLIENT_OWNER));
+        }
+        if let Some(value) = env.get(ENV_CLIENT_GROUP) {
+            config.pipes_config.client_group = Some(parse_group(ENV_CLIENT_GROUP, value)?);
+            sources.insert("client-group", SettingSource::Env(ENV_CLIENT_GROUP));
+        }
+        if let Some(value) = env.get(ENV_CLIENT_DIR_MODE) {
+            config.pipes_config.client_dir_mode = Some(parse_mode(ENV_CLIENT_DIR_MODE, value)?);
+            sources.insert("client-dir-mode", SettingSource::Env(ENV_CLIENT_DIR_MODE));
+        }
+        if let Some(value) = env.get(ENV_PROTOCOL_VERSION) {
+            config.protocol_config.version = parse_value(ENV_PROTOCOL_VERSION, value)?;
+            sources.insert("protocol-version", SettingSource::Env(ENV_PROTOCOL_VERSION));
+        }
+        if let Some(value) = env.get(ENV_DEFAULT_TTL) {
+            config.protocol_config.default_ttl = parse_value(ENV_DEFAULT_TTL, value)?;
+            sources.insert("default-ttl", SettingSource::Env(ENV_DEFAULT_TTL));
+        }
+        if let Some(value) = env.get(ENV_ADMIN_SOCKET) {
+            config.admin_config.socket = value.clone();
+            sources.insert("admin-socket", SettingSource::Env(ENV_ADMIN_SOCKET));
+        }
+        if let Some(value) = env.get(ENV_SHUTDOWN_GRACE_PERIOD) {
+            config.server_config.shutdown_grace_period =
+                parse_value(ENV_SHUTDOWN_GRACE_PERIOD, value)?;
+            sources.insert(
+                "shutdown-grace-period",
+                SettingSource::Env(ENV_SHUTDOWN_GRACE_PERIOD),
+            );
+        }
+        if let Some(value) = env.get(ENV_DEAD_CLIENT_CHECK_INTERVAL) {
+            config.server_config.dead_client_check_interval =
+                parse_value(ENV_DEAD_CLIENT_CHECK_INTERVAL, value)?;
+            sources.insert(
+                "dead-client-check-interval",
+                SettingSource::Env(ENV_DEAD_CLIENT_CHECK_INTERVAL),
+            );
+        }
+        if let Some(value) = env.get(ENV_MAX_MISSED_DELIVERIES) {
+            config.server_config.max_missed_deliveries =
+                parse_value(ENV_MAX_MISSED_DELIVERIES, value)?;
+            sources.insert(
+                "max-missed-deliveries",
+                SettingSource::Env(ENV_MAX_MISSED_DELIVERIES),
+            );
+        }
+        if let Some(value) = env.get(ENV_HEARTBEAT_INTERVAL) {
+            config.server_config.heartbeat_interval = parse_value(ENV_HEARTBEAT_INTERVAL, value)?;
+            sources.insert(
+                "heartbeat-interval",
+                SettingSource::Env(ENV_HEARTBEAT_INTERVAL),
+            );
+        }
+        if let Some(value) = env.get(ENV_HEARTBEAT_MISSED_BEATS) {
+            config.server_config.heartbeat_missed_beats =
+                parse_value(ENV_HEARTBEAT_MISSED_BEATS, value)?;
+            sources.insert(
+                "heartbeat-missed-beats",
+                SettingSource::Env(ENV_HEARTBEAT_MISSED_BEATS),
+            );
+        }
+        if let Some(value) = env.get(ENV_QUEUE_SIZE) {
+            config.server_config.queue_size = parse_value(ENV_QUEUE_SIZE, value)?;
+            sources.insert("queue-size", SettingSource::Env(ENV_QUEUE_SIZE));
+        }
+        if let Some(value) = env.get(ENV_OVERFLOW_POLICY) {
+            config.server_config.overflow_policy = parse_value(ENV_OVERFLOW_POLICY, value)?;
+            sources.insert("overflow-policy", SettingSource::Env(ENV_OVERFLOW_POLICY));
+        }
+        if let Some(value) = env.get(ENV_QUEUE_GROUP_POLICY) {
+            config.server_config.queue_group_policy = parse_value(ENV_QUEUE_GROUP_POLICY, value)?;
+            sources.insert("queue-group-policy", SettingSource::Env(ENV_QUEUE_GROUP_POLICY));
+        }
+        if let Some(value) = env.get(ENV_USER) {
+            config.server_config.user = Some(parse_user(ENV_USER, value)?);
+            sources.insert("user", SettingSource::Env(ENV_USER));
+        }
+        if let Some(value) = env.get(ENV_GROUP) {
+            config.server_config.group = Some(parse_group(ENV_GROUP, value)?);
+            sources.insert("group", SettingSource::Env(ENV_GROUP));
+        }
+        if let Some(value) = env.get(ENV_CHROOT) {
+            config.server_config.chroot = parse_bool(ENV_CHROOT, value)?;
+            sources.insert("chroot", SettingSource::Env(ENV_CHROOT));
+        }
+        if let Some(value) = env.get(ENV_DEAD_LETTER_GROUP) {
+            config.server_config.dead_letter_group = parse_dead_letter_group(ENV_DEAD_LETTER_GROUP, value)?;
+            sources.insert("dead-letter-group", SettingSource::Env(ENV_DEAD_LETTER_GROUP));
+        }
+        if let Some(value) = env.get(ENV_SYSTEM_EVENTS) {
+            config.server_config.system_events = parse_bool(ENV_SYSTEM_EVENTS, value)?;
+            sources.insert("system-events", SettingSource::Env(ENV_SYSTEM_EVENTS));
+        }
+        if let Some(value) = env.get(ENV_METRICS_LISTEN) {
+            config.metrics_config.listen = parse_metrics_listen(ENV_METRICS_LISTEN, value)?;
+            sources.insert("metrics-listen", SettingSource::Env(ENV_METRICS_LISTEN));
+        }
+        if let Some(value) = env.get(ENV_METRICS_FILE) {
+            config.metrics_config.file = Some(value.clone()).filter(|file| !file.is_empty());
+            sources.insert("metrics-file", SettingSource::Env(ENV_METRICS_FILE));
+        }
+        if let Some(value) = env.get(ENV_STATUS_FILE) {
+            config.status_config.file = Some(value.clone()).filter(|file| !file.is_empty());
+            sources.insert("status-file", SettingSource::Env(ENV_STATUS_FILE));
+        }
+        if let Some(value) = env.get(ENV_STATUS_INTERVAL) {
+            config.status_config.interval = parse_value(ENV_STATUS_INTERVAL, value)?;
+            sources.insert("status-interval", SettingSource::Env(ENV_STATUS_INTERVAL));
+        }
+        if let Some(value) = env.get(ENV_CAPTURE_FILE) {
+            config.capture_config.file = Some(value.clone()).filter(|file| !file.is_empty());
+            sources.insert("capture-file", SettingSource::Env(ENV_CAPTURE_FILE));
+        }
+        if let Some(value) = env.get(ENV_DATA_DIR) {
+            config.durable_config.data_dir = value.clone();
+            sources.insert("data-dir", SettingSource::Env(ENV_DATA_DIR));
+        }
+        let mut pid_file: Option<String> = None;
+        sources.insert("pidfile", SettingSource::Default);
+        if let Some(value) = env.get(ENV_PID_FILE) {
+            pid_file = Some(value.clone());
+            sources.insert("pidfile", SettingSource::Env(ENV_PID_FILE));
+        }
+        //CLI layer
+        if let Some(value) = &cli.log_level {
+            config.log_config.log_level = parse_value("log-level", value)?;
+            sources.insert("log-level", SettingSource::Cli);
+        }
+        if let Some(value) = &cli.log_file {
+            config.log_config.log_file = value.clone();
+            sources.insert("log-file", SettingSource::Cli);
+        }
+        if let Some(value) = &cli.cap_path {
+            config.pipes_config.cap_path = value.clone();
+            sources.insert("cap-pipe", SettingSource::Cli);
+        }
+        if let Some(value) = &cli.client_dir {
+            config.pipes_config.client_dir = value.clone();
+            sources.insert("client-dir", SettingSource::Cli);
+        }
+        if let Some(value) = &cli.pid_file {
+            pid_file = Some(value.clone());
+            sources.insert("pidfile", SettingSource::Cli);
+        }
+        Ok(Settings {
+            config,
+            config_file,
+            pid_file,
+            sources,
+        })
+    }
+
+    /// ### source
+    ///
+    /// `source` returns the layer the provided setting key has been taken from
+    pub fn source(&self, key: &str) -> SettingSource {
+        match self.sources.get(key) {
+            Some(source) => source.clone(),
+            None => SettingSource::Default,
+        }
+    }
+}
+
+impl fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingSource::Default => write!(f, "default"),
+            SettingSource::File => write!(f, "file"),
+            SettingSource::Env(var) => write!(f, "env {}", var),
+            SettingSource::Cli => write!(f, "cli"),
+        }
+    }
+}
+
+/// ### parse_acl_config
+///
+/// `parse_acl_config` parse a YAML document and get the Acl.
+/// The 'acl' section is optional: without it, everything is allowed; once set, the default policy is deny
+fn parse_acl_config(config_doc: &Yaml) -> Result<Acl, ConfigError> {
+    if config_doc.is_badvalue() {
+        return Ok(Acl::default());
+    }
+    let default: AclPolicy = match optional_str(config_doc, "default", "acl")? {
+        Some(policy) => match policy.parse::<AclPolicy>() {
+            Ok(policy) => policy,
+            Err(_) => {
+                return Err(ConfigError {
+                    code: ConfigErrorCode::YamlSyntaxError,
+                    message: format!(
+                        "'default' in 'acl' must be one of allow, deny (found '{}')",
+                        policy
+                    ),
+                })
+            }
+        },
+        None => AclPolicy::Deny,
+    };
+    let rules_doc: &[Yaml] = match &config_doc["rules"] {
+        Yaml::BadValue => &[],
+        Yaml::Array(rules) => rules.as_slice(),
+        _ => {
+            return Err(ConfigError {
+                code: ConfigErrorCode::YamlSyntaxError,
+                message: String::from("'rules' in 'acl' must be a list"),
+            })
+        }
+    };
+    let mut rules: Vec<AclRule> = Vec::with_capacity(rules_doc.len());
+    for rule_doc in rules_doc.iter() {
+        if rule_doc.as_hash().is_none() {
+            return Err(ConfigError {
+                code: ConfigErrorCode::YamlSyntaxError,
+                message: String::from("Each rule in 'acl.rules' must be a map"),
+            });
+        }
+        //The user of a client can't be established, so rules can only match its name
+        for key in ["uid", "gid"].iter() {
+            if !rule_doc[*key].is_badvalue() {
+                return Err(ConfigError {
+                    code: ConfigErrorCode::InvalidValue,
+                    message: format!("'{}' in 'acl.rules' is not supported: the user of a client can't be established", key),
+                });
+            }
+        }
+        rules.push(AclRule {
+            client: optional_str(rule_doc, "client", "acl.rules")?,
+            names: optional_str_list(rule_doc, "names", "acl.rules")?,
+            subscribe: optional_str_list(rule_doc, "subscribe", "acl.rules")?,
+            send: optional_str_list(rule_doc, "send", "acl.rules")?,
+        });
+    }
+    Ok(Acl::new(default, rules))
+}
+
+/// ### parse_queue_groups
+///
+/// `parse_queue_groups` parse a YAML document and get the groups whose subscribers form a queue group, with their policy.
+/// The 'queue_groups' section is optional
+fn parse_queue_groups(config_doc: &Yaml) -> Result<BTreeMap<String, SelectionPolicy>, ConfigError> {
+    let mut queue_groups: BTreeMap<String, SelectionPolicy> = BTreeMap::new();
+    let groups_doc = match config_doc {
+        Yaml::BadValue => return Ok(queue_groups),
+        Yaml::Hash(groups_doc) => groups_doc,
+        _ => {
+            return Err(ConfigError {
+                code: ConfigErrorCode::YamlSyntaxError,
+                message: String::from("'queue_groups' must be a map of groups and policies"),
+            })
+        }
+    };
+    for (group, policy) in groups_doc.iter() {
+        let (group, policy): (&str, &str) = match (group.as_str(), policy.as_str()) {
+            (Some(group), Some(policy)) => (group, policy),
+            _ => {
                 return Err(ConfigError {
                     code: ConfigErrorCode::YamlSyntaxError,
-                    message: String::from("Could not find 'version' in 'protocol'"),
+                    message: String::from("'queue_groups' must be a map of groups and policies"),
                 })
             }
         };
-        Ok(ProtocolConfig {
-            version: protocol_version,
-        })
+        if groups::queue_group(group).is_some() || !groups::is_valid_group(group) {
+            return Err(ConfigError {
+                code: ConfigErrorCode::InvalidValue,
+                message: format!("Invalid group '{}' in 'queue_groups'", group),
+            });
+        }
+        let policy: SelectionPolicy = parse_selection_policy(&format!("'{}' in 'queue_groups'", group), policy)?;
+        queue_groups.insert(String::from(group), policy);
+    }
+    Ok(queue_groups)
+}
+
+/// ### parse_retained_groups
+///
+/// `parse_retained_groups` parse a YAML document and get the groups whose last message is retained.
+/// The 'retained_groups' section is optional
+fn parse_retained_groups(config_doc: &Yaml) -> Result<Vec<String>, ConfigError> {
+    let error = || ConfigError {
+        code: ConfigErrorCode::YamlSyntaxError,
+        message: String::from("'retained_groups' must be a list of groups"),
+    };
+    let groups_doc = match config_doc {
+        Yaml::BadValue => return Ok(Vec::new()),
+        Yaml::Array(groups_doc) => groups_doc,
+        _ => return Err(error()),
+    };
+    let mut retained_groups: Vec<String> = Vec::with_capacity(groups_doc.len());
+    for group in groups_doc.iter() {
+        let group: &str = group.as_str().ok_or_else(error)?;
+        if groups::queue_group(group).is_some() || !groups::is_valid_group(group) {
+            return Err(ConfigError {
+                code: ConfigErrorCode::InvalidValue,
+                message: format!("Invalid group '{}' in 'retained_groups'", group),
+            });
+        }
+        retained_groups.push(String::from(group));
+    }
+    Ok(retained_groups)
+}
+
+/// ### parse_dead_letter_group
+///
+/// `parse_dead_letter_group` parses the group where undeliverable messages are republished; an empty value disables it.
+/// The group must be a plain group, which clients can send to
+fn parse_dead_letter_group(key: &str, group: &str) -> Result<Option<String>, ConfigError> {
+    if group.is_empty() {
+        return Ok(None);
+    }
+    if groups::queue_group(group).is_some() || groups::is_system_group(group) || !groups::is_valid_group(group) || group.contains(['+', '#']) {
+        return Err(ConfigError {
+            code: ConfigErrorCode::InvalidValue,
+            message: format!("Invalid group '{}' for {}", group, key),
+        });
+    }
+    Ok(Some(String::from(group)))
+}
+
+/// ### parse_metrics_listen
+///
+/// `parse_metrics_listen` parses the address the metrics are served on; an empty value disables it
+fn parse_metrics_listen(key: &str, address: &str) -> Result<Option<String>, ConfigError> {
+    if address.is_empty() {
+        return Ok(None);
+    }
+    match address.parse::<SocketAddr>() {
+        Ok(_) => Ok(Some(String::from(address))),
+        Err(_) => Err(ConfigError {
+            code: ConfigErrorCode::InvalidValue,
+            message: format!("Invalid address '{}' for {}", address, key),
+        }),
+    }
+}
+
+/// ### parse_selection_policy
+///
+/// `parse_selection_policy` parses the policy used to choose the member of a queue group
+fn parse_selection_policy(key: &str, policy: &str) -> Result<SelectionPolicy, ConfigError> {
+    match policy.parse::<SelectionPolicy>() {
+        Ok(policy) => Ok(policy),
+        Err(_) => Err(ConfigError {
+            code: ConfigErrorCode::YamlSyntaxError,
+            message: format!(
+                "{} must be one of round-robin, least-queued (found '{}')",
+                key, policy
+            ),
+        }),
+    }
+}
+
+/// ### parse_value
+///
+/// `parse_value` parses a setting value provided as a string
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    match value.trim().parse::<T>() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(ConfigError {
+            code: ConfigErrorCode::InvalidValue,
+            message: format!("Invalid value '{}' for '{}'", value, key),
+        }),
+    }
+}
+
+/// ### parse_mode
+///
+/// `parse_mode` parses a file mode provided as an octal string (e.g. "0660")
+fn parse_mode(key: &str, value: &str) -> Result<u32, ConfigError> {
+    match u32::from_str_radix(value.trim(), 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(ConfigError {
+            code: ConfigErrorCode::InvalidValue,
+            message: format!("Invalid mode '{}' for '{}' (octal expected)", value, key),
+        }),
+    }
+}
+
+/// ### parse_user
+///
+/// `parse_user` resolves a user provided by name or by uid
+fn parse_user(key: &str, value: &str) -> Result<u32, ConfigError> {
+    process::user_id(value.trim()).ok_or_else(|| ConfigError {
+        code: ConfigErrorCode::InvalidValue,
+        message: format!("No such user '{}' for '{}'", value, key),
+    })
+}
+
+/// ### parse_group
+///
+/// `parse_group` resolves a group provided by name or by gid
+fn parse_group(key: &str, value: &str) -> Result<u32, ConfigError> {
+    process::group_id(value.trim()).ok_or_else(|| ConfigError {
+        code: ConfigErrorCode::InvalidValue,
+        message: format!("No such group '{}' for '{}'", value, key),
+    })
+}
+
+/// ### optional_bool
+///
+/// `optional_bool` gets an optional boolean key from a YAML section
+fn optional_bool(config_doc: &Yaml, key: &str, section: &str) -> Result<Option<bool>, ConfigError> {
+    match &config_doc[key] {
+        Yaml::BadValue => Ok(None),
+        value => match value.as_bool() {
+            Some(value) => Ok(Some(value)),
+            None => Err(ConfigError {
+                code: ConfigErrorCode::YamlSyntaxError,
+                message: format!("'{}' in '{}' must be a boolean", key, section),
+            }),
+        },
+    }
+}
+
+/// ### optional_str
+///
+/// `optional_str` gets an optional string key from a YAML section
+fn optional_str(config_doc: &Yaml, key: &str, section: &str) -> Result<Option<String>, ConfigError> {
+    match &config_doc[key] {
+        Yaml::BadValue => Ok(None),
+        value => match value.as_str() {
+            Some(value) => Ok(Some(String::from(value))),
+            None => Err(ConfigError {
+                code: ConfigErrorCode::YamlSyntaxError,
+                message: format!("'{}' in '{}' must be a string", key, section),
+            }),
+        },
+    }
+}
+
+/// ### optional_u64
+///
+/// `optional_u64` gets an optional unsigned integer key from a YAML section
+fn optional_u64(config_doc: &Yaml, key: &str, section: &str) -> Result<Option<u64>, ConfigError> {
+    match &config_doc[key] {
+        Yaml::BadValue => Ok(None),
+        value => match value.as_i64() {
+            Some(value) if value >= 0 => Ok(Some(value as u64)),
+            _ => Err(ConfigError {
+                code: ConfigErrorCode::YamlSyntaxError,
+                message: format!("'{}' in '{}' must be a positive integer", key, section),
+            }),
+        },
+    }
+}
+
+/// ### optional_str_list
+///
+/// `optional_str_list` gets an optional list of strings from a YAML section
+fn optional_str_list(
+    config_doc: &Yaml,
+    key: &str,
+    section: &str,
+) -> Result<Option<Vec<String>>, ConfigError> {
+    let error = || ConfigError {
+        code: ConfigErrorCode::YamlSyntaxError,
+        message: format!("'{}' in '{}' must be a list of strings", key, section),
+    };
+    match &config_doc[key] {
+        Yaml::BadValue => Ok(None),
+        Yaml::Array(values) => values
+            .iter()
+            .map(|value| value.as_str().map(String::from).ok_or_else(error))
+            .collect::<Result<Vec<String>, ConfigError>>()
+            .map(Some),
+        _ => Err(error()),
+    }
+}
+
+/// ### parse_bool
+///
+/// `parse_bool` parses a boolean setting value provided as a string
+fn parse_bool(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError {
+            code: ConfigErrorCode::InvalidValue,
+            message: format!("Invalid value '{}' for '{}'", value, key),
+        }),
     }
 }
 
@@ -244,12 +1516,24 @@ mod tests {
                 //Log config
                 assert_eq!(config.log_config.log_file, String::from("/var/log/octopipes/octopipes.log"));
                 assert_eq!(config.log_config.log_level, 1);
-                assert_eq!(config.log_config.stdout, true);
+                assert!(config.log_config.stdout);
                 //Pipes config
                 assert_eq!(config.pipes_config.cap_path, String::from("/tmp/octopipes/cap.pipe"));
                 assert_eq!(config.pipes_config.client_dir, String::from("/tmp/octopipes/clients/"));
                 //Protocol config
                 assert_eq!(config.protocol_config.version, 1);
+                assert_eq!(config.protocol_config.default_ttl, DEFAULT_TTL);
+                //Admin config (not in file)
+                assert!(config.admin_config.enabled);
+                assert_eq!(config.admin_config.socket, String::from(DEFAULT_ADMIN_SOCKET));
+                //Server config (not in file)
+                assert_eq!(config.server_config.shutdown_grace_period, DEFAULT_SHUTDOWN_GRACE_PERIOD);
+                assert_eq!(config.server_config.dead_client_check_interval, DEFAULT_DEAD_CLIENT_CHECK_INTERVAL);
+                assert_eq!(config.server_config.max_missed_deliveries, DEFAULT_MAX_MISSED_DELIVERIES);
+                assert_eq!(config.server_config.heartbeat_interval, DEFAULT_HEARTBEAT_INTERVAL);
+                assert_eq!(config.server_config.heartbeat_missed_beats, DEFAULT_HEARTBEAT_MISSED_BEATS);
+                assert_eq!(config.server_config.queue_size, DEFAULT_QUEUE_SIZE);
+                assert_eq!(config.server_config.overflow_policy, DEFAULT_OVERFLOW_POLICY);
             },
             Err(error) => {
                 panic!("Parse_config should have returned OK, but returned {} ({:?})", error.message, error.code)
@@ -257,6 +1541,513 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_config_admin() {
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "admin:\n    enabled: false\n    socket: \"/run/octopipes/admin.sock\"\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert!(!config.admin_config.enabled);
+        assert_eq!(config.admin_config.socket, String::from("/run/octopipes/admin.sock"));
+        //Bad type
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "admin:\n    enabled: 12\n").unwrap();
+        if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
+        } else {
+            panic!("parse_config with a bad admin section returned Ok");
+        }
+    }
+
+    #[test]
+    fn test_config_pipes_ownership() {
+        let mut tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
+        write!(tmpfile, "logging:\n    log_level: 1\n    log_file: \"/tmp/octopipes.log\"\n    stdout: true\npipes:\n    cap_path: \"/tmp/octopipes/cap.pipe\"\n    client_dir: \"/tmp/octopipes/clients/\"\n    cap_mode: \"0622\"\n    cap_owner: \"root\"\n    client_mode: \"660\"\n    client_group: \"100\"\n    client_dir_mode: \"0750\"\n    peer_owner: false\nprotocol:\n    version: 1\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        let (cap, client, client_dir) = config.pipes_config.ownership();
+        assert_eq!(cap, Ownership { mode: Some(0o622), owner: Some(0), group: None });
+        assert_eq!(client, Ownership { mode: Some(0o660), owner: None, group: Some(100) });
+        assert_eq!(client_dir, Ownership { mode: Some(0o750), owner: None, group: Some(100) });
+        //Defaults leave pipes untouched
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.pipes_config.ownership(), (Ownership::default(), Ownership::default(), Ownership::default()));
+        //Bad values
+        for (key, value) in [("cap_mode", "rw-rw-rw-"), ("cap_mode", "10000"), ("client_owner", "no-such-user-octopipes"), ("client_group", "no-such-group-octopipes")].iter() {
+            let mut tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
+            write!(tmpfile, "logging:\n    log_level: 1\n    log_file: \"/tmp/octopipes.log\"\n    stdout: true\npipes:\n    cap_path: \"/tmp/octopipes/cap.pipe\"\n    client_dir: \"/tmp/octopipes/clients/\"\n    {}: \"{}\"\nprotocol:\n    version: 1\n", key, value).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, ConfigErrorCode::InvalidValue);
+            } else {
+                panic!("parse_config with {} '{}' returned Ok", key, value);
+            }
+        }
+        //Pipes can't be given to the subscribing user
+        let mut tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
+        write!(tmpfile, "logging:\n    log_level: 1\n    log_file: \"/tmp/octopipes.log\"\n    stdout: true\npipes:\n    cap_path: \"/tmp/octopipes/cap.pipe\"\n    client_dir: \"/tmp/octopipes/clients/\"\n    peer_owner: true\nprotocol:\n    version: 1\n").unwrap();
+        match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Err(error) => assert_eq!(error.code, ConfigErrorCode::InvalidValue),
+            Ok(_) => panic!("parse_config with peer_owner returned Ok"),
+        }
+    }
+
+    #[test]
+    fn test_config_protocol() {
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        writeln!(tmpfile, "    default_ttl: 30").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.protocol_config.version, 1);
+        assert_eq!(config.protocol_config.default_ttl, 30);
+    }
+
+    #[test]
+    fn test_config_server() {
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "server:\n    shutdown_grace_period: 500\n    dead_client_check_interval: 0\n    max_missed_deliveries: 5\n    heartbeat_interval: 1000\n    heartbeat_missed_beats: 2\n    queue_size: 16\n    overflow_policy: \"block\"\n    queue_group_policy: \"least-queued\"\n    user: \"root\"\n    group: \"0\"\n    chroot: true\n    dead_letter_group: \"deadletter\"\n    system_events: true\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.server_config.shutdown_grace_period, 500);
+        assert_eq!(config.server_config.dead_client_check_interval, 0);
+        assert_eq!(config.server_config.max_missed_deliveries, 5);
+        assert_eq!(config.server_config.heartbeat_interval, 1000);
+        assert_eq!(config.server_config.heartbeat_missed_beats, 2);
+        assert_eq!(config.server_config.queue_size, 16);
+        assert_eq!(config.server_config.overflow_policy, OverflowPolicy::Block);
+        assert_eq!(config.server_config.queue_group_policy, SelectionPolicy::LeastQueued);
+        assert_eq!(config.server_config.user, Some(0));
+        assert_eq!(config.server_config.group, Some(0));
+        assert!(config.server_config.chroot);
+        assert_eq!(config.server_config.dead_letter_group, Some(String::from("deadletter")));
+        assert!(config.server_config.system_events);
+        //Dead-letter group and system events are disabled by default; dead-letter group when empty too
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.server_config.dead_letter_group, None);
+        assert!(!config.server_config.system_events);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "server:\n    dead_letter_group: \"\"\n").unwrap();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.server_config.dead_letter_group, None);
+        //Wildcards and the system group are not allowed in the dead-letter group
+        for group in ["deadletter/#", "+", "$queue/deadletter", "$SYS/deadletter"].iter() {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "server:\n    dead_letter_group: \"{}\"\n", group).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, ConfigErrorCode::InvalidValue);
+            } else {
+                panic!("parse_config with a bad dead-letter group returned Ok: {}", group);
+            }
+        }
+        //Negative values are not allowed
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "server:\n    shutdown_grace_period: -1\n").unwrap();
+        if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
+        } else {
+            panic!("parse_config with a negative grace period returned Ok");
+        }
+        //Unknown overflow policy
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "server:\n    overflow_policy: \"wait\"\n").unwrap();
+        if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
+        } else {
+            panic!("parse_config with an unknown overflow policy returned Ok");
+        }
+    }
+
+    #[test]
+    fn test_config_acl() {
+        //Without the acl section everything is allowed
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.acl_config, Acl::default());
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "acl:\n    rules:\n        - client: \"logger\"\n        - client: \"sensor-*\"\n          names: [\"sensor-*\"]\n          subscribe: [\"sensors\"]\n          send: []\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.acl_config.default, AclPolicy::Deny);
+        assert_eq!(
+            config.acl_config.rules,
+            vec![
+                AclRule {
+                    client: Some(String::from("logger")),
+                    ..AclRule::default()
+                },
+                AclRule {
+                    client: Some(String::from("sensor-*")),
+                    names: Some(vec![String::from("sensor-*")]),
+                    subscribe: Some(vec![String::from("sensors")]),
+                    send: Some(vec![])
+                }
+            ]
+        );
+        //Bad values
+        for acl in [
+            "acl:\n    default: \"maybe\"\n",
+            "acl:\n    rules: \"logger\"\n",
+            "acl:\n    rules:\n        - send: \"foo\"\n",
+        ]
+        .iter()
+        {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "{}", acl).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
+            } else {
+                panic!("parse_config with a bad acl returned Ok: {}", acl);
+            }
+        }
+        //Users can't be matched
+        for acl in ["acl:\n    rules:\n        - uid: 1000\n", "acl:\n    rules:\n        - client: \"foo\"\n          gid: 100\n"].iter() {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "{}", acl).unwrap();
+            match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                Err(error) => assert_eq!(error.code, ConfigErrorCode::InvalidValue),
+                Ok(_) => panic!("parse_config with a uid or gid rule returned Ok: {}", acl),
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_queue_groups() {
+        //Without the queue_groups section there are no queue groups
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert!(config.queue_groups.is_empty());
+        assert_eq!(config.server_config.queue_group_policy, DEFAULT_QUEUE_GROUP_POLICY);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "queue_groups:\n    jobs: \"round-robin\"\n    \"tasks/+\": \"least-queued\"\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(
+            config.queue_groups.into_iter().collect::<Vec<(String, SelectionPolicy)>>(),
+            vec![
+                (String::from("jobs"), SelectionPolicy::RoundRobin),
+                (String::from("tasks/+"), SelectionPolicy::LeastQueued)
+            ]
+        );
+        //Bad values
+        for (queue_groups, code) in [
+            ("queue_groups:\n    - \"jobs\"\n", ConfigErrorCode::YamlSyntaxError),
+            ("queue_groups:\n    jobs: \"random\"\n", ConfigErrorCode::YamlSyntaxError),
+            ("queue_groups:\n    \"jobs/#/build\": \"round-robin\"\n", ConfigErrorCode::InvalidValue),
+            ("queue_groups:\n    \"$queue/jobs\": \"round-robin\"\n", ConfigErrorCode::InvalidValue),
+        ]
+        .iter()
+        {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "{}", queue_groups).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, *code);
+            } else {
+                panic!("parse_config with bad queue groups returned Ok: {}", queue_groups);
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_retained_groups() {
+        //Without the retained_groups section no group is retained
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert!(config.retained_groups.is_empty());
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "retained_groups:\n    - \"status\"\n    - \"sensors/#\"\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.retained_groups, vec![String::from("status"), String::from("sensors/#")]);
+        //Bad values
+        for (retained_groups, code) in [
+            ("retained_groups: \"status\"\n", ConfigErrorCode::YamlSyntaxError),
+            ("retained_groups:\n    - 1\n", ConfigErrorCode::YamlSyntaxError),
+            ("retained_groups:\n    - \"sensors/#/temp\"\n", ConfigErrorCode::InvalidValue),
+            ("retained_groups:\n    - \"$queue/jobs\"\n", ConfigErrorCode::InvalidValue),
+        ]
+        .iter()
+        {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "{}", retained_groups).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, *code);
+            } else {
+                panic!("parse_config with bad retained groups returned Ok: {}", retained_groups);
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_durable() {
+        //Without the durable section there are no durable clients
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert!(config.durable_config.clients.is_empty());
+        assert_eq!(config.durable_config.data_dir, String::from(DEFAULT_DATA_DIR));
+        assert_eq!(config.durable_config.max_size, DEFAULT_DURABLE_MAX_SIZE);
+        assert_eq!(config.durable_config.max_age, DEFAULT_DURABLE_MAX_AGE);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "durable:\n    data_dir: \"/var/spool/octopipes\"\n    clients:\n        - \"logger\"\n        - \"billing\"\n    max_size: 4096\n    max_age: 0\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.durable_config.data_dir, String::from("/var/spool/octopipes"));
+        assert_eq!(config.durable_config.clients, vec![String::from("logger"), String::from("billing")]);
+        assert_eq!(config.durable_config.max_size, 4096);
+        assert_eq!(config.durable_config.max_age, 0);
+        //Bad values
+        for (durable, code) in [
+            ("durable:\n    clients: \"logger\"\n", ConfigErrorCode::YamlSyntaxError),
+            ("durable:\n    max_size: \"big\"\n", ConfigErrorCode::YamlSyntaxError),
+            ("durable:\n    clients:\n        - \"../logger\"\n", ConfigErrorCode::InvalidValue),
+            ("durable:\n    clients:\n        - \"logger/#\"\n", ConfigErrorCode::InvalidValue),
+        ]
+        .iter()
+        {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "{}", durable).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, *code);
+            } else {
+                panic!("parse_config with bad durable section returned Ok: {}", durable);
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_metrics() {
+        //Without the metrics section metrics aren't exported
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.metrics_config.listen, None);
+        assert_eq!(config.metrics_config.file, None);
+        assert_eq!(config.metrics_config.interval, DEFAULT_METRICS_INTERVAL);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "metrics:\n    listen: \"127.0.0.1:9464\"\n    file: \"/var/lib/node_exporter/octopipes.prom\"\n    interval: 5000\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.metrics_config.listen, Some(String::from("127.0.0.1:9464")));
+        assert_eq!(config.metrics_config.file, Some(String::from("/var/lib/node_exporter/octopipes.prom")));
+        assert_eq!(config.metrics_config.interval, 5000);
+        //Empty values disable the exports
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "metrics:\n    listen: \"\"\n    file: \"\"\n").unwrap();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.metrics_config.listen, None);
+        assert_eq!(config.metrics_config.file, None);
+        //Bad values
+        for (metrics, code) in [
+            ("metrics:\n    listen: \"localhost\"\n", ConfigErrorCode::InvalidValue),
+            ("metrics:\n    listen: \"127.0.0.1:http\"\n", ConfigErrorCode::InvalidValue),
+            ("metrics:\n    interval: \"often\"\n", ConfigErrorCode::YamlSyntaxError),
+        ]
+        .iter()
+        {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "{}", metrics).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, *code);
+            } else {
+                panic!("parse_config with bad metrics section returned Ok: {}", metrics);
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_status() {
+        //Without the status section the status file isn't written
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.status_config.file, None);
+        assert_eq!(config.status_config.interval, DEFAULT_STATUS_INTERVAL);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "status:\n    file: \"/run/octopipes/status.json\"\n    interval: 10000\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.status_config.file, Some(String::from("/run/octopipes/status.json")));
+        assert_eq!(config.status_config.interval, 10000);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "status:\n    file: \"\"\n").unwrap();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.status_config.file, None);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "status:\n    interval: \"often\"\n").unwrap();
+        if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
+        } else {
+            panic!("parse_config with bad status section returned Ok");
+        }
+    }
+
+    #[test]
+    fn test_config_capture() {
+        //Without the capture section nothing is recorded
+        let tmpfile: tempfile::NamedTempFile = write_config_file();
+        let config: Config = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())).ok().unwrap();
+        assert_eq!(config.capture_config.file, None);
+        assert!(config.capture_config.clients.is_empty());
+        assert!(config.capture_config.groups.is_empty());
+        assert_eq!(config.capture_config.max_size, DEFAULT_CAPTURE_MAX_SIZE);
+        assert_eq!(config.capture_config.max_files, DEFAULT_CAPTURE_MAX_FILES);
+        let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+        write!(tmpfile, "capture:\n    file: \"/var/log/octopipes/traffic.cap\"\n    clients:\n        - \"sensor-*\"\n    groups:\n        - \"sensors/#\"\n        - \"collector\"\n    max_size: 1048576\n    max_files: 0\n").unwrap();
+        let config: Config = match Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+            Ok(config) => config,
+            Err(error) => panic!("Parse_config should have returned OK, but returned {}", error.message),
+        };
+        assert_eq!(config.capture_config.file, Some(String::from("/var/log/octopipes/traffic.cap")));
+        assert_eq!(config.capture_config.clients, vec![String::from("sensor-*")]);
+        assert_eq!(config.capture_config.groups, vec![String::from("sensors/#"), String::from("collector")]);
+        assert_eq!(config.capture_config.max_size, 1048576);
+        assert_eq!(config.capture_config.max_files, 0);
+        //Bad values
+        for (capture, code) in [
+            ("capture:\n    clients: \"sensor-*\"\n", ConfigErrorCode::YamlSyntaxError),
+            ("capture:\n    max_size: \"big\"\n", ConfigErrorCode::YamlSyntaxError),
+            ("capture:\n    groups:\n        - \"sensors/#/temp\"\n", ConfigErrorCode::InvalidValue),
+            ("capture:\n    groups:\n        - \"$queue/jobs\"\n", ConfigErrorCode::InvalidValue),
+        ]
+        .iter()
+        {
+            let mut tmpfile: tempfile::NamedTempFile = write_config_file();
+            write!(tmpfile, "{}", capture).unwrap();
+            if let Err(error) = Config::parse_config(String::from(tmpfile.path().to_str().unwrap())) {
+                assert_eq!(error.code, *code);
+            } else {
+                panic!("parse_config with bad capture section returned Ok: {}", capture);
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_effective() {
+        let mut config: Config = Config::default();
+        config.pipes_config.client_mode = Some(0o660);
+        config.queue_groups.insert(String::from("jobs"), SelectionPolicy::LeastQueued);
+        config.durable_config.clients = vec![String::from("logger"), String::from("billing")];
+        let effective: Vec<(&'static str, String)> = config.effective();
+        let value = |key: &str| -> String {
+            effective.iter().find(|(name, _)| *name == key).map(|(_, value)| value.clone()).unwrap()
+        };
+        assert_eq!(value("cap-pipe"), String::from(DEFAULT_CAP_PATH));
+        assert_eq!(value("log-stdout"), String::from("true"));
+        assert_eq!(value("client-mode"), String::from("0660"));
+        assert_eq!(value("client-owner"), String::from("unset"));
+        assert_eq!(value("overflow-policy"), String::from("drop-oldest"));
+        assert_eq!(value("queue-groups"), String::from("jobs:least-queued"));
+        assert_eq!(value("durable-clients"), String::from("logger,billing"));
+        assert_eq!(value("status-file"), String::from("unset"));
+        //Every setting whose source is tracked is reported
+        let settings: Settings = Settings::resolve(&CliOverrides::default(), &HashMap::new()).ok().unwrap();
+        for key in settings.sources.keys().filter(|key| !["config-file", "pidfile"].contains(key)) {
+            assert!(effective.iter().any(|(name, _)| name == key), "missing '{}'", key);
+        }
+    }
+
+    #[test]
+    fn test_config_reload() {
+        let mut config: Config = Config::default();
+        let mut new_config: Config = Config::default();
+        new_config.log_config.log_level = 1;
+        new_config.log_config.log_file = String::from("/tmp/octopipes.log");
+        new_config.log_config.stdout = false;
+        new_config.server_config.shutdown_grace_period = 0;
+        new_config.protocol_config.default_ttl = 30;
+        new_config.acl_config = Acl::new(AclPolicy::Deny, Vec::new());
+        new_config.queue_groups.insert(String::from("jobs"), SelectionPolicy::LeastQueued);
+        new_config.retained_groups.push(String::from("status"));
+        new_config.durable_config.clients.push(String::from("logger"));
+        new_config.durable_config.max_age = 3600;
+        new_config.metrics_config.interval = 1000;
+        new_config.status_config.interval = 1000;
+        new_config.capture_config.groups.push(String::from("sensors/#"));
+        new_config.capture_config.max_files = 1;
+        //Only reloadable settings have changed
+        assert!(config.reload(new_config).is_empty());
+        assert_eq!(config.log_config.log_level, 1);
+        assert_eq!(config.log_config.log_file, String::from("/tmp/octopipes.log"));
+        assert!(!config.log_config.stdout);
+        assert_eq!(config.server_config.shutdown_grace_period, 0);
+        assert_eq!(config.protocol_config.default_ttl, 30);
+        assert_eq!(config.acl_config.default, AclPolicy::Deny);
+        assert_eq!(config.queue_groups.get("jobs"), Some(&SelectionPolicy::LeastQueued));
+        assert_eq!(config.retained_groups, vec![String::from("status")]);
+        assert_eq!(config.durable_config.clients, vec![String::from("logger")]);
+        assert_eq!(config.durable_config.max_age, 3600);
+        assert_eq!(config.metrics_config.interval, 1000);
+        assert_eq!(config.status_config.interval, 1000);
+        assert_eq!(config.capture_config.groups, vec![String::from("sensors/#")]);
+        assert_eq!(config.capture_config.max_files, 1);
+        //Non reloadable settings are reported and kept
+        let mut new_config: Config = Config::default();
+        new_config.pipes_config.cap_path = String::from("/run/octopipes/cap.pipe");
+        new_config.pipes_config.client_mode = Some(0o600);
+        new_config.admin_config.socket = String::from("/run/octopipes/admin.sock");
+        new_config.server_config.user = Some(65534);
+        new_config.server_config.chroot = true;
+        new_config.durable_config.data_dir = String::from("/var/spool/octopipes");
+        new_config.metrics_config.listen = Some(String::from("127.0.0.1:9464"));
+        new_config.status_config.file = Some(String::from("/run/octopipes/status.json"));
+        new_config.capture_config.file = Some(String::from("/var/log/octopipes/traffic.cap"));
+        assert_eq!(
+            config.reload(new_config),
+            vec!["cap-pipe", "pipes-ownership", "admin-socket", "user", "chroot", "data-dir", "metrics-listen", "status-file", "capture-file"]
+        );
+        assert_eq!(config.capture_config.file, None);
+        assert_eq!(config.status_config.file, None);
+        assert_eq!(config.metrics_config.listen, None);
+        assert_eq!(config.durable_config.data_dir, String::from(DEFAULT_DATA_DIR));
+        assert_eq!(config.server_config.user, None);
+        assert!(!config.server_config.chroot);
+        assert_eq!(config.pipes_config.cap_path, String::from(DEFAULT_CAP_PATH));
+        assert_eq!(config.admin_config.socket, String::from(DEFAULT_ADMIN_SOCKET));
+        assert_eq!(config.log_config.log_level, DEFAULT_LOG_LEVEL);
+    }
+
+    #[test]
+    fn test_config_privilege_conflicts() {
+        let mut config: Config = Config::default();
+        //Dead clients check is disabled by default
+        assert!(config.privilege_conflicts(Some(65534), Some(65534), true).is_empty());
+        config.pipes_config.client_owner = Some(1000);
+        config.pipes_config.client_group = Some(65534);
+        config.server_config.dead_client_check_interval = 5000;
+        //Nothing is dropped
+        assert!(config.privilege_conflicts(None, None, false).is_empty());
+        //Root can still change the owner of the pipes, but it can't see /proc in a chroot
+        assert_eq!(
+            config.privilege_conflicts(Some(0), Some(0), true),
+            vec!["dead-client-check-interval"]
+        );
+        assert_eq!(
+            config.privilege_conflicts(Some(65534), Some(65534), false),
+            vec!["client-owner", "dead-client-check-interval"]
+        );
+        //The server can give the pipes to itself
+        config.pipes_config.client_owner = Some(65534);
+        assert_eq!(config.privilege_conflicts(Some(65534), Some(1000), false)[0], "client-group");
+    }
+
     #[test]
     fn test_config_no_such_file() {
         if let Err(error) = Config::parse_config(String::from("unexisting_config.yml")) {
@@ -268,32 +2059,167 @@ mod tests {
 
     #[test]
     fn test_config_bad_syntax() {
-        //No protocol
+        //Protocol version is not an integer
         let config_file: tempfile::NamedTempFile = write_config_file_bad1();
         let config_file_path: String = String::from(config_file.path().to_str().unwrap());
-        if let Err(error) = Config::parse_config(String::from(config_file_path)) {
+        if let Err(error) = Config::parse_config(config_file_path) {
             assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
         } else {
             panic!("parse_config of a config file with bad syntax returned Ok");
         }
-        //No Pipes
+        //CAP path is not a string
         let config_file: tempfile::NamedTempFile = write_config_file_bad2();
         let config_file_path: String = String::from(config_file.path().to_str().unwrap());
-        if let Err(error) = Config::parse_config(String::from(config_file_path)) {
+        if let Err(error) = Config::parse_config(config_file_path) {
             assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
         } else {
             panic!("parse_config of a config file with bad syntax returned Ok");
         }
-        //No logging
+        //Log level is not an integer
         let config_file: tempfile::NamedTempFile = write_config_file_bad3();
         let config_file_path: String = String::from(config_file.path().to_str().unwrap());
-        if let Err(error) = Config::parse_config(String::from(config_file_path)) {
+        if let Err(error) = Config::parse_config(config_file_path) {
             assert_eq!(error.code, ConfigErrorCode::YamlSyntaxError);
         } else {
             panic!("parse_config of a config file with bad syntax returned Ok");
         }
     }
 
+    #[test]
+    fn test_settings_defaults() {
+        //Without configuration file, environment and CLI, defaults are used
+        let settings: Settings =
+            match Settings::resolve(&CliOverrides::default(), &HashMap::new()) {
+                Ok(settings) => settings,
+                Err(error) => panic!("Resolve should have returned OK, but returned {}", error.message),
+            };
+        assert!(settings.config_file.is_none());
+        assert!(settings.pid_file.is_none());
+        assert_eq!(settings.config.log_config.log_level, DEFAULT_LOG_LEVEL);
+        assert_eq!(settings.config.pipes_config.cap_path, String::from(DEFAULT_CAP_PATH));
+        assert_eq!(settings.config.pipes_config.client_dir, String::from(DEFAULT_CLIENT_DIR));
+        assert_eq!(settings.source("cap-pipe"), SettingSource::Default);
+        assert_eq!(settings.source("config-file"), SettingSource::Default);
+    }
+
+    #[test]
+    fn test_settings_precedence() {
+        let config_file: tempfile::NamedTempFile = write_config_file();
+        let config_file_path: String = String::from(config_file.path().to_str().unwrap());
+        let mut env: HashMap<String, String> = HashMap::new();
+        env.insert(String::from(ENV_CONFIG_FILE), config_file_path.clone());
+        env.insert(String::from(ENV_CAP_PATH), String::from("/run/octopipes/cap.pipe"));
+        env.insert(String::from(ENV_LOG_LEVEL), String::from("3"));
+        env.insert(String::from(ENV_LOG_STDOUT), String::from("false"));
+        env.insert(String::from(ENV_CLIENT_MODE), String::from("0600"));
+        env.insert(String::from(ENV_CLIENT_OWNER), String::from("root"));
+        env.insert(String::from(ENV_QUEUE_GROUP_POLICY), String::from("least-queued"));
+        env.insert(String::from(ENV_DATA_DIR), String::from("/var/spool/octopipes"));
+        env.insert(String::from(ENV_DEAD_LETTER_GROUP), String::from("deadletter"));
+        env.insert(String::from(ENV_SYSTEM_EVENTS), String::from("true"));
+        env.insert(String::from(ENV_METRICS_LISTEN), String::from("127.0.0.1:9464"));
+        env.insert(String::from(ENV_STATUS_INTERVAL), String::from("1000"));
+        env.insert(String::from(ENV_CAPTURE_FILE), String::from("/var/log/octopipes/traffic.cap"));
+        let cli: CliOverrides = CliOverrides {
+            log_level: Some(String::from("4")),
+            pid_file: Some(String::from("/run/octopipes.pid")),
+            ..CliOverrides::default()
+        };
+        let settings: Settings = match Settings::resolve(&cli, &env) {
+            Ok(settings) => settings,
+            Err(error) => panic!("Resolve should have returned OK, but returned {}", error.message),
+        };
+        //Config file from env
+        assert_eq!(settings.config_file, Some(config_file_path));
+        assert_eq!(settings.source("config-file"), SettingSource::Env(ENV_CONFIG_FILE));
+        //File
+        assert_eq!(settings.config.pipes_config.client_dir, String::from("/tmp/octopipes/clients/"));
+        assert_eq!(settings.source("client-dir"), SettingSource::File);
+        assert_eq!(settings.config.protocol_config.version, 1);
+        //Env overrides file
+        assert_eq!(settings.config.pipes_config.cap_path, String::from("/run/octopipes/cap.pipe"));
+        assert_eq!(settings.source("cap-pipe"), SettingSource::Env(ENV_CAP_PATH));
+        assert!(!settings.config.log_config.stdout);
+        assert_eq!(settings.config.pipes_config.client_mode, Some(0o600));
+        assert_eq!(settings.config.pipes_config.client_owner, Some(0));
+        assert_eq!(settings.source("client-owner"), SettingSource::Env(ENV_CLIENT_OWNER));
+        assert_eq!(settings.source("cap-owner"), SettingSource::Default);
+        assert_eq!(settings.config.server_config.queue_group_policy, SelectionPolicy::LeastQueued);
+        assert_eq!(settings.source("queue-group-policy"), SettingSource::Env(ENV_QUEUE_GROUP_POLICY));
+        assert_eq!(settings.config.durable_config.data_dir, String::from("/var/spool/octopipes"));
+        assert_eq!(settings.source("data-dir"), SettingSource::Env(ENV_DATA_DIR));
+        assert_eq!(settings.config.server_config.dead_letter_group, Some(String::from("deadletter")));
+        assert_eq!(settings.source("dead-letter-group"), SettingSource::Env(ENV_DEAD_LETTER_GROUP));
+        assert!(settings.config.server_config.system_events);
+        assert_eq!(settings.source("system-events"), SettingSource::Env(ENV_SYSTEM_EVENTS));
+        assert_eq!(settings.config.metrics_config.listen, Some(String::from("127.0.0.1:9464")));
+        assert_eq!(settings.source("metrics-listen"), SettingSource::Env(ENV_METRICS_LISTEN));
+        assert_eq!(settings.source("metrics-file"), SettingSource::Default);
+        assert_eq!(settings.config.status_config.interval, 1000);
+        assert_eq!(settings.source("status-interval"), SettingSource::Env(ENV_STATUS_INTERVAL));
+        assert_eq!(settings.config.capture_config.file, Some(String::from("/var/log/octopipes/traffic.cap")));
+        assert_eq!(settings.source("capture-file"), SettingSource::Env(ENV_CAPTURE_FILE));
+        //CLI overrides env
+        assert_eq!(settings.config.log_config.log_level, 4);
+        assert_eq!(settings.source("log-level"), SettingSource::Cli);
+        assert_eq!(settings.pid_file, Some(String::from("/run/octopipes.pid")));
+        assert_eq!(settings.source("pidfile"), SettingSource::Cli);
+        assert_eq!(format!("{}", settings.source("cap-pipe")), String::from("env OCTOPIPES_CAP_PATH"));
+    }
+
+    #[test]
+    fn test_settings_partial_file() {
+        //Keys missing in the configuration file are taken from defaults
+        let mut config_file: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
+        write!(config_file, "pipes:\n    cap_path: \"/run/octopipes/cap.pipe\"\nserver:\n    queue_size: 64\n").unwrap();
+        let cli: CliOverrides = CliOverrides {
+            config_file: Some(String::from(config_file.path().to_str().unwrap())),
+            ..CliOverrides::default()
+        };
+        let settings: Settings = match Settings::resolve(&cli, &HashMap::new()) {
+            Ok(settings) => settings,
+            Err(error) => panic!("Resolve should have returned OK, but returned {}", error.message),
+        };
+        //Set in file
+        assert_eq!(settings.config.pipes_config.cap_path, String::from("/run/octopipes/cap.pipe"));
+        assert_eq!(settings.source("cap-pipe"), SettingSource::File);
+        assert_eq!(settings.config.server_config.queue_size, 64);
+        assert_eq!(settings.source("queue-size"), SettingSource::File);
+        //Missing in file
+        assert_eq!(settings.config.pipes_config.client_dir, String::from(DEFAULT_CLIENT_DIR));
+        assert_eq!(settings.source("client-dir"), SettingSource::Default);
+        assert_eq!(settings.config.log_config.log_level, DEFAULT_LOG_LEVEL);
+        assert_eq!(settings.config.log_config.log_file, String::from(DEFAULT_LOG_FILE));
+        assert_eq!(settings.config.log_config.stdout, DEFAULT_LOG_STDOUT);
+        assert_eq!(settings.source("log-level"), SettingSource::Default);
+        assert_eq!(settings.source("log-stdout"), SettingSource::Default);
+        assert_eq!(settings.config.protocol_config.version, DEFAULT_PROTOCOL_VERSION);
+        assert_eq!(settings.source("protocol-version"), SettingSource::Default);
+        assert_eq!(settings.config.server_config.shutdown_grace_period, DEFAULT_SHUTDOWN_GRACE_PERIOD);
+        assert_eq!(settings.source("shutdown-grace-period"), SettingSource::Default);
+        assert_eq!(settings.source("config-file"), SettingSource::Cli);
+    }
+
+    #[test]
+    fn test_settings_invalid_value() {
+        let mut env: HashMap<String, String> = HashMap::new();
+        env.insert(String::from(ENV_LOG_STDOUT), String::from("maybe"));
+        if let Err(error) = Settings::resolve(&CliOverrides::default(), &env) {
+            assert_eq!(error.code, ConfigErrorCode::InvalidValue);
+        } else {
+            panic!("resolve with an invalid environment value returned Ok");
+        }
+        let cli: CliOverrides = CliOverrides {
+            log_level: Some(String::from("debug")),
+            ..CliOverrides::default()
+        };
+        if let Err(error) = Settings::resolve(&cli, &HashMap::new()) {
+            assert_eq!(error.code, ConfigErrorCode::InvalidValue);
+        } else {
+            panic!("resolve with an invalid CLI value returned Ok");
+        }
+    }
+
     /// ### write_config_file
     /// Write configuration file to a temporary directory and return the file path
     fn write_config_file() -> tempfile::NamedTempFile {
@@ -308,7 +2234,7 @@ mod tests {
     fn write_config_file_bad1() -> tempfile::NamedTempFile {
         // Write
         let mut tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
-        write!(tmpfile, "logging:\n    enabled: true\n    log_level: 1\n    log_file: \"/var/log/octopipes/octopipes.log\"\n    stdout: true\npipes:\n    cap_path: \"/tmp/octopipes/cap.pipe\"\n    client_dir: \"/tmp/octopipes/clients/\"\n").unwrap();
+        write!(tmpfile, "logging:\n    enabled: true\n    log_level: 1\n    log_file: \"/var/log/octopipes/octopipes.log\"\n    stdout: true\npipes:\n    cap_path: \"/tmp/octopipes/cap.pipe\"\n    client_dir: \"/tmp/octopipes/clients/\"\nprotocol:\n    version: \"one\"\n").unwrap();
         tmpfile
     }
 
@@ -317,7 +2243,7 @@ mod tests {
     fn write_config_file_bad2() -> tempfile::NamedTempFile {
         // Write
         let mut tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
-        write!(tmpfile, "logging:\n    enabled: true\n    log_level: 1\n    log_file: \"/var/log/octopipes/octopipes.log\"\n    stdout: true\nprotocol:\n    version: 1\n").unwrap();
+        write!(tmpfile, "logging:\n    enabled: true\n    log_level: 1\n    log_file: \"/var/log/octopipes/octopipes.log\"\n    stdout: true\npipes:\n    cap_path:\n        - \"/tmp/octopipes/cap.pipe\"\nprotocol:\n    version: 1\n").unwrap();
         tmpfile
     }
 
@@ -326,7 +2252,7 @@ mod tests {
     fn write_config_file_bad3() -> tempfile::NamedTempFile {
         // Write
         let mut tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
-        write!(tmpfile, "pipes:\n    cap_path: \"/tmp/octopipes/cap.pipe\"\n    client_dir: \"/tmp/octopipes/clients/\"\nprotocol:\n    version: 1\n").unwrap();
+        write!(tmpfile, "logging:\n    log_level: \"high\"\npipes:\n    cap_path: \"/tmp/octopipes/cap.pipe\"\n    client_dir: \"/tmp/octopipes/clients/\"\nprotocol:\n    version: 1\n").unwrap();
         tmpfile
     }
 }
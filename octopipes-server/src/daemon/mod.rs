@@ -0,0 +1,230 @@
+//! ### daemon
+//!
+//! `daemon` is the module which provides the functions to run the server as a daemon, to drop its privileges and to handle its PID file
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+extern crate libc;
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+/// ### PidFile
+///
+/// `PidFile` is a PID file locked by the running server. The lock is held until the PidFile is dropped
+pub struct PidFile {
+    path: String,
+    file: File,
+}
+
+/// ### PidFileError
+///
+/// `PidFileError` describes the reason why the PID file couldn't be acquired
+#[derive(fmt::Debug)]
+pub enum PidFileError {
+    /// Another live server holds the lock (with its PID if readable)
+    AlreadyRunning(Option<u32>),
+    Io(Error),
+}
+
+impl PidFile {
+    /// ### acquire
+    ///
+    /// `acquire` opens the PID file and takes an exclusive lock on it.
+    /// If another process holds the lock, AlreadyRunning is returned; a stale PID file (not locked) is reused
+    pub fn acquire(path: &str) -> Result<PidFile, PidFileError> {
+        let mut file: File = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o644)
+            .custom_flags(libc::O_CLOEXEC)
+            .open(path)
+            .map_err(PidFileError::Io)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+            let error: Error = Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(libc::EWOULDBLOCK) => {
+                    let mut content: String = String::new();
+                    let pid: Option<u32> = match file.read_to_string(&mut content) {
+                        Ok(_) => content.trim().parse::<u32>().ok(),
+                        Err(_) => None,
+                    };
+                    Err(PidFileError::AlreadyRunning(pid))
+                }
+                _ => Err(PidFileError::Io(error)),
+            };
+        }
+        Ok(PidFile {
+            path: String::from(path),
+            file,
+        })
+    }
+
+    /// ### write_pid
+    ///
+    /// `write_pid` replaces the content of the PID file with the provided PID
+    pub fn write_pid(&mut self, pid: u32) -> std::io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        write!(self.file, "{}", pid)?;
+        self.file.sync_all()
+    }
+
+    /// ### path
+    ///
+    /// `path` returns the path of the PID file
+    pub fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    /// ### remove
+    ///
+    /// `remove` deletes the PID file and releases the lock
+    pub fn remove(self) -> std::io::Result<()> {
+        //File is removed while the lock is still held, then closed on drop
+        std::fs::remove_file(&self.path)
+    }
+}
+
+/// ### daemonize
+///
+/// `daemonize` detaches the process from the terminal: the process forks (the parent exits),
+/// becomes the leader of a new session and forks again, so that the daemon isn't a session leader
+/// and can't acquire a controlling terminal; then its standard streams are redirected to /dev/null.
+/// The working directory is kept, so relative paths in the configuration are still valid
+pub fn daemonize() -> std::io::Result<()> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } == -1 {
+        return Err(Error::last_os_error());
+    }
+    fork_and_exit_parent()?;
+    let dev_null: CString = CString::new("/dev/null").unwrap();
+    let fd: libc::c_int = unsafe { libc::open(dev_null.as_ptr(), libc::O_RDWR) };
+    if fd == -1 {
+        return Err(Error::last_os_error());
+    }
+    for stdio in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO].iter() {
+        if unsafe { libc::dup2(fd, *stdio) } == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    if fd > libc::STDERR_FILENO {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+    Ok(())
+}
+
+/// ### fork_and_exit_parent
+///
+/// `fork_and_exit_parent` forks the process; the parent exits, the child returns
+fn fork_and_exit_parent() -> std::io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// ### drop_privileges
+///
+/// `drop_privileges` makes the process run with the provided user and group, which replaces the supplementary groups too.
+/// If `root` is set, the root directory of the process is changed to it first (chroot), then the working directory is moved to the new root.
+/// Changing the root, the user and the group requires the process to be privileged
+pub fn drop_privileges(
+    uid: Option<u32>,
+    gid: Option<u32>,
+    root: Option<&str>,
+) -> std::io::Result<()> {
+    if let Some(root) = root {
+        let c_root: CString = CString::new(root)?;
+        if unsafe { libc::chroot(c_root.as_ptr()) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        std::env::set_current_dir("/")?;
+    }
+    //Group must be changed before the user, which won't be allowed to change it anymore
+    if let Some(gid) = gid {
+        if unsafe { libc::setgroups(1, &gid) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        if unsafe { libc::setgid(gid) } == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    if let Some(uid) = uid {
+        if unsafe { libc::setuid(uid) } == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for PidFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PidFileError::AlreadyRunning(Some(pid)) => {
+                write!(f, "Another server is already running with PID {}", pid)
+            }
+            PidFileError::AlreadyRunning(None) => write!(f, "Another server is already running"),
+            PidFileError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_pidfile_lock() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("octopipes.pid").display().to_string();
+        //Stale pid file with a longer PID
+        std::fs::write(&path, "123456789").unwrap();
+        let mut pidfile: PidFile = PidFile::acquire(&path).unwrap();
+        assert!(pidfile.write_pid(42).is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "42");
+        //Lock is held
+        match PidFile::acquire(&path) {
+            Err(PidFileError::AlreadyRunning(pid)) => assert_eq!(pid, Some(42)),
+            _ => panic!("acquire of a locked PID file didn't return AlreadyRunning"),
+        }
+        //Remove
+        assert!(pidfile.remove().is_ok());
+        assert!(!std::path::Path::new(&path).exists());
+        //Lock is released when dropped
+        let pidfile: PidFile = PidFile::acquire(&path).unwrap();
+        drop(pidfile);
+        assert!(PidFile::acquire(&path).is_ok());
+    }
+}
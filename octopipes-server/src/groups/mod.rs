@@ -0,0 +1,462 @@
+//! ### groups
+//!
+//! `groups` is the module which provides the trie used to match group names against the subscriptions
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use std::collections::{BTreeSet, HashMap};
+
+//Separator between the levels of a group name (e.g. sensors/kitchen/temp)
+pub const LEVEL_SEPARATOR: char = '/';
+//Wildcard matching exactly one level
+pub const SINGLE_LEVEL_WILDCARD: &str = "+";
+//Wildcard matching any amount of levels, even none; it can only be the last level
+pub const MULTI_LEVEL_WILDCARD: &str = "#";
+//Prefix of the subscriptions which join the queue group of a group (e.g. $queue/jobs)
+pub const QUEUE_GROUP_PREFIX: &str = "$queue/";
+//Group where the server publishes its system events (e.g. $SYS/clients/subscribed); clients can't send to it
+pub const SYSTEM_GROUP: &str = "$SYS";
+//Groups starting with this character aren't matched by wildcards in the first level (# doesn't match $SYS/...)
+const RESERVED_PREFIX: char = '$';
+
+/// ### GroupTree
+///
+/// `GroupTree` is a trie of the groups the clients are subscribed to, where each node is a level of the group name.
+/// Looking up the subscriptions matching a group walks its levels only, whatever the amount of subscribed clients
+#[derive(Default)]
+pub struct GroupTree {
+    root: Node,
+}
+
+/// ### Subscription
+///
+/// `Subscription` is a group the clients are subscribed to: each subscriber receives every message,
+/// while the members of its queue group take turns
+#[derive(Default)]
+pub struct Subscription {
+    pub group: String,
+    pub subscribers: BTreeSet<String>,
+    pub members: BTreeSet<String>,
+    //Position of the queue group member which is chosen next
+    pub cursor: usize,
+}
+
+/// ### Node
+///
+/// `Node` is a level of the trie, with the subscription to the group ending at this level
+#[derive(Default)]
+struct Node {
+    subscription: Subscription,
+    children: HashMap<String, Node>,
+}
+
+impl GroupTree {
+    /// ### new
+    ///
+    /// Instantiates a new empty GroupTree
+    pub fn new() -> GroupTree {
+        GroupTree::default()
+    }
+
+    /// ### insert
+    ///
+    /// `insert` subscribes the client to the provided group, which may contain wildcards.
+    /// With the queue group prefix, the client joins the queue group of the group instead
+    pub fn insert(&mut self, group: &str, client: &str) {
+        let (group, member): (&str, bool) = match queue_group(group) {
+            Some(group) => (group, true),
+            None => (group, false),
+        };
+        let mut node: &mut Node = &mut self.root;
+        for level in group.split(LEVEL_SEPARATOR) {
+            node = node.children.entry(String::from(level)).or_default();
+        }
+        let subscription: &mut Subscription = &mut node.subscription;
+        subscription.group = String::from(group);
+        match member {
+            true => subscription.members.insert(String::from(client)),
+            false => subscription.subscribers.insert(String::from(client)),
+        };
+    }
+
+    /// ### remove
+    ///
+    /// `remove` unsubscribes the client from the provided group. Levels left without subscriptions are removed
+    pub fn remove(&mut self, group: &str, client: &str) {
+        let (group, member): (&str, bool) = match queue_group(group) {
+            Some(group) => (group, true),
+            None => (group, false),
+        };
+        let levels: Vec<&str> = group.split(LEVEL_SEPARATOR).collect();
+        self.root.remove(&levels, client, member);
+    }
+
+    /// ### get
+    ///
+    /// `get` returns the subscription to the provided group, as it has been subscribed (wildcards aren't expanded)
+    pub fn get(&self, group: &str) -> Option<&Subscription> {
+        let mut node: &Node = &self.root;
+        for level in group.split(LEVEL_SEPARATOR) {
+            node = node.children.get(level)?;
+        }
+        Some(&node.subscription).filter(|subscription| !subscription.is_empty())
+    }
+
+    /// ### get_mut
+    ///
+    /// `get_mut` returns the subscription to the provided group as mutable
+    pub fn get_mut(&mut self, group: &str) -> Option<&mut Subscription> {
+        let mut node: &mut Node = &mut self.root;
+        for level in group.split(LEVEL_SEPARATOR) {
+            node = node.children.get_mut(level)?;
+        }
+        Some(&mut node.subscription).filter(|subscription| !subscription.is_empty())
+    }
+
+    /// ### matches
+    ///
+    /// `matches` returns the subscriptions to the groups matching the provided one.
+    /// Wildcards have a meaning only in subscriptions: the provided group is matched as is.
+    /// Groups starting with `$` are matched only by subscriptions with the same first level
+    pub fn matches(&self, group: &str) -> Vec<&Subscription> {
+        let levels: Vec<&str> = group.split(LEVEL_SEPARATOR).collect();
+        let mut subscriptions: Vec<&Subscription> = Vec::new();
+        match levels.split_first() {
+            Some((level, levels)) if level.starts_with(RESERVED_PREFIX) => {
+                if let Some(child) = self.root.children.get(*level) {
+                    child.collect(levels, &mut subscriptions);
+                }
+            }
+            _ => self.root.collect(&levels, &mut subscriptions),
+        }
+        subscriptions.retain(|subscription| !subscription.is_empty());
+        subscriptions
+    }
+
+    /// ### is_empty
+    ///
+    /// `is_empty` returns whether no client is subscribed to any group
+    pub fn is_empty(&self) -> bool {
+        self.root.children.is_empty()
+    }
+}
+
+impl Subscription {
+    /// ### is_empty
+    ///
+    /// `is_empty` returns whether no client is subscribed to the group
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty() && self.members.is_empty()
+    }
+}
+
+impl Node {
+    /// ### remove
+    ///
+    /// `remove` removes the client (or the queue group member) from the group made of the provided levels, below this node.
+    /// Returns whether the node has been left empty
+    fn remove(&mut self, levels: &[&str], client: &str, member: bool) -> bool {
+        match levels.split_first() {
+            None if member => {
+                self.subscription.members.remove(client);
+            }
+            None => {
+                self.subscription.subscribers.remove(client);
+            }
+            Some((level, levels)) => {
+                if let Some(child) = self.children.get_mut(*level) {
+                    if child.remove(levels, client, member) {
+                        self.children.remove(*level);
+                    }
+                }
+            }
+        }
+        self.subscription.is_empty() && self.children.is_empty()
+    }
+
+    /// ### collect
+    ///
+    /// `collect` adds to `subscriptions` the subscriptions below this node which match the provided levels
+    fn collect<'a>(&'a self, levels: &[&str], subscriptions: &mut Vec<&'a Subscription>) {
+        //Multi level wildcard matches the parent level too (sensors/# matches sensors)
+        if let Some(child) = self.children.get(MULTI_LEVEL_WILDCARD) {
+            subscriptions.push(&child.subscription);
+        }
+        match levels.split_first() {
+            None => subscriptions.push(&self.subscription),
+            Some((level, levels)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.collect(levels, subscriptions);
+                }
+                //The level is matched once if it is the wildcard itself
+                if *level != SINGLE_LEVEL_WILDCARD {
+                    if let Some(child) = self.children.get(SINGLE_LEVEL_WILDCARD) {
+                        child.collect(levels, subscriptions);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// ### queue_group
+///
+/// `queue_group` returns the group whose queue group is joined by the provided subscription, if it has the queue group prefix
+pub fn queue_group(subscription: &str) -> Option<&str> {
+    subscription.strip_prefix(QUEUE_GROUP_PREFIX)
+}
+
+/// ### filter_matches
+///
+/// `filter_matches` returns whether the group matches the provided filter, which may contain wildcards,
+/// the same way the group would match a subscription to the filter
+pub fn filter_matches(filter: &str, group: &str) -> bool {
+    if group.starts_with(RESERVED_PREFIX) && !filter.starts_with(RESERVED_PREFIX) {
+        return false;
+    }
+    let mut filter_levels = filter.split(LEVEL_SEPARATOR);
+    let mut group_levels = group.split(LEVEL_SEPARATOR);
+    loop {
+        match (filter_levels.next(), group_levels.next()) {
+            (Some(MULTI_LEVEL_WILDCARD), _) => return true,
+            (Some(SINGLE_LEVEL_WILDCARD), Some(_)) => continue,
+            (Some(filter_level), Some(group_level)) if filter_level == group_level => continue,
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// ### is_system_group
+///
+/// `is_system_group` returns whether the group is the system group or below it
+pub fn is_system_group(group: &str) -> bool {
+    group
+        .strip_prefix(SYSTEM_GROUP)
+        .is_some_and(|group| group.is_empty() || group.starts_with(LEVEL_SEPARATOR))
+}
+
+/// ### is_valid_group
+///
+/// `is_valid_group` returns whether the group can be subscribed to: wildcards must take a whole level
+/// and the multi level wildcard can only be the last level
+pub fn is_valid_group(group: &str) -> bool {
+    let group: &str = queue_group(group).unwrap_or(group);
+    let levels: Vec<&str> = group.split(LEVEL_SEPARATOR).collect();
+    levels
+        .iter()
+        .enumerate()
+        .all(|(index, level)| match *level {
+            SINGLE_LEVEL_WILDCARD => true,
+            MULTI_LEVEL_WILDCARD => index + 1 == levels.len(),
+            level => !level.contains(['+', '#']),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_groups_matches() {
+        let mut groups: GroupTree = GroupTree::new();
+        assert!(groups.is_empty());
+        groups.insert("BROADCAST", "foo");
+        groups.insert("sensors/kitchen/temp", "foo");
+        groups.insert("sensors/+/temp", "bar");
+        groups.insert("sensors/#", "baz");
+        groups.insert("#", "logger");
+        groups.insert("+", "omar");
+        assert_eq!(
+            subscribers(&groups, "sensors/kitchen/temp"),
+            clients(&["bar", "baz", "foo", "logger"])
+        );
+        assert_eq!(
+            subscribers(&groups, "sensors/bedroom/temp"),
+            clients(&["bar", "baz", "logger"])
+        );
+        //Multi level wildcard matches the parent level; single level wildcard needs a level
+        assert_eq!(
+            subscribers(&groups, "sensors"),
+            clients(&["baz", "logger", "omar"])
+        );
+        assert_eq!(
+            subscribers(&groups, "sensors/kitchen"),
+            clients(&["baz", "logger"])
+        );
+        assert_eq!(
+            subscribers(&groups, "BROADCAST"),
+            clients(&["foo", "logger", "omar"])
+        );
+        //Wildcards in the group are matched as they are
+        assert_eq!(
+            groups
+                .matches("sensors/+/temp")
+                .iter()
+                .map(|subscription| subscription.group.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["#", "sensors/#", "sensors/+/temp"]
+        );
+        //Wildcards in the first level don't match groups starting with $
+        groups.insert("$SYS/#", "monitor");
+        assert_eq!(
+            subscribers(&groups, "$SYS/clients/subscribed"),
+            clients(&["monitor"])
+        );
+        assert_eq!(subscribers(&groups, "$SYS"), clients(&["monitor"]));
+        groups.remove("$SYS/#", "monitor");
+        assert!(groups.matches("$SYS/clients/subscribed").is_empty());
+        //Remove subscriptions
+        groups.remove("#", "logger");
+        groups.remove("+", "omar");
+        groups.remove("sensors/#", "baz");
+        groups.remove("sensors/+/temp", "bar");
+        assert_eq!(
+            subscribers(&groups, "sensors/kitchen/temp"),
+            clients(&["foo"])
+        );
+        assert!(groups.matches("sensors/bedroom/temp").is_empty());
+        //Removing a group which isn't subscribed does nothing
+        groups.remove("sensors/kitchen", "foo");
+        groups.remove("BROADCAST", "bar");
+        groups.remove("sensors/kitchen/temp", "foo");
+        groups.remove("BROADCAST", "foo");
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn test_groups_queue_groups() {
+        let mut groups: GroupTree = GroupTree::new();
+        groups.insert("$queue/jobs/+", "worker-1");
+        groups.insert("$queue/jobs/+", "worker-2");
+        groups.insert("jobs/+", "monitor");
+        let subscriptions: Vec<&Subscription> = groups.matches("jobs/build");
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].group, String::from("jobs/+"));
+        assert_eq!(subscriptions[0].subscribers, clients(&["monitor"]));
+        assert_eq!(subscriptions[0].members, clients(&["worker-1", "worker-2"]));
+        //Cursor is kept by the subscription
+        groups.get_mut("jobs/+").unwrap().cursor = 1;
+        assert_eq!(groups.get("jobs/+").unwrap().cursor, 1);
+        assert!(groups.get("jobs/build").is_none());
+        assert!(groups.get("jobs").is_none());
+        //Remove members
+        groups.remove("jobs/+", "monitor");
+        groups.remove("$queue/jobs/+", "worker-1");
+        assert_eq!(
+            groups.get("jobs/+").unwrap().members,
+            clients(&["worker-2"])
+        );
+        groups.remove("$queue/jobs/+", "worker-2");
+        assert!(groups.get("jobs/+").is_none());
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn test_groups_filter_matches() {
+        assert!(filter_matches("BROADCAST", "BROADCAST"));
+        assert!(!filter_matches("BROADCAST", "BROADCAST/foo"));
+        assert!(filter_matches("sensors/+/temp", "sensors/kitchen/temp"));
+        assert!(!filter_matches("sensors/+/temp", "sensors/kitchen"));
+        assert!(!filter_matches("sensors/+", "sensors"));
+        assert!(filter_matches("sensors/#", "sensors"));
+        assert!(filter_matches("sensors/#", "sensors/kitchen/temp"));
+        assert!(!filter_matches("sensors/#", "lights/kitchen"));
+        assert!(filter_matches("#", "lights/kitchen"));
+        assert!(filter_matches("+", "BROADCAST"));
+        assert!(!filter_matches("#", "$SYS/clients/died"));
+        assert!(!filter_matches("+/clients/died", "$SYS/clients/died"));
+        assert!(filter_matches("$SYS/+/died", "$SYS/clients/died"));
+        //Same as the subscriptions in the trie
+        let mut groups: GroupTree = GroupTree::new();
+        for filter in ["sensors/+/temp", "sensors/#", "+", "#", "lights", "$SYS/#"].iter() {
+            groups.insert(filter, "foo");
+        }
+        for group in [
+            "sensors",
+            "sensors/kitchen/temp",
+            "lights",
+            "lights/kitchen",
+            "$SYS/clients/died",
+        ]
+        .iter()
+        {
+            let mut expected: Vec<&str> = groups
+                .matches(group)
+                .iter()
+                .map(|subscription| subscription.group.as_str())
+                .collect();
+            expected.sort_unstable();
+            let mut matching: Vec<&str> =
+                ["sensors/+/temp", "sensors/#", "+", "#", "lights", "$SYS/#"]
+                    .iter()
+                    .copied()
+                    .filter(|filter| filter_matches(filter, group))
+                    .collect();
+            matching.sort_unstable();
+            assert_eq!(matching, expected);
+        }
+    }
+
+    #[test]
+    fn test_groups_is_valid_group() {
+        assert!(is_valid_group("BROADCAST"));
+        assert!(is_valid_group("sensors/kitchen/temp"));
+        assert!(is_valid_group("sensors/+/temp"));
+        assert!(is_valid_group("sensors/#"));
+        assert!(is_valid_group("+/+/#"));
+        assert!(is_valid_group("#"));
+        assert!(is_valid_group("$queue/jobs/#"));
+        assert!(!is_valid_group("sensors/#/temp"));
+        assert!(!is_valid_group("sensors/kitchen+/temp"));
+        assert!(!is_valid_group("sensors#"));
+        assert!(!is_valid_group("sensors/##"));
+        assert!(!is_valid_group("$queue/jobs#"));
+        assert_eq!(queue_group("$queue/jobs"), Some("jobs"));
+        assert_eq!(queue_group("jobs"), None);
+    }
+
+    #[test]
+    fn test_groups_is_system_group() {
+        assert!(is_system_group("$SYS"));
+        assert!(is_system_group("$SYS/clients/subscribed"));
+        assert!(is_system_group("$SYS/#"));
+        assert!(!is_system_group("$SYSTEM"));
+        assert!(!is_system_group("SYS/clients"));
+        assert!(!is_system_group("#"));
+    }
+
+    fn subscribers(groups: &GroupTree, group: &str) -> BTreeSet<String> {
+        groups
+            .matches(group)
+            .iter()
+            .flat_map(|subscription| subscription.subscribers.iter().cloned())
+            .collect()
+    }
+
+    fn clients(clients: &[&str]) -> BTreeSet<String> {
+        clients.iter().map(|client| String::from(*client)).collect()
+    }
+}
@@ -25,5 +25,18 @@
 // SOFTWARE.
 //
 
+pub mod acl;
+pub mod admin;
+pub mod capture;
 pub mod config;
+pub mod daemon;
+pub mod groups;
 pub mod logger;
+pub mod metrics;
+pub mod pipes;
+pub mod poller;
+pub mod process;
+pub mod protocol;
+pub mod server;
+pub mod status;
+pub mod store;
@@ -28,8 +28,8 @@
 extern crate chrono;
 extern crate log;
 
-use chrono::{Datelike, Local, Timelike};
-use std::fs::OpenOptions;
+use chrono::Local;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 
 #[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
@@ -45,6 +45,8 @@ pub struct OctoLogger {
     level: log::Level,
     file: String,
     stdout: bool,
+    //Log file is kept open, so that it can still be written after dropping privileges
+    handle: Option<File>,
 }
 
 impl OctoLogLevel {
@@ -68,11 +70,39 @@ impl OctoLogger {
     /// Instantiates a new OctoLogger struct with the provided parameters
     pub fn new(enabled: bool, level: OctoLogLevel, file: String, stdout: bool) -> OctoLogger {
         let log_level: log::Level = OctoLogger::level_from_int(level);
+        let handle: Option<File> = match enabled {
+            true => OctoLogger::open_file(&file),
+            false => None,
+        };
         OctoLogger {
-            enabled: enabled,
+            enabled,
             level: log_level,
-            file: file,
-            stdout: stdout,
+            file,
+            stdout,
+            handle,
+        }
+    }
+
+    /// ### reconfigure
+    ///
+    /// Change the logger settings at runtime. The log file is reopened only if it has changed
+    pub fn reconfigure(&mut self, enabled: bool, level: OctoLogLevel, file: String, stdout: bool) {
+        if file != self.file {
+            self.handle = None;
+            self.file = file;
+        }
+        self.stdout = stdout;
+        self.set_level(enabled, level);
+    }
+
+    /// ### set_level
+    ///
+    /// Change the logger level at runtime
+    pub fn set_level(&mut self, enabled: bool, level: OctoLogLevel) {
+        self.enabled = enabled;
+        self.level = OctoLogger::level_from_int(level);
+        if self.enabled && self.handle.is_none() {
+            self.handle = OctoLogger::open_file(&self.file);
         }
     }
 
@@ -140,24 +170,18 @@ impl OctoLogger {
         }
     }
 
+    /// ### open_file
+    ///
+    /// Open the log file for appending
+    fn open_file(file: &str) -> Option<File> {
+        OpenOptions::new().create(true).append(true).open(file).ok()
+    }
+
+    /// ### format_time
+    ///
+    /// Format the current local time with a fixed width (e.g. 2020/02/08-17:45:35)
     fn format_time() -> String {
-        let t_now = Local::now();
-        String::from(
-            [
-                t_now.year().to_string(),
-                String::from("/"),
-                t_now.month().to_string(),
-                String::from("/"),
-                t_now.day().to_string(),
-                String::from("-"),
-                t_now.hour().to_string(),
-                String::from(":"),
-                t_now.minute().to_string(),
-                String::from(":"),
-                t_now.second().to_string(),
-            ]
-            .join(""),
-        )
+        Local::now().format("%Y/%m/%d-%H:%M:%S").to_string()
     }
 }
 
@@ -180,19 +204,30 @@ impl log::Log for OctoLogger {
                     record.args()
                 );
             }
-            //Write to file
-            if let Ok(mut file) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(self.file.as_str())
-            {
-                if let Err(_) = writeln!(
-                    file,
-                    "{} [{}]: {}",
-                    OctoLogger::format_time(),
-                    record.level(),
-                    record.args()
-                ) {
+            //Write to file; if it couldn't be kept open, it is opened again
+            let file: Option<File> = match &self.handle {
+                Some(handle) => handle.try_clone().ok(),
+                None => OctoLogger::open_file(&self.file),
+            };
+            match file {
+                Some(mut file) => {
+                    let written = writeln!(
+                        file,
+                        "{} [{}]: {}",
+                        OctoLogger::format_time(),
+                        record.level(),
+                        record.args()
+                    );
+                    if written.is_err() && self.stdout {
+                        println!(
+                            "{} [{}]: Could not write to file {}",
+                            OctoLogger::format_time(),
+                            record.level(),
+                            self.file.as_str()
+                        );
+                    }
+                }
+                None => {
                     if self.stdout {
                         println!(
                             "{} [{}]: Could not open file {}",
@@ -203,15 +238,6 @@ impl log::Log for OctoLogger {
                     }
                 }
             }
-        } else {
-            if self.stdout {
-                println!(
-                    "{} [{}]: Could not open file {}",
-                    OctoLogger::format_time(),
-                    record.level(),
-                    self.file.as_str()
-                );
-            }
         }
     }
 
@@ -241,7 +267,7 @@ mod tests {
         let mut log_content: String = String::with_capacity(2048);
         tmp_log_file.read_to_string(&mut log_content).unwrap();
         println!("Read log file content: '{}'", log_content);
-        assert_eq!(log_content.len(), String::from("2020/2/8-17:45:35 [DEBUG]: Testing a DEBUG message\n2020/2/8-17:45:35 [INFO]: Testing a INFO message\n2020/2/8-17:45:35 [WARN]: Testing a WARN message\n2020/2/8-17:45:35 [ERROR]: Testing a ERROR message\n").len());
+        assert_eq!(log_content.len(), String::from("2020/02/08-17:45:35 [DEBUG]: Testing a DEBUG message\n2020/02/08-17:45:35 [INFO]: Testing a INFO message\n2020/02/08-17:45:35 [WARN]: Testing a WARN message\n2020/02/08-17:45:35 [ERROR]: Testing a ERROR message\n").len());
     }
 
     #[test]
@@ -262,7 +288,41 @@ mod tests {
         let mut log_content: String = String::with_capacity(2048);
         tmp_log_file.read_to_string(&mut log_content).unwrap();
         println!("Read log file content: '{}'", log_content);
-        assert_eq!(log_content.len(), String::from("2020/2/8-17:45:35 [WARN]: Testing a WARN message\n2020/2/8-17:45:35 [ERROR]: Testing a ERROR message\n").len());
+        assert_eq!(log_content.len(), String::from("2020/02/08-17:45:35 [WARN]: Testing a WARN message\n2020/02/08-17:45:35 [ERROR]: Testing a ERROR message\n").len());
+    }
+
+    #[test]
+    fn test_logger_set_level() {
+        let mut tmp_log_file: tempfile::NamedTempFile = generate_log_file();
+        let tmp_log_file_path: String = String::from(tmp_log_file.path().to_str().unwrap());
+        let mut logger: OctoLogger = OctoLogger::new(true, OctoLogLevel::Error, tmp_log_file_path, false);
+        logger.debug(format_args!("Testing a {} message", "DEBUG"));
+        //Lower level at runtime
+        logger.set_level(true, OctoLogLevel::Debug);
+        logger.debug(format_args!("Testing a {} message", "DEBUG"));
+        //Disable logger
+        logger.set_level(false, OctoLogLevel::Debug);
+        logger.error(format_args!("Testing a {} message", "ERROR"));
+        let mut log_content: String = String::with_capacity(2048);
+        tmp_log_file.read_to_string(&mut log_content).unwrap();
+        assert_eq!(log_content.len(), String::from("2020/02/08-17:45:35 [DEBUG]: Testing a DEBUG message\n").len());
+    }
+
+    #[test]
+    fn test_logger_reconfigure() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let log_file: String = tmpdir.path().join("octopipes.log").display().to_string();
+        let mut logger: OctoLogger = OctoLogger::new(true, OctoLogLevel::Info, log_file.clone(), false);
+        //Log file is kept open, even if it is removed
+        std::fs::remove_file(&log_file).unwrap();
+        logger.reconfigure(true, OctoLogLevel::Debug, log_file.clone(), false);
+        logger.debug(format_args!("Testing a {} message", "DEBUG"));
+        assert!(!std::path::Path::new(&log_file).exists());
+        //Another file is opened
+        let new_log_file: String = tmpdir.path().join("new.log").display().to_string();
+        logger.reconfigure(true, OctoLogLevel::Debug, new_log_file.clone(), false);
+        logger.debug(format_args!("Testing a {} message", "DEBUG"));
+        assert_eq!(std::fs::read_to_string(&new_log_file).unwrap().len(), String::from("2020/02/08-17:45:35 [DEBUG]: Testing a DEBUG message\n").len());
     }
 
     /// ### write_config_file
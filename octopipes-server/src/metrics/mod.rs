@@ -0,0 +1,817 @@
+//! ### metrics
+//!
+//! `metrics` is the module which collects the metrics of the server and exposes them in the Prometheus text format,
+//! through a local HTTP listener or a file rewritten periodically
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write as FmtWrite};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+//Upper bounds of the routing latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+//Requests whose header is longer than this are refused and the connection is closed
+const MAX_REQUEST_SIZE: usize = 8192;
+//Connections beyond this are closed as soon as they're accepted
+const MAX_CONNECTIONS: usize = 16;
+//Connections which neither send their request nor read the response for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// ### Metrics
+///
+/// `Metrics` contains the metrics collected by the server which aren't plain counters of the server stats:
+/// traffic by client and by group, CAP requests and routing latency
+#[derive(Default)]
+pub struct Metrics {
+    bytes_routed: u64,
+    clients: BTreeMap<String, ClientMetrics>,
+    groups: BTreeMap<String, Traffic>,
+    //Requests on the CAP by (type, result)
+    cap_requests: BTreeMap<(&'static str, &'static str), u64>,
+    routing_latency: Histogram,
+}
+
+/// ### ClientMetrics
+///
+/// `ClientMetrics` contains the traffic of a subscribed client
+#[derive(Default)]
+struct ClientMetrics {
+    sent: Traffic,
+    received: Traffic,
+}
+
+//Reads one of the counters of a client
+type ClientCounter = fn(&ClientMetrics) -> u64;
+
+/// ### Traffic
+///
+/// `Traffic` counts messages and their size
+#[derive(Default)]
+struct Traffic {
+    messages: u64,
+    bytes: u64,
+}
+
+/// ### Histogram
+///
+/// `Histogram` counts the observations falling in each of the latency buckets
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// ### Exposition
+///
+/// `Exposition` builds a page in the Prometheus text format
+#[derive(Default)]
+pub struct Exposition {
+    out: String,
+}
+
+/// ### MetricsListener
+///
+/// `MetricsListener` is the HTTP listener serving the metrics, with its connections, each one identified by a token
+pub struct MetricsListener {
+    listener: TcpListener,
+    connections: HashMap<u64, MetricsConnection>,
+}
+
+/// ### MetricsConnection
+///
+/// `MetricsConnection` is a client connected to the HTTP listener
+struct MetricsConnection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    //Response which hasn't been written yet
+    out: Vec<u8>,
+    //Whether the request has already been replied
+    replied: bool,
+    last_activity: Instant,
+}
+
+/// ### HttpRequest
+///
+/// `HttpRequest` is the request line of an HTTP request received by the listener
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+}
+
+impl Metrics {
+    /// ### new
+    ///
+    /// Instantiates a new empty Metrics
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// ### bytes_routed
+    ///
+    /// `bytes_routed` returns the size of all the messages routed
+    pub fn bytes_routed(&self) -> u64 {
+        self.bytes_routed
+    }
+
+    /// ### account_routed
+    ///
+    /// `account_routed` accounts a message of `bytes` routed from the sender to the remote group.
+    /// Messages sent by the server itself have no sender
+    pub fn account_routed(&mut self, sender: &str, remote: &str, bytes: usize) {
+        self.bytes_routed += bytes as u64;
+        if !sender.is_empty() {
+            self.clients
+                .entry(String::from(sender))
+                .or_default()
+                .sent
+                .add(bytes);
+        }
+        self.groups
+            .entry(String::from(remote))
+            .or_default()
+            .add(bytes);
+    }
+
+    /// ### account_delivered
+    ///
+    /// `account_delivered` accounts a message of `bytes` written on the client's RX pipe, `latency` after it has been queued
+    pub fn account_delivered(&mut self, client: &str, bytes: usize, latency: Duration) {
+        self.clients
+            .entry(String::from(client))
+            .or_default()
+            .received
+            .add(bytes);
+        self.routing_latency.observe(latency);
+    }
+
+    /// ### account_cap_request
+    ///
+    /// `account_cap_request` accounts a request received on the CAP with its result
+    pub fn account_cap_request(&mut self, request: &'static str, result: &'static str) {
+        *self.cap_requests.entry((request, result)).or_default() += 1;
+    }
+
+    /// ### remove_client
+    ///
+    /// `remove_client` forgets the traffic of a client which has gone away
+    pub fn remove_client(&mut self, client: &str) {
+        self.clients.remove(client);
+    }
+
+    /// ### render
+    ///
+    /// `render` writes the metrics to the exposition
+    pub fn render(&self, exposition: &mut Exposition) {
+        exposition.family(
+            "octopipes_bytes_routed_total",
+            "counter",
+            "Bytes of the routed messages",
+        );
+        exposition.sample("octopipes_bytes_routed_total", &[], self.bytes_routed);
+        let client_families: [(&str, &str, ClientCounter); 4] = [
+            (
+                "octopipes_client_sent_messages_total",
+                "Messages sent by the client",
+                |client| client.sent.messages,
+            ),
+            (
+                "octopipes_client_sent_bytes_total",
+                "Bytes sent by the client",
+                |client| client.sent.bytes,
+            ),
+            (
+                "octopipes_client_received_messages_total",
+                "Messages delivered to the client",
+                |client| client.received.messages,
+            ),
+            (
+                "octopipes_client_received_bytes_total",
+                "Bytes delivered to the client",
+                |client| client.received.bytes,
+            ),
+        ];
+        for (name, help, value) in client_families.iter() {
+            exposition.family(name, "counter", help);
+            for (client, metrics) in self.clients.iter() {
+                exposition.sample(name, &[("client", client)], value(metrics));
+            }
+        }
+        exposition.family(
+            "octopipes_group_messages_total",
+            "counter",
+            "Messages sent to the group",
+        );
+        for (group, traffic) in self.groups.iter() {
+            exposition.sample(
+                "octopipes_group_messages_total",
+                &[("group", group)],
+                traffic.messages,
+            );
+        }
+        exposition.family(
+            "octopipes_group_bytes_total",
+            "counter",
+            "Bytes sent to the group",
+        );
+        for (group, traffic) in self.groups.iter() {
+            exposition.sample(
+                "octopipes_group_bytes_total",
+                &[("group", group)],
+                traffic.bytes,
+            );
+        }
+        exposition.family(
+            "octopipes_cap_requests_total",
+            "counter",
+            "Requests received on the CAP, by type and result",
+        );
+        for ((request, result), value) in self.cap_requests.iter() {
+            exposition.sample(
+                "octopipes_cap_requests_total",
+                &[("type", request), ("result", result)],
+                value,
+            );
+        }
+        exposition.family(
+            "octopipes_routing_latency_seconds",
+            "histogram",
+            "Time elapsed from routing a message to writing it on the recipient's RX pipe",
+        );
+        self.routing_latency
+            .render("octopipes_routing_latency_seconds", exposition);
+    }
+}
+
+impl Traffic {
+    /// ### add
+    ///
+    /// `add` accounts a message of `bytes`
+    fn add(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            buckets: [0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    /// ### observe
+    ///
+    /// `observe` accounts an observation in the buckets it falls in
+    fn observe(&mut self, value: Duration) {
+        let seconds: f64 = value.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    /// ### render
+    ///
+    /// `render` writes the cumulative buckets, the sum and the count of the histogram
+    fn render(&self, name: &str, exposition: &mut Exposition) {
+        let bucket_name: String = format!("{}_bucket", name);
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            exposition.sample(&bucket_name, &[("le", &bound.to_string())], bucket);
+        }
+        exposition.sample(&bucket_name, &[("le", "+Inf")], self.count);
+        exposition.sample(&format!("{}_sum", name), &[], self.sum);
+        exposition.sample(&format!("{}_count", name), &[], self.count);
+    }
+}
+
+impl Exposition {
+    /// ### new
+    ///
+    /// Instantiates a new empty Exposition
+    pub fn new() -> Exposition {
+        Exposition::default()
+    }
+
+    /// ### family
+    ///
+    /// `family` starts a metric family, writing its help and its type
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    /// ### sample
+    ///
+    /// `sample` writes a sample of the current family with its labels
+    pub fn sample<T: fmt::Display>(&mut self, name: &str, labels: &[(&str, &str)], value: T) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    /// ### into_string
+    ///
+    /// `into_string` returns the page
+    pub fn into_string(self) -> String {
+        self.out
+    }
+}
+
+impl MetricsListener {
+    /// ### bind
+    ///
+    /// `bind` starts listening for HTTP requests on the provided address
+    pub fn bind(address: &str) -> std::io::Result<MetricsListener> {
+        let address: SocketAddr = address
+            .parse::<SocketAddr>()
+            .map_err(|_| std::io::Error::from(ErrorKind::InvalidInput))?;
+        let listener: TcpListener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(MetricsListener {
+            listener,
+            connections: HashMap::new(),
+        })
+    }
+
+    /// ### local_addr
+    ///
+    /// `local_addr` returns the address the listener is bound to
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// ### accept
+    ///
+    /// `accept` accepts a pending connection, if any. The connection must then be added with `add_connection`.
+    /// Once the maximum amount of connections has been reached, the new connections are closed right away
+    pub fn accept(&mut self) -> Option<TcpStream> {
+        loop {
+            match self.listener.accept() {
+                Ok(_) if self.connections.len() >= MAX_CONNECTIONS => continue,
+                Ok((stream, _)) => {
+                    return match stream.set_nonblocking(true) {
+                        Ok(_) => Some(stream),
+                        Err(_) => None,
+                    }
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// ### add_connection
+    ///
+    /// `add_connection` starts tracking a connection with the provided token
+    pub fn add_connection(&mut self, token: u64, stream: TcpStream) {
+        self.connections.insert(
+            token,
+            MetricsConnection {
+                stream,
+                buffer: Vec::new(),
+                out: Vec::new(),
+                replied: false,
+                last_activity: Instant::now(),
+            },
+        );
+    }
+
+    /// ### has_connection
+    ///
+    /// `has_connection` returns whether the token belongs to a connection of the listener
+    pub fn has_connection(&self, token: u64) -> bool {
+        self.connections.contains_key(&token)
+    }
+
+    /// ### connection_fd
+    ///
+    /// `connection_fd` returns the file descriptor of the connection
+    pub fn connection_fd(&self, token: u64) -> Option<RawFd> {
+        self.connections
+            .get(&token)
+            .map(|connection| connection.stream.as_raw_fd())
+    }
+
+    /// ### read
+    ///
+    /// `read` reads the available data on the connection and returns the request, once its header is complete.
+    /// If the connection has been closed by the peer, or the request is malformed, an error is returned; the connection must then be closed.
+    /// Once the request has been replied, no other request is returned
+    pub fn read(&mut self, token: u64) -> std::io::Result<Option<HttpRequest>> {
+        let connection: &mut MetricsConnection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return Err(std::io::Error::from(ErrorKind::NotConnected)),
+        };
+        //Once the request has been replied, the data sent by the peer is discarded
+        let replied: bool = connection.replied;
+        let mut chunk: [u8; 1024] = [0; 1024];
+        loop {
+            match connection.stream.read(&mut chunk) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(_) if replied => continue,
+                Ok(bytes) => {
+                    connection.buffer.extend_from_slice(&chunk[..bytes]);
+                    connection.last_activity = Instant::now();
+                }
+                Err(error) => match error.kind() {
+                    ErrorKind::WouldBlock => break,
+                    ErrorKind::Interrupted => continue,
+                    _ => return Err(error),
+                },
+            }
+        }
+        if replied {
+            return Ok(None);
+        }
+        //The body of the request, if any, is ignored
+        match connection
+            .buffer
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        {
+            Some(end) => parse_request(&connection.buffer[..end])
+                .map(Some)
+                .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidData)),
+            None if connection.buffer.len() > MAX_REQUEST_SIZE => {
+                Err(std::io::Error::from(ErrorKind::InvalidData))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// ### reply
+    ///
+    /// `reply` writes the response to the request to the connection: the metrics for `GET /metrics` (or `/`), an error otherwise.
+    /// The response is written without blocking: returns whether part of it is still queued; if so, the rest must be written with `flush`
+    /// once the connection is writable. Once the response has been written, the connection must be closed
+    pub fn reply(
+        &mut self,
+        token: u64,
+        request: &HttpRequest,
+        metrics: &str,
+    ) -> std::io::Result<bool> {
+        let connection: &mut MetricsConnection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return Err(std::io::Error::from(ErrorKind::NotConnected)),
+        };
+        let (status, body): (&str, &str) = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") | ("GET", "/") => ("200 OK", metrics),
+            ("GET", _) => ("404 Not Found", "Not Found\n"),
+            _ => ("405 Method Not Allowed", "Method Not Allowed\n"),
+        };
+        let response: String = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            CONTENT_TYPE,
+            body.len(),
+            body
+        );
+        connection.out = response.into_bytes();
+        connection.replied = true;
+        self.flush(token)
+    }
+
+    /// ### flush
+    ///
+    /// `flush` writes the queued response on the connection, until it can't take more data.
+    /// Returns whether part of the response is still queued
+    pub fn flush(&mut self, token: u64) -> std::io::Result<bool> {
+        let connection: &mut MetricsConnection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return Err(std::io::Error::from(ErrorKind::NotConnected)),
+        };
+        while !connection.out.is_empty() {
+            match connection.stream.write(&connection.out) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero)),
+                Ok(bytes) => {
+                    connection.out.drain(..bytes);
+                    connection.last_activity = Instant::now();
+                }
+                Err(error) => match error.kind() {
+                    ErrorKind::WouldBlock => break,
+                    ErrorKind::Interrupted => continue,
+                    _ => return Err(error),
+                },
+            }
+        }
+        Ok(!connection.out.is_empty())
+    }
+
+    /// ### idle
+    ///
+    /// `idle` returns the tokens of the connections which have been idle for too long; they must be closed
+    pub fn idle(&self) -> Vec<u64> {
+        self.connections
+            .iter()
+            .filter(|(_, connection)| connection.last_activity.elapsed() >= IDLE_TIMEOUT)
+            .map(|(token, _)| *token)
+            .collect()
+    }
+
+    /// ### next_idle_check
+    ///
+    /// `next_idle_check` returns when the first connection will have been idle for too long, if any
+    pub fn next_idle_check(&self) -> Option<Instant> {
+        self.connections
+            .values()
+            .map(|connection| connection.last_activity + IDLE_TIMEOUT)
+            .min()
+    }
+
+    /// ### close
+    ///
+    /// `close` closes the connection
+    pub fn close(&mut self, token: u64) {
+        self.connections.remove(&token);
+    }
+
+    /// ### tokens
+    ///
+    /// `tokens` returns the tokens of all the connections
+    pub fn tokens(&self) -> Vec<u64> {
+        self.connections.keys().cloned().collect()
+    }
+}
+
+impl AsRawFd for MetricsListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+/// ### write_file
+///
+/// `write_file` writes the content (the metrics or the status of the server) to the file,
+/// replacing it atomically so that readers never find it half written
+pub fn write_file(path: &str, content: &str) -> std::io::Result<()> {
+    let file_name: String = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidInput))?;
+    let tmp_path: String = Path::new(path)
+        .with_file_name(format!(".{}.tmp", file_name))
+        .display()
+        .to_string();
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// ### parse_request
+///
+/// `parse_request` parses the request line of an HTTP request header
+fn parse_request(header: &[u8]) -> Option<HttpRequest> {
+    let header: &str = std::str::from_utf8(header).ok()?;
+    let mut tokens = header.lines().next()?.split(' ');
+    let method: &str = tokens.next()?;
+    let path: &str = tokens.next()?;
+    match tokens.next() {
+        Some(version) if version.starts_with("HTTP/") => Some(HttpRequest {
+            method: String::from(method),
+            //Query string is ignored
+            path: String::from(path.split('?').next().unwrap_or(path)),
+        }),
+        _ => None,
+    }
+}
+
+/// ### escape_label
+///
+/// `escape_label` escapes backslashes, double quotes and line feeds in a label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_metrics_render() {
+        let mut metrics: Metrics = Metrics::new();
+        metrics.account_routed("foo", "sensors/temp", 20);
+        metrics.account_routed("foo", "sensors/temp", 30);
+        metrics.account_routed("", "$SYS/clients/subscribed", 10);
+        metrics.account_delivered("bar", 20, Duration::from_micros(300));
+        metrics.account_delivered("bar", 30, Duration::from_millis(20));
+        metrics.account_cap_request("subscription", "ok");
+        metrics.account_cap_request("subscription", "ok");
+        metrics.account_cap_request("subscription", "name_already_taken");
+        let mut exposition: Exposition = Exposition::new();
+        metrics.render(&mut exposition);
+        let page: String = exposition.into_string();
+        let lines: Vec<&str> = page.lines().collect();
+        assert!(lines.contains(&"# TYPE octopipes_bytes_routed_total counter"));
+        assert!(lines.contains(&"octopipes_bytes_routed_total 60"));
+        assert!(lines.contains(&"octopipes_client_sent_messages_total{client=\"foo\"} 2"));
+        assert!(lines.contains(&"octopipes_client_sent_bytes_total{client=\"foo\"} 50"));
+        assert!(lines.contains(&"octopipes_client_received_messages_total{client=\"bar\"} 2"));
+        assert!(lines.contains(&"octopipes_client_received_bytes_total{client=\"bar\"} 50"));
+        assert!(lines.contains(&"octopipes_client_sent_messages_total{client=\"bar\"} 0"));
+        assert!(lines.contains(&"octopipes_group_messages_total{group=\"sensors/temp\"} 2"));
+        assert!(
+            lines.contains(&"octopipes_group_bytes_total{group=\"$SYS/clients/subscribed\"} 10")
+        );
+        assert!(
+            lines.contains(&"octopipes_cap_requests_total{type=\"subscription\",result=\"ok\"} 2")
+        );
+        assert!(lines.contains(
+            &"octopipes_cap_requests_total{type=\"subscription\",result=\"name_already_taken\"} 1"
+        ));
+        //Histogram buckets are cumulative
+        assert!(lines.contains(&"# TYPE octopipes_routing_latency_seconds histogram"));
+        assert!(lines.contains(&"octopipes_routing_latency_seconds_bucket{le=\"0.0005\"} 1"));
+        assert!(lines.contains(&"octopipes_routing_latency_seconds_bucket{le=\"0.01\"} 1"));
+        assert!(lines.contains(&"octopipes_routing_latency_seconds_bucket{le=\"0.025\"} 2"));
+        assert!(lines.contains(&"octopipes_routing_latency_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(lines.contains(&"octopipes_routing_latency_seconds_count 2"));
+        //Clients which have gone away are forgotten
+        metrics.remove_client("foo");
+        let mut exposition: Exposition = Exposition::new();
+        metrics.render(&mut exposition);
+        assert!(!exposition.into_string().contains("client=\"foo\""));
+    }
+
+    #[test]
+    fn test_metrics_exposition() {
+        let mut exposition: Exposition = Exposition::new();
+        exposition.family("octopipes_clients", "gauge", "Subscribed clients");
+        exposition.sample("octopipes_clients", &[], 2);
+        exposition.sample("octopipes_queue_depth", &[("client", "a\"b\\c\nd")], 1);
+        assert_eq!(
+            exposition.into_string(),
+            "# HELP octopipes_clients Subscribed clients\n# TYPE octopipes_clients gauge\noctopipes_clients 2\noctopipes_queue_depth{client=\"a\\\"b\\\\c\\nd\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_metrics_listener() {
+        let mut listener: MetricsListener = MetricsListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        assert!(MetricsListener::bind("localhost").is_err());
+        let requests: [(&str, &str); 3] = [
+            (
+                "GET /metrics?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+            ),
+            ("GET /foo HTTP/1.0\r\n\r\n", "HTTP/1.1 404 Not Found"),
+            (
+                "POST /metrics HTTP/1.1\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed",
+            ),
+        ];
+        for (token, (request, status)) in requests.iter().enumerate() {
+            let mut client: TcpStream = TcpStream::connect(address).unwrap();
+            let mut stream: Option<TcpStream> = None;
+            for _ in 0..100 {
+                stream = listener.accept();
+                if stream.is_some() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            listener.add_connection(token as u64, stream.unwrap());
+            assert!(listener.has_connection(token as u64));
+            //Incomplete request
+            let (head, tail): (&str, &str) = request.split_at(10);
+            client.write_all(head.as_bytes()).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(listener.read(token as u64).unwrap(), None);
+            client.write_all(tail.as_bytes()).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            let request: HttpRequest = listener.read(token as u64).unwrap().unwrap();
+            assert!(!listener
+                .reply(token as u64, &request, "octopipes_clients 0\n")
+                .unwrap());
+            //Once replied, the connection isn't read anymore
+            client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            assert_eq!(listener.read(token as u64).unwrap(), None);
+            listener.close(token as u64);
+            let mut response: String = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with(status));
+            assert_eq!(response.ends_with("octopipes_clients 0\n"), token == 0);
+        }
+        //Bad request
+        assert_eq!(parse_request(b"GET /metrics"), None);
+        assert_eq!(parse_request(b"hello"), None);
+        assert_eq!(
+            parse_request(b"GET /metrics HTTP/1.1\r\nHost: localhost"),
+            Some(HttpRequest {
+                method: String::from("GET"),
+                path: String::from("/metrics")
+            })
+        );
+    }
+
+    #[test]
+    fn test_metrics_listener_limits() {
+        let mut listener: MetricsListener = MetricsListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let mut clients: Vec<TcpStream> = Vec::new();
+        for token in 0..MAX_CONNECTIONS {
+            clients.push(TcpStream::connect(address).unwrap());
+            let mut stream: Option<TcpStream> = None;
+            for _ in 0..100 {
+                stream = listener.accept();
+                if stream.is_some() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            listener.add_connection(token as u64, stream.unwrap());
+        }
+        //Connections beyond the limit are closed
+        let mut client: TcpStream = TcpStream::connect(address).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(listener.accept().is_none());
+        let mut data: [u8; 16] = [0; 16];
+        assert_eq!(client.read(&mut data).unwrap(), 0);
+        //Responses which don't fit in the socket buffer are queued
+        clients[0]
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let request: HttpRequest = listener.read(0).unwrap().unwrap();
+        let page: String = "#".repeat(16 * 1024 * 1024);
+        assert!(listener.reply(0, &request, &page).unwrap());
+        let mut data: [u8; 1024] = [0; 1024];
+        clients[0].read_exact(&mut data).unwrap();
+        assert!(listener.flush(0).unwrap());
+        //Idle connections
+        assert!(listener.idle().is_empty());
+        assert!(listener.next_idle_check().unwrap() > Instant::now());
+        listener.connections.get_mut(&1).unwrap().last_activity -= IDLE_TIMEOUT;
+        assert_eq!(listener.idle(), vec![1]);
+        for token in listener.tokens() {
+            listener.close(token);
+        }
+        assert_eq!(listener.next_idle_check(), None);
+    }
+
+    #[test]
+    fn test_metrics_write_file() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("octopipes.prom").display().to_string();
+        assert!(write_file(&path, "octopipes_clients 1\n").is_ok());
+        assert!(write_file(&path, "octopipes_clients 2\n").is_ok());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "octopipes_clients 2\n");
+        //Temporary file has been renamed
+        assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 1);
+        assert!(write_file(
+            &tmpdir
+                .path()
+                .join("nodir/octopipes.prom")
+                .display()
+                .to_string(),
+            ""
+        )
+        .is_err());
+    }
+}
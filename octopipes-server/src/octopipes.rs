@@ -25,62 +25,300 @@
 // SOFTWARE.
 //
 
-extern crate ctrlc;
 extern crate getopts;
-extern crate rustypipes;
+extern crate libc;
+extern crate octopipes_server;
 
 const OCTOPIPES_SERVER_VERSION: &str = "0.1.0";
 
 use getopts::Options;
-use rustypipes::{OctopipesProtocolVersion, OctopipesServer};
+use octopipes_server::capture::CaptureFilter;
+use octopipes_server::protocol::ProtocolVersion;
+use octopipes_server::server::{OctoServer, ServerEvent};
+use octopipes_server::store::MessageStore;
+use octopipes_server::{config, daemon, logger, poller, process};
+use std::collections::HashMap;
 use std::env;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::sync::mpsc;
+use std::path::Path;
+use std::time::Duration;
 
-mod config;
-mod logger;
-
-fn print_usage(program: &String, opts: Options) {
+fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} [options]", program);
     print!("{}", opts.usage(&brief));
 }
 
-fn write_pid(pid_file: String, pid: u32) -> Result<u32, std::io::Error> {
-    match OpenOptions::new().create(true).write(true).open(pid_file) {
-        Ok(mut file_hnd) => {
-            //Write pid to file
-            if let Err(ioerr) = write!(file_hnd, "{}", pid) {
-                return Err(ioerr);
+fn int_to_protocol_version(version: u8) -> ProtocolVersion {
+    match ProtocolVersion::from_u8(version) {
+        Some(version) => version,
+        None => ProtocolVersion::Version1,
+    }
+}
+
+/// ### format_mode
+///
+/// Format an optional file mode for the configuration dump
+fn format_mode(mode: Option<u32>) -> String {
+    match mode {
+        Some(mode) => format!("{:04o}", mode),
+        None => String::from("unset"),
+    }
+}
+
+/// ### format_id
+///
+/// Format an optional uid or gid for the configuration dump
+fn format_id(id: Option<u32>) -> String {
+    match id {
+        Some(id) => id.to_string(),
+        None => String::from("unset"),
+    }
+}
+
+/// ### reload_configuration
+///
+/// Resolve the configuration again, apply the reloadable settings and report the ones which require a restart.
+/// Returns whether the configuration has been reloaded
+fn reload_configuration(
+    log: &mut logger::OctoLogger,
+    cli_overrides: &config::CliOverrides,
+    octopipes_cfg: &mut config::Config,
+    (uid, gid): (Option<u32>, Option<u32>),
+    chroot_dir: Option<&str>,
+) -> bool {
+    //The configuration file, the users and groups database and the log file may be outside the root directory
+    if let Some(root) = chroot_dir {
+        log.error(format_args!(
+            "Could not reload configuration: the server runs in {}, restart it to apply the changes",
+            root
+        ));
+        return false;
+    }
+    let environment: HashMap<String, String> = env::vars().collect();
+    match config::Settings::resolve(cli_overrides, &environment) {
+        Ok(settings) => {
+            let conflicts: Vec<&str> = settings.config.privilege_conflicts(uid, gid, false);
+            if !conflicts.is_empty() {
+                log.error(format_args!(
+                    "Could not reload configuration: {} can't be applied after dropping privileges",
+                    conflicts.join(", ")
+                ));
+                return false;
             }
-            Ok(pid)
+            let restart_required: Vec<&str> = octopipes_cfg.reload(settings.config);
+            let log_config: &config::LogConfig = &octopipes_cfg.log_config;
+            //Log file is kept open if not changed, since it may not be accessible anymore
+            log.reconfigure(
+                log_config.log_level != 0,
+                logger::OctoLogLevel::from_int(log_config.log_level),
+                log_config.log_file.clone(),
+                log_config.stdout,
+            );
+            log.info(format_args!("Configuration reloaded"));
+            for key in restart_required.iter() {
+                log.warn(format_args!(
+                    "'{}' has changed, but it requires a restart to be applied",
+                    key
+                ));
+            }
+            true
+        }
+        Err(error) => {
+            log.error(format_args!(
+                "Could not reload configuration: {} ({:?})",
+                error.message, error.code
+            ));
+            false
         }
-        Err(io_err) => return Err(io_err),
     }
 }
 
-fn int_to_protocol_version(version: u8) -> OctopipesProtocolVersion {
-    match version {
-        1 => OctopipesProtocolVersion::Version1,
-        _ => OctopipesProtocolVersion::Version1,
+/// ### log_event
+///
+/// Report a server event to the log
+fn log_event(log: &logger::OctoLogger, octopipes_server: &OctoServer, event: &ServerEvent) {
+    match event {
+        ServerEvent::Subscribed(client, groups) => {
+            log.info(format_args!(
+                "Client '{}' subscribed to {:?}",
+                client, groups
+            ));
+            for client in octopipes_server.get_clients() {
+                log.debug(format_args!(
+                    "Client '{}' is subscribed to {:?}",
+                    client,
+                    octopipes_server.get_subscriptions(&client)
+                ));
+            }
+        }
+        ServerEvent::SubscriptionRefused(client, error) => {
+            log.warn(format_args!(
+                "Refused subscription from '{}': {}",
+                client, error
+            ));
+        }
+        ServerEvent::Unsubscribed(client) => {
+            log.info(format_args!("Client '{}' unsubscribed", client));
+        }
+        ServerEvent::Routed(origin, remote, recipients) => {
+            log.debug(format_args!(
+                "Routed message from '{}' to '{}' ({} recipients)",
+                origin, remote, recipients
+            ));
+        }
+        ServerEvent::MessageExpired(client) => {
+            log.warn(format_args!(
+                "Message to '{}' has expired before being delivered",
+                client
+            ));
+        }
+        ServerEvent::MessageDropped(client) => {
+            log.warn(format_args!(
+                "Message to '{}' has been dropped, since its queue is full",
+                client
+            ));
+        }
+        ServerEvent::Overflowed(client) => {
+            log.warn(format_args!(
+                "Client '{}' has been unsubscribed, since its queue is full",
+                client
+            ));
+        }
+        ServerEvent::DeliveryFailed(client, error) => {
+            log.warn(format_args!(
+                "Could not deliver message to {}: {}",
+                client, error
+            ));
+        }
+        ServerEvent::CapFailed(error) => {
+            log.warn(format_args!("Could not serve request on CAP: {}", error));
+        }
+        ServerEvent::ClientFailed(client, error) => {
+            log.warn(format_args!(
+                "Could not process request from {}: {}",
+                client, error
+            ));
+        }
+        ServerEvent::Kicked(client) => {
+            log.info(format_args!("Client '{}' has been kicked", client));
+        }
+        ServerEvent::LogLevelChanged(level) => {
+            log.info(format_args!("Log level changed to {}", level));
+        }
+        ServerEvent::ReloadRequested => {}
+        ServerEvent::ShutdownNotified(client) => {
+            log.debug(format_args!("Shutdown notice delivered to '{}'", client));
+        }
+        ServerEvent::ClientDied(client, cause) => {
+            log.warn(format_args!(
+                "Client '{}' is dead ({}), it has been unsubscribed",
+                client, cause
+            ));
+        }
+        ServerEvent::AccessDenied(client, access) => {
+            log.warn(format_args!(
+                "Access denied: '{}' is not allowed to {}",
+                client, access
+            ));
+        }
+        ServerEvent::Reassigned(client, member) => {
+            log.debug(format_args!(
+                "Message to '{}' has been handed to '{}', member of the same queue group",
+                client, member
+            ));
+        }
+        ServerEvent::RetainedDelivered(client, messages) => {
+            log.debug(format_args!(
+                "{} retained messages have been queued for '{}'",
+                messages, client
+            ));
+        }
+        ServerEvent::Stored(client, messages) => {
+            log.debug(format_args!(
+                "{} messages have been stored for '{}', which is offline",
+                messages, client
+            ));
+        }
+        ServerEvent::Replayed(client, messages) => {
+            log.info(format_args!(
+                "{} stored messages have been queued for '{}'",
+                messages, client
+            ));
+        }
+        ServerEvent::DeadLettered(origin, remote, reason) => {
+            log.debug(format_args!(
+                "Message from '{}' to '{}' couldn't be delivered ({}): republished to the dead-letter group",
+                origin, remote, reason
+            ));
+        }
+        ServerEvent::MetricsFailed(err) => {
+            log.warn(format_args!("Could not write metrics file: {}", err));
+        }
+        ServerEvent::StatusFailed(err) => {
+            log.warn(format_args!("Could not write status file: {}", err));
+        }
+        ServerEvent::CaptureFailed(err) => {
+            log.error(format_args!("{}: capture stopped", err));
+        }
+        ServerEvent::Notification(_) => {}
     }
 }
 
+/// ### configure_server
+///
+/// Apply the reloadable settings to the octopipes server
+fn configure_server(octopipes_server: &mut OctoServer, octopipes_cfg: &config::Config) {
+    let server_config: &config::ServerConfig = &octopipes_cfg.server_config;
+    octopipes_server.set_default_ttl(Duration::from_secs(
+        octopipes_cfg.protocol_config.default_ttl,
+    ));
+    octopipes_server.set_dead_client_detection(
+        Duration::from_millis(server_config.dead_client_check_interval),
+        server_config.max_missed_deliveries,
+    );
+    octopipes_server.set_queue_policy(
+        server_config.queue_size as usize,
+        server_config.overflow_policy,
+    );
+    octopipes_server.set_heartbeat(
+        Duration::from_millis(server_config.heartbeat_interval),
+        server_config.heartbeat_missed_beats,
+    );
+    octopipes_server.set_queue_groups(
+        server_config.queue_group_policy,
+        octopipes_cfg.queue_groups.clone(),
+    );
+    octopipes_server.set_retained_groups(octopipes_cfg.retained_groups.clone());
+    octopipes_server.set_dead_letter_group(server_config.dead_letter_group.clone());
+    octopipes_server.set_system_events(server_config.system_events);
+    let durable_config: &config::DurableConfig = &octopipes_cfg.durable_config;
+    octopipes_server.set_durable_clients(
+        durable_config.clients.clone(),
+        durable_config.max_size,
+        Duration::from_secs(durable_config.max_age),
+    );
+    octopipes_server.set_acl(octopipes_cfg.acl_config.clone());
+    octopipes_server.set_metrics_interval(Duration::from_millis(
+        octopipes_cfg.metrics_config.interval,
+    ));
+    octopipes_server.set_status_interval(Duration::from_millis(
+        octopipes_cfg.status_config.interval,
+    ));
+    octopipes_server.set_status_config(octopipes_cfg.effective());
+    let capture_config: &config::CaptureConfig = &octopipes_cfg.capture_config;
+    octopipes_server.set_capture_filter(CaptureFilter {
+        clients: capture_config.clients.clone(),
+        groups: capture_config.groups.clone(),
+    });
+    octopipes_server.set_capture_rotation(
+        capture_config.max_size,
+        capture_config.max_files as usize,
+    );
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program: String = args[0].clone();
-    //Program CLI options
-    //Pidfile
-    let mut pid_file: Option<String> = None;
-    //Config
-    let configuration_file: String;
-    //Pipes
-    let mut cap_path: Option<String> = None; //Overridable
-    let mut client_dir: Option<String> = None; //Overridable
-                                               //Logging
-    let mut log_level: Option<usize> = None; //Overridable
-    let mut log_file: Option<String> = None; //Overridable
-                                             //@! Get opts
+    //@! Get opts
     let mut opts = Options::new();
     opts.optopt(
         "C",
@@ -113,71 +351,74 @@ fn main() {
         "Specify the file where the pidfile will be written",
         "<pidfile>",
     );
+    opts.optflag(
+        "D",
+        "daemon",
+        "Run in background, detached from the terminal",
+    );
     opts.optflag("h", "help", "print this help menu");
     //Get options
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
-    };
-    if matches.opt_present("h") {
-        print_usage(&program, opts);
-        std::process::exit(255);
-    };
-    //Let's start with the configuration
-    match matches.opt_str("C") {
-        Some(cfg_file) => {
-            configuration_file = cfg_file;
-        }
-        None => {
-            println!("Missing Configuration file!");
+        Err(f) => {
+            println!("{}", f);
             print_usage(&program, opts);
             std::process::exit(255);
         }
     };
-    if let Some(cap) = matches.opt_str("c") {
-        cap_path = Some(cap);
-    };
-    if let Some(cli_dir) = matches.opt_str("d") {
-        client_dir = Some(cli_dir);
-    };
-    if let Some(log_level_str) = matches.opt_str("l") {
-        log_level = Some(log_level_str.parse().unwrap());
-    };
-    if let Some(lfile) = matches.opt_str("L") {
-        log_file = Some(lfile);
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        std::process::exit(255);
     };
-    if let Some(pfile) = matches.opt_str("P") {
-        pid_file = Some(pfile);
+    //CLI options have the highest priority
+    let cli_overrides: config::CliOverrides = config::CliOverrides {
+        config_file: matches.opt_str("C"),
+        cap_path: matches.opt_str("c"),
+        client_dir: matches.opt_str("d"),
+        log_level: matches.opt_str("l"),
+        log_file: matches.opt_str("L"),
+        pid_file: matches.opt_str("P"),
     };
-    //Parse configuration
-    let octopipes_cfg: config::Config =
-        match config::Config::parse_config(configuration_file.clone()) {
-            Ok(cfg) => cfg,
+    //Resolve configuration (CLI > env > YAML > defaults)
+    let environment: HashMap<String, String> = env::vars().collect();
+    let mut settings: config::Settings =
+        match config::Settings::resolve(&cli_overrides, &environment) {
+            Ok(settings) => settings,
             Err(error) => {
-                panic!(
-                    "Could not parse configuration file '{}': {} ({:?})",
-                    configuration_file, error.message, error.code
+                println!(
+                    "Could not resolve configuration: {} ({:?})",
+                    error.message, error.code
                 );
+                std::process::exit(255);
             }
         };
-    //Override logger options
-    if log_file.is_none() {
-        log_file = Some(octopipes_cfg.log_config.log_file);
-    };
-    if log_level.is_none() {
-        log_level = Some(octopipes_cfg.log_config.log_level);
-    };
-    let log_enabled: bool = match log_level.unwrap() {
-        0 => false,
-        _ => true,
-    };
+    let octopipes_cfg: &config::Config = &settings.config;
+    let log_enabled: bool = octopipes_cfg.log_config.log_level != 0;
     //@! Initialize logger
-    let log: logger::OctoLogger = logger::OctoLogger::new(
+    let mut log: logger::OctoLogger = logger::OctoLogger::new(
         log_enabled,
-        logger::OctoLogLevel::from_int(log_level.unwrap()),
-        String::from(log_file.as_ref().unwrap()),
+        logger::OctoLogLevel::from_int(octopipes_cfg.log_config.log_level),
+        octopipes_cfg.log_config.log_file.clone(),
         octopipes_cfg.log_config.stdout,
     );
+    //@! Lock PID file; refuse to start if another server holds it
+    let mut pid_file: Option<daemon::PidFile> = match &settings.pid_file {
+        Some(path) => match daemon::PidFile::acquire(path) {
+            Ok(pid_file) => Some(pid_file),
+            Err(err) => {
+                log.error(format_args!("Could not lock PID file {}: {}", path, err));
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    //@! Detach from terminal
+    if matches.opt_present("D") {
+        if let Err(err) = daemon::daemonize() {
+            log.error(format_args!("Could not run as daemon: {}", err));
+            std::process::exit(1);
+        }
+    }
     //Get PID and report program started
     let pid: u32 = std::process::id();
     log.info(format_args!(
@@ -185,104 +426,504 @@ fn main() {
         OCTOPIPES_SERVER_VERSION, pid
     ));
     //Dump configuration
+    match &settings.config_file {
+        Some(file) => log.debug(format_args!(
+            "config-file: {} ({})",
+            file,
+            settings.source("config-file")
+        )),
+        None => log.debug(format_args!("config-file: none (using defaults)")),
+    };
     log.debug(format_args!("Logging configuration"));
     log.debug(format_args!("log-enabled: {}", log_enabled));
-    log.debug(format_args!("log-level: {}", log_level.unwrap()));
-    log.debug(format_args!("log-file: {}", log_file.unwrap()));
     log.debug(format_args!(
-        "log-stdout: {}",
-        octopipes_cfg.log_config.stdout
+        "log-level: {} ({})",
+        octopipes_cfg.log_config.log_level,
+        settings.source("log-level")
+    ));
+    log.debug(format_args!(
+        "log-file: {} ({})",
+        octopipes_cfg.log_config.log_file,
+        settings.source("log-file")
+    ));
+    log.debug(format_args!(
+        "log-stdout: {} ({})",
+        octopipes_cfg.log_config.stdout,
+        settings.source("log-stdout")
     ));
     log.debug(format_args!("Pipes configuration"));
-    log.debug(format_args!("cap-pipe: {}", cap_path.as_ref().unwrap()));
-    log.debug(format_args!("client-dir: {}", client_dir.as_ref().unwrap()));
+    log.debug(format_args!(
+        "cap-pipe: {} ({})",
+        octopipes_cfg.pipes_config.cap_path,
+        settings.source("cap-pipe")
+    ));
+    log.debug(format_args!(
+        "client-dir: {} ({})",
+        octopipes_cfg.pipes_config.client_dir,
+        settings.source("client-dir")
+    ));
+    let pipes_config: &config::PipesConfig = &octopipes_cfg.pipes_config;
+    for (key, value) in [
+        ("cap-mode", format_mode(pipes_config.cap_mode)),
+        ("cap-owner", format_id(pipes_config.cap_owner)),
+        ("cap-group", format_id(pipes_config.cap_group)),
+        ("client-mode", format_mode(pipes_config.client_mode)),
+        ("client-owner", format_id(pipes_config.client_owner)),
+        ("client-group", format_id(pipes_config.client_group)),
+        ("client-dir-mode", format_mode(pipes_config.client_dir_mode)),
+    ]
+    .iter()
+    {
+        log.debug(format_args!("{}: {} ({})", key, value, settings.source(key)));
+    }
     log.debug(format_args!("Protocol configuration"));
     log.debug(format_args!(
-        "protocol_version: {}",
-        octopipes_cfg.protocol_config.version
+        "protocol_version: {} ({})",
+        octopipes_cfg.protocol_config.version,
+        settings.source("protocol-version")
+    ));
+    log.debug(format_args!(
+        "default-ttl: {} s ({})",
+        octopipes_cfg.protocol_config.default_ttl,
+        settings.source("default-ttl")
+    ));
+    log.debug(format_args!("Admin configuration"));
+    log.debug(format_args!(
+        "admin-enabled: {}",
+        octopipes_cfg.admin_config.enabled
+    ));
+    log.debug(format_args!(
+        "admin-socket: {} ({})",
+        octopipes_cfg.admin_config.socket,
+        settings.source("admin-socket")
+    ));
+    log.debug(format_args!("Server configuration"));
+    log.debug(format_args!(
+        "shutdown-grace-period: {} ms ({})",
+        octopipes_cfg.server_config.shutdown_grace_period,
+        settings.source("shutdown-grace-period")
+    ));
+    log.debug(format_args!(
+        "dead-client-check-interval: {} ms ({})",
+        octopipes_cfg.server_config.dead_client_check_interval,
+        settings.source("dead-client-check-interval")
+    ));
+    log.debug(format_args!(
+        "max-missed-deliveries: {} ({})",
+        octopipes_cfg.server_config.max_missed_deliveries,
+        settings.source("max-missed-deliveries")
+    ));
+    log.debug(format_args!(
+        "heartbeat-interval: {} ms ({})",
+        octopipes_cfg.server_config.heartbeat_interval,
+        settings.source("heartbeat-interval")
+    ));
+    log.debug(format_args!(
+        "heartbeat-missed-beats: {} ({})",
+        octopipes_cfg.server_config.heartbeat_missed_beats,
+        settings.source("heartbeat-missed-beats")
+    ));
+    log.debug(format_args!(
+        "queue-size: {} ({})",
+        octopipes_cfg.server_config.queue_size,
+        settings.source("queue-size")
+    ));
+    log.debug(format_args!(
+        "overflow-policy: {} ({})",
+        octopipes_cfg.server_config.overflow_policy,
+        settings.source("overflow-policy")
+    ));
+    log.debug(format_args!(
+        "queue-group-policy: {} ({})",
+        octopipes_cfg.server_config.queue_group_policy,
+        settings.source("queue-group-policy")
+    ));
+    log.debug(format_args!(
+        "user: {} ({})",
+        format_id(octopipes_cfg.server_config.user),
+        settings.source("user")
+    ));
+    log.debug(format_args!(
+        "group: {} ({})",
+        format_id(octopipes_cfg.server_config.group),
+        settings.source("group")
+    ));
+    log.debug(format_args!(
+        "chroot: {} ({})",
+        octopipes_cfg.server_config.chroot,
+        settings.source("chroot")
+    ));
+    log.debug(format_args!(
+        "dead-letter-group: {} ({})",
+        octopipes_cfg
+            .server_config
+            .dead_letter_group
+            .as_deref()
+            .unwrap_or("none"),
+        settings.source("dead-letter-group")
+    ));
+    log.debug(format_args!(
+        "system-events: {} ({})",
+        octopipes_cfg.server_config.system_events,
+        settings.source("system-events")
+    ));
+    for (group, policy) in octopipes_cfg.queue_groups.iter() {
+        log.debug(format_args!("queue-group: {} ({})", group, policy));
+    }
+    for group in octopipes_cfg.retained_groups.iter() {
+        log.debug(format_args!("retained-group: {}", group));
+    }
+    log.debug(format_args!(
+        "data-dir: {} ({})",
+        octopipes_cfg.durable_config.data_dir,
+        settings.source("data-dir")
+    ));
+    log.debug(format_args!(
+        "durable-max-size: {}",
+        octopipes_cfg.durable_config.max_size
+    ));
+    log.debug(format_args!(
+        "durable-max-age: {}",
+        octopipes_cfg.durable_config.max_age
+    ));
+    for client in octopipes_cfg.durable_config.clients.iter() {
+        log.debug(format_args!("durable-client: {}", client));
+    }
+    log.debug(format_args!(
+        "metrics-listen: {} ({})",
+        octopipes_cfg
+            .metrics_config
+            .listen
+            .as_deref()
+            .unwrap_or("none"),
+        settings.source("metrics-listen")
+    ));
+    log.debug(format_args!(
+        "metrics-file: {} ({})",
+        octopipes_cfg.metrics_config.file.as_deref().unwrap_or("none"),
+        settings.source("metrics-file")
+    ));
+    log.debug(format_args!(
+        "metrics-interval: {}",
+        octopipes_cfg.metrics_config.interval
+    ));
+    log.debug(format_args!(
+        "status-file: {} ({})",
+        octopipes_cfg.status_config.file.as_deref().unwrap_or("none"),
+        settings.source("status-file")
+    ));
+    log.debug(format_args!(
+        "status-interval: {} ({})",
+        octopipes_cfg.status_config.interval,
+        settings.source("status-interval")
+    ));
+    log.debug(format_args!(
+        "capture-file: {} ({})",
+        octopipes_cfg.capture_config.file.as_deref().unwrap_or("none"),
+        settings.source("capture-file")
+    ));
+    for client in octopipes_cfg.capture_config.clients.iter() {
+        log.debug(format_args!("capture-client: {}", client));
+    }
+    for group in octopipes_cfg.capture_config.groups.iter() {
+        log.debug(format_args!("capture-group: {}", group));
+    }
+    log.debug(format_args!(
+        "capture-max-size: {}",
+        octopipes_cfg.capture_config.max_size
+    ));
+    log.debug(format_args!(
+        "capture-max-files: {}",
+        octopipes_cfg.capture_config.max_files
+    ));
+    log.debug(format_args!("ACL configuration"));
+    log.debug(format_args!(
+        "acl-default: {}",
+        octopipes_cfg.acl_config.default
+    ));
+    log.debug(format_args!(
+        "acl-rules: {}",
+        octopipes_cfg.acl_config.rules.len()
     ));
     //@! Write PID file
-    if pid_file.is_some() {
-        if let Err(err) = write_pid(pid_file.unwrap(), pid) {
+    if let (Some(path), Some(pid_file)) = (&settings.pid_file, pid_file.as_mut()) {
+        log.debug(format_args!(
+            "pidfile: {} ({})",
+            path,
+            settings.source("pidfile")
+        ));
+        if let Err(err) = pid_file.write_pid(pid) {
             log.error(format_args!("Could not write PID to file: {}", err));
         };
     };
-    //@! Start SIGINT listener
-    let (tx_channel, rx_channel) = mpsc::channel();
-    ctrlc::set_handler(move || {
-        if let Err(..) = tx_channel.send(1) {
-            panic!("Could not send CTRL-C");
-        }
-    })
-    .expect("Error setting Ctrl-C handler");
     //@! Initialize OctopipesServer
-    let protocol_version: OctopipesProtocolVersion =
+    let protocol_version: ProtocolVersion =
         int_to_protocol_version(octopipes_cfg.protocol_config.version);
-    let mut octopipes_server: OctopipesServer =
-        OctopipesServer::new(protocol_version, cap_path.unwrap(), client_dir.unwrap());
-    log.debug(format_args!("Initialized Octopipes Server"));
-    //Start server
-    if let Err(err) = octopipes_server.start_cap_listener() {
-        log.error(format_args!("Could not start octopipes server: {}", err));
+    let mut octopipes_server: OctoServer = match OctoServer::new(
+        protocol_version,
+        octopipes_cfg.pipes_config.cap_path.clone(),
+        octopipes_cfg.pipes_config.client_dir.clone(),
+    ) {
+        Ok(server) => server,
+        Err(err) => {
+            log.error(format_args!("Could not initialize octopipes server: {}", err));
+            std::process::exit(1);
+        }
+    };
+    octopipes_server.set_store(MessageStore::new(&octopipes_cfg.durable_config.data_dir));
+    octopipes_server.set_metrics_file(octopipes_cfg.metrics_config.file.clone());
+    octopipes_server.set_status_file(octopipes_cfg.status_config.file.clone());
+    configure_server(&mut octopipes_server, octopipes_cfg);
+    //Without a group, the server runs with the primary group of its user
+    let server_config: &config::ServerConfig = &octopipes_cfg.server_config;
+    let (uid, gid): (Option<u32>, Option<u32>) = match (server_config.user, server_config.group) {
+        (uid, Some(gid)) => (uid, Some(gid)),
+        (Some(uid), None) => match process::primary_group(uid) {
+            Some(gid) => (Some(uid), Some(gid)),
+            None => {
+                log.error(format_args!(
+                    "Could not find the primary group of user {}: 'group' must be set",
+                    uid
+                ));
+                std::process::exit(1);
+            }
+        },
+        (None, None) => (None, None),
+    };
+    //Settings which need access to the other users' pipes and processes can't work without privileges
+    let conflicts: Vec<&str> =
+        octopipes_cfg.privilege_conflicts(uid, gid, octopipes_cfg.server_config.chroot);
+    if !conflicts.is_empty() {
+        log.error(format_args!(
+            "Could not drop privileges: {} can't be used by an unprivileged or chrooted server",
+            conflicts.join(", ")
+        ));
         std::process::exit(1);
     }
-    log.info(format_args!("Octopipes Server CAP listener started"));
-    //@!Main loop
-    loop {
-        //@! Process CAP message
-        match octopipes_server.process_cap_once() {
-            Ok(requests) => {
-                if requests > 0 {
-                    log.info(format_args!("Served {} requests on the CAP", requests));
-                    let clients: Vec<String> = octopipes_server.get_clients();
-                    for client in clients {
-                        log.info(format_args!(
-                            "Client '{}' is subscribed to {:?}",
-                            client,
-                            octopipes_server.get_subscriptions(client.clone())
-                        ));
-                    }
+    let (cap_ownership, client_ownership, mut client_dir_ownership) =
+        octopipes_cfg.pipes_config.ownership();
+    //The server must still be able to create the client pipes after dropping privileges
+    if let (Some(uid), None) = (uid, client_dir_ownership.owner) {
+        client_dir_ownership.owner = Some(uid);
+        client_dir_ownership.group = client_dir_ownership.group.or(gid);
+    }
+    octopipes_server.set_pipes_ownership(
+        cap_ownership,
+        client_ownership,
+        client_dir_ownership,
+    );
+    //The server is chrooted into the CAP directory, which must contain the client directory
+    let chroot_dir: Option<String> = match octopipes_cfg.server_config.chroot {
+        true => {
+            let cap_dir: &Path = Path::new(&octopipes_cfg.pipes_config.cap_path)
+                .parent()
+                .unwrap_or_else(|| Path::new("/"));
+            if !Path::new(&octopipes_cfg.pipes_config.client_dir).starts_with(cap_dir) {
+                log.error(format_args!(
+                    "Could not chroot into {}: client directory {} is outside of it",
+                    cap_dir.display(),
+                    octopipes_cfg.pipes_config.client_dir
+                ));
+                std::process::exit(1);
+            }
+            if !octopipes_cfg.durable_config.clients.is_empty()
+                && !Path::new(&octopipes_cfg.durable_config.data_dir).starts_with(cap_dir)
+            {
+                log.error(format_args!(
+                    "Could not chroot into {}: data directory {} is outside of it",
+                    cap_dir.display(),
+                    octopipes_cfg.durable_config.data_dir
+                ));
+                std::process::exit(1);
+            }
+            if let Some(metrics_file) = octopipes_cfg.metrics_config.file.as_deref() {
+                if !Path::new(metrics_file).starts_with(cap_dir) {
+                    log.error(format_args!(
+                        "Could not chroot into {}: metrics file {} is outside of it",
+                        cap_dir.display(),
+                        metrics_file
+                    ));
+                    std::process::exit(1);
                 }
             }
-            Err(error) => {
-                log.warn(format_args!("Could not serve request on CAP: {}", error));
+            if let Some(status_file) = octopipes_cfg.status_config.file.as_deref() {
+                if !Path::new(status_file).starts_with(cap_dir) {
+                    log.error(format_args!(
+                        "Could not chroot into {}: status file {} is outside of it",
+                        cap_dir.display(),
+                        status_file
+                    ));
+                    std::process::exit(1);
+                }
             }
-        };
-        //@! Process inbox
-        match octopipes_server.process_once() {
-            Ok(requests) => {
-                if requests > 0 {
-                    log.info(format_args!("Processed {} requests from clients", requests));
+            if let Some(capture_file) = octopipes_cfg.capture_config.file.as_deref() {
+                if !Path::new(capture_file).starts_with(cap_dir) {
+                    log.error(format_args!(
+                        "Could not chroot into {}: capture file {} is outside of it",
+                        cap_dir.display(),
+                        capture_file
+                    ));
+                    std::process::exit(1);
                 }
             }
-            Err((client, error)) => {
-                log.warn(format_args!(
-                    "Could not process request from {}: {}",
-                    client, error
-                ));
+            Some(cap_dir.display().to_string())
+        }
+        false => None,
+    };
+    log.debug(format_args!("Initialized Octopipes Server"));
+    //@! Start signal listeners (signals wake up the server loop)
+    //SIGINT and SIGTERM terminate the server, SIGHUP reloads the configuration
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP].iter() {
+        if let Err(err) = poller::forward_signal(*signal, &octopipes_server.notifier()) {
+            log.error(format_args!("Could not set handler for signal {}: {}", signal, err));
+            std::process::exit(1);
+        }
+    }
+    //Start server
+    match octopipes_server.start() {
+        Ok(removed_pipes) => {
+            for pipe in removed_pipes.iter() {
+                log.info(format_args!("Removed leftover client pipe {}", pipe));
             }
-        };
-        //@! Check SIGINT
-        match rx_channel.try_recv() {
-            Ok(..) => {
-                break;
+        }
+        Err(err) => {
+            log.error(format_args!("Could not start octopipes server: {}", err));
+            std::process::exit(1);
+        }
+    }
+    log.info(format_args!("Octopipes Server CAP listener started"));
+    //Start admin socket
+    if octopipes_cfg.admin_config.enabled {
+        match octopipes_server.start_admin(&octopipes_cfg.admin_config.socket) {
+            Ok(_) => log.info(format_args!(
+                "Admin socket listening on {}",
+                octopipes_cfg.admin_config.socket
+            )),
+            Err(err) => log.error(format_args!(
+                "Could not start admin socket on {}: {}",
+                octopipes_cfg.admin_config.socket, err
+            )),
+        }
+    }
+    //Start metrics listener
+    if let Some(address) = octopipes_cfg.metrics_config.listen.as_deref() {
+        match octopipes_server.start_metrics(address) {
+            Ok(_) => log.info(format_args!("Metrics served on http://{}/metrics", address)),
+            Err(err) => log.error(format_args!(
+                "Could not serve metrics on {}: {}",
+                address, err
+            )),
+        }
+    }
+    //Start recording the routed messages
+    if let Some(capture_file) = octopipes_cfg.capture_config.file.as_deref() {
+        match octopipes_server.start_capture(capture_file) {
+            Ok(_) => log.info(format_args!("Recording traffic to {}", capture_file)),
+            Err(err) => log.error(format_args!(
+                "Could not record traffic to {}: {}",
+                capture_file, err
+            )),
+        }
+    }
+    //@! Drop privileges, once the CAP and the admin socket have been created
+    let privileges_dropped: bool = uid.is_some() || gid.is_some() || chroot_dir.is_some();
+    if privileges_dropped {
+        if let Err(err) = daemon::drop_privileges(uid, gid, chroot_dir.as_deref()) {
+            log.error(format_args!("Could not drop privileges: {}", err));
+            octopipes_server.stop();
+            std::process::exit(1);
+        }
+        if let Some(root) = &chroot_dir {
+            if let Err(err) = octopipes_server.set_root(root) {
+                log.error(format_args!("Could not change root to {}: {}", root, err));
+                octopipes_server.stop();
+                std::process::exit(1);
             }
-            Err(error) => match error {
-                mpsc::TryRecvError::Empty => {
-                    //Continue
-                }
-                _ => {
-                    panic!("CTRL+C thread is dead");
+            log.info(format_args!("Root directory changed to {}", root));
+        }
+        log.info(format_args!(
+            "Running as uid {} gid {}",
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() }
+        ));
+    }
+    //@!Main loop
+    let mut terminate: bool = false;
+    while !terminate {
+        //@! Wait for requests on CAP and messages from clients
+        match octopipes_server.poll(None) {
+            Ok(events) => {
+                for event in events.iter() {
+                    match event {
+                        ServerEvent::Notification(signal) if *signal == libc::SIGHUP as u8 => {
+                            log.info(format_args!("Received SIGHUP, reloading configuration..."));
+                            if reload_configuration(
+                                &mut log,
+                                &cli_overrides,
+                                &mut settings.config,
+                                (uid, gid),
+                                chroot_dir.as_deref(),
+                            ) {
+                                configure_server(&mut octopipes_server, &settings.config);
+                            }
+                        }
+                        ServerEvent::Notification(signal) => {
+                            log.info(format_args!("Received signal {}, terminating...", signal));
+                            terminate = true;
+                        }
+                        ServerEvent::LogLevelChanged(level) => {
+                            log.set_level(*level != 0, logger::OctoLogLevel::from_int(*level));
+                        }
+                        ServerEvent::ReloadRequested => {
+                            log.info(format_args!("Reloading configuration..."));
+                            if reload_configuration(
+                                &mut log,
+                                &cli_overrides,
+                                &mut settings.config,
+                                (uid, gid),
+                                chroot_dir.as_deref(),
+                            ) {
+                                configure_server(&mut octopipes_server, &settings.config);
+                            }
+                        }
+                        _ => {}
+                    }
+                    log_event(&log, &octopipes_server, event);
                 }
-            },
+            }
+            Err(error) => {
+                log.error(format_args!("Could not poll server: {}", error));
+                terminate = true;
+            }
         };
-        //Sleep 50ms
-        std::thread::sleep(std::time::Duration::from_millis(50));
     }
-    //@! Stop server
-    if let Err(err) = octopipes_server.stop_server() {
-        log.error(format_args!("Could not stop octopipes server: {}", err));
+    //@! Stop server, routing the messages in flight
+    let grace_period: Duration =
+        Duration::from_millis(settings.config.server_config.shutdown_grace_period);
+    log.info(format_args!(
+        "Shutting down (grace period: {} ms)...",
+        grace_period.as_millis()
+    ));
+    for event in octopipes_server.shutdown(grace_period).iter() {
+        log_event(&log, &octopipes_server, event);
+    }
+    //@! Remove PID file
+    if let Some(pid_file) = pid_file {
+        //The PID file isn't unlinked before dropping privileges, or another server could lock a new file in its path while this one is running;
+        //if it can't be removed now, it's left unlocked and it will be reused at the next start
+        if chroot_dir.is_some() {
+            //Its path isn't valid inside the new root
+            log.warn(format_args!("PID file {} left in place after changing root; it will be reused at the next start", pid_file.path()));
+        } else {
+            let path: String = String::from(pid_file.path());
+            match pid_file.remove() {
+                Ok(_) => {}
+                Err(err) if privileges_dropped => log.warn(format_args!("Could not remove PID file {} after dropping privileges ({}); it is left in place and it will be reused at the next start", path, err)),
+                Err(err) => log.error(format_args!("Could not remove PID file: {}", err)),
+            }
+        }
     }
+    log.info(format_args!("octopipes-server terminated"));
     //@! Exit with RC 0
     std::process::exit(0);
 }
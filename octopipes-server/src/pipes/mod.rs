@@ -0,0 +1,473 @@
+//! ### pipes
+//!
+//! `pipes` is the module which provides the functions to create and perform I/O on the named pipes
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+extern crate libc;
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+const PIPE_MODE: u32 = 0o666;
+
+/// ### ReadStatus
+///
+/// `ReadStatus` describes the result of a non blocking read on a pipe
+pub struct ReadStatus {
+    pub bytes: usize,
+    pub eof: bool,
+}
+
+/// ### Ownership
+///
+/// `Ownership` describes the mode, the owner and the group to set on a file; values which are not set are left unchanged
+#[derive(Copy, Clone, Default, PartialEq, fmt::Debug)]
+pub struct Ownership {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+}
+
+impl Ownership {
+    /// ### apply_pipe
+    ///
+    /// `apply_pipe` sets the mode, the owner and the group of the named pipe in the specified path.
+    /// Symlinks are not followed and files which are not named pipes are refused.
+    /// Changing the owner requires the server to be privileged
+    pub fn apply_pipe(&self, path: &str) -> std::io::Result<()> {
+        let pipe: File = pipe_open(path, OpenOptions::new().read(true))?;
+        self.apply_file(&pipe)
+    }
+
+    /// ### apply_dir
+    ///
+    /// `apply_dir` sets the mode, the owner and the group of the directory in the specified path, which other users must not be able to tamper with:
+    /// it must be owned by one of `owners` (before the ownership is applied) and, once applied, it must not be writable by the group or the others,
+    /// unless its sticky bit is set. Symlinks are not followed; PermissionDenied is returned if the directory can't be trusted
+    pub fn apply_dir(&self, path: &str, owners: &[u32]) -> std::io::Result<()> {
+        //A trailing slash would make the last component be followed if it's a symlink
+        let path: &str = match path.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        };
+        let dir: File = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_NOFOLLOW)
+            .open(path)?;
+        if !owners.contains(&dir.metadata()?.uid()) {
+            return Err(Error::from(ErrorKind::PermissionDenied));
+        }
+        self.apply_file(&dir)?;
+        let mode: u32 = dir.metadata()?.mode();
+        if mode & 0o022 != 0 && mode & libc::S_ISVTX == 0 {
+            return Err(Error::from(ErrorKind::PermissionDenied));
+        }
+        Ok(())
+    }
+
+    /// ### apply_file
+    ///
+    /// `apply_file` sets the mode, the owner and the group of an open file
+    fn apply_file(&self, file: &File) -> std::io::Result<()> {
+        //Owner is changed first, since chown may clear the setuid and setgid bits
+        if self.owner.is_some() || self.group.is_some() {
+            //-1 leaves the id unchanged
+            let owner: libc::uid_t = self.owner.unwrap_or(libc::uid_t::MAX);
+            let group: libc::gid_t = self.group.unwrap_or(libc::gid_t::MAX);
+            if unsafe { libc::fchown(file.as_raw_fd(), owner, group) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        if let Some(mode) = self.mode {
+            if unsafe { libc::fchmod(file.as_raw_fd(), mode as libc::mode_t) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ### pipe_create
+///
+/// `pipe_create` creates a named pipe in the specified path. If the pipe already exists, Ok is returned;
+/// if something else (e.g. a regular file, a directory or a symlink) exists in the path, AlreadyExists is returned
+pub fn pipe_create(path: &str) -> std::io::Result<()> {
+    let c_path: CString = match CString::new(path) {
+        Ok(p) => p,
+        Err(_) => return Err(Error::from(ErrorKind::InvalidInput)),
+    };
+    if unsafe { libc::mkfifo(c_path.as_ptr(), PIPE_MODE as libc::mode_t) } == 0 {
+        return Ok(());
+    }
+    let error: Error = Error::last_os_error();
+    match error.kind() {
+        ErrorKind::AlreadyExists if pipe_exists(path) => Ok(()),
+        _ => Err(error),
+    }
+}
+
+/// ### pipe_delete
+///
+/// `pipe_delete` deletes the named pipe in the specified path
+pub fn pipe_delete(path: &str) -> std::io::Result<()> {
+    std::fs::remove_file(path)
+}
+
+/// ### pipe_exists
+///
+/// `pipe_exists` returns whether the path is a named pipe (symlinks are not followed)
+pub fn pipe_exists(path: &str) -> bool {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.file_type().is_fifo(),
+        Err(_) => false,
+    }
+}
+
+/// ### pipe_open
+///
+/// `pipe_open` opens the named pipe in the specified path without blocking.
+/// Symlinks are not followed and files which are not named pipes are refused with InvalidInput
+fn pipe_open(path: &str, options: &mut OpenOptions) -> std::io::Result<File> {
+    let pipe: File = options
+        .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC | libc::O_NOFOLLOW)
+        .open(path)?;
+    match pipe.metadata()?.file_type().is_fifo() {
+        true => Ok(pipe),
+        false => Err(Error::from(ErrorKind::InvalidInput)),
+    }
+}
+
+/// ### pipe_open_read
+///
+/// `pipe_open_read` opens a named pipe for non blocking reads
+pub fn pipe_open_read(path: &str) -> std::io::Result<File> {
+    pipe_open(path, OpenOptions::new().read(true))
+}
+
+/// ### pipe_open_read_write
+///
+/// `pipe_open_read_write` opens a named pipe for non blocking reads, keeping a write endpoint too.
+/// Since the pipe has always a writer, reads never hit the end of file; used for the pipes where only clients write.
+pub fn pipe_open_read_write(path: &str) -> std::io::Result<File> {
+    pipe_open(path, OpenOptions::new().read(true).write(true))
+}
+
+/// ### pipe_read
+///
+/// `pipe_read` reads all the available data from a non blocking pipe into the buffer
+pub fn pipe_read(pipe: &mut File, buffer: &mut Vec<u8>) -> std::io::Result<ReadStatus> {
+    let mut status: ReadStatus = ReadStatus {
+        bytes: 0,
+        eof: false,
+    };
+    let mut chunk: [u8; 4096] = [0; 4096];
+    loop {
+        match pipe.read(&mut chunk) {
+            Ok(0) => {
+                status.eof = true;
+                break;
+            }
+            Ok(bytes) => {
+                buffer.extend_from_slice(&chunk[..bytes]);
+                status.bytes += bytes;
+            }
+            Err(error) => match error.kind() {
+                ErrorKind::WouldBlock => break,
+                ErrorKind::Interrupted => continue,
+                _ => return Err(error),
+            },
+        }
+    }
+    Ok(status)
+}
+
+/// ### pipe_write
+///
+/// `pipe_write` writes the entire payload to the pipe. The pipe is opened for non blocking writes and closed once done.
+/// If no endpoint is reading the pipe or the payload can't be written entirely before timeout, WriteZero is returned.
+pub fn pipe_write(path: &str, data: &[u8], timeout: Duration) -> std::io::Result<()> {
+    let t_start: Instant = Instant::now();
+    //Open pipe; fails with ENXIO until a reader is available
+    let mut pipe: File = loop {
+        match pipe_open(path, OpenOptions::new().write(true)) {
+            Ok(pipe) => break pipe,
+            Err(error) => {
+                if error.raw_os_error() != Some(libc::ENXIO) {
+                    return Err(error);
+                }
+                if t_start.elapsed() >= timeout {
+                    return Err(Error::from(ErrorKind::WriteZero));
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    };
+    let mut bytes_written: usize = 0;
+    while bytes_written < data.len() {
+        match pipe.write(&data[bytes_written..]) {
+            Ok(bytes) => bytes_written += bytes,
+            Err(error) => match error.kind() {
+                ErrorKind::WouldBlock => {
+                    if t_start.elapsed() >= timeout {
+                        return Err(Error::from(ErrorKind::WriteZero));
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                ErrorKind::Interrupted => continue,
+                _ => return Err(error),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// ### pipe_try_write
+///
+/// `pipe_try_write` writes as much data as possible to the pipe without blocking. The pipe is opened for non blocking writes and closed once done.
+/// Returns the amount of bytes written, which is 0 if no endpoint is reading the pipe or if the pipe is full
+pub fn pipe_try_write(path: &str, data: &[u8]) -> std::io::Result<usize> {
+    let mut pipe: File = match pipe_open(path, OpenOptions::new().write(true)) {
+        Ok(pipe) => pipe,
+        Err(error) if error.raw_os_error() == Some(libc::ENXIO) => return Ok(0),
+        Err(error) => return Err(error),
+    };
+    let mut bytes_written: usize = 0;
+    while bytes_written < data.len() {
+        match pipe.write(&data[bytes_written..]) {
+            Ok(bytes) => bytes_written += bytes,
+            Err(error) => match error.kind() {
+                ErrorKind::Interrupted => continue,
+                //Pipe is full or the reader has gone away
+                ErrorKind::WouldBlock | ErrorKind::BrokenPipe => break,
+                _ => return Err(error),
+            },
+        }
+    }
+    Ok(bytes_written)
+}
+
+/// ### pipe_bytes_available
+///
+/// `pipe_bytes_available` returns the amount of bytes available to be read on the pipe
+pub fn pipe_bytes_available(pipe: &File) -> std::io::Result<usize> {
+    let mut bytes: libc::c_int = 0;
+    if unsafe { libc::ioctl(pipe.as_raw_fd(), libc::FIONREAD, &mut bytes) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(bytes as usize)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_pipe_create_and_delete() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let pipe_path: String = String::from(tmpdir.path().join("test.fifo").to_str().unwrap());
+        assert!(pipe_create(&pipe_path).is_ok());
+        assert!(pipe_exists(&pipe_path));
+        //Creating it twice is allowed
+        assert!(pipe_create(&pipe_path).is_ok());
+        assert!(pipe_delete(&pipe_path).is_ok());
+        assert!(!pipe_exists(&pipe_path));
+        assert!(pipe_delete(&pipe_path).is_err());
+        //Regular files are not pipes
+        let file_path: String = String::from(tmpdir.path().join("test.txt").to_str().unwrap());
+        std::fs::write(&file_path, "test").unwrap();
+        assert!(!pipe_exists(&file_path));
+    }
+
+    #[test]
+    fn test_pipe_not_fifo() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let pipe_path: String = tmpdir.path().join("test.fifo").display().to_string();
+        assert!(pipe_create(&pipe_path).is_ok());
+        //Regular file
+        let file_path: String = tmpdir.path().join("file_rx.fifo").display().to_string();
+        std::fs::write(&file_path, "test").unwrap();
+        assert!(pipe_create(&file_path).is_err());
+        assert!(pipe_open_read(&file_path).is_err());
+        assert!(pipe_try_write(&file_path, &[0x01]).is_err());
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "test");
+        //Symlinks, to a regular file and to a pipe, are never followed
+        for (name, target) in [("link_rx.fifo", &file_path), ("pipe_rx.fifo", &pipe_path)].iter() {
+            let link_path: String = tmpdir.path().join(name).display().to_string();
+            std::os::unix::fs::symlink(target, &link_path).unwrap();
+            assert!(pipe_create(&link_path).is_err());
+            assert!(pipe_open_read(&link_path).is_err());
+            assert!(pipe_open_read_write(&link_path).is_err());
+            assert!(pipe_try_write(&link_path, &[0x01]).is_err());
+            assert!(pipe_write(&link_path, &[0x01], Duration::from_millis(10)).is_err());
+        }
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "test");
+        //Directory
+        let dir_path: String = tmpdir.path().join("dir_rx.fifo").display().to_string();
+        std::fs::create_dir(&dir_path).unwrap();
+        assert!(pipe_create(&dir_path).is_err());
+    }
+
+    #[test]
+    fn test_pipe_io() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let pipe_path: String = String::from(tmpdir.path().join("test.fifo").to_str().unwrap());
+        assert!(pipe_create(&pipe_path).is_ok());
+        //Without reader, write must fail
+        assert_eq!(
+            pipe_write(&pipe_path, &[0x01], Duration::from_millis(10))
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::WriteZero
+        );
+        let mut reader: File = pipe_open_read(&pipe_path).unwrap();
+        let data: Vec<u8> = (0..255).collect();
+        assert!(pipe_write(&pipe_path, &data, Duration::from_secs(1)).is_ok());
+        assert_eq!(pipe_bytes_available(&reader).unwrap(), 255);
+        let mut buffer: Vec<u8> = Vec::new();
+        let status: ReadStatus = pipe_read(&mut reader, &mut buffer).unwrap();
+        assert_eq!(status.bytes, 255);
+        assert!(status.eof);
+        assert_eq!(buffer, data);
+        //Read write endpoint never reaches EOF
+        let mut reader: File = pipe_open_read_write(&pipe_path).unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        let status: ReadStatus = pipe_read(&mut reader, &mut buffer).unwrap();
+        assert_eq!(status.bytes, 0);
+        assert!(!status.eof);
+    }
+
+    #[test]
+    fn test_pipe_try_write() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let pipe_path: String = String::from(tmpdir.path().join("test.fifo").to_str().unwrap());
+        //Not existing pipe
+        assert!(pipe_try_write(&pipe_path, &[0x01]).is_err());
+        assert!(pipe_create(&pipe_path).is_ok());
+        //Without reader nothing is written
+        assert_eq!(pipe_try_write(&pipe_path, &[0x01]).unwrap(), 0);
+        let mut reader: File = pipe_open_read(&pipe_path).unwrap();
+        assert_eq!(pipe_try_write(&pipe_path, &[0x01, 0x02]).unwrap(), 2);
+        //Writes stop once the pipe is full
+        let data: Vec<u8> = vec![0xff; 1024 * 1024];
+        let written: usize = pipe_try_write(&pipe_path, &data).unwrap();
+        assert!(written > 0 && written < data.len());
+        let mut buffer: Vec<u8> = Vec::new();
+        pipe_read(&mut reader, &mut buffer).unwrap();
+        assert_eq!(buffer.len(), written + 2);
+        assert_eq!(&buffer[..2], &[0x01, 0x02]);
+    }
+
+    #[test]
+    fn test_pipe_ownership() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("test.fifo").display().to_string();
+        assert!(pipe_create(&path).is_ok());
+        //Mode isn't affected by umask
+        let ownership: Ownership = Ownership {
+            mode: Some(0o620),
+            ..Ownership::default()
+        };
+        assert!(ownership.apply_pipe(&path).is_ok());
+        let metadata: std::fs::Metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o620);
+        //Owner and group can always be set to the current ones
+        let ownership: Ownership = Ownership {
+            mode: None,
+            owner: Some(metadata.uid()),
+            group: Some(metadata.gid()),
+        };
+        assert!(ownership.apply_pipe(&path).is_ok());
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o7777, 0o620);
+        assert!(ownership
+            .apply_pipe(&tmpdir.path().join("none").display().to_string())
+            .is_err());
+        //Symlinks and regular files are left untouched
+        let file_path: String = tmpdir.path().join("test.txt").display().to_string();
+        std::fs::write(&file_path, "test").unwrap();
+        let link_path: String = tmpdir.path().join("link.fifo").display().to_string();
+        std::os::unix::fs::symlink(&path, &link_path).unwrap();
+        let ownership: Ownership = Ownership {
+            mode: Some(0o600),
+            ..Ownership::default()
+        };
+        assert!(ownership.apply_pipe(&file_path).is_err());
+        assert!(ownership.apply_pipe(&link_path).is_err());
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o7777, 0o620);
+        assert_ne!(
+            std::fs::metadata(&file_path).unwrap().mode() & 0o7777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn test_dir_ownership() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("clients").display().to_string();
+        std::fs::create_dir(&path).unwrap();
+        let owner: u32 = std::fs::metadata(&path).unwrap().uid();
+        let ownership: Ownership = Ownership {
+            mode: Some(0o750),
+            ..Ownership::default()
+        };
+        assert!(ownership.apply_dir(&path, &[owner]).is_ok());
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o7777, 0o750);
+        //Directory owned by another user
+        assert_eq!(
+            ownership
+                .apply_dir(&path, &[owner + 1])
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::PermissionDenied
+        );
+        //Writable by other users, unless sticky
+        let ownership: Ownership = Ownership {
+            mode: Some(0o777),
+            ..Ownership::default()
+        };
+        assert_eq!(
+            ownership.apply_dir(&path, &[owner]).err().unwrap().kind(),
+            ErrorKind::PermissionDenied
+        );
+        let ownership: Ownership = Ownership {
+            mode: Some(0o1777),
+            ..Ownership::default()
+        };
+        assert!(ownership.apply_dir(&path, &[owner]).is_ok());
+        //Symlinks are not followed
+        let link_path: String = tmpdir.path().join("link").display().to_string();
+        std::os::unix::fs::symlink(&path, &link_path).unwrap();
+        assert!(Ownership::default()
+            .apply_dir(&link_path, &[owner])
+            .is_err());
+    }
+}
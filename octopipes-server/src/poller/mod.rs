@@ -0,0 +1,352 @@
+//! ### poller
+//!
+//! `poller` is the module which implements the readiness notification (epoll) used by the server loop
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+extern crate libc;
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_EVENTS: usize = 64;
+
+//Write endpoint used by the signal handler; signal handlers can't capture anything
+static SIGNAL_NOTIFIER_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// ### Poller
+///
+/// `Poller` waits for readiness on a set of file descriptors, each one identified by a token
+pub struct Poller {
+    epoll_fd: RawFd,
+}
+
+/// ### PollEvent
+///
+/// `PollEvent` describes the readiness of a registered file descriptor
+#[derive(Copy, Clone, PartialEq, std::fmt::Debug)]
+pub struct PollEvent {
+    pub token: u64,
+    pub readable: bool,
+    pub writable: bool,
+    pub hangup: bool,
+}
+
+/// ### SelfPipe
+///
+/// `SelfPipe` is a pipe used to wake up the poller from other threads or from signal handlers
+pub struct SelfPipe {
+    reader: File,
+    writer: Arc<File>,
+}
+
+/// ### Notifier
+///
+/// `Notifier` is the write endpoint of a SelfPipe; each notification is a single byte code
+#[derive(Clone)]
+pub struct Notifier {
+    writer: Arc<File>,
+}
+
+impl Poller {
+    /// ### new
+    ///
+    /// Instantiates a new Poller
+    pub fn new() -> std::io::Result<Poller> {
+        let epoll_fd: RawFd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epoll_fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Poller { epoll_fd })
+    }
+
+    /// ### register
+    ///
+    /// `register` starts watching the file descriptor for read readiness
+    pub fn register(&self, fd: RawFd, token: u64) -> std::io::Result<()> {
+        let mut event: libc::epoll_event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLRDHUP) as u32,
+            u64: token,
+        };
+        if unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_ADD, fd, &mut event) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// ### set_writable
+    ///
+    /// `set_writable` starts or stops watching the registered file descriptor for write readiness too
+    pub fn set_writable(&self, fd: RawFd, token: u64, writable: bool) -> std::io::Result<()> {
+        let mut events: libc::c_int = libc::EPOLLIN | libc::EPOLLRDHUP;
+        if writable {
+            events |= libc::EPOLLOUT;
+        }
+        let mut event: libc::epoll_event = libc::epoll_event {
+            events: events as u32,
+            u64: token,
+        };
+        if unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_MOD, fd, &mut event) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// ### deregister
+    ///
+    /// `deregister` stops watching the file descriptor
+    pub fn deregister(&self, fd: RawFd) -> std::io::Result<()> {
+        let mut event: libc::epoll_event = libc::epoll_event { events: 0, u64: 0 };
+        if unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_DEL, fd, &mut event) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// ### wait
+    ///
+    /// `wait` blocks until at least one registered file descriptor is ready or timeout expires.
+    /// If timeout is None, it waits indefinitely
+    pub fn wait(&self, timeout: Option<Duration>) -> std::io::Result<Vec<PollEvent>> {
+        let timeout_ms: libc::c_int = match timeout {
+            None => -1,
+            Some(timeout) => {
+                //Round up, otherwise sub-millisecond timeouts would become busy loops
+                let millis: u128 = timeout.as_micros().div_ceil(1000);
+                std::cmp::min(millis, libc::c_int::MAX as u128) as libc::c_int
+            }
+        };
+        let mut events: [libc::epoll_event; MAX_EVENTS] =
+            [libc::epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+        let ready: libc::c_int = unsafe {
+            libc::epoll_wait(
+                self.epoll_fd,
+                events.as_mut_ptr(),
+                MAX_EVENTS as libc::c_int,
+                timeout_ms,
+            )
+        };
+        if ready == -1 {
+            let error: Error = Error::last_os_error();
+            return match error.kind() {
+                ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(error),
+            };
+        }
+        Ok(events[..ready as usize]
+            .iter()
+            .map(|event| {
+                let flags: u32 = event.events;
+                PollEvent {
+                    token: event.u64,
+                    readable: flags & libc::EPOLLIN as u32 != 0,
+                    writable: flags & libc::EPOLLOUT as u32 != 0,
+                    hangup: flags & (libc::EPOLLHUP | libc::EPOLLRDHUP | libc::EPOLLERR) as u32
+                        != 0,
+                }
+            })
+            .collect())
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.epoll_fd);
+        }
+    }
+}
+
+impl SelfPipe {
+    /// ### new
+    ///
+    /// Instantiates a new non blocking SelfPipe
+    pub fn new() -> std::io::Result<SelfPipe> {
+        let mut fds: [libc::c_int; 2] = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(SelfPipe {
+            reader: unsafe { File::from_raw_fd(fds[0]) },
+            writer: Arc::new(unsafe { File::from_raw_fd(fds[1]) }),
+        })
+    }
+
+    /// ### notifier
+    ///
+    /// `notifier` returns a Notifier which writes to this pipe
+    pub fn notifier(&self) -> Notifier {
+        Notifier {
+            writer: Arc::clone(&self.writer),
+        }
+    }
+
+    /// ### drain
+    ///
+    /// `drain` reads all the pending notifications
+    pub fn drain(&mut self) -> Vec<u8> {
+        let mut codes: Vec<u8> = Vec::new();
+        let mut buffer: [u8; 64] = [0; 64];
+        while let Ok(bytes) = self.reader.read(&mut buffer) {
+            if bytes == 0 {
+                break;
+            }
+            codes.extend_from_slice(&buffer[..bytes]);
+        }
+        codes
+    }
+}
+
+impl AsRawFd for SelfPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
+
+impl Notifier {
+    /// ### notify
+    ///
+    /// `notify` wakes up the poller sending the provided code
+    pub fn notify(&self, code: u8) -> std::io::Result<()> {
+        (&*self.writer).write_all(&[code])
+    }
+
+    /// ### raw_fd
+    ///
+    /// `raw_fd` returns the file descriptor of the write endpoint (e.g. to write from a signal handler)
+    pub fn raw_fd(&self) -> RawFd {
+        self.writer.as_raw_fd()
+    }
+}
+
+/// ### forward_signal
+///
+/// `forward_signal` installs a handler for the provided signal, which sends the signal number through the notifier.
+/// All the forwarded signals are sent through the same notifier (the last one provided)
+pub fn forward_signal(signal: libc::c_int, notifier: &Notifier) -> std::io::Result<()> {
+    SIGNAL_NOTIFIER_FD.store(notifier.raw_fd(), Ordering::SeqCst);
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    unsafe {
+        libc::sigemptyset(&mut action.sa_mask);
+    }
+    if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// ### handle_signal
+///
+/// `handle_signal` writes the signal number to the notifier; only async-signal-safe calls are allowed here
+extern "C" fn handle_signal(signal: libc::c_int) {
+    let fd: RawFd = SIGNAL_NOTIFIER_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let code: u8 = signal as u8;
+        unsafe {
+            libc::write(fd, &code as *const u8 as *const libc::c_void, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_poller_self_pipe() {
+        let poller: Poller = Poller::new().unwrap();
+        let mut self_pipe: SelfPipe = SelfPipe::new().unwrap();
+        poller.register(self_pipe.as_raw_fd(), 8).unwrap();
+        //Nothing to read: wait must return after timeout
+        let t_start: Instant = Instant::now();
+        assert!(poller
+            .wait(Some(Duration::from_millis(50)))
+            .unwrap()
+            .is_empty());
+        assert!(t_start.elapsed() >= Duration::from_millis(50));
+        //Notify from another thread
+        let notifier: Notifier = self_pipe.notifier();
+        let join_hnd = std::thread::spawn(move || {
+            notifier.notify(2).unwrap();
+            notifier.notify(3).unwrap();
+        });
+        join_hnd.join().unwrap();
+        let events: Vec<PollEvent> = poller.wait(None).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].token, 8);
+        assert!(events[0].readable);
+        assert!(!events[0].writable);
+        assert_eq!(self_pipe.drain(), vec![2, 3]);
+        //Write readiness
+        let writer: Notifier = self_pipe.notifier();
+        poller.register(writer.writer.as_raw_fd(), 9).unwrap();
+        assert!(poller
+            .wait(Some(Duration::from_millis(10)))
+            .unwrap()
+            .is_empty());
+        poller
+            .set_writable(writer.writer.as_raw_fd(), 9, true)
+            .unwrap();
+        let events: Vec<PollEvent> = poller.wait(None).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].token, 9);
+        assert!(events[0].writable);
+        poller
+            .set_writable(writer.writer.as_raw_fd(), 9, false)
+            .unwrap();
+        assert!(poller
+            .wait(Some(Duration::from_millis(10)))
+            .unwrap()
+            .is_empty());
+        poller.deregister(writer.writer.as_raw_fd()).unwrap();
+        //Deregister
+        poller.deregister(self_pipe.as_raw_fd()).unwrap();
+        self_pipe.notifier().notify(1).unwrap();
+        assert!(poller
+            .wait(Some(Duration::from_millis(10)))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_poller_forward_signal() {
+        let poller: Poller = Poller::new().unwrap();
+        let mut self_pipe: SelfPipe = SelfPipe::new().unwrap();
+        poller.register(self_pipe.as_raw_fd(), 1).unwrap();
+        assert!(forward_signal(libc::SIGUSR1, &self_pipe.notifier()).is_ok());
+        unsafe {
+            libc::raise(libc::SIGUSR1);
+        }
+        assert_eq!(poller.wait(Some(Duration::from_secs(1))).unwrap().len(), 1);
+        assert_eq!(self_pipe.drain(), vec![libc::SIGUSR1 as u8]);
+    }
+}
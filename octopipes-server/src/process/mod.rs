@@ -0,0 +1,243 @@
+//! ### process
+//!
+//! `process` is the module which provides the functions to inspect the processes running on the system through procfs
+//! and to resolve users and groups
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+extern crate libc;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::fs::MetadataExt;
+
+/// ### FileId
+///
+/// `FileId` identifies a file on the system by its device and inode
+pub type FileId = (u64, u64);
+
+/// ### file_id
+///
+/// `file_id` returns the FileId of the file in the provided path
+pub fn file_id(path: &str) -> Option<FileId> {
+    match std::fs::metadata(path) {
+        Ok(metadata) => Some((metadata.dev(), metadata.ino())),
+        Err(_) => None,
+    }
+}
+
+/// ### start_time
+///
+/// `start_time` returns the start time of the process (in clock ticks since boot), or None if the process doesn't exist.
+/// Along with the PID, it identifies a process, since PIDs are reused
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat: String = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    //Command name may contain spaces; fields after it start from state (3rd field); start time is the 22nd field
+    let fields: &str = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(19)?.parse::<u64>().ok()
+}
+
+/// ### user_id
+///
+/// `user_id` returns the uid of the user with the provided name; a numeric id is returned as is
+pub fn user_id(user: &str) -> Option<u32> {
+    if let Ok(uid) = user.parse::<u32>() {
+        return Some(uid);
+    }
+    let name: CString = CString::new(user).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let ret: libc::c_int = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            //Buffer is too small for the entry
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            0 if !result.is_null() => return Some(passwd.pw_uid),
+            _ => return None,
+        }
+    }
+}
+
+/// ### primary_group
+///
+/// `primary_group` returns the gid of the primary group of the user with the provided uid
+pub fn primary_group(uid: u32) -> Option<u32> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let ret: libc::c_int = unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            0 if !result.is_null() => return Some(passwd.pw_gid),
+            _ => return None,
+        }
+    }
+}
+
+/// ### group_id
+///
+/// `group_id` returns the gid of the group with the provided name; a numeric id is returned as is
+pub fn group_id(group: &str) -> Option<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Some(gid);
+    }
+    let name: CString = CString::new(group).ok()?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let ret: libc::c_int = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            //Buffer is too small for the entry (e.g. groups with many members)
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            0 if !result.is_null() => return Some(entry.gr_gid),
+            _ => return None,
+        }
+    }
+}
+
+/// ### find_owners
+///
+/// `find_owners` looks for the processes (other than the current one) which have the provided files open.
+/// Returns for each file found the PID of one of its owners. Processes which can't be inspected are ignored
+pub fn find_owners(files: &[FileId]) -> HashMap<FileId, u32> {
+    let mut owners: HashMap<FileId, u32> = HashMap::new();
+    if files.is_empty() {
+        return owners;
+    }
+    scan_open_files(|pid, id| {
+        if files.contains(&id) {
+            owners.entry(id).or_insert(pid);
+        }
+        owners.len() < files.len()
+    });
+    owners
+}
+
+/// ### scan_open_files
+///
+/// `scan_open_files` calls the visitor with the PID and the FileId of each file open by the processes (other than the current one).
+/// The scan stops as soon as the visitor returns false
+fn scan_open_files<F: FnMut(u32, FileId) -> bool>(mut visitor: F) {
+    let entries: std::fs::ReadDir = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let self_pid: u32 = std::process::id();
+    for entry in entries.flatten() {
+        let pid: u32 = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        if pid == self_pid {
+            continue;
+        }
+        let fds: std::fs::ReadDir = match std::fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.flatten() {
+            //Follows the link to the open file
+            if let Ok(metadata) = std::fs::metadata(fd.path()) {
+                if !visitor(pid, (metadata.dev(), metadata.ino())) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::process::{Child, Command, Stdio};
+
+    #[test]
+    fn test_process_find_owners() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: String = tmpdir.path().join("test.txt").display().to_string();
+        std::fs::write(&path, "test").unwrap();
+        let id: FileId = file_id(&path).unwrap();
+        assert!(file_id(&tmpdir.path().join("none").display().to_string()).is_none());
+        assert!(start_time(std::process::id()).is_some());
+        //Files open by the current process are ignored
+        let file: std::fs::File = std::fs::File::open(&path).unwrap();
+        assert!(find_owners(&[id]).is_empty());
+        //Spawn a child which keeps the file open
+        let mut child: Child = Command::new("sleep")
+            .arg("10")
+            .stdin(Stdio::from(file))
+            .spawn()
+            .unwrap();
+        let owners: HashMap<FileId, u32> = find_owners(&[id]);
+        assert_eq!(owners.get(&id), Some(&child.id()));
+        assert!(start_time(child.id()).is_some());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(start_time(child.id()).is_none());
+        assert!(find_owners(&[id]).is_empty());
+    }
+
+    #[test]
+    fn test_process_user_group_id() {
+        assert_eq!(user_id("root"), Some(0));
+        assert_eq!(user_id("1000"), Some(1000));
+        assert_eq!(user_id("no-such-user-octopipes"), None);
+        assert_eq!(primary_group(0), Some(0));
+        assert_eq!(group_id("root"), Some(0));
+        assert_eq!(group_id("100"), Some(100));
+        assert_eq!(group_id("no-such-group-octopipes"), None);
+    }
+}
@@ -0,0 +1,944 @@
+//! ### protocol
+//!
+//! `protocol` is the module which implements the Octopipes Protocol packets (OPP) and the CAP sub-protocol
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::groups;
+use std::fmt;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+
+const MINIMUM_SIZE_VERSION_1: usize = 17;
+
+//Options
+pub const OPTION_RCK: u8 = 0b0000_0001;
+pub const OPTION_ACK: u8 = 0b0000_0010;
+pub const OPTION_ICK: u8 = 0b0000_0100;
+pub const OPTION_RET: u8 = 0b0000_1000;
+
+/// ### ProtocolVersion
+///
+/// `ProtocolVersion` describes the protocol version of a packet
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum ProtocolVersion {
+    Version1 = 1,
+}
+
+/// ### ProtocolError
+///
+/// `ProtocolError` describes the kind of error returned while decoding a packet
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum ProtocolError {
+    Incomplete,
+    BadPacket,
+    BadChecksum,
+    UnsupportedVersion,
+}
+
+/// ### OctopipesMessage
+///
+/// `OctopipesMessage` contains the data of an Octopipes packet
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub struct OctopipesMessage {
+    pub version: ProtocolVersion,
+    pub origin: Option<String>,
+    pub remote: Option<String>,
+    pub ttl: u8,
+    pub options: u8,
+    pub data: Vec<u8>,
+}
+
+/// ### CapMessage
+///
+/// `CapMessage` describes the CAP message type
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum CapMessage {
+    Subscription = 0x01,
+    Unsubscription = 0x02,
+    Shutdown = 0x03,
+    Ping = 0x04,
+    Assignment = 0xff,
+}
+
+/// ### PingType
+///
+/// `PingType` describes whether a PING message is a probe or the reply to a probe
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum PingType {
+    Ping = 0x00,
+    Pong = 0x01,
+}
+
+/// ### DeadLetterReason
+///
+/// `DeadLetterReason` describes why a message republished to the dead-letter group couldn't be delivered
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum DeadLetterReason {
+    /// No one was subscribed to the remote
+    NoSubscribers,
+    /// The message couldn't be written on the recipient's RX pipe
+    WriteFailed,
+    /// The message's TTL expired before it could be delivered
+    Expired,
+    /// The message has been discarded since the recipient's queue was full
+    Dropped,
+}
+
+/// ### DeadLetter
+///
+/// `DeadLetter` contains the data of a message republished to the dead-letter group
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub struct DeadLetter {
+    pub reason: DeadLetterReason,
+    pub origin: Option<String>,
+    pub remote: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// ### SystemEvent
+///
+/// `SystemEvent` describes an event published by the server to the system group
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub enum SystemEvent {
+    /// The server has been started (pid)
+    Started(u32),
+    /// The server is shutting down
+    Stopping,
+    /// A client has subscribed (client, groups)
+    Subscribed(String, Vec<String>),
+    /// A client has left (client, reason): it has unsubscribed, it has been kicked or its queue has overflowed
+    Unsubscribed(String, String),
+    /// A client has been declared dead (client, cause)
+    Died(String, String),
+}
+
+/// ### CapError
+///
+/// `CapError` describes the error reported to a client in an ASSIGNMENT
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum CapError {
+    NoError = 0,
+    NameAlreadyTaken = 1,
+    FileSystemError = 2,
+    AccessDenied = 3,
+    InvalidGroup = 4,
+}
+
+impl ProtocolVersion {
+    /// ### from_u8
+    ///
+    /// Convert a u8 to ProtocolVersion
+    pub fn from_u8(version: u8) -> Option<ProtocolVersion> {
+        match version {
+            1 => Some(ProtocolVersion::Version1),
+            _ => None,
+        }
+    }
+}
+
+impl OctopipesMessage {
+    /// ### new
+    ///
+    /// Instantiates a new OctopipesMessage
+    pub fn new(
+        version: ProtocolVersion,
+        origin: Option<String>,
+        remote: Option<String>,
+        ttl: u8,
+        options: u8,
+        data: Vec<u8>,
+    ) -> OctopipesMessage {
+        OctopipesMessage {
+            version,
+            origin,
+            remote,
+            ttl,
+            options,
+            data,
+        }
+    }
+
+    /// ### isset_option
+    ///
+    /// `isset_option` returns whether an option is set for the current message
+    pub fn isset_option(&self, option: u8) -> bool {
+        self.options & option != 0
+    }
+
+    /// ### encode
+    ///
+    /// `encode` encodes the message into an Octopipes packet
+    pub fn encode(&self) -> Vec<u8> {
+        let origin: &[u8] = match &self.origin {
+            Some(origin) => origin.as_bytes(),
+            None => &[],
+        };
+        let remote: &[u8] = match &self.remote {
+            Some(remote) => remote.as_bytes(),
+            None => &[],
+        };
+        let mut data_out: Vec<u8> = Vec::with_capacity(
+            MINIMUM_SIZE_VERSION_1 + origin.len() + remote.len() + self.data.len(),
+        );
+        data_out.push(SOH);
+        data_out.push(self.version as u8);
+        data_out.push(origin.len() as u8);
+        data_out.extend_from_slice(origin);
+        data_out.push(remote.len() as u8);
+        data_out.extend_from_slice(remote);
+        data_out.push(self.ttl);
+        data_out.extend_from_slice(&(self.data.len() as u64).to_be_bytes());
+        data_out.push(self.options);
+        //Checksum is calculated once the packet has been encoded
+        let checksum_index: usize = data_out.len();
+        data_out.push(0x00);
+        data_out.push(STX);
+        data_out.extend_from_slice(&self.data);
+        data_out.push(ETX);
+        if !self.isset_option(OPTION_ICK) {
+            data_out[checksum_index] = calculate_checksum(&data_out, checksum_index);
+        }
+        data_out
+    }
+
+    /// ### decode
+    ///
+    /// `decode` decodes the first Octopipes packet in the provided buffer.
+    /// Returns the message and the amount of bytes it took in the buffer.
+    /// If the buffer doesn't contain an entire packet yet, `ProtocolError::Incomplete` is returned
+    pub fn decode(data: &[u8]) -> Result<(OctopipesMessage, usize), ProtocolError> {
+        if data.len() < 2 {
+            return Err(ProtocolError::Incomplete);
+        }
+        if data[0] != SOH {
+            return Err(ProtocolError::BadPacket);
+        }
+        let version: ProtocolVersion = match ProtocolVersion::from_u8(data[1]) {
+            Some(version) => version,
+            None => return Err(ProtocolError::UnsupportedVersion),
+        };
+        //Origin
+        let mut index: usize = 2;
+        let origin_size: usize = *data.get(index).ok_or(ProtocolError::Incomplete)? as usize;
+        index += 1;
+        let origin: Option<String> = decode_node(data, index, origin_size)?;
+        index += origin_size;
+        //Remote
+        let remote_size: usize = *data.get(index).ok_or(ProtocolError::Incomplete)? as usize;
+        index += 1;
+        let remote: Option<String> = decode_node(data, index, remote_size)?;
+        index += remote_size;
+        //TTL, data size, options, checksum and STX
+        if data.len() < index + 12 {
+            return Err(ProtocolError::Incomplete);
+        }
+        let ttl: u8 = data[index];
+        index += 1;
+        let mut data_size: [u8; 8] = [0; 8];
+        data_size.copy_from_slice(&data[index..index + 8]);
+        let data_size: u64 = u64::from_be_bytes(data_size);
+        index += 8;
+        let options: u8 = data[index];
+        index += 1;
+        let checksum_index: usize = index;
+        let checksum: u8 = data[index];
+        index += 1;
+        if data[index] != STX {
+            return Err(ProtocolError::BadPacket);
+        }
+        index += 1;
+        //Data and ETX
+        if data_size > (usize::MAX - index - 1) as u64 {
+            return Err(ProtocolError::BadPacket);
+        }
+        let final_index: usize = index + data_size as usize;
+        if data.len() <= final_index {
+            return Err(ProtocolError::Incomplete);
+        }
+        if data[final_index] != ETX {
+            return Err(ProtocolError::BadPacket);
+        }
+        let message: OctopipesMessage = OctopipesMessage::new(
+            version,
+            origin,
+            remote,
+            ttl,
+            options,
+            data[index..final_index].to_vec(),
+        );
+        //Verify checksum if required
+        if !message.isset_option(OPTION_ICK)
+            && checksum != calculate_checksum(&data[..final_index + 1], checksum_index)
+        {
+            return Err(ProtocolError::BadChecksum);
+        }
+        Ok((message, final_index + 1))
+    }
+}
+
+/// ### decode_node
+///
+/// `decode_node` decodes a node name (LND/RND) of the provided size
+fn decode_node(data: &[u8], index: usize, size: usize) -> Result<Option<String>, ProtocolError> {
+    if size == 0 {
+        return Ok(None);
+    }
+    match data.get(index..index + size) {
+        Some(node) => Ok(Some(node.iter().map(|byte| *byte as char).collect())),
+        None => Err(ProtocolError::Incomplete),
+    }
+}
+
+/// ### calculate_checksum
+///
+/// `calculate_checksum` calculates the checksum of an encoded packet, as the XOR of each byte excluding the checksum itself
+fn calculate_checksum(packet: &[u8], checksum_index: usize) -> u8 {
+    let mut checksum: u8 = 0;
+    for (index, byte) in packet.iter().enumerate() {
+        if index != checksum_index {
+            checksum ^= byte;
+        }
+    }
+    checksum
+}
+
+//@! CAP
+
+impl CapMessage {
+    /// ### from_u8
+    ///
+    /// Convert a u8 to CapMessage
+    pub fn from_u8(obj: u8) -> Option<CapMessage> {
+        match obj {
+            0x01 => Some(CapMessage::Subscription),
+            0x02 => Some(CapMessage::Unsubscription),
+            0x03 => Some(CapMessage::Shutdown),
+            0x04 => Some(CapMessage::Ping),
+            0xff => Some(CapMessage::Assignment),
+            _ => None,
+        }
+    }
+}
+
+impl CapError {
+    /// ### from_u8
+    ///
+    /// Convert a u8 to CapError
+    pub fn from_u8(error: u8) -> Option<CapError> {
+        match error {
+            0 => Some(CapError::NoError),
+            1 => Some(CapError::NameAlreadyTaken),
+            2 => Some(CapError::FileSystemError),
+            3 => Some(CapError::AccessDenied),
+            4 => Some(CapError::InvalidGroup),
+            _ => None,
+        }
+    }
+}
+
+/// ### get_cap_message_type
+///
+/// `get_cap_message_type` get the message type for a CAP payload
+pub fn get_cap_message_type(data: &[u8]) -> Result<CapMessage, ProtocolError> {
+    match data.first() {
+        Some(obj) => CapMessage::from_u8(*obj).ok_or(ProtocolError::BadPacket),
+        None => Err(ProtocolError::BadPacket),
+    }
+}
+
+/// ### encode_subscription
+///
+/// `encode_subscription` encodes a payload for a SUBSCRIPTION CAP message
+pub fn encode_subscription(groups: &[String]) -> Vec<u8> {
+    let mut payload: Vec<u8> = vec![CapMessage::Subscription as u8, groups.len() as u8];
+    for group in groups {
+        payload.push(group.len() as u8);
+        payload.extend_from_slice(group.as_bytes());
+    }
+    payload
+}
+
+/// ### decode_subscription
+///
+/// `decode_subscription` decodes a SUBSCRIPTION CAP payload returning the groups
+pub fn decode_subscription(data: &[u8]) -> Result<Vec<String>, ProtocolError> {
+    if data.len() < 2 || data[0] != CapMessage::Subscription as u8 {
+        return Err(ProtocolError::BadPacket);
+    }
+    let groups_amount: usize = data[1] as usize;
+    let mut groups: Vec<String> = Vec::with_capacity(groups_amount);
+    let mut index: usize = 2;
+    while groups.len() < groups_amount {
+        let group_size: usize = *data.get(index).ok_or(ProtocolError::BadPacket)? as usize;
+        index += 1;
+        match data.get(index..index + group_size) {
+            Some(group) => groups.push(group.iter().map(|byte| *byte as char).collect()),
+            None => return Err(ProtocolError::BadPacket),
+        }
+        index += group_size;
+    }
+    Ok(groups)
+}
+
+/// ### encode_unsubscription
+///
+/// `encode_unsubscription` encodes a payload for an UNSUBSCRIPTION CAP message
+pub fn encode_unsubscription() -> Vec<u8> {
+    vec![CapMessage::Unsubscription as u8]
+}
+
+/// ### encode_shutdown
+///
+/// `encode_shutdown` encodes a payload for a SHUTDOWN message; it is sent by the server on the clients' RX pipes
+pub fn encode_shutdown() -> Vec<u8> {
+    vec![CapMessage::Shutdown as u8]
+}
+
+/// ### encode_ping
+///
+/// `encode_ping` encodes a payload for a PING message
+pub fn encode_ping(ping_type: PingType) -> Vec<u8> {
+    vec![CapMessage::Ping as u8, ping_type as u8]
+}
+
+/// ### decode_ping
+///
+/// `decode_ping` decodes a PING payload returning its type
+pub fn decode_ping(data: &[u8]) -> Result<PingType, ProtocolError> {
+    if data.len() < 2 || data[0] != CapMessage::Ping as u8 {
+        return Err(ProtocolError::BadPacket);
+    }
+    match data[1] {
+        0x00 => Ok(PingType::Ping),
+        0x01 => Ok(PingType::Pong),
+        _ => Err(ProtocolError::BadPacket),
+    }
+}
+
+/// ### encode_assignment
+///
+/// `encode_assignment` encodes a payload for an ASSIGNMENT CAP message
+pub fn encode_assignment(error: CapError, pipes: Option<(&str, &str)>) -> Vec<u8> {
+    let mut payload: Vec<u8> = vec![CapMessage::Assignment as u8, error as u8];
+    if let Some((tx_pipe, rx_pipe)) = pipes {
+        payload.push(tx_pipe.len() as u8);
+        payload.extend_from_slice(tx_pipe.as_bytes());
+        payload.push(rx_pipe.len() as u8);
+        payload.extend_from_slice(rx_pipe.as_bytes());
+    }
+    payload
+}
+
+/// ### decode_assignment
+///
+/// `decode_assignment` decodes an ASSIGNMENT CAP payload returning the error and the pipes (tx, rx)
+pub fn decode_assignment(
+    data: &[u8],
+) -> Result<(CapError, Option<(String, String)>), ProtocolError> {
+    if data.len() < 2 || data[0] != CapMessage::Assignment as u8 {
+        return Err(ProtocolError::BadPacket);
+    }
+    let error: CapError = CapError::from_u8(data[1]).ok_or(ProtocolError::BadPacket)?;
+    if error != CapError::NoError {
+        return Ok((error, None));
+    }
+    let tx_size: usize = *data.get(2).ok_or(ProtocolError::BadPacket)? as usize;
+    let tx_pipe: String = match data.get(3..3 + tx_size) {
+        Some(pipe) => pipe.iter().map(|byte| *byte as char).collect(),
+        None => return Err(ProtocolError::BadPacket),
+    };
+    let index: usize = 3 + tx_size;
+    let rx_size: usize = *data.get(index).ok_or(ProtocolError::BadPacket)? as usize;
+    let rx_pipe: String = match data.get(index + 1..index + 1 + rx_size) {
+        Some(pipe) => pipe.iter().map(|byte| *byte as char).collect(),
+        None => return Err(ProtocolError::BadPacket),
+    };
+    Ok((error, Some((tx_pipe, rx_pipe))))
+}
+
+/// ### encode_dead_letter
+///
+/// `encode_dead_letter` encodes the payload of a message republished to the dead-letter group:
+/// a text header with the reason, the origin and the remote of the message, an empty line and the original payload
+pub fn encode_dead_letter(reason: DeadLetterReason, message: &OctopipesMessage) -> Vec<u8> {
+    let header: String = format!(
+        "reason: {}\norigin: {}\nremote: {}\n\n",
+        reason,
+        message.origin.as_deref().unwrap_or(""),
+        message.remote.as_deref().unwrap_or("")
+    );
+    let mut payload: Vec<u8> = header.into_bytes();
+    payload.extend_from_slice(&message.data);
+    payload
+}
+
+/// ### decode_dead_letter
+///
+/// `decode_dead_letter` decodes the payload of a dead letter returning the reason, the origin, the remote and the original payload
+pub fn decode_dead_letter(data: &[u8]) -> Result<DeadLetter, ProtocolError> {
+    let separator: usize = data
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .ok_or(ProtocolError::BadPacket)?;
+    let header: &str =
+        std::str::from_utf8(&data[..separator]).map_err(|_| ProtocolError::BadPacket)?;
+    let mut fields = header.lines();
+    let mut field = |name: &str| -> Result<&str, ProtocolError> {
+        fields
+            .next()
+            .and_then(|line| line.strip_prefix(name))
+            .and_then(|line| line.strip_prefix(": "))
+            .ok_or(ProtocolError::BadPacket)
+    };
+    let reason: DeadLetterReason = field("reason")?
+        .parse::<DeadLetterReason>()
+        .map_err(|_| ProtocolError::BadPacket)?;
+    let origin: Option<String> = Some(field("origin")?)
+        .filter(|origin| !origin.is_empty())
+        .map(String::from);
+    let remote: Option<String> = Some(field("remote")?)
+        .filter(|remote| !remote.is_empty())
+        .map(String::from);
+    Ok(DeadLetter {
+        reason,
+        origin,
+        remote,
+        data: data[separator + 2..].to_vec(),
+    })
+}
+
+/// ### encode_system_event
+///
+/// `encode_system_event` encodes the payload of a system event: a `name: value` line for each field of the event
+pub fn encode_system_event(event: &SystemEvent) -> Vec<u8> {
+    let fields: Vec<(&str, String)> = match event {
+        SystemEvent::Started(pid) => vec![("pid", pid.to_string())],
+        SystemEvent::Stopping => Vec::new(),
+        SystemEvent::Subscribed(client, groups) => std::iter::once(("client", client.clone()))
+            .chain(groups.iter().map(|group| ("group", group.clone())))
+            .collect(),
+        SystemEvent::Unsubscribed(client, reason) => {
+            vec![("client", client.clone()), ("reason", reason.clone())]
+        }
+        SystemEvent::Died(client, cause) => {
+            vec![("client", client.clone()), ("cause", cause.clone())]
+        }
+    };
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// ### decode_system_event
+///
+/// `decode_system_event` decodes the system event published to the provided group
+pub fn decode_system_event(group: &str, data: &[u8]) -> Result<SystemEvent, ProtocolError> {
+    let payload: &str = std::str::from_utf8(data).map_err(|_| ProtocolError::BadPacket)?;
+    let fields: Vec<(&str, &str)> = payload
+        .lines()
+        .map(|line| line.split_once(": ").ok_or(ProtocolError::BadPacket))
+        .collect::<Result<Vec<(&str, &str)>, ProtocolError>>()?;
+    let field = |name: &str| -> Result<String, ProtocolError> {
+        fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| String::from(*value))
+            .ok_or(ProtocolError::BadPacket)
+    };
+    let event: &str = group
+        .strip_prefix(groups::SYSTEM_GROUP)
+        .and_then(|event| event.strip_prefix(groups::LEVEL_SEPARATOR))
+        .ok_or(ProtocolError::BadPacket)?;
+    match event {
+        "server/started" => field("pid")?
+            .parse::<u32>()
+            .map(SystemEvent::Started)
+            .map_err(|_| ProtocolError::BadPacket),
+        "server/stopping" => Ok(SystemEvent::Stopping),
+        "clients/subscribed" => Ok(SystemEvent::Subscribed(
+            field("client")?,
+            fields
+                .iter()
+                .filter(|(field, _)| *field == "group")
+                .map(|(_, group)| String::from(*group))
+                .collect(),
+        )),
+        "clients/unsubscribed" => Ok(SystemEvent::Unsubscribed(
+            field("client")?,
+            field("reason")?,
+        )),
+        "clients/died" => Ok(SystemEvent::Died(field("client")?, field("cause")?)),
+        _ => Err(ProtocolError::BadPacket),
+    }
+}
+
+impl SystemEvent {
+    /// ### group
+    ///
+    /// `group` returns the group, below the system group, where the event is published
+    pub fn group(&self) -> String {
+        let event: &str = match self {
+            SystemEvent::Started(_) => "server/started",
+            SystemEvent::Stopping => "server/stopping",
+            SystemEvent::Subscribed(_, _) => "clients/subscribed",
+            SystemEvent::Unsubscribed(_, _) => "clients/unsubscribed",
+            SystemEvent::Died(_, _) => "clients/died",
+        };
+        format!(
+            "{}{}{}",
+            groups::SYSTEM_GROUP,
+            groups::LEVEL_SEPARATOR,
+            event
+        )
+    }
+}
+
+impl std::str::FromStr for DeadLetterReason {
+    type Err = ();
+
+    fn from_str(reason: &str) -> Result<DeadLetterReason, ()> {
+        match reason {
+            "no-subscribers" => Ok(DeadLetterReason::NoSubscribers),
+            "write-failed" => Ok(DeadLetterReason::WriteFailed),
+            "expired" => Ok(DeadLetterReason::Expired),
+            "dropped" => Ok(DeadLetterReason::Dropped),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for DeadLetterReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason: &str = match self {
+            DeadLetterReason::NoSubscribers => "no-subscribers",
+            DeadLetterReason::WriteFailed => "write-failed",
+            DeadLetterReason::Expired => "expired",
+            DeadLetterReason::Dropped => "dropped",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description: &str = match self {
+            ProtocolError::Incomplete => "Incomplete packet",
+            ProtocolError::BadPacket => "Bad packet",
+            ProtocolError::BadChecksum => "Bad checksum",
+            ProtocolError::UnsupportedVersion => "Unsupported protocol version",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl fmt::Display for CapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description: &str = match self {
+            CapError::NoError => "No error",
+            CapError::NameAlreadyTaken => "Name already taken",
+            CapError::FileSystemError => "Could not create FIFO",
+            CapError::AccessDenied => "Access denied",
+            CapError::InvalidGroup => "Invalid group",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_message_encode_decode() {
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("TEST_CLIENT")),
+            Some(String::from("BROADCAST")),
+            60,
+            OPTION_RCK,
+            vec![0x48, 0x49],
+        );
+        let packet: Vec<u8> = message.encode();
+        assert_eq!(packet.len(), MINIMUM_SIZE_VERSION_1 + 11 + 9 + 2);
+        assert_eq!(packet[0], SOH);
+        assert_eq!(*packet.last().unwrap(), ETX);
+        //Decode
+        let (decoded, size): (OctopipesMessage, usize) = OctopipesMessage::decode(&packet).unwrap();
+        assert_eq!(size, packet.len());
+        assert_eq!(decoded, message);
+        assert!(decoded.isset_option(OPTION_RCK));
+        assert!(!decoded.isset_option(OPTION_ACK));
+    }
+
+    #[test]
+    fn test_message_decode_stream() {
+        //Two packets in the same buffer, the second is truncated
+        let first: Vec<u8> = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            None,
+            0,
+            0,
+            vec![0x01],
+        )
+        .encode();
+        let second: Vec<u8> = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("bar")),
+            Some(String::from("foo")),
+            0,
+            0,
+            vec![0x01, 0x02, 0x03],
+        )
+        .encode();
+        let mut buffer: Vec<u8> = first.clone();
+        buffer.extend_from_slice(&second[..second.len() - 2]);
+        let (message, size) = OctopipesMessage::decode(&buffer).unwrap();
+        assert_eq!(size, first.len());
+        assert_eq!(message.origin, Some(String::from("foo")));
+        assert_eq!(message.remote, None);
+        assert_eq!(
+            OctopipesMessage::decode(&buffer[size..]).err().unwrap(),
+            ProtocolError::Incomplete
+        );
+    }
+
+    #[test]
+    fn test_message_decode_errors() {
+        let mut packet: Vec<u8> = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("bar")),
+            0,
+            0,
+            vec![0x01, 0x02],
+        )
+        .encode();
+        //Bad checksum
+        let data_index: usize = packet.len() - 2;
+        packet[data_index] = 0xff;
+        assert_eq!(
+            OctopipesMessage::decode(&packet).err().unwrap(),
+            ProtocolError::BadChecksum
+        );
+        //Ignore checksum
+        let packet: Vec<u8> = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("bar")),
+            0,
+            OPTION_ICK,
+            vec![0x01, 0x02],
+        )
+        .encode();
+        assert!(OctopipesMessage::decode(&packet).is_ok());
+        //Bad SOH and version
+        assert_eq!(
+            OctopipesMessage::decode(&[0x05, 0x01, 0x00]).err().unwrap(),
+            ProtocolError::BadPacket
+        );
+        assert_eq!(
+            OctopipesMessage::decode(&[SOH, 0x09, 0x00]).err().unwrap(),
+            ProtocolError::UnsupportedVersion
+        );
+    }
+
+    #[test]
+    fn test_cap_subscription() {
+        let groups: Vec<String> = vec![String::from("SUBSCRIBE"), String::from("SYSTEM")];
+        let payload: Vec<u8> = encode_subscription(&groups);
+        assert_eq!(payload.len(), 2 + 10 + 7);
+        assert_eq!(
+            get_cap_message_type(&payload).unwrap(),
+            CapMessage::Subscription
+        );
+        assert_eq!(decode_subscription(&payload).unwrap(), groups);
+        //Truncated
+        assert!(decode_subscription(&payload[..payload.len() - 1]).is_err());
+        //Unsubscription
+        let payload: Vec<u8> = encode_unsubscription();
+        assert_eq!(
+            get_cap_message_type(&payload).unwrap(),
+            CapMessage::Unsubscription
+        );
+        //Shutdown
+        assert_eq!(
+            get_cap_message_type(&encode_shutdown()).unwrap(),
+            CapMessage::Shutdown
+        );
+        //Ping
+        let payload: Vec<u8> = encode_ping(PingType::Ping);
+        assert_eq!(get_cap_message_type(&payload).unwrap(), CapMessage::Ping);
+        assert_eq!(decode_ping(&payload).unwrap(), PingType::Ping);
+        assert_eq!(
+            decode_ping(&encode_ping(PingType::Pong)).unwrap(),
+            PingType::Pong
+        );
+        assert!(decode_ping(&[0x04]).is_err());
+        assert!(decode_ping(&[0x04, 0x02]).is_err());
+        assert!(decode_ping(&encode_shutdown()).is_err());
+        assert!(get_cap_message_type(&[0x10]).is_err());
+    }
+
+    #[test]
+    fn test_cap_assignment() {
+        let payload: Vec<u8> = encode_assignment(
+            CapError::NoError,
+            Some(("/tmp/foo_tx.fifo", "/tmp/foo_rx.fifo")),
+        );
+        let (error, pipes) = decode_assignment(&payload).unwrap();
+        assert_eq!(error, CapError::NoError);
+        assert_eq!(
+            pipes,
+            Some((
+                String::from("/tmp/foo_tx.fifo"),
+                String::from("/tmp/foo_rx.fifo")
+            ))
+        );
+        let payload: Vec<u8> = encode_assignment(CapError::NameAlreadyTaken, None);
+        assert_eq!(payload, vec![0xff, 0x01]);
+        let (error, pipes) = decode_assignment(&payload).unwrap();
+        assert_eq!(error, CapError::NameAlreadyTaken);
+        assert!(pipes.is_none());
+        let payload: Vec<u8> = encode_assignment(CapError::AccessDenied, None);
+        assert_eq!(payload, vec![0xff, 0x03]);
+        let (error, pipes) = decode_assignment(&payload).unwrap();
+        assert_eq!(error, CapError::AccessDenied);
+        assert!(pipes.is_none());
+        let payload: Vec<u8> = encode_assignment(CapError::InvalidGroup, None);
+        assert_eq!(payload, vec![0xff, 0x04]);
+        let (error, pipes) = decode_assignment(&payload).unwrap();
+        assert_eq!(error, CapError::InvalidGroup);
+        assert!(pipes.is_none());
+    }
+
+    #[test]
+    fn test_dead_letter() {
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("sensors/temp")),
+            60,
+            0,
+            b"21\n\n22".to_vec(),
+        );
+        let payload: Vec<u8> = encode_dead_letter(DeadLetterReason::NoSubscribers, &message);
+        assert_eq!(
+            payload,
+            b"reason: no-subscribers\norigin: foo\nremote: sensors/temp\n\n21\n\n22".to_vec()
+        );
+        assert_eq!(
+            decode_dead_letter(&payload).unwrap(),
+            DeadLetter {
+                reason: DeadLetterReason::NoSubscribers,
+                origin: Some(String::from("foo")),
+                remote: Some(String::from("sensors/temp")),
+                data: b"21\n\n22".to_vec()
+            }
+        );
+        //Without origin
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            None,
+            Some(String::from("bar")),
+            0,
+            0,
+            Vec::new(),
+        );
+        let payload: Vec<u8> = encode_dead_letter(DeadLetterReason::Expired, &message);
+        assert_eq!(
+            decode_dead_letter(&payload).unwrap(),
+            DeadLetter {
+                reason: DeadLetterReason::Expired,
+                origin: None,
+                remote: Some(String::from("bar")),
+                data: Vec::new()
+            }
+        );
+        //Bad payloads
+        assert!(decode_dead_letter(b"reason: expired\norigin: foo\nremote: bar\n").is_err());
+        assert!(decode_dead_letter(b"reason: lost\norigin: foo\nremote: bar\n\n").is_err());
+        assert!(decode_dead_letter(b"reason: expired\nremote: bar\n\n").is_err());
+    }
+
+    #[test]
+    fn test_system_event() {
+        let event: SystemEvent = SystemEvent::Subscribed(
+            String::from("foo"),
+            vec![String::from("foo"), String::from("sensors/#")],
+        );
+        assert_eq!(event.group(), "$SYS/clients/subscribed");
+        let payload: Vec<u8> = encode_system_event(&event);
+        assert_eq!(
+            payload,
+            b"client: foo\ngroup: foo\ngroup: sensors/#\n".to_vec()
+        );
+        assert_eq!(
+            decode_system_event(&event.group(), &payload).unwrap(),
+            event
+        );
+        let event: SystemEvent =
+            SystemEvent::Died(String::from("bar"), String::from("no-heartbeat"));
+        assert_eq!(event.group(), "$SYS/clients/died");
+        assert_eq!(
+            encode_system_event(&event),
+            b"client: bar\ncause: no-heartbeat\n".to_vec()
+        );
+        assert_eq!(
+            decode_system_event(&event.group(), &encode_system_event(&event)).unwrap(),
+            event
+        );
+        let event: SystemEvent =
+            SystemEvent::Unsubscribed(String::from("bar"), String::from("kicked"));
+        assert_eq!(
+            decode_system_event(&event.group(), &encode_system_event(&event)).unwrap(),
+            event
+        );
+        let event: SystemEvent = SystemEvent::Started(1234);
+        assert_eq!(event.group(), "$SYS/server/started");
+        assert_eq!(encode_system_event(&event), b"pid: 1234\n".to_vec());
+        assert_eq!(
+            decode_system_event(&event.group(), b"pid: 1234\n").unwrap(),
+            event
+        );
+        assert_eq!(SystemEvent::Stopping.group(), "$SYS/server/stopping");
+        assert!(encode_system_event(&SystemEvent::Stopping).is_empty());
+        assert_eq!(
+            decode_system_event("$SYS/server/stopping", b"").unwrap(),
+            SystemEvent::Stopping
+        );
+        //Bad events
+        assert!(decode_system_event("$SYS/server/started", b"pid: foo\n").is_err());
+        assert!(decode_system_event("$SYS/clients/died", b"client: foo\n").is_err());
+        assert!(decode_system_event("$SYS/clients/left", b"client: foo\n").is_err());
+        assert!(decode_system_event("clients/died", b"client: foo\ncause: x\n").is_err());
+        assert!(decode_system_event("$SYS/server/stopping", b"garbage").is_err());
+    }
+}
@@ -0,0 +1,4966 @@
+//! ### server
+//!
+//! `server` is the module which implements the Octopipes Server: it serves the CAP and routes the messages between the clients.
+//! The server is driven by a single event loop which waits for readiness on the CAP, on the clients' TX pipes and on a self pipe.
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::acl::{Access, Acl};
+use crate::admin::{AdminCommand, AdminFormat, AdminReply, AdminRequest, AdminSocket};
+use crate::capture::{Capture, CaptureFilter, CaptureRecord};
+use crate::groups::{self, GroupTree, Subscription};
+use crate::metrics::{self, Exposition, HttpRequest, Metrics, MetricsListener};
+use crate::pipes::{self, Ownership};
+use crate::poller::{Notifier, PollEvent, Poller, SelfPipe};
+use crate::process;
+use crate::protocol::{
+    self, CapError, CapMessage, DeadLetterReason, OctopipesMessage, PingType, ProtocolError,
+    ProtocolVersion, SystemEvent,
+};
+use crate::status::{ClientStatus, Status};
+use crate::store::MessageStore;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const CAP_TOKEN: u64 = 0;
+const NOTIFIER_TOKEN: u64 = 1;
+const ADMIN_TOKEN: u64 = 2;
+const METRICS_TOKEN: u64 = 3;
+const FIRST_CLIENT_TOKEN: u64 = 16;
+
+//Time given to the client to read a reply written on the CAP
+const CAP_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+//Interval used to check whether the client has read the reply
+const CAP_REPLY_CHECK_INTERVAL: Duration = Duration::from_millis(5);
+//TTL for messages with TTL 0
+const DEFAULT_TTL: Duration = Duration::from_secs(5);
+//Max amount of messages queued for each client
+const DEFAULT_QUEUE_SIZE: usize = 1024;
+//Interval used to retry writing the pending messages
+const PENDING_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+//Time given to flush the shutdown notice, when the grace period has already expired
+const SHUTDOWN_NOTICE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// ### OctoServer
+///
+/// `OctoServer` is the Octopipes Server
+pub struct OctoServer {
+    version: ProtocolVersion,
+    cap_path: String,
+    client_dir: String,
+    poller: Poller,
+    self_pipe: SelfPipe,
+    cap: Option<File>,
+    cap_buffer: Vec<u8>,
+    cap_reply: Option<CapReply>,
+    clients: HashMap<String, OctoClient>,
+    //Subscribers of each group, used to route the messages
+    groups: GroupTree,
+    tokens: HashMap<u64, String>,
+    next_token: u64,
+    admin: Option<AdminSocket>,
+    stats: ServerStats,
+    metrics: Metrics,
+    metrics_listener: Option<MetricsListener>,
+    //File where the metrics are written every metrics interval, if any
+    metrics_file: Option<String>,
+    metrics_interval: Duration,
+    next_metrics_export: Instant,
+    //File where the status is written every status interval, if any
+    status_file: Option<String>,
+    status_interval: Duration,
+    next_status_export: Instant,
+    //Effective configuration of the server, reported in the status
+    status_config: Vec<(&'static str, String)>,
+    //Capture file where the routed messages are recorded, if any
+    capture: Option<Capture>,
+    capture_filter: CaptureFilter,
+    //Maximum size of the capture file and rotated capture files kept
+    capture_rotation: (u64, usize),
+    started: Instant,
+    dead_client_check_interval: Duration,
+    max_missed_deliveries: u64,
+    next_dead_client_check: Instant,
+    heartbeat_interval: Duration,
+    heartbeat_missed_beats: u64,
+    next_heartbeat: Instant,
+    default_ttl: Duration,
+    queue_size: usize,
+    overflow_policy: OverflowPolicy,
+    queue_group_policy: SelectionPolicy,
+    //Groups whose subscribers form a queue group
+    queue_groups: BTreeMap<String, SelectionPolicy>,
+    //Messages delivered through a queue group to a member which has failed, to be handed to another member
+    orphans: Vec<(String, PendingMessage)>,
+    //Groups whose last message is retained even without the retain option
+    retained_groups: Vec<String>,
+    //Last message retained for each group, delivered to the clients subscribing later
+    retained: BTreeMap<String, OctopipesMessage>,
+    //Store of the messages for the durable clients, while they're offline
+    store: Option<MessageStore>,
+    //Durable clients, with the groups they were subscribed to the last time
+    durable: BTreeMap<String, Vec<String>>,
+    //Messages which were queued for durable clients which have gone away, to be stored
+    undelivered: Vec<(String, PendingMessage)>,
+    //Group where the messages which couldn't be delivered are republished
+    dead_letter_group: Option<String>,
+    //Messages which couldn't be delivered, to be republished to the dead-letter group
+    dead_letters: Vec<(Vec<u8>, DeadLetterReason)>,
+    //Whether the server publishes its events to the system group
+    system_events: bool,
+    //Events of the server itself, to be published to the system group
+    announcements: Vec<SystemEvent>,
+    acl: Acl,
+    cap_ownership: Ownership,
+    client_ownership: Ownership,
+    client_dir_ownership: Ownership,
+    //Root directory of the server, if it has been changed
+    root: Option<String>,
+}
+
+/// ### OctoClient
+///
+/// `OctoClient` contains the data of a subscribed client
+struct OctoClient {
+    groups: Vec<String>,
+    tx_pipe: String,
+    rx_pipe: String,
+    tx: File,
+    buffer: Vec<u8>,
+    token: u64,
+    process: Option<(u32, u64)>,
+    missed_deliveries: u64,
+    //Heartbeats missed in a row; None if the client doesn't take part in the heartbeat
+    missed_beats: Option<u64>,
+    pending: VecDeque<PendingMessage>,
+    //Whether the TX pipe isn't read, since the queue of a recipient is full
+    blocked: bool,
+}
+
+/// ### PendingMessage
+///
+/// `PendingMessage` is a message which hasn't been written entirely on the recipient's RX pipe yet.
+/// Once expired, it is discarded
+struct PendingMessage {
+    data: Vec<u8>,
+    written: usize,
+    expiration: Instant,
+    //When the message has been queued, to measure the routing latency
+    queued: Instant,
+    //Queue group the message has been delivered through (group, sender), if any
+    queue_group: Option<(String, String)>,
+    //Heartbeat frames aren't messages: they're neither accounted, nor stored, nor republished as dead letters
+    control: bool,
+}
+
+/// ### QueueGroup
+///
+/// `QueueGroup` is the queue group of a subscription, whose members take turns receiving the messages
+struct QueueGroup {
+    group: String,
+    members: Vec<String>,
+    policy: SelectionPolicy,
+}
+
+/// ### CapReply
+///
+/// `CapReply` describes a reply written on the CAP which hasn't been read by the client yet.
+/// While a reply is pending, the server doesn't read from the CAP, otherwise it would read its own reply
+struct CapReply {
+    size: usize,
+    deadline: Instant,
+}
+
+/// ### ServerStats
+///
+/// `ServerStats` contains the counters of the server since it has been started
+#[derive(Copy, Clone, Default, PartialEq, fmt::Debug)]
+pub struct ServerStats {
+    pub subscriptions: u64,
+    pub refused_subscriptions: u64,
+    pub unsubscriptions: u64,
+    pub kicked_clients: u64,
+    pub messages_routed: u64,
+    pub messages_delivered: u64,
+    pub delivery_failures: u64,
+    pub cap_errors: u64,
+    pub client_errors: u64,
+    pub dead_clients: u64,
+    pub expired_messages: u64,
+    pub dropped_messages: u64,
+    pub overflow_disconnections: u64,
+    pub access_denied: u64,
+    pub reassigned_messages: u64,
+    pub retained_deliveries: u64,
+    pub stored_messages: u64,
+    pub replayed_messages: u64,
+    pub dead_letters: u64,
+}
+
+/// ### OverflowPolicy
+///
+/// `OverflowPolicy` describes what happens when a message has to be queued for a client whose queue is full
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum OverflowPolicy {
+    /// The sender isn't read anymore, until there's room in the queue
+    Block,
+    /// The oldest message in the queue is discarded
+    DropOldest,
+    /// The new message is discarded
+    DropNewest,
+    /// The client is unsubscribed
+    Disconnect,
+}
+
+/// ### SelectionPolicy
+///
+/// `SelectionPolicy` describes how the member of a queue group which receives a message is chosen
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum SelectionPolicy {
+    /// Members take turns
+    RoundRobin,
+    /// The member with the fewest queued messages is chosen
+    LeastQueued,
+}
+
+/// ### ServerEvent
+///
+/// `ServerEvent` describes something which happened while processing the server loop
+#[derive(Clone, PartialEq, fmt::Debug)]
+pub enum ServerEvent {
+    /// A client subscribed (client, groups)
+    Subscribed(String, Vec<String>),
+    /// A subscription has been refused (client, error)
+    SubscriptionRefused(String, CapError),
+    /// A client unsubscribed (client)
+    Unsubscribed(String),
+    /// A message has been routed (origin, remote, recipients)
+    Routed(String, String, usize),
+    /// A message has expired before being delivered to a recipient (recipient)
+    MessageExpired(String),
+    /// A message for a recipient has been discarded since the recipient's queue was full (recipient)
+    MessageDropped(String),
+    /// A client has been unsubscribed since its queue was full (client)
+    Overflowed(String),
+    /// A message couldn't be delivered to a recipient (recipient, error)
+    DeliveryFailed(String, ServerError),
+    /// A request on the CAP couldn't be served
+    CapFailed(ServerError),
+    /// A message from a client couldn't be processed (client, error)
+    ClientFailed(String, ServerError),
+    /// A code has been written through the Notifier
+    Notification(u8),
+    /// A client has been forcibly unsubscribed through the admin socket (client)
+    Kicked(String),
+    /// The log level has been changed through the admin socket (level)
+    LogLevelChanged(usize),
+    /// A configuration reload has been requested through the admin socket
+    ReloadRequested,
+    /// The shutdown notice has been delivered to the client (client)
+    ShutdownNotified(String),
+    /// A client has been detected as dead and has been unsubscribed (client, cause)
+    ClientDied(String, DeathCause),
+    /// An operation has been denied by the ACL (client, operation)
+    AccessDenied(String, Access),
+    /// A message delivered through a queue group has been handed to another member (member, new member)
+    Reassigned(String, String),
+    /// The messages retained for the groups a client has subscribed to have been queued for it (client, messages)
+    RetainedDelivered(String, usize),
+    /// Messages for a durable client have been stored, since it was offline (client, messages)
+    Stored(String, usize),
+    /// The messages stored for a durable client have been queued for it, once it has subscribed again (client, messages)
+    Replayed(String, usize),
+    /// A message which couldn't be delivered has been republished to the dead-letter group (origin, remote, reason)
+    DeadLettered(String, String, DeadLetterReason),
+    /// The metrics couldn't be written to the metrics file (error)
+    MetricsFailed(ServerError),
+    /// The status couldn't be written to the status file (error)
+    StatusFailed(ServerError),
+    /// The routed messages couldn't be recorded to the capture file, which has been closed (error)
+    CaptureFailed(ServerError),
+}
+
+/// ### DeathCause
+///
+/// `DeathCause` describes why a client has been considered dead
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum DeathCause {
+    /// The process which had the client's pipes open has terminated (pid)
+    ProcessTerminated(u32),
+    /// The client's RX pipe has been removed
+    PipeRemoved,
+    /// Too many messages in a row couldn't be delivered, since no one was reading the RX pipe
+    NoReader,
+    /// The client hasn't replied to too many heartbeats in a row
+    NoHeartbeat,
+}
+
+/// ### ServerError
+///
+/// `ServerError` describes the kind of error returned by an operation on the OctoServer
+#[derive(Copy, Clone, PartialEq, fmt::Debug)]
+pub enum ServerError {
+    BadPacket,
+    BadChecksum,
+    UnsupportedVersion,
+    OpenFailed,
+    WriteFailed,
+    ReadFailed,
+    PollFailed,
+    NameAlreadyTaken,
+    ClientNotFound,
+    NoRecipient,
+    BadClientDir,
+    AdminFailed,
+    StoreFailed,
+    MetricsFailed,
+    StatusFailed,
+    CaptureFailed,
+    BadOrigin,
+}
+
+impl OctoServer {
+    /// ### new
+    ///
+    /// Instantiates a new OctoServer
+    pub fn new(
+        version: ProtocolVersion,
+        cap_path: String,
+        client_dir: String,
+    ) -> Result<OctoServer, ServerError> {
+        let poller: Poller = Poller::new().map_err(|_| ServerError::PollFailed)?;
+        let self_pipe: SelfPipe = SelfPipe::new().map_err(|_| ServerError::PollFailed)?;
+        poller
+            .register(self_pipe.as_raw_fd(), NOTIFIER_TOKEN)
+            .map_err(|_| ServerError::PollFailed)?;
+        Ok(OctoServer {
+            version,
+            cap_path,
+            client_dir,
+            poller,
+            self_pipe,
+            cap: None,
+            cap_buffer: Vec::new(),
+            cap_reply: None,
+            clients: HashMap::new(),
+            groups: GroupTree::new(),
+            tokens: HashMap::new(),
+            next_token: FIRST_CLIENT_TOKEN,
+            admin: None,
+            stats: ServerStats::default(),
+            metrics: Metrics::new(),
+            metrics_listener: None,
+            metrics_file: None,
+            metrics_interval: Duration::from_secs(0),
+            next_metrics_export: Instant::now(),
+            status_file: None,
+            status_interval: Duration::from_secs(0),
+            next_status_export: Instant::now(),
+            status_config: Vec::new(),
+            capture: None,
+            capture_filter: CaptureFilter::default(),
+            capture_rotation: (0, 0),
+            started: Instant::now(),
+            dead_client_check_interval: Duration::from_secs(0),
+            max_missed_deliveries: 0,
+            next_dead_client_check: Instant::now(),
+            heartbeat_interval: Duration::from_secs(0),
+            heartbeat_missed_beats: 0,
+            next_heartbeat: Instant::now(),
+            default_ttl: DEFAULT_TTL,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            overflow_policy: OverflowPolicy::DropOldest,
+            queue_group_policy: SelectionPolicy::RoundRobin,
+            queue_groups: BTreeMap::new(),
+            orphans: Vec::new(),
+            retained_groups: Vec::new(),
+            retained: BTreeMap::new(),
+            store: None,
+            durable: BTreeMap::new(),
+            undelivered: Vec::new(),
+            dead_letter_group: None,
+            dead_letters: Vec::new(),
+            system_events: false,
+            announcements: Vec::new(),
+            acl: Acl::default(),
+            cap_ownership: Ownership::default(),
+            client_ownership: Ownership::default(),
+            client_dir_ownership: Ownership::default(),
+            root: None,
+        })
+    }
+
+    /// ### notifier
+    ///
+    /// `notifier` returns a Notifier which can be used to wake up the server loop from another thread
+    pub fn notifier(&self) -> Notifier {
+        self.self_pipe.notifier()
+    }
+
+    /// ### set_dead_client_detection
+    ///
+    /// `set_dead_client_detection` configures how dead clients are detected:
+    /// every `check_interval` the server checks whether the client's RX pipe still exists and whether the process
+    /// which had the client's pipes open is still alive; besides, a client is considered dead after `max_missed_deliveries`
+    /// messages in a row expired before being delivered, since no one was reading its RX pipe.
+    /// A zero interval disables the periodic check; zero max missed deliveries disables the check on delivery
+    pub fn set_dead_client_detection(
+        &mut self,
+        check_interval: Duration,
+        max_missed_deliveries: u64,
+    ) {
+        self.dead_client_check_interval = check_interval;
+        self.max_missed_deliveries = max_missed_deliveries;
+        self.next_dead_client_check = Instant::now() + check_interval;
+    }
+
+    /// ### set_heartbeat
+    ///
+    /// `set_heartbeat` configures the heartbeat: every `interval` the server sends a PING to each client which
+    /// has taken part in the heartbeat (by sending a PING to the server on its TX pipe).
+    /// A client which doesn't send anything for `missed_beats` heartbeats in a row is considered dead.
+    /// A zero interval disables the heartbeat
+    pub fn set_heartbeat(&mut self, interval: Duration, missed_beats: u64) {
+        self.heartbeat_interval = interval;
+        self.heartbeat_missed_beats = missed_beats;
+        self.next_heartbeat = Instant::now() + interval;
+    }
+
+    /// ### set_default_ttl
+    ///
+    /// `set_default_ttl` sets the time to live of the messages with TTL 0
+    pub fn set_default_ttl(&mut self, ttl: Duration) {
+        self.default_ttl = ttl;
+    }
+
+    /// ### set_queue_policy
+    ///
+    /// `set_queue_policy` sets the max amount of messages queued for each client (0 means unbounded)
+    /// and what happens when a message has to be queued for a client whose queue is full
+    pub fn set_queue_policy(&mut self, queue_size: usize, overflow_policy: OverflowPolicy) {
+        self.queue_size = queue_size;
+        self.overflow_policy = overflow_policy;
+    }
+
+    /// ### set_queue_groups
+    ///
+    /// `set_queue_groups` sets how the member of a queue group which receives a message is chosen (`policy`)
+    /// and the groups whose subscribers all form a queue group, each with its own policy.
+    /// Besides, clients can join the queue group of any group subscribing with the queue group prefix
+    pub fn set_queue_groups(
+        &mut self,
+        policy: SelectionPolicy,
+        queue_groups: BTreeMap<String, SelectionPolicy>,
+    ) {
+        self.queue_group_policy = policy;
+        self.queue_groups = queue_groups;
+    }
+
+    /// ### set_retained_groups
+    ///
+    /// `set_retained_groups` sets the groups (wildcards are allowed) whose last message is retained,
+    /// as if it had been sent with the retain option. Messages already retained are kept
+    pub fn set_retained_groups(&mut self, retained_groups: Vec<String>) {
+        self.retained_groups = retained_groups;
+    }
+
+    /// ### set_store
+    ///
+    /// `set_store` sets the store where the messages for the durable clients are kept while they're offline
+    pub fn set_store(&mut self, store: MessageStore) {
+        self.store = Some(store);
+    }
+
+    /// ### set_durable_clients
+    ///
+    /// `set_durable_clients` sets the clients whose messages are stored while they're offline and replayed once they subscribe again,
+    /// the max size of the log of each client (in bytes) and the max age of the stored messages; 0 means unbounded
+    pub fn set_durable_clients(&mut self, clients: Vec<String>, max_size: u64, max_age: Duration) {
+        if let Some(store) = self.store.as_mut() {
+            store.set_limits(max_size, max_age);
+        }
+        let mut durable: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for client in clients.into_iter() {
+            //Groups of a client which has never subscribed are unknown: only the messages sent to its name are stored
+            let groups: Vec<String> =
+                match (self.clients.get(&client), self.durable.remove(&client)) {
+                    (Some(subscribed), _) => subscribed.groups.clone(),
+                    (None, Some(groups)) => groups,
+                    (None, None) => self
+                        .store
+                        .as_ref()
+                        .and_then(|store| store.load_groups(&client).ok().flatten())
+                        .unwrap_or_else(|| vec![client.clone()]),
+                };
+            durable.insert(client, groups);
+        }
+        self.durable = durable;
+    }
+
+    /// ### set_dead_letter_group
+    ///
+    /// `set_dead_letter_group` sets the group where the messages which couldn't be delivered are republished,
+    /// wrapped with their origin, their remote and the reason; `None` disables it
+    pub fn set_dead_letter_group(&mut self, group: Option<String>) {
+        self.dead_letter_group = group;
+    }
+
+    /// ### set_system_events
+    ///
+    /// `set_system_events` sets whether the server publishes to the system group the clients subscribing,
+    /// unsubscribing and dying, and its own start and shutdown
+    pub fn set_system_events(&mut self, enabled: bool) {
+        self.system_events = enabled;
+    }
+
+    /// ### set_metrics_file
+    ///
+    /// `set_metrics_file` sets the file where the metrics are periodically written; `None` disables it.
+    /// The file is replaced atomically, so its directory must be writable
+    pub fn set_metrics_file(&mut self, path: Option<String>) {
+        self.metrics_file = path;
+        self.next_metrics_export = Instant::now();
+    }
+
+    /// ### set_metrics_interval
+    ///
+    /// `set_metrics_interval` sets how often the metrics are written to the metrics file
+    pub fn set_metrics_interval(&mut self, interval: Duration) {
+        self.metrics_interval = interval;
+    }
+
+    /// ### set_status_file
+    ///
+    /// `set_status_file` sets the file where the status is periodically written; `None` disables it.
+    /// The file is replaced atomically, so its directory must be writable
+    pub fn set_status_file(&mut self, path: Option<String>) {
+        self.status_file = path;
+        self.next_status_export = Instant::now();
+    }
+
+    /// ### set_status_interval
+    ///
+    /// `set_status_interval` sets how often the status is written to the status file
+    pub fn set_status_interval(&mut self, interval: Duration) {
+        self.status_interval = interval;
+    }
+
+    /// ### set_status_config
+    ///
+    /// `set_status_config` sets the effective configuration reported in the status, as (key, value)
+    pub fn set_status_config(&mut self, config: Vec<(&'static str, String)>) {
+        self.status_config = config;
+    }
+
+    /// ### set_capture_filter
+    ///
+    /// `set_capture_filter` sets which of the routed messages are recorded to the capture file
+    pub fn set_capture_filter(&mut self, filter: CaptureFilter) {
+        self.capture_filter = filter;
+    }
+
+    /// ### set_capture_rotation
+    ///
+    /// `set_capture_rotation` sets the maximum size of the capture file (0 means unlimited) and how many rotated files are kept
+    pub fn set_capture_rotation(&mut self, max_size: u64, max_files: usize) {
+        self.capture_rotation = (max_size, max_files);
+        if let Some(capture) = self.capture.as_mut() {
+            capture.set_rotation(max_size, max_files);
+        }
+    }
+
+    /// ### set_acl
+    ///
+    /// `set_acl` sets the access control list checked on subscriptions and sends.
+    /// The ACL applies to the following requests; clients already subscribed are kept
+    pub fn set_acl(&mut self, acl: Acl) {
+        self.acl = acl;
+    }
+
+    /// ### set_pipes_ownership
+    ///
+    /// `set_pipes_ownership` sets the mode, owner and group of the CAP, of the client pipes and of the client directory.
+    /// The ownership of the CAP and of the client directory is applied when the server is started
+    pub fn set_pipes_ownership(
+        &mut self,
+        cap: Ownership,
+        client_pipes: Ownership,
+        client_dir: Ownership,
+    ) {
+        self.cap_ownership = cap;
+        self.client_ownership = client_pipes;
+        self.client_dir_ownership = client_dir;
+    }
+
+    /// ### set_root
+    ///
+    /// `set_root` tells the server that its root directory has been changed (chroot) to `root`, after it has been started.
+    /// The paths of the CAP and of the client directory, which must be inside the new root, are translated, while the clients
+    /// keep being assigned the pipes with their paths outside of it
+    pub fn set_root(&mut self, root: &str) -> Result<(), ServerError> {
+        let inner_path = |path: &str| -> Option<String> {
+            Path::new(path)
+                .strip_prefix(root)
+                .ok()
+                .map(|path| Path::new("/").join(path).display().to_string())
+        };
+        let cap_path: String = inner_path(&self.cap_path).ok_or(ServerError::OpenFailed)?;
+        let client_dir: String = inner_path(&self.client_dir).ok_or(ServerError::BadClientDir)?;
+        //The data directory must be inside the new root only if there are durable clients
+        if let Some(store) = self.store.as_mut() {
+            match inner_path(store.get_data_dir()) {
+                Some(data_dir) => store.set_data_dir(data_dir),
+                None if !self.durable.is_empty() => return Err(ServerError::StoreFailed),
+                None => {}
+            }
+        }
+        if let Some(metrics_file) = self.metrics_file.as_deref() {
+            self.metrics_file = Some(inner_path(metrics_file).ok_or(ServerError::MetricsFailed)?);
+        }
+        if let Some(status_file) = self.status_file.as_deref() {
+            self.status_file = Some(inner_path(status_file).ok_or(ServerError::StatusFailed)?);
+        }
+        //The capture file is kept open, but it must be inside the new root to be rotated
+        if let Some(capture) = self.capture.as_mut() {
+            capture.set_path(inner_path(capture.get_path()).ok_or(ServerError::CaptureFailed)?);
+        }
+        self.cap_path = cap_path;
+        self.client_dir = client_dir;
+        self.root = Some(String::from(root));
+        Ok(())
+    }
+
+    /// ### start
+    ///
+    /// `start` creates the client directory and the CAP and starts listening on the CAP.
+    /// The client directory must be owned by the server (or by the owner it's assigned) and must not be writable by other users, unless sticky.
+    /// The client pipes left in the client directory by a previous run are removed and returned
+    pub fn start(&mut self) -> Result<Vec<String>, ServerError> {
+        let euid: u32 = unsafe { libc::geteuid() };
+        let owners: [u32; 2] = [euid, self.client_dir_ownership.owner.unwrap_or(euid)];
+        if std::fs::create_dir_all(&self.client_dir).is_err()
+            || self
+                .client_dir_ownership
+                .apply_dir(&self.client_dir, &owners)
+                .is_err()
+        {
+            return Err(ServerError::BadClientDir);
+        }
+        let removed_pipes: Vec<String> = self.remove_leftover_pipes()?;
+        //The data directory is private to the server, which must be able to write it after dropping privileges
+        if let Some(store) = self.store.as_ref().filter(|_| !self.durable.is_empty()) {
+            let ownership: Ownership = Ownership {
+                mode: Some(0o700),
+                ..self.client_dir_ownership
+            };
+            if std::fs::create_dir_all(store.get_data_dir()).is_err()
+                || ownership.apply_dir(store.get_data_dir(), &owners).is_err()
+            {
+                return Err(ServerError::StoreFailed);
+            }
+        }
+        if let Some(cap_dir) = Path::new(&self.cap_path).parent() {
+            if std::fs::create_dir_all(cap_dir).is_err() {
+                return Err(ServerError::OpenFailed);
+            }
+        }
+        if pipes::pipe_create(&self.cap_path).is_err() {
+            return Err(ServerError::OpenFailed);
+        }
+        if self.cap_ownership.apply_pipe(&self.cap_path).is_err() {
+            let _ = pipes::pipe_delete(&self.cap_path);
+            return Err(ServerError::OpenFailed);
+        }
+        self.open_cap()?;
+        if self.system_events {
+            self.announcements
+                .push(SystemEvent::Started(std::process::id()));
+        }
+        Ok(removed_pipes)
+    }
+
+    /// ### start_admin
+    ///
+    /// `start_admin` creates the admin socket in the provided path and starts serving admin requests
+    pub fn start_admin(&mut self, path: &str) -> Result<(), ServerError> {
+        if let Some(socket_dir) = Path::new(path).parent() {
+            if std::fs::create_dir_all(socket_dir).is_err() {
+                return Err(ServerError::AdminFailed);
+            }
+        }
+        let admin: AdminSocket = AdminSocket::bind(path).map_err(|_| ServerError::AdminFailed)?;
+        self.poller
+            .register(admin.as_raw_fd(), ADMIN_TOKEN)
+            .map_err(|_| ServerError::PollFailed)?;
+        self.admin = Some(admin);
+        Ok(())
+    }
+
+    /// ### start_metrics
+    ///
+    /// `start_metrics` starts serving the metrics over HTTP on the provided address (e.g. `127.0.0.1:9464`)
+    pub fn start_metrics(&mut self, address: &str) -> Result<(), ServerError> {
+        let listener: MetricsListener =
+            MetricsListener::bind(address).map_err(|_| ServerError::MetricsFailed)?;
+        self.poller
+            .register(listener.as_raw_fd(), METRICS_TOKEN)
+            .map_err(|_| ServerError::PollFailed)?;
+        self.metrics_listener = Some(listener);
+        Ok(())
+    }
+
+    /// ### start_capture
+    ///
+    /// `start_capture` starts recording the routed messages which match the capture filter to the capture file
+    pub fn start_capture(&mut self, path: &str) -> Result<(), ServerError> {
+        let mut capture: Capture = Capture::open(path).map_err(|_| ServerError::CaptureFailed)?;
+        capture.set_rotation(self.capture_rotation.0, self.capture_rotation.1);
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    /// ### stop
+    ///
+    /// `stop` removes all the clients and their pipes and deletes the CAP
+    pub fn stop(&mut self) {
+        for client in self.get_clients() {
+            let _ = self.remove_client(&client);
+        }
+        self.orphans.clear();
+        self.dead_letters.clear();
+        self.announcements.clear();
+        self.store_undelivered(&mut Vec::new());
+        self.close_cap();
+        let _ = pipes::pipe_delete(&self.cap_path);
+        self.stop_admin();
+        self.stop_metrics();
+        //Dropping the capture flushes it
+        self.capture = None;
+        //A status file left behind would describe a server which isn't running anymore
+        if let Some(status_file) = self.status_file.as_deref() {
+            let _ = std::fs::remove_file(status_file);
+        }
+    }
+
+    /// ### shutdown
+    ///
+    /// `shutdown` stops the server gracefully: the CAP is closed, so no new client can subscribe,
+    /// then the messages already written by the clients are routed, until none is left or the grace period expires.
+    /// Eventually a SHUTDOWN notice is queued for every client, the queues are flushed until the grace period expires
+    /// and the server is stopped
+    pub fn shutdown(&mut self, grace_period: Duration) -> Vec<ServerEvent> {
+        let mut events: Vec<ServerEvent> = Vec::new();
+        let t_start: Instant = Instant::now();
+        self.close_cap();
+        let _ = pipes::pipe_delete(&self.cap_path);
+        self.cap_reply = None;
+        if self.system_events {
+            self.announcements.push(SystemEvent::Stopping);
+            self.publish_system_events(&mut events, 0);
+        }
+        //Drain clients' TX pipes
+        while self.has_pending_messages() && t_start.elapsed() < grace_period {
+            let remaining: Duration = grace_period - t_start.elapsed();
+            match self.poll(Some(remaining)) {
+                Ok(mut new_events) => events.append(&mut new_events),
+                Err(_) => break,
+            }
+        }
+        //Notify clients: the notice is queued behind the pending messages and flushed within the grace period
+        let deadline: Instant = std::cmp::max(
+            t_start + grace_period,
+            Instant::now() + SHUTDOWN_NOTICE_TIMEOUT,
+        );
+        let clients: Vec<String> = self.get_clients();
+        for client_id in clients.iter() {
+            let notice: OctopipesMessage = OctopipesMessage::new(
+                self.version,
+                None,
+                Some(client_id.clone()),
+                0,
+                0,
+                protocol::encode_shutdown(),
+            );
+            if let Some(client) = self.clients.get_mut(client_id) {
+                //The notice outlives the flush, so it's never discarded as expired
+                client.pending.push_back(PendingMessage::control(
+                    notice.encode(),
+                    deadline.saturating_duration_since(Instant::now()) + SHUTDOWN_NOTICE_TIMEOUT,
+                ));
+            }
+        }
+        let mut notice_events: Vec<ServerEvent> = Vec::new();
+        loop {
+            self.flush_pending(&mut notice_events);
+            if Instant::now() >= deadline
+                || self
+                    .clients
+                    .values()
+                    .all(|client| client.pending.is_empty())
+            {
+                break;
+            }
+            std::thread::sleep(PENDING_RETRY_INTERVAL);
+        }
+        events.append(&mut notice_events);
+        for client_id in clients.into_iter() {
+            let failed: bool = events.iter().any(|event| match event {
+                ServerEvent::DeliveryFailed(client, _) => *client == client_id,
+                _ => false,
+            });
+            match self.clients.get(&client_id) {
+                Some(client) if !client.pending.is_empty() => events.push(
+                    ServerEvent::DeliveryFailed(client_id, ServerError::WriteFailed),
+                ),
+                Some(_) if !failed => events.push(ServerEvent::ShutdownNotified(client_id)),
+                _ => {}
+            }
+        }
+        self.stop();
+        events
+    }
+
+    /// ### poll
+    ///
+    /// `poll` waits until the CAP or a client has data available (or timeout expires) and processes it.
+    /// If timeout is None, it waits until something happens
+    pub fn poll(&mut self, timeout: Option<Duration>) -> Result<Vec<ServerEvent>, ServerError> {
+        let mut events: Vec<ServerEvent> = Vec::new();
+        //While a CAP reply is pending, wake up periodically to check whether it has been read
+        let timeout: Option<Duration> = match (&self.cap_reply, timeout) {
+            (None, timeout) => timeout,
+            (Some(_), None) => Some(CAP_REPLY_CHECK_INTERVAL),
+            (Some(_), Some(timeout)) => Some(std::cmp::min(timeout, CAP_REPLY_CHECK_INTERVAL)),
+        };
+        //Wake up to retry writing the pending messages, for the next dead client check and heartbeat too
+        let mut timeout: Option<Duration> = match self
+            .clients
+            .values()
+            .any(|client| !client.pending.is_empty())
+        {
+            true => Some(timeout.map_or(PENDING_RETRY_INTERVAL, |timeout| {
+                std::cmp::min(timeout, PENDING_RETRY_INTERVAL)
+            })),
+            false => timeout,
+        };
+        //Announcements are published right away
+        if !self.announcements.is_empty() {
+            timeout = Some(Duration::from_secs(0));
+        }
+        let metrics_interval: Duration = match self.metrics_file {
+            Some(_) => self.metrics_interval,
+            None => Duration::from_secs(0),
+        };
+        let status_interval: Duration = match self.status_file {
+            Some(_) => self.status_interval,
+            None => Duration::from_secs(0),
+        };
+        //Wake up to close the idle connections
+        let next_idle_check: Option<Instant> = self
+            .admin
+            .as_ref()
+            .and_then(|admin| admin.next_idle_check())
+            .into_iter()
+            .chain(
+                self.metrics_listener
+                    .as_ref()
+                    .and_then(|listener| listener.next_idle_check()),
+            )
+            .min();
+        if let Some(next_time) = next_idle_check {
+            let next_time: Duration = next_time.saturating_duration_since(Instant::now());
+            timeout = Some(timeout.map_or(next_time, |timeout| std::cmp::min(timeout, next_time)));
+        }
+        for (interval, next_time) in [
+            (self.dead_client_check_interval, self.next_dead_client_check),
+            (self.heartbeat_interval, self.next_heartbeat),
+            (metrics_interval, self.next_metrics_export),
+            (status_interval, self.next_status_export),
+        ]
+        .iter()
+        {
+            if interval.as_millis() > 0 {
+                let next_time: Duration = next_time.saturating_duration_since(Instant::now());
+                timeout =
+                    Some(timeout.map_or(next_time, |timeout| std::cmp::min(timeout, next_time)));
+            }
+        }
+        let ready: Vec<PollEvent> = self
+            .poller
+            .wait(timeout)
+            .map_err(|_| ServerError::PollFailed)?;
+        for event in ready.iter() {
+            match event.token {
+                CAP_TOKEN => self.read_cap(&mut events),
+                NOTIFIER_TOKEN => {
+                    for code in self.self_pipe.drain() {
+                        events.push(ServerEvent::Notification(code));
+                    }
+                }
+                ADMIN_TOKEN => self.accept_admin(),
+                METRICS_TOKEN => self.accept_metrics(),
+                token => {
+                    if let Some(client) = self.tokens.get(&token).cloned() {
+                        self.read_client(&client, &mut events);
+                    } else if self
+                        .metrics_listener
+                        .as_ref()
+                        .is_some_and(|listener| listener.has_connection(token))
+                    {
+                        if event.writable {
+                            self.write_metrics(token);
+                        }
+                        if event.readable || event.hangup {
+                            self.read_metrics(token);
+                        }
+                    } else {
+                        if event.writable {
+                            self.write_admin(token);
+                        }
+                        if event.readable || event.hangup {
+                            self.read_admin(token, &mut events);
+                        }
+                    }
+                }
+            }
+        }
+        self.close_idle_connections();
+        self.check_cap_reply(&mut events);
+        self.flush_pending(&mut events);
+        self.unblock_clients(&mut events);
+        self.check_dead_clients(&mut events);
+        self.check_heartbeats(&mut events);
+        self.reassign_orphans(&mut events);
+        self.store_undelivered(&mut events);
+        //Publishing system events and dead letters may remove more clients, which are announced as well
+        let mut examined: usize = 0;
+        loop {
+            examined = self.publish_system_events(&mut events, examined);
+            self.route_dead_letters(&mut events);
+            if examined == events.len() {
+                break;
+            }
+        }
+        self.stats.account(&events);
+        self.flush_capture(&mut events);
+        self.export_metrics(&mut events);
+        self.export_status(&mut events);
+        Ok(events)
+    }
+
+    //@! Getters
+
+    /// ### get_clients
+    ///
+    /// `get_clients` returns the id of all the subscribed clients
+    pub fn get_clients(&self) -> Vec<String> {
+        let mut clients: Vec<String> = self.clients.keys().cloned().collect();
+        clients.sort();
+        clients
+    }
+
+    /// ### get_subscriptions
+    ///
+    /// `get_subscriptions` returns the groups the client is subscribed to
+    pub fn get_subscriptions(&self, client: &str) -> Option<Vec<String>> {
+        self.clients.get(client).map(|client| client.groups.clone())
+    }
+
+    /// ### get_groups
+    ///
+    /// `get_groups` returns all the groups with their subscribers, sorted by name
+    pub fn get_groups(&self) -> Vec<(String, Vec<String>)> {
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (client_id, client) in self.clients.iter() {
+            for group in client.groups.iter() {
+                groups
+                    .entry(group.clone())
+                    .or_default()
+                    .push(client_id.clone());
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(group, mut clients)| {
+                clients.sort();
+                (group, clients)
+            })
+            .collect()
+    }
+
+    /// ### get_stats
+    ///
+    /// `get_stats` returns the server counters
+    pub fn get_stats(&self) -> ServerStats {
+        self.stats
+    }
+
+    /// ### get_uptime
+    ///
+    /// `get_uptime` returns the time elapsed since the server has been instantiated
+    pub fn get_uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// ### get_metrics
+    ///
+    /// `get_metrics` returns the metrics of the server in the Prometheus text format
+    pub fn get_metrics(&self) -> String {
+        let mut exposition: Exposition = Exposition::new();
+        let gauges: [(&str, &str, u64); 4] = [
+            (
+                "octopipes_uptime_seconds",
+                "Time elapsed since the server has been started",
+                self.get_uptime().as_secs(),
+            ),
+            (
+                "octopipes_clients",
+                "Subscribed clients",
+                self.clients.len() as u64,
+            ),
+            (
+                "octopipes_active_subscriptions",
+                "Groups the clients are subscribed to, for each client",
+                self.clients
+                    .values()
+                    .map(|client| client.groups.len() as u64)
+                    .sum(),
+            ),
+            (
+                "octopipes_retained_messages",
+                "Messages retained for the groups",
+                self.retained.len() as u64,
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            exposition.family(name, "gauge", help);
+            exposition.sample(name, &[], value);
+        }
+        exposition.family(
+            "octopipes_group_subscribers",
+            "gauge",
+            "Clients subscribed to the group",
+        );
+        for (group, clients) in self.get_groups().iter() {
+            exposition.sample(
+                "octopipes_group_subscribers",
+                &[("group", group)],
+                clients.len(),
+            );
+        }
+        exposition.family(
+            "octopipes_queue_depth",
+            "gauge",
+            "Messages queued for the client",
+        );
+        for (client, depth) in self.get_queues().iter() {
+            exposition.sample("octopipes_queue_depth", &[("client", client)], depth);
+        }
+        for (name, help, value) in self.stats.counters().iter() {
+            let name: String = format!("octopipes_{}_total", name);
+            exposition.family(&name, "counter", help);
+            exposition.sample(&name, &[], value);
+        }
+        self.metrics.render(&mut exposition);
+        exposition.into_string()
+    }
+
+    /// ### get_status
+    ///
+    /// `get_status` returns a snapshot of the state of the server: its configuration, the subscribed clients and the counters
+    pub fn get_status(&self) -> Status {
+        let mut clients: Vec<ClientStatus> = self
+            .clients
+            .iter()
+            .map(|(name, client)| ClientStatus {
+                name: name.clone(),
+                groups: client.groups.clone(),
+                tx_pipe: self.outer_path(&client.tx_pipe),
+                rx_pipe: self.outer_path(&client.rx_pipe),
+                queued: client.pending.len(),
+            })
+            .collect();
+        clients.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut counters: Vec<(&'static str, u64)> = self
+            .stats
+            .counters()
+            .into_iter()
+            .map(|(name, _, value)| (name, value))
+            .collect();
+        counters.push(("bytes_routed", self.metrics.bytes_routed()));
+        Status {
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            protocol_version: self.version as u8,
+            pid: std::process::id(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            uptime: self.get_uptime(),
+            config: self.status_config.clone(),
+            clients,
+            counters,
+        }
+    }
+
+    /// ### get_metrics_address
+    ///
+    /// `get_metrics_address` returns the address the metrics are served on, if the listener has been started
+    pub fn get_metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// ### get_queues
+    ///
+    /// `get_queues` returns the amount of messages queued for each client, sorted by client
+    pub fn get_queues(&self) -> Vec<(String, usize)> {
+        let mut queues: Vec<(String, usize)> = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| (client_id.clone(), client.pending.len()))
+            .collect();
+        queues.sort();
+        queues
+    }
+
+    //@! CAP
+
+    /// ### open_cap
+    ///
+    /// `open_cap` opens the CAP for reading and registers it to the poller
+    fn open_cap(&mut self) -> Result<(), ServerError> {
+        let cap: File =
+            pipes::pipe_open_read(&self.cap_path).map_err(|_| ServerError::OpenFailed)?;
+        self.poller
+            .register(cap.as_raw_fd(), CAP_TOKEN)
+            .map_err(|_| ServerError::PollFailed)?;
+        self.cap = Some(cap);
+        Ok(())
+    }
+
+    /// ### close_cap
+    ///
+    /// `close_cap` deregisters and closes the CAP
+    fn close_cap(&mut self) {
+        if let Some(cap) = self.cap.take() {
+            let _ = self.poller.deregister(cap.as_raw_fd());
+        }
+    }
+
+    /// ### read_cap
+    ///
+    /// `read_cap` reads the available data on the CAP and serves the requests
+    fn read_cap(&mut self, events: &mut Vec<ServerEvent>) {
+        let status = match self.cap.as_mut() {
+            Some(cap) => pipes::pipe_read(cap, &mut self.cap_buffer),
+            None => return,
+        };
+        match status {
+            Ok(status) => {
+                //When all the writers have closed the CAP, it must be reopened, otherwise it would be always ready
+                if status.eof {
+                    self.close_cap();
+                    if let Err(err) = self.open_cap() {
+                        events.push(ServerEvent::CapFailed(err));
+                    }
+                }
+            }
+            Err(_) => events.push(ServerEvent::CapFailed(ServerError::ReadFailed)),
+        }
+        self.process_cap_buffer(events);
+    }
+
+    /// ### process_cap_buffer
+    ///
+    /// `process_cap_buffer` serves the requests in the CAP buffer, until a reply has to be read by a client
+    fn process_cap_buffer(&mut self, events: &mut Vec<ServerEvent>) {
+        while self.cap_reply.is_none() && !self.cap_buffer.is_empty() {
+            match OctopipesMessage::decode(&self.cap_buffer) {
+                Ok((message, size)) => {
+                    self.cap_buffer.drain(..size);
+                    let first_event: usize = events.len();
+                    self.manage_cap_message(&message, events);
+                    self.account_cap_request(&message, &events[first_event..]);
+                }
+                Err(ProtocolError::Incomplete) => break,
+                Err(err) => {
+                    self.cap_buffer.clear();
+                    events.push(ServerEvent::CapFailed(ServerError::from(err)));
+                    self.metrics.account_cap_request("unknown", "error");
+                }
+            }
+        }
+    }
+
+    /// ### check_cap_reply
+    ///
+    /// `check_cap_reply` checks whether the pending reply has been read by the client; if so, it restarts listening on the CAP.
+    /// If the reply hasn't been read before the timeout, listening is restarted anyway and the reply will be discarded.
+    fn check_cap_reply(&mut self, events: &mut Vec<ServerEvent>) {
+        let reply_read: bool = match (&self.cap_reply, &self.cap) {
+            (Some(reply), Some(cap)) => match pipes::pipe_bytes_available(cap) {
+                Ok(available) => available < reply.size || Instant::now() >= reply.deadline,
+                Err(_) => true,
+            },
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if reply_read {
+            self.cap_reply = None;
+            if let Some(cap) = self.cap.as_ref() {
+                if self.poller.register(cap.as_raw_fd(), CAP_TOKEN).is_err() {
+                    events.push(ServerEvent::CapFailed(ServerError::PollFailed));
+                }
+            }
+            //Serve requests received in the meanwhile
+            self.process_cap_buffer(events);
+        }
+    }
+
+    /// ### write_cap
+    ///
+    /// `write_cap` writes a reply for the client on the CAP
+    fn write_cap(&mut self, client: &str, payload: Vec<u8>) -> Result<(), ServerError> {
+        let message: OctopipesMessage = OctopipesMessage::new(
+            self.version,
+            None,
+            Some(String::from(client)),
+            60,
+            0,
+            payload,
+        );
+        let data_out: Vec<u8> = message.encode();
+        //Stop reading from CAP until the client has read the reply
+        if let Some(cap) = self.cap.as_ref() {
+            let _ = self.poller.deregister(cap.as_raw_fd());
+        }
+        self.cap_reply = Some(CapReply {
+            size: data_out.len(),
+            deadline: Instant::now() + CAP_REPLY_TIMEOUT,
+        });
+        pipes::pipe_write(&self.cap_path, &data_out, CAP_REPLY_TIMEOUT)
+            .map_err(|_| ServerError::WriteFailed)
+    }
+
+    /// ### account_cap_request
+    ///
+    /// `account_cap_request` accounts a request served on the CAP in the metrics, by type and result.
+    /// The result is told by the events raised while serving it
+    fn account_cap_request(&mut self, message: &OctopipesMessage, events: &[ServerEvent]) {
+        let request: &'static str = match protocol::get_cap_message_type(&message.data) {
+            Ok(CapMessage::Subscription) => "subscription",
+            Ok(CapMessage::Unsubscription) => "unsubscription",
+            Ok(CapMessage::Shutdown) => "shutdown",
+            Ok(CapMessage::Ping) => "ping",
+            //Stale replies of the server aren't requests
+            Ok(CapMessage::Assignment) => return,
+            Err(_) => "unknown",
+        };
+        let refused: Option<CapError> = events.iter().find_map(|event| match event {
+            ServerEvent::SubscriptionRefused(_, error) => Some(*error),
+            _ => None,
+        });
+        let failed: bool = events.iter().any(|event| {
+            matches!(
+                event,
+                ServerEvent::CapFailed(_) | ServerEvent::ClientFailed(_, _)
+            )
+        });
+        let result: &'static str = match (refused, failed) {
+            (Some(CapError::NameAlreadyTaken), _) => "name_already_taken",
+            (Some(CapError::FileSystemError), _) => "fs",
+            (Some(CapError::AccessDenied), _) => "access_denied",
+            (Some(CapError::InvalidGroup), _) => "invalid_group",
+            (Some(CapError::NoError), _) | (None, true) => "error",
+            (None, false) => "ok",
+        };
+        self.metrics.account_cap_request(request, result);
+    }
+
+    /// ### manage_cap_message
+    ///
+    /// `manage_cap_message` serves a request received on the CAP
+    fn manage_cap_message(&mut self, message: &OctopipesMessage, events: &mut Vec<ServerEvent>) {
+        let cap_message: CapMessage = match protocol::get_cap_message_type(&message.data) {
+            Ok(cap_message) => cap_message,
+            Err(err) => {
+                events.push(ServerEvent::CapFailed(ServerError::from(err)));
+                return;
+            }
+        };
+        //Stale replies of the server are ignored
+        if cap_message == CapMessage::Assignment {
+            return;
+        }
+        let origin: String = match &message.origin {
+            Some(origin) => origin.clone(),
+            None => {
+                events.push(ServerEvent::CapFailed(ServerError::NoRecipient));
+                return;
+            }
+        };
+        match cap_message {
+            CapMessage::Subscription => match protocol::decode_subscription(&message.data) {
+                Ok(groups) => self.manage_subscription(origin, groups, events),
+                Err(err) => events.push(ServerEvent::CapFailed(ServerError::from(err))),
+            },
+            CapMessage::Unsubscription => self.manage_unsubscription(origin, events),
+            //Only the server can send a shutdown notice
+            CapMessage::Shutdown => events.push(ServerEvent::CapFailed(ServerError::BadPacket)),
+            //Clients can check whether the server is alive, before subscribing too
+            CapMessage::Ping => match protocol::decode_ping(&message.data) {
+                Ok(PingType::Ping) => {
+                    if let Err(err) = self.write_cap(&origin, protocol::encode_ping(PingType::Pong))
+                    {
+                        events.push(ServerEvent::CapFailed(err));
+                    }
+                }
+                Ok(PingType::Pong) => events.push(ServerEvent::CapFailed(ServerError::BadPacket)),
+                Err(err) => events.push(ServerEvent::CapFailed(ServerError::from(err))),
+            },
+            CapMessage::Assignment => {}
+        }
+    }
+
+    /// ### manage_subscription
+    ///
+    /// `manage_subscription` handles a subscription request, assigning the pipes to the client if its id is available
+    fn manage_subscription(
+        &mut self,
+        client: String,
+        mut groups: Vec<String>,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        if self.clients.contains_key(&client) {
+            self.refuse_subscription(client, CapError::NameAlreadyTaken, events);
+            return;
+        }
+        //Wildcards are allowed in the groups, but not in the client name, which is a group too
+        if client.contains(['+', '#']) || !groups.iter().all(|group| groups::is_valid_group(group))
+        {
+            self.refuse_subscription(client, CapError::InvalidGroup, events);
+            return;
+        }
+        let denied: Option<Access> = std::iter::once(Access::Claim)
+            .chain(
+                groups
+                    .iter()
+                    .filter(|group| **group != client)
+                    .map(|group| {
+                        Access::Subscribe(String::from(groups::queue_group(group).unwrap_or(group)))
+                    }),
+            )
+            .find(|access| !self.acl.allows(&client, access));
+        if let Some(access) = denied {
+            events.push(ServerEvent::AccessDenied(client.clone(), access));
+            self.refuse_subscription(client, CapError::AccessDenied, events);
+            return;
+        }
+        //Each client is implicitly subscribed to itself
+        if !groups.contains(&client) {
+            groups.push(client.clone());
+        }
+        let (tx_pipe, rx_pipe): (String, String) = match self.create_client(&client, &groups) {
+            Ok(pipes) => pipes,
+            Err(_) => {
+                self.refuse_subscription(client, CapError::FileSystemError, events);
+                return;
+            }
+        };
+        //Clients see the pipes outside of the server's root
+        let (tx_pipe, rx_pipe): (String, String) =
+            (self.outer_path(&tx_pipe), self.outer_path(&rx_pipe));
+        let payload: Vec<u8> =
+            protocol::encode_assignment(CapError::NoError, Some((&tx_pipe, &rx_pipe)));
+        match self.write_cap(&client, payload) {
+            Ok(_) => {
+                events.push(ServerEvent::Subscribed(client.clone(), groups));
+                if self.durable.contains_key(&client) {
+                    self.replay_stored(&client, events);
+                }
+                self.deliver_retained(&client, events);
+            }
+            Err(err) => {
+                let _ = self.remove_client(&client);
+                events.push(ServerEvent::CapFailed(err));
+            }
+        }
+    }
+
+    /// ### manage_unsubscription
+    ///
+    /// `manage_unsubscription` handles an unsubscription request. The client must still be allowed to claim its name
+    fn manage_unsubscription(&mut self, client: String, events: &mut Vec<ServerEvent>) {
+        let allowed: bool = match self.clients.get(&client) {
+            Some(_) => self.acl.allows(&client, &Access::Claim),
+            None => {
+                events.push(ServerEvent::ClientFailed(
+                    client,
+                    ServerError::ClientNotFound,
+                ));
+                return;
+            }
+        };
+        if !allowed {
+            events.push(ServerEvent::AccessDenied(client, Access::Claim));
+            return;
+        }
+        match self.remove_client(&client) {
+            Ok(_) => events.push(ServerEvent::Unsubscribed(client)),
+            Err(err) => events.push(ServerEvent::ClientFailed(client, err)),
+        }
+    }
+
+    /// ### refuse_subscription
+    ///
+    /// `refuse_subscription` sends an ASSIGNMENT with the provided error to the client
+    fn refuse_subscription(
+        &mut self,
+        client: String,
+        error: CapError,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        let payload: Vec<u8> = protocol::encode_assignment(error, None);
+        if let Err(err) = self.write_cap(&client, payload) {
+            events.push(ServerEvent::CapFailed(err));
+        }
+        events.push(ServerEvent::SubscriptionRefused(client, error));
+    }
+
+    /// ### outer_path
+    ///
+    /// `outer_path` returns the path of the provided file outside of the server's root directory
+    fn outer_path(&self, path: &str) -> String {
+        match &self.root {
+            Some(root) => Path::new(root)
+                .join(path.trim_start_matches('/'))
+                .display()
+                .to_string(),
+            None => String::from(path),
+        }
+    }
+
+    /// ### has_pending_messages
+    ///
+    /// `has_pending_messages` returns whether any client has data on its TX pipe which hasn't been routed yet
+    fn has_pending_messages(&self) -> bool {
+        self.clients.values().any(|client| {
+            !client.buffer.is_empty()
+                || !client.pending.is_empty()
+                || pipes::pipe_bytes_available(&client.tx).unwrap_or(0) > 0
+        })
+    }
+
+    //@! Admin
+
+    /// ### stop_admin
+    ///
+    /// `stop_admin` closes all the admin connections and removes the admin socket
+    fn stop_admin(&mut self) {
+        if let Some(admin) = self.admin.take() {
+            for token in admin.tokens() {
+                if let Some(fd) = admin.connection_fd(token) {
+                    let _ = self.poller.deregister(fd);
+                }
+            }
+            let _ = self.poller.deregister(admin.as_raw_fd());
+        }
+    }
+
+    /// ### accept_admin
+    ///
+    /// `accept_admin` accepts the pending connections on the admin socket
+    fn accept_admin(&mut self) {
+        let admin: &mut AdminSocket = match self.admin.as_mut() {
+            Some(admin) => admin,
+            None => return,
+        };
+        while let Some(stream) = admin.accept() {
+            let token: u64 = self.next_token;
+            self.next_token += 1;
+            if self.poller.register(stream.as_raw_fd(), token).is_ok() {
+                admin.add_connection(token, stream);
+            }
+        }
+    }
+
+    /// ### read_admin
+    ///
+    /// `read_admin` reads the requests on an admin connection and replies to them
+    fn read_admin(&mut self, token: u64, events: &mut Vec<ServerEvent>) {
+        let lines: Option<Vec<String>> = match self.admin.as_mut() {
+            Some(admin) if admin.has_connection(token) => admin.read(token),
+            _ => return,
+        };
+        let lines: Vec<String> = match lines {
+            Some(lines) => lines,
+            None => {
+                self.close_admin_connection(token);
+                return;
+            }
+        };
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            let reply: String = match AdminRequest::parse(line) {
+                Ok(request) => self
+                    .manage_admin_command(request.command, events)
+                    .encode(request.format),
+                Err(err) => AdminReply::Error(err).encode(AdminFormat::Text),
+            };
+            let replied: std::io::Result<bool> = match self.admin.as_mut() {
+                Some(admin) => admin.reply(token, &reply),
+                None => return,
+            };
+            if !self.watch_admin_replies(token, replied) {
+                return;
+            }
+        }
+    }
+
+    /// ### write_admin
+    ///
+    /// `write_admin` writes the replies queued on an admin connection, once it's writable
+    fn write_admin(&mut self, token: u64) {
+        let flushed: std::io::Result<bool> = match self.admin.as_mut() {
+            Some(admin) if admin.has_connection(token) => admin.flush(token),
+            _ => return,
+        };
+        self.watch_admin_replies(token, flushed);
+    }
+
+    /// ### watch_admin_replies
+    ///
+    /// `watch_admin_replies` waits for the admin connection to be writable while some replies are queued, or closes it if they couldn't be written.
+    /// Returns whether the connection is still open
+    fn watch_admin_replies(&mut self, token: u64, pending: std::io::Result<bool>) -> bool {
+        let fd: Option<RawFd> = self
+            .admin
+            .as_ref()
+            .and_then(|admin| admin.connection_fd(token));
+        let watched: bool = match (pending, fd) {
+            (Ok(pending), Some(fd)) => self.poller.set_writable(fd, token, pending).is_ok(),
+            _ => false,
+        };
+        if !watched {
+            self.close_admin_connection(token);
+        }
+        watched
+    }
+
+    /// ### close_idle_connections
+    ///
+    /// `close_idle_connections` closes the admin and metrics connections which have been idle for too long
+    fn close_idle_connections(&mut self) {
+        let idle: Vec<u64> = self.admin.as_ref().map_or(Vec::new(), |admin| admin.idle());
+        for token in idle.into_iter() {
+            self.close_admin_connection(token);
+        }
+        let idle: Vec<u64> = self
+            .metrics_listener
+            .as_ref()
+            .map_or(Vec::new(), |listener| listener.idle());
+        for token in idle.into_iter() {
+            self.close_metrics_connection(token);
+        }
+    }
+
+    /// ### close_admin_connection
+    ///
+    /// `close_admin_connection` stops listening on the admin connection and closes it
+    fn close_admin_connection(&mut self, token: u64) {
+        if let Some(admin) = self.admin.as_mut() {
+            if let Some(fd) = admin.connection_fd(token) {
+                let _ = self.poller.deregister(fd);
+            }
+            admin.close(token);
+        }
+    }
+
+    /// ### manage_admin_command
+    ///
+    /// `manage_admin_command` executes an admin command and returns its reply
+    fn manage_admin_command(
+        &mut self,
+        command: AdminCommand,
+        events: &mut Vec<ServerEvent>,
+    ) -> AdminReply {
+        match command {
+            AdminCommand::ListClients => AdminReply::Clients(
+                self.get_clients()
+                    .into_iter()
+                    .map(|client| {
+                        let groups: Vec<String> =
+                            self.get_subscriptions(&client).unwrap_or_default();
+                        (client, groups)
+                    })
+                    .collect(),
+            ),
+            AdminCommand::ListGroups => AdminReply::Groups(self.get_groups()),
+            AdminCommand::Subscriptions(client) => match self.get_subscriptions(&client) {
+                Some(groups) => AdminReply::Subscriptions(client, groups),
+                None => AdminReply::Error(ServerError::ClientNotFound.to_string()),
+            },
+            AdminCommand::Kick(client) => match self.remove_client(&client) {
+                Ok(_) => {
+                    events.push(ServerEvent::Kicked(client.clone()));
+                    AdminReply::Kicked(client)
+                }
+                Err(err) => AdminReply::Error(err.to_string()),
+            },
+            AdminCommand::Stats => {
+                let stats: ServerStats = self.stats;
+                AdminReply::Stats(vec![
+                    ("uptime", self.get_uptime().as_secs()),
+                    ("clients", self.clients.len() as u64),
+                    ("subscriptions", stats.subscriptions),
+                    ("refused_subscriptions", stats.refused_subscriptions),
+                    ("unsubscriptions", stats.unsubscriptions),
+                    ("kicked_clients", stats.kicked_clients),
+                    ("messages_routed", stats.messages_routed),
+                    ("messages_delivered", stats.messages_delivered),
+                    ("delivery_failures", stats.delivery_failures),
+                    ("cap_errors", stats.cap_errors),
+                    ("client_errors", stats.client_errors),
+                    ("dead_clients", stats.dead_clients),
+                    ("expired_messages", stats.expired_messages),
+                    ("dropped_messages", stats.dropped_messages),
+                    ("overflow_disconnections", stats.overflow_disconnections),
+                    ("access_denied", stats.access_denied),
+                    ("reassigned_messages", stats.reassigned_messages),
+                    ("retained_deliveries", stats.retained_deliveries),
+                    ("retained_messages", self.retained.len() as u64),
+                    ("stored_messages", stats.stored_messages),
+                    ("replayed_messages", stats.replayed_messages),
+                    ("dead_letters", stats.dead_letters),
+                    (
+                        "queued_messages",
+                        self.clients
+                            .values()
+                            .map(|client| client.pending.len() as u64)
+                            .sum(),
+                    ),
+                ])
+            }
+            AdminCommand::Queues => AdminReply::Queues(
+                self.get_queues()
+                    .into_iter()
+                    .map(|(client, depth)| (client, depth as u64))
+                    .collect(),
+            ),
+            AdminCommand::LogLevel(level) => {
+                events.push(ServerEvent::LogLevelChanged(level));
+                AdminReply::LogLevel(level)
+            }
+            AdminCommand::Reload => {
+                events.push(ServerEvent::ReloadRequested);
+                AdminReply::Reload
+            }
+        }
+    }
+
+    //@! Metrics
+
+    /// ### stop_metrics
+    ///
+    /// `stop_metrics` closes all the connections to the metrics listener and stops listening
+    fn stop_metrics(&mut self) {
+        if let Some(listener) = self.metrics_listener.take() {
+            for token in listener.tokens() {
+                if let Some(fd) = listener.connection_fd(token) {
+                    let _ = self.poller.deregister(fd);
+                }
+            }
+            let _ = self.poller.deregister(listener.as_raw_fd());
+        }
+    }
+
+    /// ### accept_metrics
+    ///
+    /// `accept_metrics` accepts the pending connections to the metrics listener
+    fn accept_metrics(&mut self) {
+        let listener: &mut MetricsListener = match self.metrics_listener.as_mut() {
+            Some(listener) => listener,
+            None => return,
+        };
+        while let Some(stream) = listener.accept() {
+            let token: u64 = self.next_token;
+            self.next_token += 1;
+            if self.poller.register(stream.as_raw_fd(), token).is_ok() {
+                listener.add_connection(token, stream);
+            }
+        }
+    }
+
+    /// ### read_metrics
+    ///
+    /// `read_metrics` reads the request on a connection to the metrics listener; once complete, it's replied and the connection is closed
+    /// as soon as the response has been written
+    fn read_metrics(&mut self, token: u64) {
+        let request: HttpRequest = match self
+            .metrics_listener
+            .as_mut()
+            .map(|listener| listener.read(token))
+        {
+            Some(Ok(Some(request))) => request,
+            Some(Ok(None)) => return,
+            _ => {
+                self.close_metrics_connection(token);
+                return;
+            }
+        };
+        let page: String = self.get_metrics();
+        let replied: std::io::Result<bool> = match self.metrics_listener.as_mut() {
+            Some(listener) => listener.reply(token, &request, &page),
+            None => return,
+        };
+        self.watch_metrics_response(token, replied);
+    }
+
+    /// ### write_metrics
+    ///
+    /// `write_metrics` writes the response queued on a connection to the metrics listener, once it's writable
+    fn write_metrics(&mut self, token: u64) {
+        let flushed: std::io::Result<bool> = match self.metrics_listener.as_mut() {
+            Some(listener) if listener.has_connection(token) => listener.flush(token),
+            _ => return,
+        };
+        self.watch_metrics_response(token, flushed);
+    }
+
+    /// ### watch_metrics_response
+    ///
+    /// `watch_metrics_response` waits for the connection to the metrics listener to be writable while part of the response is queued;
+    /// the connection is closed once the response has been written, or if it couldn't be
+    fn watch_metrics_response(&mut self, token: u64, pending: std::io::Result<bool>) {
+        let fd: Option<RawFd> = self
+            .metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.connection_fd(token));
+        let watched: bool = match (pending, fd) {
+            (Ok(true), Some(fd)) => self.poller.set_writable(fd, token, true).is_ok(),
+            _ => false,
+        };
+        if !watched {
+            self.close_metrics_connection(token);
+        }
+    }
+
+    /// ### close_metrics_connection
+    ///
+    /// `close_metrics_connection` stops listening on the connection to the metrics listener and closes it
+    fn close_metrics_connection(&mut self, token: u64) {
+        if let Some(listener) = self.metrics_listener.as_mut() {
+            if let Some(fd) = listener.connection_fd(token) {
+                let _ = self.poller.deregister(fd);
+            }
+            listener.close(token);
+        }
+    }
+
+    /// ### export_metrics
+    ///
+    /// `export_metrics` writes the metrics to the metrics file, once the metrics interval has elapsed
+    fn export_metrics(&mut self, events: &mut Vec<ServerEvent>) {
+        let path: &str = match self.metrics_file.as_deref() {
+            Some(path) if Instant::now() >= self.next_metrics_export => path,
+            _ => return,
+        };
+        self.next_metrics_export = Instant::now() + self.metrics_interval;
+        if metrics::write_file(path, &self.get_metrics()).is_err() {
+            events.push(ServerEvent::MetricsFailed(ServerError::MetricsFailed));
+        }
+    }
+
+    /// ### export_status
+    ///
+    /// `export_status` writes the status to the status file, once the status interval has elapsed
+    fn export_status(&mut self, events: &mut Vec<ServerEvent>) {
+        let path: &str = match self.status_file.as_deref() {
+            Some(path) if Instant::now() >= self.next_status_export => path,
+            _ => return,
+        };
+        self.next_status_export = Instant::now() + self.status_interval;
+        if metrics::write_file(path, &self.get_status().to_json()).is_err() {
+            events.push(ServerEvent::StatusFailed(ServerError::StatusFailed));
+        }
+    }
+
+    //@! Capture
+
+    /// ### capture_message
+    ///
+    /// `capture_message` records the message routed from sender to the capture file, if it matches the capture filter.
+    /// If it can't be recorded, the capture is stopped
+    fn capture_message(
+        &mut self,
+        sender: &str,
+        message: &OctopipesMessage,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        let remote: &str = message.remote.as_deref().unwrap_or_default();
+        let capture: &mut Capture = match self.capture.as_mut() {
+            Some(capture) if self.capture_filter.matches(sender, remote) => capture,
+            _ => return,
+        };
+        let record: CaptureRecord = CaptureRecord::now(
+            message.version as u8,
+            message.origin.as_deref().unwrap_or_default(),
+            remote,
+            message.ttl,
+            message.options,
+            message.data.clone(),
+        );
+        if capture.record(&record).is_err() {
+            self.capture = None;
+            events.push(ServerEvent::CaptureFailed(ServerError::CaptureFailed));
+        }
+    }
+
+    /// ### flush_capture
+    ///
+    /// `flush_capture` writes the messages recorded during the loop iteration to the capture file.
+    /// If they can't be written, the capture is stopped
+    fn flush_capture(&mut self, events: &mut Vec<ServerEvent>) {
+        if let Some(capture) = self.capture.as_mut() {
+            if capture.flush().is_err() {
+                self.capture = None;
+                events.push(ServerEvent::CaptureFailed(ServerError::CaptureFailed));
+            }
+        }
+    }
+
+    //@! Clients
+
+    /// ### remove_leftover_pipes
+    ///
+    /// `remove_leftover_pipes` deletes the client pipes (`*_rx.fifo`, `*_tx.fifo`) found in the client directory.
+    /// Files with the name of a client pipe which are not named pipes (e.g. symlinks or regular files) are removed too, since they
+    /// would prevent the client from subscribing; symlinks are removed, not their target. Directories and other files are left untouched
+    fn remove_leftover_pipes(&self) -> Result<Vec<String>, ServerError> {
+        let entries: std::fs::ReadDir = match std::fs::read_dir(&self.client_dir) {
+            Ok(entries) => entries,
+            Err(_) => return Err(ServerError::BadClientDir),
+        };
+        let mut removed_pipes: Vec<String> = Vec::new();
+        for entry in entries.flatten() {
+            let path: String = entry.path().display().to_string();
+            let is_client_pipe: bool = match entry.file_name().to_str() {
+                Some(name) => name.ends_with("_rx.fifo") || name.ends_with("_tx.fifo"),
+                None => false,
+            };
+            //File type is not followed through symlinks
+            let is_dir: bool = entry
+                .file_type()
+                .map_or(true, |file_type| file_type.is_dir());
+            if is_client_pipe && !is_dir && pipes::pipe_delete(&path).is_ok() {
+                removed_pipes.push(path);
+            }
+        }
+        removed_pipes.sort();
+        Ok(removed_pipes)
+    }
+
+    /// ### create_client
+    ///
+    /// `create_client` creates the client's pipes and starts listening on its TX pipe. Returns the pipes (tx, rx)
+    fn create_client(
+        &mut self,
+        client: &str,
+        groups: &[String],
+    ) -> Result<(String, String), ServerError> {
+        //Client id is used as file name
+        if client.is_empty() || client.contains('/') || client == "." || client == ".." {
+            return Err(ServerError::OpenFailed);
+        }
+        let dir: &Path = Path::new(&self.client_dir);
+        let tx_pipe: String = dir
+            .join(format!("{}_tx.fifo", client))
+            .display()
+            .to_string();
+        let rx_pipe: String = dir
+            .join(format!("{}_rx.fifo", client))
+            .display()
+            .to_string();
+        if pipes::pipe_create(&tx_pipe).is_err() {
+            return Err(ServerError::OpenFailed);
+        }
+        if pipes::pipe_create(&rx_pipe).is_err() {
+            let _ = pipes::pipe_delete(&tx_pipe);
+            return Err(ServerError::OpenFailed);
+        }
+        let ownership: Ownership = self.client_ownership;
+        if ownership.apply_pipe(&tx_pipe).is_err() || ownership.apply_pipe(&rx_pipe).is_err() {
+            let _ = pipes::pipe_delete(&tx_pipe);
+            let _ = pipes::pipe_delete(&rx_pipe);
+            return Err(ServerError::OpenFailed);
+        }
+        //Only the client writes on the TX pipe, so it is kept open for writing too to never hit EOF
+        let tx: File = match pipes::pipe_open_read_write(&tx_pipe) {
+            Ok(tx) => tx,
+            Err(_) => {
+                let _ = pipes::pipe_delete(&tx_pipe);
+                let _ = pipes::pipe_delete(&rx_pipe);
+                return Err(ServerError::OpenFailed);
+            }
+        };
+        let token: u64 = self.next_token;
+        self.next_token += 1;
+        if self.poller.register(tx.as_raw_fd(), token).is_err() {
+            let _ = pipes::pipe_delete(&tx_pipe);
+            let _ = pipes::pipe_delete(&rx_pipe);
+            return Err(ServerError::PollFailed);
+        }
+        self.tokens.insert(token, String::from(client));
+        for group in groups.iter() {
+            self.groups.insert(group, client);
+        }
+        self.clients.insert(
+            String::from(client),
+            OctoClient {
+                groups: groups.to_vec(),
+                tx_pipe: tx_pipe.clone(),
+                rx_pipe: rx_pipe.clone(),
+                tx,
+                buffer: Vec::new(),
+                token,
+                process: None,
+                missed_deliveries: 0,
+                missed_beats: None,
+                pending: VecDeque::new(),
+                blocked: false,
+            },
+        );
+        Ok((tx_pipe, rx_pipe))
+    }
+
+    /// ### remove_client
+    ///
+    /// `remove_client` stops listening on the client's TX pipe and deletes its pipes
+    fn remove_client(&mut self, client: &str) -> Result<(), ServerError> {
+        match self.clients.remove(client) {
+            Some(removed) => {
+                let _ = self.poller.deregister(removed.tx.as_raw_fd());
+                self.tokens.remove(&removed.token);
+                self.metrics.remove_client(client);
+                for group in removed.groups.iter() {
+                    self.groups.remove(group, client);
+                }
+                let _ = pipes::pipe_delete(&removed.tx_pipe);
+                let _ = pipes::pipe_delete(&removed.rx_pipe);
+                //Messages delivered through a queue group are handed to another member,
+                //while the other messages for a durable client are stored
+                let durable: bool = self.durable.contains_key(client);
+                for message in removed.pending.into_iter() {
+                    if message.control {
+                        continue;
+                    } else if message.queue_group.is_some() {
+                        self.orphans.push((String::from(client), message));
+                    } else if durable {
+                        self.undelivered.push((String::from(client), message));
+                    }
+                }
+                Ok(())
+            }
+            None => Err(ServerError::ClientNotFound),
+        }
+    }
+
+    /// ### read_client
+    ///
+    /// `read_client` reads the available data on the client's TX pipe and routes the received messages
+    fn read_client(&mut self, client_id: &str, events: &mut Vec<ServerEvent>) {
+        if let Some(client) = self.clients.get_mut(client_id) {
+            if pipes::pipe_read(&mut client.tx, &mut client.buffer).is_err() {
+                events.push(ServerEvent::ClientFailed(
+                    String::from(client_id),
+                    ServerError::ReadFailed,
+                ));
+            }
+        }
+        self.process_client_buffer(client_id, events);
+    }
+
+    /// ### process_client_buffer
+    ///
+    /// `process_client_buffer` routes the messages in the client's buffer.
+    /// With the block overflow policy, if the queue of a recipient is full, the message is kept in the buffer
+    /// and the client's TX pipe isn't read anymore, until there's room in the queue
+    fn process_client_buffer(&mut self, client_id: &str, events: &mut Vec<ServerEvent>) {
+        loop {
+            let (message, size): (OctopipesMessage, usize) = match self.clients.get_mut(client_id) {
+                Some(client) if !client.buffer.is_empty() => {
+                    match OctopipesMessage::decode(&client.buffer) {
+                        Ok(decoded) => decoded,
+                        Err(ProtocolError::Incomplete) => return,
+                        Err(err) => {
+                            client.buffer.clear();
+                            events.push(ServerEvent::ClientFailed(
+                                String::from(client_id),
+                                ServerError::from(err),
+                            ));
+                            return;
+                        }
+                    }
+                }
+                _ => return,
+            };
+            //Clients can only send messages on their own behalf
+            if message.origin.as_deref() != Some(client_id) {
+                if let Some(client) = self.clients.get_mut(client_id) {
+                    client.buffer.drain(..size);
+                }
+                events.push(ServerEvent::ClientFailed(
+                    String::from(client_id),
+                    ServerError::BadOrigin,
+                ));
+                continue;
+            }
+            if let Some(remote) = &message.remote {
+                let access: Access = Access::Send(remote.clone());
+                let allowed: bool =
+                    self.clients.contains_key(client_id) && self.acl.allows(client_id, &access);
+                //Denied messages are discarded, as well as the messages sent to the system group
+                if !allowed || groups::is_system_group(remote) {
+                    if let Some(client) = self.clients.get_mut(client_id) {
+                        client.buffer.drain(..size);
+                    }
+                    events.push(ServerEvent::AccessDenied(String::from(client_id), access));
+                    continue;
+                }
+            }
+            if self.overflow_policy == OverflowPolicy::Block
+                && self.is_queue_full(client_id, &message)
+            {
+                self.block_client(client_id);
+                return;
+            }
+            if let Some(client) = self.clients.get_mut(client_id) {
+                client.buffer.drain(..size);
+                //Any message from the client is a heartbeat
+                if client.missed_beats.is_some() {
+                    client.missed_beats = Some(0);
+                }
+            }
+            match message.remote {
+                Some(_) => self.dispatch_message(client_id, &message, events),
+                //Messages without remote are addressed to the server
+                None => self.manage_server_message(client_id, &message, events),
+            }
+        }
+    }
+
+    /// ### is_queue_full
+    ///
+    /// `is_queue_full` returns whether the queue of any recipient of the message is full
+    fn is_queue_full(&self, sender: &str, message: &OctopipesMessage) -> bool {
+        let remote: &String = match (&message.remote, self.queue_size) {
+            (Some(remote), queue_size) if queue_size > 0 => remote,
+            _ => return false,
+        };
+        let is_full = |client_id: &String| -> bool {
+            self.clients
+                .get(client_id)
+                .is_some_and(|client| client.pending.len() >= self.queue_size)
+        };
+        //A queue group is full if all of its members are
+        let (subscribers, queue_groups) = self.route(sender, remote);
+        subscribers.iter().any(is_full)
+            || queue_groups
+                .iter()
+                .any(|queue_group| queue_group.members.iter().all(is_full))
+    }
+
+    /// ### block_client
+    ///
+    /// `block_client` stops reading the client's TX pipe
+    fn block_client(&mut self, client_id: &str) {
+        if let Some(client) = self.clients.get_mut(client_id) {
+            if !client.blocked {
+                client.blocked = true;
+                let _ = self.poller.deregister(client.tx.as_raw_fd());
+            }
+        }
+    }
+
+    /// ### unblock_clients
+    ///
+    /// `unblock_clients` restarts reading the TX pipe of the blocked clients and routes the messages in their buffers.
+    /// A client is blocked again if there's still no room in the recipient's queue
+    fn unblock_clients(&mut self, events: &mut Vec<ServerEvent>) {
+        let mut blocked: Vec<String> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.blocked)
+            .map(|(client_id, _)| client_id.clone())
+            .collect();
+        blocked.sort();
+        for client_id in blocked.iter() {
+            if let Some(client) = self.clients.get_mut(client_id) {
+                client.blocked = false;
+                if self
+                    .poller
+                    .register(client.tx.as_raw_fd(), client.token)
+                    .is_err()
+                {
+                    events.push(ServerEvent::ClientFailed(
+                        client_id.clone(),
+                        ServerError::PollFailed,
+                    ));
+                }
+            }
+            self.process_client_buffer(client_id, events);
+        }
+    }
+
+    /// ### manage_server_message
+    ///
+    /// `manage_server_message` serves a message sent by a client to the server on its TX pipe.
+    /// The only messages addressed to the server are PINGs: a PING is replied with a PONG on the client's RX pipe
+    /// and makes the client take part in the heartbeat
+    fn manage_server_message(
+        &mut self,
+        client_id: &str,
+        message: &OctopipesMessage,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        let ping_type: PingType = match protocol::get_cap_message_type(&message.data) {
+            Ok(CapMessage::Ping) => match protocol::decode_ping(&message.data) {
+                Ok(ping_type) => ping_type,
+                Err(err) => {
+                    events.push(ServerEvent::ClientFailed(
+                        String::from(client_id),
+                        ServerError::from(err),
+                    ));
+                    return;
+                }
+            },
+            _ => {
+                events.push(ServerEvent::ClientFailed(
+                    String::from(client_id),
+                    ServerError::NoRecipient,
+                ));
+                return;
+            }
+        };
+        let version: ProtocolVersion = self.version;
+        let default_ttl: Duration = self.default_ttl;
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.missed_beats = Some(0);
+            if ping_type == PingType::Ping {
+                let reply: OctopipesMessage = OctopipesMessage::new(
+                    version,
+                    None,
+                    Some(String::from(client_id)),
+                    0,
+                    0,
+                    protocol::encode_ping(PingType::Pong),
+                );
+                //The reply is queued, so it can't be written in the middle of a pending message
+                client
+                    .pending
+                    .push_back(PendingMessage::control(reply.encode(), default_ttl));
+            }
+        }
+    }
+
+    /// ### dispatch_message
+    ///
+    /// `dispatch_message` sends the message to all the clients subscribed to its remote, except the sender
+    fn dispatch_message(
+        &mut self,
+        sender: &str,
+        message: &OctopipesMessage,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        let remote: &String = match &message.remote {
+            Some(remote) => remote,
+            None => {
+                events.push(ServerEvent::ClientFailed(
+                    String::from(sender),
+                    ServerError::NoRecipient,
+                ));
+                return;
+            }
+        };
+        let retained: bool = message.isset_option(protocol::OPTION_RET)
+            || self
+                .retained_groups
+                .iter()
+                .any(|filter| groups::filter_matches(filter, remote));
+        if retained {
+            self.retain(remote, message);
+        }
+        let data_out: Vec<u8> = message.encode();
+        let ttl: Duration = match message.ttl {
+            0 => self.default_ttl,
+            ttl => Duration::from_secs(ttl as u64),
+        };
+        let queued: Instant = Instant::now();
+        let expiration: Instant = queued + ttl;
+        //Messages are queued and written on the RX pipes by flush_pending
+        let mut recipients: usize = 0;
+        let mut overflowed: Vec<String> = Vec::new();
+        let (subscribers, queue_groups) = self.route(sender, remote);
+        //Each recipient, with the queue group it has been chosen from
+        let mut deliveries: BTreeMap<String, Option<String>> = subscribers
+            .into_iter()
+            .map(|client_id| (client_id, None))
+            .collect();
+        for queue_group in queue_groups.iter() {
+            let member: String = self.select_member(queue_group);
+            deliveries
+                .entry(member)
+                .or_insert_with(|| Some(queue_group.group.clone()));
+        }
+        let no_subscribers: bool = deliveries.is_empty();
+        for (client_id, queue_group) in deliveries.into_iter() {
+            let client: &mut OctoClient = match self.clients.get_mut(&client_id) {
+                Some(client) => client,
+                None => continue,
+            };
+            if self.queue_size > 0 && client.pending.len() >= self.queue_size {
+                match self.overflow_policy {
+                    OverflowPolicy::DropOldest => {
+                        //A message which has been partially written must be completed
+                        let oldest: usize = match client.pending.front() {
+                            Some(message) if message.written > 0 => 1,
+                            _ => 0,
+                        };
+                        while client.pending.len() >= self.queue_size
+                            && client.pending.len() > oldest
+                        {
+                            if let Some(dropped) = client.pending.remove(oldest) {
+                                if dropped.control {
+                                    continue;
+                                }
+                                events.push(ServerEvent::MessageDropped(client_id.clone()));
+                                self.dead_letters
+                                    .push((dropped.data, DeadLetterReason::Dropped));
+                            }
+                        }
+                        //If the partially written message fills the queue, the new one is dropped
+                        if client.pending.len() >= self.queue_size {
+                            events.push(ServerEvent::MessageDropped(client_id.clone()));
+                            self.dead_letters
+                                .push((data_out.clone(), DeadLetterReason::Dropped));
+                            continue;
+                        }
+                    }
+                    OverflowPolicy::DropNewest => {
+                        events.push(ServerEvent::MessageDropped(client_id.clone()));
+                        self.dead_letters
+                            .push((data_out.clone(), DeadLetterReason::Dropped));
+                        continue;
+                    }
+                    OverflowPolicy::Disconnect => {
+                        overflowed.push(client_id.clone());
+                        continue;
+                    }
+                    //The sender is blocked before dispatching; queue can be full only if its size has been reduced
+                    OverflowPolicy::Block => {}
+                }
+            }
+            client.pending.push_back(PendingMessage {
+                data: data_out.clone(),
+                written: 0,
+                expiration,
+                queued,
+                queue_group: queue_group.map(|group| (group, String::from(sender))),
+                control: false,
+            });
+            recipients += 1;
+        }
+        self.metrics.account_routed(sender, remote, data_out.len());
+        self.capture_message(sender, message, events);
+        let stored: bool = self.store_offline(sender, remote, &data_out, events);
+        //Messages kept for later subscribers aren't lost, while system events are published whether anyone listens or not
+        if no_subscribers && !stored && !retained && !groups::is_system_group(remote) {
+            self.dead_letters
+                .push((data_out, DeadLetterReason::NoSubscribers));
+        }
+        let origin: String = message.origin.clone().unwrap_or_default();
+        events.push(ServerEvent::Routed(origin, remote.clone(), recipients));
+        overflowed.sort();
+        for client in overflowed.into_iter() {
+            if self.remove_client(&client).is_ok() {
+                events.push(ServerEvent::Overflowed(client));
+            }
+        }
+    }
+
+    /// ### retain
+    ///
+    /// `retain` keeps the message as the last value of the remote group, replacing the previous one.
+    /// A message without payload clears the retained message instead
+    fn retain(&mut self, remote: &str, message: &OctopipesMessage) {
+        match message.data.is_empty() {
+            true => {
+                self.retained.remove(remote);
+            }
+            false => {
+                //Clients receiving the retained message later can tell it from a live one
+                let mut retained: OctopipesMessage = message.clone();
+                retained.options |= protocol::OPTION_RET;
+                self.retained.insert(String::from(remote), retained);
+            }
+        }
+    }
+
+    /// ### deliver_retained
+    ///
+    /// `deliver_retained` queues for a client which has just subscribed the messages retained for the groups matching its subscriptions.
+    /// Queue group subscriptions don't receive retained messages, since they've already been handed to a member
+    fn deliver_retained(&mut self, client_id: &str, events: &mut Vec<ServerEvent>) {
+        let queue_groups: &BTreeMap<String, SelectionPolicy> = &self.queue_groups;
+        let client: &mut OctoClient = match self.clients.get_mut(client_id) {
+            Some(client) => client,
+            None => return,
+        };
+        let filters: Vec<String> = client
+            .groups
+            .iter()
+            .filter(|group| {
+                groups::queue_group(group).is_none() && !queue_groups.contains_key(*group)
+            })
+            .cloned()
+            .collect();
+        let limit: usize = match self.queue_size {
+            0 => usize::MAX,
+            queue_size => queue_size,
+        };
+        let now: Instant = Instant::now();
+        let mut delivered: usize = 0;
+        for (group, message) in self.retained.iter() {
+            if delivered >= limit {
+                break;
+            }
+            if !filters
+                .iter()
+                .any(|filter| groups::filter_matches(filter, group))
+            {
+                continue;
+            }
+            let ttl: Duration = match message.ttl {
+                0 => self.default_ttl,
+                ttl => Duration::from_secs(ttl as u64),
+            };
+            client.pending.push_back(PendingMessage {
+                data: message.encode(),
+                written: 0,
+                expiration: now + ttl,
+                queued: now,
+                queue_group: None,
+                control: false,
+            });
+            delivered += 1;
+        }
+        if delivered > 0 {
+            events.push(ServerEvent::RetainedDelivered(
+                String::from(client_id),
+                delivered,
+            ));
+        }
+    }
+
+    /// ### store_offline
+    ///
+    /// `store_offline` stores the message for the durable clients which are offline, whose last subscriptions match the remote.
+    /// Queue group subscriptions are excluded, since the message is handed to the members which are online.
+    /// Returns whether the message has been stored for any client
+    fn store_offline(
+        &mut self,
+        sender: &str,
+        remote: &str,
+        data: &[u8],
+        events: &mut Vec<ServerEvent>,
+    ) -> bool {
+        let offline: Vec<String> = self
+            .durable
+            .iter()
+            .filter(|(client, groups)| {
+                !self.clients.contains_key(*client)
+                    && *client != sender
+                    && groups.iter().any(|group| {
+                        groups::queue_group(group).is_none()
+                            && !self.queue_groups.contains_key(group)
+                            && groups::filter_matches(group, remote)
+                    })
+            })
+            .map(|(client, _)| client.clone())
+            .collect();
+        for client in offline.iter() {
+            self.store_messages(client, vec![data.to_vec()], events);
+        }
+        !offline.is_empty()
+    }
+
+    /// ### store_undelivered
+    ///
+    /// `store_undelivered` stores the messages which were queued for the durable clients which have gone away
+    fn store_undelivered(&mut self, events: &mut Vec<ServerEvent>) {
+        let mut undelivered: BTreeMap<String, Vec<Vec<u8>>> = BTreeMap::new();
+        for (client, message) in self.undelivered.drain(..) {
+            undelivered.entry(client).or_default().push(message.data);
+        }
+        for (client, messages) in undelivered.into_iter() {
+            self.store_messages(&client, messages, events);
+        }
+    }
+
+    /// ### store_messages
+    ///
+    /// `store_messages` appends the messages to the log of the durable client
+    fn store_messages(
+        &mut self,
+        client: &str,
+        messages: Vec<Vec<u8>>,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        let store: &mut MessageStore = match &mut self.store {
+            Some(store) => store,
+            None => return,
+        };
+        match store.append(client, &messages) {
+            Ok(discarded) => {
+                events.push(ServerEvent::Stored(String::from(client), messages.len()));
+                for _ in 0..discarded {
+                    events.push(ServerEvent::MessageDropped(String::from(client)));
+                }
+            }
+            Err(_) => events.push(ServerEvent::DeliveryFailed(
+                String::from(client),
+                ServerError::StoreFailed,
+            )),
+        }
+    }
+
+    /// ### replay_stored
+    ///
+    /// `replay_stored` queues for a durable client which has just subscribed the messages stored while it was offline,
+    /// in the order they've been stored, and remembers its groups
+    fn replay_stored(&mut self, client_id: &str, events: &mut Vec<ServerEvent>) {
+        let (store, client): (&MessageStore, &mut OctoClient) =
+            match (&self.store, self.clients.get_mut(client_id)) {
+                (Some(store), Some(client)) => (store, client),
+                _ => return,
+            };
+        self.durable
+            .insert(String::from(client_id), client.groups.clone());
+        if store.save_groups(client_id, &client.groups).is_err() {
+            events.push(ServerEvent::DeliveryFailed(
+                String::from(client_id),
+                ServerError::StoreFailed,
+            ));
+        }
+        let (messages, expired): (Vec<Vec<u8>>, usize) = match store.take(client_id) {
+            Ok(stored) => stored,
+            Err(_) => {
+                events.push(ServerEvent::DeliveryFailed(
+                    String::from(client_id),
+                    ServerError::StoreFailed,
+                ));
+                return;
+            }
+        };
+        for _ in 0..expired {
+            events.push(ServerEvent::MessageExpired(String::from(client_id)));
+        }
+        //Stored messages aren't bound to the queue size, since the store has its own limits
+        let now: Instant = Instant::now();
+        let replayed: usize = messages.len();
+        for data in messages.into_iter() {
+            let ttl: Duration = match OctopipesMessage::decode(&data) {
+                Ok((message, _)) if message.ttl > 0 => Duration::from_secs(message.ttl as u64),
+                _ => self.default_ttl,
+            };
+            client.pending.push_back(PendingMessage {
+                data,
+                written: 0,
+                expiration: now + ttl,
+                queued: now,
+                queue_group: None,
+                control: false,
+            });
+        }
+        if replayed > 0 {
+            events.push(ServerEvent::Replayed(String::from(client_id), replayed));
+        }
+    }
+
+    /// ### route
+    ///
+    /// `route` returns the clients subscribed to the remote, which receive a copy of the message each,
+    /// and the queue groups matching the remote, where only one member receives the message. The sender is excluded
+    fn route(&self, sender: &str, remote: &str) -> (BTreeSet<String>, Vec<QueueGroup>) {
+        let mut subscribers: BTreeSet<String> = BTreeSet::new();
+        let mut queue_groups: Vec<QueueGroup> = Vec::new();
+        for subscription in self.groups.matches(remote) {
+            if !self.queue_groups.contains_key(&subscription.group) {
+                subscribers.extend(
+                    subscription
+                        .subscribers
+                        .iter()
+                        .filter(|client_id| *client_id != sender)
+                        .cloned(),
+                );
+            }
+            if let Some(queue_group) = self.queue_group(subscription, sender) {
+                queue_groups.push(queue_group);
+            }
+        }
+        (subscribers, queue_groups)
+    }
+
+    /// ### queue_group
+    ///
+    /// `queue_group` returns the queue group of the subscription, without the sender, if it has any member.
+    /// The subscribers of the groups configured as queue groups are members too
+    fn queue_group(&self, subscription: &Subscription, sender: &str) -> Option<QueueGroup> {
+        let (policy, members): (SelectionPolicy, BTreeSet<&String>) =
+            match self.queue_groups.get(&subscription.group) {
+                Some(policy) => (
+                    *policy,
+                    subscription
+                        .members
+                        .iter()
+                        .chain(subscription.subscribers.iter())
+                        .collect(),
+                ),
+                None => (
+                    self.queue_group_policy,
+                    subscription.members.iter().collect(),
+                ),
+            };
+        let members: Vec<String> = members
+            .into_iter()
+            .filter(|client_id| *client_id != sender)
+            .cloned()
+            .collect();
+        match members.is_empty() {
+            true => None,
+            false => Some(QueueGroup {
+                group: subscription.group.clone(),
+                members,
+                policy,
+            }),
+        }
+    }
+
+    /// ### select_member
+    ///
+    /// `select_member` chooses the member of the queue group which receives a message, according to its policy.
+    /// Members are considered starting from the one after the last chosen, so that ties are broken in turn;
+    /// members whose queue is full are skipped, unless all of them are full
+    fn select_member(&mut self, queue_group: &QueueGroup) -> String {
+        let members: &[String] = &queue_group.members;
+        let cursor: usize = self
+            .groups
+            .get(&queue_group.group)
+            .map_or(0, |subscription| subscription.cursor);
+        let candidates: Vec<usize> = (0..members.len())
+            .map(|offset| (cursor + offset) % members.len())
+            .collect();
+        let queued = |index: &usize| -> usize {
+            self.clients
+                .get(&members[*index])
+                .map_or(0, |client| client.pending.len())
+        };
+        let chosen: Option<&usize> = match queue_group.policy {
+            SelectionPolicy::RoundRobin => candidates
+                .iter()
+                .find(|index| self.queue_size == 0 || queued(index) < self.queue_size),
+            SelectionPolicy::LeastQueued => candidates.iter().min_by_key(|index| queued(index)),
+        };
+        let chosen: usize = chosen.or_else(|| candidates.first()).copied().unwrap_or(0);
+        if let Some(subscription) = self.groups.get_mut(&queue_group.group) {
+            subscription.cursor = chosen + 1;
+        }
+        members[chosen].clone()
+    }
+
+    /// ### reassign_orphans
+    ///
+    /// `reassign_orphans` hands the messages delivered through a queue group to a member which has failed,
+    /// or which has gone away, to another member of the queue group. If no other member can take a message, it is discarded
+    fn reassign_orphans(&mut self, events: &mut Vec<ServerEvent>) {
+        let now: Instant = Instant::now();
+        for (failed, mut message) in std::mem::take(&mut self.orphans).into_iter() {
+            let (group, sender): (String, String) = match &message.queue_group {
+                Some(queue_group) => queue_group.clone(),
+                None => continue,
+            };
+            if now >= message.expiration {
+                events.push(ServerEvent::MessageExpired(failed));
+                self.dead_letters
+                    .push((message.data, DeadLetterReason::Expired));
+                continue;
+            }
+            let queue_group: Option<QueueGroup> = self
+                .groups
+                .get(&group)
+                .and_then(|subscription| self.queue_group(subscription, &sender))
+                .map(|mut queue_group| {
+                    queue_group.members.retain(|member| *member != failed);
+                    queue_group
+                })
+                .filter(|queue_group| !queue_group.members.is_empty());
+            let member: Option<String> =
+                queue_group.map(|queue_group| self.select_member(&queue_group));
+            let queue_size: usize = self.queue_size;
+            match member
+                .and_then(|member| self.clients.get_mut(&member).map(|client| (member, client)))
+            {
+                Some((member, client)) if queue_size == 0 || client.pending.len() < queue_size => {
+                    message.written = 0;
+                    client.pending.push_back(message);
+                    events.push(ServerEvent::Reassigned(failed, member));
+                }
+                _ => {
+                    events.push(ServerEvent::MessageDropped(failed));
+                    self.dead_letters
+                        .push((message.data, DeadLetterReason::Dropped));
+                }
+            }
+        }
+    }
+
+    /// ### route_dead_letters
+    ///
+    /// `route_dead_letters` republishes the messages which couldn't be delivered to the dead-letter group, if any.
+    /// Dead letters which can't be delivered in turn are discarded
+    fn route_dead_letters(&mut self, events: &mut Vec<ServerEvent>) {
+        let group: String = match &self.dead_letter_group {
+            Some(group) => group.clone(),
+            None => {
+                self.dead_letters.clear();
+                return;
+            }
+        };
+        while !self.dead_letters.is_empty() {
+            for (data, reason) in std::mem::take(&mut self.dead_letters).into_iter() {
+                let message: OctopipesMessage = match OctopipesMessage::decode(&data) {
+                    Ok((message, _)) => message,
+                    Err(_) => continue,
+                };
+                if message.remote.as_deref() == Some(group.as_str()) {
+                    continue;
+                }
+                events.push(ServerEvent::DeadLettered(
+                    message.origin.clone().unwrap_or_default(),
+                    message.remote.clone().unwrap_or_default(),
+                    reason,
+                ));
+                let dead_letter: OctopipesMessage = OctopipesMessage::new(
+                    self.version,
+                    message.origin.clone(),
+                    Some(group.clone()),
+                    message.ttl,
+                    0,
+                    protocol::encode_dead_letter(reason, &message),
+                );
+                self.dispatch_message("", &dead_letter, events);
+            }
+        }
+    }
+
+    /// ### publish_system_events
+    ///
+    /// `publish_system_events` publishes to the system group the announcements of the server and the clients joining and leaving,
+    /// looking for them in the events from `from` on. Returns the amount of events which have been examined
+    fn publish_system_events(&mut self, events: &mut Vec<ServerEvent>, from: usize) -> usize {
+        let examined: usize = events.len();
+        if !self.system_events {
+            self.announcements.clear();
+            return examined;
+        }
+        self.announcements
+            .extend(events[from..].iter().filter_map(ServerEvent::system_event));
+        for announcement in std::mem::take(&mut self.announcements).into_iter() {
+            let message: OctopipesMessage = OctopipesMessage::new(
+                self.version,
+                None,
+                Some(announcement.group()),
+                0,
+                0,
+                protocol::encode_system_event(&announcement),
+            );
+            self.dispatch_message("", &message, events);
+        }
+        examined
+    }
+
+    /// ### flush_pending
+    ///
+    /// `flush_pending` writes the pending messages on the recipients' RX pipes, without blocking,
+    /// until a pipe can't take more data (e.g. no one is reading it). Expired messages are discarded
+    fn flush_pending(&mut self, events: &mut Vec<ServerEvent>) {
+        let now: Instant = Instant::now();
+        let mut dead_clients: Vec<(String, DeathCause)> = Vec::new();
+        for (client_id, client) in self.clients.iter_mut() {
+            while let Some(message) = client.pending.front_mut() {
+                if now >= message.expiration {
+                    //An expired heartbeat frame is just discarded; the heartbeat accounts the missed beats
+                    if let Some(expired) = client
+                        .pending
+                        .pop_front()
+                        .filter(|expired| !expired.control)
+                    {
+                        self.dead_letters
+                            .push((expired.data, DeadLetterReason::Expired));
+                        client.missed_deliveries += 1;
+                        events.push(ServerEvent::MessageExpired(client_id.clone()));
+                    }
+                    continue;
+                }
+                match pipes::pipe_try_write(&client.rx_pipe, &message.data[message.written..]) {
+                    Ok(bytes) => {
+                        message.written += bytes;
+                        if message.written < message.data.len() {
+                            break;
+                        }
+                        if let Some(delivered) = client
+                            .pending
+                            .pop_front()
+                            .filter(|delivered| !delivered.control)
+                        {
+                            self.metrics.account_delivered(
+                                client_id,
+                                delivered.data.len(),
+                                delivered.queued.elapsed(),
+                            );
+                            client.missed_deliveries = 0;
+                            self.stats.messages_delivered += 1;
+                        }
+                    }
+                    Err(err) => {
+                        //A message delivered through a queue group is handed to another member
+                        if let Some(message) = client
+                            .pending
+                            .pop_front()
+                            .filter(|message| !message.control)
+                        {
+                            match message.queue_group {
+                                Some(_) => self.orphans.push((client_id.clone(), message)),
+                                None => self
+                                    .dead_letters
+                                    .push((message.data, DeadLetterReason::WriteFailed)),
+                            }
+                        }
+                        events.push(ServerEvent::DeliveryFailed(
+                            client_id.clone(),
+                            ServerError::WriteFailed,
+                        ));
+                        if err.kind() == std::io::ErrorKind::NotFound {
+                            dead_clients.push((client_id.clone(), DeathCause::PipeRemoved));
+                            break;
+                        }
+                    }
+                }
+            }
+            if self.max_missed_deliveries > 0
+                && client.missed_deliveries >= self.max_missed_deliveries
+                && !dead_clients.iter().any(|(dead, _)| dead == client_id)
+            {
+                dead_clients.push((client_id.clone(), DeathCause::NoReader));
+            }
+        }
+        dead_clients.sort_by(|a, b| a.0.cmp(&b.0));
+        self.remove_dead_clients(dead_clients, events);
+    }
+
+    //@! Dead clients
+
+    /// ### check_dead_clients
+    ///
+    /// `check_dead_clients` checks, once the check interval has elapsed, whether the clients are still alive.
+    /// The process which owns a client is looked for (through procfs) until found; once it has terminated, the client is dead.
+    /// A client whose RX pipe has been removed is dead too
+    fn check_dead_clients(&mut self, events: &mut Vec<ServerEvent>) {
+        if self.dead_client_check_interval.as_millis() == 0
+            || Instant::now() < self.next_dead_client_check
+        {
+            return;
+        }
+        self.next_dead_client_check = Instant::now() + self.dead_client_check_interval;
+        //Look for the owners of the clients which haven't been identified yet
+        let mut unidentified: HashMap<process::FileId, String> = HashMap::new();
+        for (client_id, client) in self.clients.iter() {
+            if client.process.is_none() {
+                for pipe in [&client.tx_pipe, &client.rx_pipe].iter() {
+                    if let Some(id) = process::file_id(pipe) {
+                        unidentified.insert(id, client_id.clone());
+                    }
+                }
+            }
+        }
+        let files: Vec<process::FileId> = unidentified.keys().copied().collect();
+        for (id, pid) in process::find_owners(&files).iter() {
+            if let Some(client) = self.clients.get_mut(&unidentified[id]) {
+                if client.process.is_none() {
+                    client.process = process::start_time(*pid).map(|start_time| (*pid, start_time));
+                }
+            }
+        }
+        //Check clients
+        let mut dead_clients: Vec<(String, DeathCause)> = Vec::new();
+        for (client_id, client) in self.clients.iter() {
+            if !pipes::pipe_exists(&client.rx_pipe) {
+                dead_clients.push((client_id.clone(), DeathCause::PipeRemoved));
+            } else if let Some((pid, start_time)) = client.process {
+                //Start time is compared too, since the PID may have been reused
+                if process::start_time(pid) != Some(start_time) {
+                    dead_clients.push((client_id.clone(), DeathCause::ProcessTerminated(pid)));
+                }
+            }
+        }
+        dead_clients.sort_by(|a, b| a.0.cmp(&b.0));
+        self.remove_dead_clients(dead_clients, events);
+    }
+
+    /// ### check_heartbeats
+    ///
+    /// `check_heartbeats` sends, once the heartbeat interval has elapsed, a PING to the clients which take part in the heartbeat.
+    /// The clients which haven't sent anything since too many heartbeats are dead
+    fn check_heartbeats(&mut self, events: &mut Vec<ServerEvent>) {
+        if self.heartbeat_interval.as_millis() == 0 || Instant::now() < self.next_heartbeat {
+            return;
+        }
+        self.next_heartbeat = Instant::now() + self.heartbeat_interval;
+        let mut dead_clients: Vec<(String, DeathCause)> = Vec::new();
+        for (client_id, client) in self.clients.iter_mut() {
+            let missed_beats: u64 = match client.missed_beats {
+                Some(missed_beats) => missed_beats,
+                None => continue,
+            };
+            if missed_beats >= self.heartbeat_missed_beats {
+                dead_clients.push((client_id.clone(), DeathCause::NoHeartbeat));
+                continue;
+            }
+            client.missed_beats = Some(missed_beats + 1);
+            let probe: OctopipesMessage = OctopipesMessage::new(
+                self.version,
+                None,
+                Some(client_id.clone()),
+                0,
+                0,
+                protocol::encode_ping(PingType::Ping),
+            );
+            //A probe which can't be written before the next one counts as a missed beat
+            client.pending.push_back(PendingMessage::control(
+                probe.encode(),
+                self.heartbeat_interval,
+            ));
+        }
+        dead_clients.sort_by(|a, b| a.0.cmp(&b.0));
+        self.remove_dead_clients(dead_clients, events);
+    }
+
+    /// ### remove_dead_clients
+    ///
+    /// `remove_dead_clients` unsubscribes the dead clients, so their names can be used again
+    fn remove_dead_clients(
+        &mut self,
+        dead_clients: Vec<(String, DeathCause)>,
+        events: &mut Vec<ServerEvent>,
+    ) {
+        for (client, cause) in dead_clients.into_iter() {
+            if self.remove_client(&client).is_ok() {
+                events.push(ServerEvent::ClientDied(client, cause));
+            }
+        }
+    }
+}
+
+impl ServerStats {
+    /// ### counters
+    ///
+    /// `counters` returns the name, the description and the value of each counter
+    pub fn counters(&self) -> Vec<(&'static str, &'static str, u64)> {
+        vec![
+            (
+                "subscriptions",
+                "Accepted subscriptions",
+                self.subscriptions,
+            ),
+            (
+                "refused_subscriptions",
+                "Refused subscriptions",
+                self.refused_subscriptions,
+            ),
+            (
+                "unsubscriptions",
+                "Unsubscriptions requested by the clients",
+                self.unsubscriptions,
+            ),
+            (
+                "kicked_clients",
+                "Clients kicked through the admin socket",
+                self.kicked_clients,
+            ),
+            ("messages_routed", "Messages routed", self.messages_routed),
+            (
+                "messages_delivered",
+                "Messages written to the clients' pipes",
+                self.messages_delivered,
+            ),
+            (
+                "delivery_failures",
+                "Messages which couldn't be written to a client's pipe",
+                self.delivery_failures,
+            ),
+            ("cap_errors", "Errors on the CAP", self.cap_errors),
+            (
+                "client_errors",
+                "Errors on the clients' pipes",
+                self.client_errors,
+            ),
+            ("dead_clients", "Clients found dead", self.dead_clients),
+            (
+                "expired_messages",
+                "Messages discarded after their TTL expired",
+                self.expired_messages,
+            ),
+            (
+                "dropped_messages",
+                "Messages dropped because a queue was full",
+                self.dropped_messages,
+            ),
+            (
+                "overflow_disconnections",
+                "Clients disconnected because their queue was full",
+                self.overflow_disconnections,
+            ),
+            (
+                "access_denied",
+                "Requests denied by the ACL",
+                self.access_denied,
+            ),
+            (
+                "reassigned_messages",
+                "Messages reassigned to another member of a queue group",
+                self.reassigned_messages,
+            ),
+            (
+                "retained_deliveries",
+                "Retained messages delivered to new subscribers",
+                self.retained_deliveries,
+            ),
+            (
+                "stored_messages",
+                "Messages stored for offline durable clients",
+                self.stored_messages,
+            ),
+            (
+                "replayed_messages",
+                "Stored messages replayed to durable clients",
+                self.replayed_messages,
+            ),
+            (
+                "dead_letters",
+                "Messages republished to the dead-letter group",
+                self.dead_letters,
+            ),
+        ]
+    }
+
+    /// ### account
+    ///
+    /// `account` updates the counters with the events of a server loop iteration
+    fn account(&mut self, events: &[ServerEvent]) {
+        for event in events.iter() {
+            match event {
+                ServerEvent::Subscribed(_, _) => self.subscriptions += 1,
+                ServerEvent::SubscriptionRefused(_, _) => self.refused_subscriptions += 1,
+                ServerEvent::Unsubscribed(_) => self.unsubscriptions += 1,
+                ServerEvent::Kicked(_) => self.kicked_clients += 1,
+                //Delivered messages are counted by flush_pending
+                ServerEvent::Routed(_, _, _) => self.messages_routed += 1,
+                ServerEvent::MessageExpired(_) => self.expired_messages += 1,
+                ServerEvent::MessageDropped(_) => self.dropped_messages += 1,
+                ServerEvent::Overflowed(_) => self.overflow_disconnections += 1,
+                ServerEvent::DeliveryFailed(_, _) => self.delivery_failures += 1,
+                ServerEvent::CapFailed(_) => self.cap_errors += 1,
+                ServerEvent::ClientFailed(_, _) => self.client_errors += 1,
+                ServerEvent::ClientDied(_, _) => self.dead_clients += 1,
+                ServerEvent::AccessDenied(_, _) => self.access_denied += 1,
+                ServerEvent::Reassigned(_, _) => self.reassigned_messages += 1,
+                ServerEvent::RetainedDelivered(_, messages) => {
+                    self.retained_deliveries += *messages as u64
+                }
+                ServerEvent::Stored(_, messages) => self.stored_messages += *messages as u64,
+                ServerEvent::Replayed(_, messages) => self.replayed_messages += *messages as u64,
+                ServerEvent::DeadLettered(_, _, _) => self.dead_letters += 1,
+                ServerEvent::Notification(_)
+                | ServerEvent::LogLevelChanged(_)
+                | ServerEvent::ReloadRequested
+                | ServerEvent::ShutdownNotified(_)
+                | ServerEvent::MetricsFailed(_)
+                | ServerEvent::StatusFailed(_)
+                | ServerEvent::CaptureFailed(_) => {}
+            }
+        }
+    }
+}
+
+impl PendingMessage {
+    /// ### control
+    ///
+    /// `control` instantiates a new heartbeat frame, which is discarded if it can't be written within `ttl`
+    fn control(data: Vec<u8>, ttl: Duration) -> PendingMessage {
+        let now: Instant = Instant::now();
+        PendingMessage {
+            data,
+            written: 0,
+            expiration: now + ttl,
+            queued: now,
+            queue_group: None,
+            control: true,
+        }
+    }
+}
+
+impl ServerEvent {
+    /// ### system_event
+    ///
+    /// `system_event` returns the system event announcing a client joining or leaving, if that's what happened
+    fn system_event(&self) -> Option<SystemEvent> {
+        let left = |client: &String, reason: &str| -> Option<SystemEvent> {
+            Some(SystemEvent::Unsubscribed(
+                client.clone(),
+                String::from(reason),
+            ))
+        };
+        match self {
+            ServerEvent::Subscribed(client, groups) => {
+                Some(SystemEvent::Subscribed(client.clone(), groups.clone()))
+            }
+            ServerEvent::Unsubscribed(client) => left(client, "unsubscribed"),
+            ServerEvent::Kicked(client) => left(client, "kicked"),
+            ServerEvent::Overflowed(client) => left(client, "overflowed"),
+            ServerEvent::ClientDied(client, cause) => Some(SystemEvent::Died(
+                client.clone(),
+                String::from(cause.name()),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl DeathCause {
+    /// ### name
+    ///
+    /// `name` returns the name of the cause, as published in the system events
+    fn name(&self) -> &'static str {
+        match self {
+            DeathCause::ProcessTerminated(_) => "process-terminated",
+            DeathCause::PipeRemoved => "pipe-removed",
+            DeathCause::NoReader => "no-reader",
+            DeathCause::NoHeartbeat => "no-heartbeat",
+        }
+    }
+}
+
+impl Drop for OctoServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl From<ProtocolError> for ServerError {
+    fn from(error: ProtocolError) -> ServerError {
+        match error {
+            ProtocolError::BadChecksum => ServerError::BadChecksum,
+            ProtocolError::UnsupportedVersion => ServerError::UnsupportedVersion,
+            _ => ServerError::BadPacket,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description: &str = match self {
+            ServerError::BadPacket => "Bad packet",
+            ServerError::BadChecksum => "Bad checksum",
+            ServerError::UnsupportedVersion => "Unsupported protocol version",
+            ServerError::OpenFailed => "Could not open pipe",
+            ServerError::WriteFailed => "Could not write to pipe",
+            ServerError::ReadFailed => "Could not read from pipe",
+            ServerError::PollFailed => "Could not poll pipes",
+            ServerError::NameAlreadyTaken => "Name already taken",
+            ServerError::ClientNotFound => "Client not found",
+            ServerError::NoRecipient => "Message has no recipient",
+            ServerError::BadClientDir => "Could not create client directory",
+            ServerError::AdminFailed => "Could not create admin socket",
+            ServerError::StoreFailed => "Could not access the data directory",
+            ServerError::MetricsFailed => "Could not export the metrics",
+            ServerError::StatusFailed => "Could not export the status",
+            ServerError::CaptureFailed => "Could not write the capture file",
+            ServerError::BadOrigin => "Origin is not the sender",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = ();
+
+    fn from_str(policy: &str) -> Result<OverflowPolicy, ()> {
+        match policy {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = match self {
+            OverflowPolicy::Block => "block",
+            OverflowPolicy::DropOldest => "drop-oldest",
+            OverflowPolicy::DropNewest => "drop-newest",
+            OverflowPolicy::Disconnect => "disconnect",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for SelectionPolicy {
+    type Err = ();
+
+    fn from_str(policy: &str) -> Result<SelectionPolicy, ()> {
+        match policy {
+            "round-robin" => Ok(SelectionPolicy::RoundRobin),
+            "least-queued" => Ok(SelectionPolicy::LeastQueued),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for SelectionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = match self {
+            SelectionPolicy::RoundRobin => "round-robin",
+            SelectionPolicy::LeastQueued => "least-queued",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for DeathCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeathCause::ProcessTerminated(pid) => write!(f, "process {} has terminated", pid),
+            DeathCause::PipeRemoved => write!(f, "its RX pipe has been removed"),
+            DeathCause::NoReader => write!(f, "no one is reading its RX pipe"),
+            DeathCause::NoHeartbeat => write!(f, "it hasn't replied to the heartbeat"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::acl::{AclPolicy, AclRule};
+    use crate::capture;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_server_subscription_and_routing() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        //Subscribe foo and bar
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &["BROADCAST"]).unwrap();
+        assert_eq!(
+            server.get_clients(),
+            vec![String::from("bar"), String::from("foo")]
+        );
+        assert_eq!(
+            server.get_subscriptions("bar"),
+            Some(vec![String::from("BROADCAST"), String::from("bar")])
+        );
+        //Name already taken
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "bar", &[]).err().unwrap(),
+            CapError::NameAlreadyTaken
+        );
+        //Send a message from foo to BROADCAST
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("BROADCAST")),
+            5,
+            0,
+            vec![0x48, 0x49],
+        );
+        pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(
+            events,
+            vec![ServerEvent::Routed(
+                String::from("foo"),
+                String::from("BROADCAST"),
+                1
+            )]
+        );
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+        assert_eq!(OctopipesMessage::decode(&buffer).unwrap().0, message);
+        //Unsubscribe foo
+        let unsubscription: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            None,
+            0,
+            0,
+            protocol::encode_unsubscription(),
+        );
+        pipes::pipe_write(&cap_path, &unsubscription.encode(), Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(events, vec![ServerEvent::Unsubscribed(String::from("foo"))]);
+        assert!(!Path::new(&foo_tx).exists());
+        assert_eq!(server.get_clients(), vec![String::from("bar")]);
+        //Stop
+        server.stop();
+        assert!(!Path::new(&cap_path).exists());
+        assert!(!Path::new(&bar_rx).exists());
+    }
+
+    #[test]
+    fn test_server_wildcard_groups() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        subscribe(&mut server, &cap_path, "kitchen", &["sensors/kitchen/#"]).unwrap();
+        subscribe(&mut server, &cap_path, "temp", &["sensors/+/temp"]).unwrap();
+        //Invalid groups
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "bar", &["sensors/#/temp"])
+                .err()
+                .unwrap(),
+            CapError::InvalidGroup
+        );
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "bar", &["sensors+"])
+                .err()
+                .unwrap(),
+            CapError::InvalidGroup
+        );
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "#", &[]).err().unwrap(),
+            CapError::InvalidGroup
+        );
+        //Route messages from foo
+        let send = |server: &mut OctoServer, remote: &str| -> Vec<ServerEvent> {
+            let message: OctopipesMessage = OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("foo")),
+                Some(String::from(remote)),
+                5,
+                0,
+                vec![0x48, 0x49],
+            );
+            pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+            server.poll(Some(Duration::from_secs(1))).unwrap()
+        };
+        let routed = |remote: &str, recipients: usize| -> Vec<ServerEvent> {
+            vec![ServerEvent::Routed(
+                String::from("foo"),
+                String::from(remote),
+                recipients,
+            )]
+        };
+        assert_eq!(
+            send(&mut server, "sensors/kitchen/temp"),
+            routed("sensors/kitchen/temp", 2)
+        );
+        assert_eq!(
+            send(&mut server, "sensors/bedroom/temp"),
+            routed("sensors/bedroom/temp", 1)
+        );
+        assert_eq!(
+            send(&mut server, "sensors/kitchen"),
+            routed("sensors/kitchen", 1)
+        );
+        assert_eq!(
+            send(&mut server, "sensors/kitchen/fridge/temp"),
+            routed("sensors/kitchen/fridge/temp", 1)
+        );
+        assert_eq!(send(&mut server, "sensors"), routed("sensors", 0));
+        //Wildcards in the remote are matched as they are
+        assert_eq!(
+            send(&mut server, "sensors/+/temp"),
+            routed("sensors/+/temp", 1)
+        );
+        //Subscriptions are removed with the client
+        assert!(server.remove_client("temp").is_ok());
+        assert_eq!(
+            send(&mut server, "sensors/bedroom/temp"),
+            routed("sensors/bedroom/temp", 0)
+        );
+        server.stop();
+        assert!(server.groups.is_empty());
+    }
+
+    #[test]
+    fn test_server_queue_groups() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        subscribe(&mut server, &cap_path, "monitor", &["jobs"]).unwrap();
+        subscribe(&mut server, &cap_path, "worker-1", &["$queue/jobs"]).unwrap();
+        subscribe(&mut server, &cap_path, "worker-2", &["$queue/jobs"]).unwrap();
+        let send = |server: &mut OctoServer, amount: usize| -> Vec<ServerEvent> {
+            let message: OctopipesMessage = OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("foo")),
+                Some(String::from("jobs")),
+                60,
+                0,
+                vec![0x48, 0x49],
+            );
+            let mut events: Vec<ServerEvent> = Vec::new();
+            for _ in 0..amount {
+                pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+                events.append(&mut server.poll(Some(Duration::from_secs(1))).unwrap());
+            }
+            events
+        };
+        let queues = |monitor: usize, worker_1: usize, worker_2: usize| -> Vec<(String, usize)> {
+            vec![
+                (String::from("foo"), 0),
+                (String::from("monitor"), monitor),
+                (String::from("worker-1"), worker_1),
+                (String::from("worker-2"), worker_2),
+            ]
+        };
+        //Subscribers receive every message, queue group members take turns
+        assert_eq!(
+            send(&mut server, 1),
+            vec![ServerEvent::Routed(
+                String::from("foo"),
+                String::from("jobs"),
+                2
+            )]
+        );
+        send(&mut server, 3);
+        assert_eq!(server.get_queues(), queues(4, 2, 2));
+        //Least queued
+        server.set_queue_groups(SelectionPolicy::LeastQueued, BTreeMap::new());
+        server
+            .clients
+            .get_mut("worker-1")
+            .unwrap()
+            .pending
+            .pop_front();
+        send(&mut server, 2);
+        assert_eq!(server.get_queues(), queues(6, 2, 3));
+        //Subscribers of a configured queue group are members too
+        let mut queue_groups: BTreeMap<String, SelectionPolicy> = BTreeMap::new();
+        queue_groups.insert(String::from("jobs"), SelectionPolicy::LeastQueued);
+        server.set_queue_groups(SelectionPolicy::RoundRobin, queue_groups);
+        assert_eq!(
+            send(&mut server, 1),
+            vec![ServerEvent::Routed(
+                String::from("foo"),
+                String::from("jobs"),
+                1
+            )]
+        );
+        assert_eq!(server.get_queues(), queues(6, 3, 3));
+        //Messages of a member which has gone away are handed to the other members
+        assert!(server.remove_client("worker-1").is_ok());
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                ServerEvent::Reassigned(String::from("worker-1"), String::from("worker-2")),
+                ServerEvent::Reassigned(String::from("worker-1"), String::from("worker-2")),
+                ServerEvent::Reassigned(String::from("worker-1"), String::from("worker-2")),
+            ]
+        );
+        assert_eq!(server.get_stats().reassigned_messages, 3);
+        assert_eq!(
+            server.get_queues(),
+            vec![
+                (String::from("foo"), 0),
+                (String::from("monitor"), 6),
+                (String::from("worker-2"), 6),
+            ]
+        );
+        //Without other members, messages are discarded
+        assert!(server.remove_client("monitor").is_ok());
+        assert!(server.remove_client("worker-2").is_ok());
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_millis(10))).unwrap();
+        //Only the messages delivered through the queue group are reassigned
+        assert_eq!(events.len(), 6);
+        assert_eq!(server.get_stats().dropped_messages, 6);
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_retained() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        server.set_retained_groups(vec![String::from("sensors/#")]);
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let send = |server: &mut OctoServer, remote: &str, options: u8, data: &[u8]| {
+            let message: OctopipesMessage = OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("foo")),
+                Some(String::from(remote)),
+                60,
+                options,
+                data.to_vec(),
+            );
+            pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+            server.poll(Some(Duration::from_secs(1))).unwrap();
+        };
+        //Retained with the option or since the group is configured; the last message replaces the previous one
+        send(&mut server, "status", protocol::OPTION_RET, b"off");
+        send(&mut server, "status", protocol::OPTION_RET, b"on");
+        send(&mut server, "sensors/kitchen/temp", 0, b"21");
+        send(&mut server, "lights", 0, b"on");
+        send(&mut server, "jobs", protocol::OPTION_RET, b"build");
+        assert_eq!(server.retained.len(), 3);
+        //Retained messages are queued after the assignment, except for queue groups
+        subscribe(
+            &mut server,
+            &cap_path,
+            "bar",
+            &["status", "sensors/+/temp", "lights", "$queue/jobs"],
+        )
+        .unwrap();
+        let messages: Vec<OctopipesMessage> = server.clients["bar"]
+            .pending
+            .iter()
+            .map(|pending| OctopipesMessage::decode(&pending.data).unwrap().0)
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].remote,
+            Some(String::from("sensors/kitchen/temp"))
+        );
+        assert_eq!(messages[0].data, b"21".to_vec());
+        assert_eq!(messages[1].remote, Some(String::from("status")));
+        assert_eq!(messages[1].data, b"on".to_vec());
+        assert!(messages
+            .iter()
+            .all(|message| message.isset_option(protocol::OPTION_RET)));
+        assert_eq!(server.get_stats().retained_deliveries, 2);
+        //An empty retained message clears the group
+        send(&mut server, "status", protocol::OPTION_RET, b"");
+        assert_eq!(server.retained.len(), 2);
+        subscribe(&mut server, &cap_path, "baz", &["status"]).unwrap();
+        assert!(server.clients["baz"].pending.is_empty());
+        //Retained messages don't exceed the queue size
+        server.set_queue_policy(1, OverflowPolicy::DropOldest);
+        subscribe(&mut server, &cap_path, "omar", &["#"]).unwrap();
+        assert_eq!(server.clients["omar"].pending.len(), 1);
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_durable() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let data_dir: String = tmpdir.path().join("data").display().to_string();
+        let new_server = || -> OctoServer {
+            let mut server: OctoServer = OctoServer::new(
+                ProtocolVersion::Version1,
+                cap_path.clone(),
+                client_dir.clone(),
+            )
+            .unwrap();
+            server.set_store(MessageStore::new(&data_dir));
+            server.set_durable_clients(vec![String::from("logger")], 0, Duration::from_secs(0));
+            assert!(server.start().is_ok());
+            server
+        };
+        let send = |server: &mut OctoServer, tx_pipe: &str, remote: &str| -> Vec<ServerEvent> {
+            let message: OctopipesMessage = OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("foo")),
+                Some(String::from(remote)),
+                60,
+                0,
+                vec![0x48, 0x49],
+            );
+            pipes::pipe_write(tx_pipe, &message.encode(), Duration::from_secs(1)).unwrap();
+            server.poll(Some(Duration::from_secs(1))).unwrap()
+        };
+        let remotes = |server: &OctoServer| -> Vec<String> {
+            server.clients["logger"]
+                .pending
+                .iter()
+                .map(|pending| {
+                    OctopipesMessage::decode(&pending.data)
+                        .unwrap()
+                        .0
+                        .remote
+                        .unwrap()
+                })
+                .collect()
+        };
+        let mut server: OctoServer = new_server();
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        //Messages for an offline durable client are stored; its groups are unknown until it subscribes
+        assert_eq!(
+            send(&mut server, &foo_tx, "logger"),
+            vec![
+                ServerEvent::Stored(String::from("logger"), 1),
+                ServerEvent::Routed(String::from("foo"), String::from("logger"), 0),
+            ]
+        );
+        send(&mut server, &foo_tx, "events/boot");
+        //Stored messages are replayed once it subscribes
+        subscribe(&mut server, &cap_path, "logger", &["events/#"]).unwrap();
+        assert_eq!(remotes(&server), vec![String::from("logger")]);
+        assert_eq!(server.get_stats().stored_messages, 1);
+        assert_eq!(server.get_stats().replayed_messages, 1);
+        //Messages which haven't been delivered yet are stored once it goes away
+        send(&mut server, &foo_tx, "events/boot");
+        assert!(server.remove_client("logger").is_ok());
+        assert_eq!(
+            server.poll(Some(Duration::from_millis(10))).unwrap(),
+            vec![ServerEvent::Stored(String::from("logger"), 2)]
+        );
+        send(&mut server, &foo_tx, "events/login");
+        server.stop();
+        //Stored messages and groups are kept across restarts
+        let mut server: OctoServer = new_server();
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        send(&mut server, &foo_tx, "events/shutdown");
+        send(&mut server, &foo_tx, "sensors/temp");
+        subscribe(&mut server, &cap_path, "logger", &["events/#"]).unwrap();
+        assert_eq!(
+            remotes(&server),
+            vec![
+                String::from("logger"),
+                String::from("events/boot"),
+                String::from("events/login"),
+                String::from("events/shutdown"),
+            ]
+        );
+        //Clients which are no longer durable aren't stored
+        server.set_durable_clients(Vec::new(), 0, Duration::from_secs(0));
+        assert!(server.remove_client("logger").is_ok());
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        send(&mut server, &foo_tx, "logger");
+        server.stop();
+        assert_eq!(MessageStore::new(&data_dir).count("logger").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_server_dead_letters() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        server.set_dead_letter_group(Some(String::from("deadletter")));
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        subscribe(&mut server, &cap_path, "monitor", &["deadletter"]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &[]).unwrap();
+        let send = |server: &mut OctoServer, remote: &str| -> Vec<ServerEvent> {
+            let message: OctopipesMessage = OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("foo")),
+                Some(String::from(remote)),
+                60,
+                0,
+                vec![0x48, 0x49],
+            );
+            pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+            server.poll(Some(Duration::from_secs(1))).unwrap()
+        };
+        let dead_letters = |events: &[ServerEvent]| -> Vec<ServerEvent> {
+            events
+                .iter()
+                .filter(|event| matches!(event, ServerEvent::DeadLettered(_, _, _)))
+                .cloned()
+                .collect()
+        };
+        //No subscribers
+        assert_eq!(
+            dead_letters(&send(&mut server, "nobody")),
+            vec![ServerEvent::DeadLettered(
+                String::from("foo"),
+                String::from("nobody"),
+                DeadLetterReason::NoSubscribers
+            )]
+        );
+        let (dead_letter, _) =
+            OctopipesMessage::decode(&server.clients["monitor"].pending[0].data).unwrap();
+        assert_eq!(dead_letter.origin, Some(String::from("foo")));
+        assert_eq!(dead_letter.remote, Some(String::from("deadletter")));
+        assert_eq!(dead_letter.ttl, 60);
+        assert_eq!(
+            protocol::decode_dead_letter(&dead_letter.data).unwrap(),
+            protocol::DeadLetter {
+                reason: DeadLetterReason::NoSubscribers,
+                origin: Some(String::from("foo")),
+                remote: Some(String::from("nobody")),
+                data: vec![0x48, 0x49]
+            }
+        );
+        //Expired
+        send(&mut server, "bar");
+        server.clients.get_mut("bar").unwrap().pending[0].expiration = Instant::now();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(
+            dead_letters(&events),
+            vec![ServerEvent::DeadLettered(
+                String::from("foo"),
+                String::from("bar"),
+                DeadLetterReason::Expired
+            )]
+        );
+        //Dropped since the queue is full
+        server.set_queue_policy(1, OverflowPolicy::DropNewest);
+        send(&mut server, "bar");
+        assert_eq!(
+            dead_letters(&send(&mut server, "bar")),
+            vec![ServerEvent::DeadLettered(
+                String::from("foo"),
+                String::from("bar"),
+                DeadLetterReason::Dropped
+            )]
+        );
+        //Write failed
+        server.set_queue_policy(16, OverflowPolicy::DropNewest);
+        std::fs::remove_file(&bar_rx).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(
+            dead_letters(&events),
+            vec![ServerEvent::DeadLettered(
+                String::from("foo"),
+                String::from("bar"),
+                DeadLetterReason::WriteFailed
+            )]
+        );
+        //The dead letter of the dropped message has been dropped too, since the monitor's queue was full
+        assert_eq!(server.clients["monitor"].pending.len(), 3);
+        assert_eq!(server.get_stats().dead_letters, 4);
+        //Dead letters which can't be delivered are discarded
+        assert!(server.remove_client("monitor").is_ok());
+        let events: Vec<ServerEvent> = send(&mut server, "nobody");
+        assert_eq!(dead_letters(&events).len(), 1);
+        assert_eq!(
+            events.last(),
+            Some(&ServerEvent::Routed(
+                String::from("foo"),
+                String::from("deadletter"),
+                0
+            ))
+        );
+        //Disabled
+        server.set_dead_letter_group(None);
+        assert!(dead_letters(&send(&mut server, "nobody")).is_empty());
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_system_events() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        server.set_system_events(true);
+        server.set_dead_letter_group(Some(String::from("deadletter")));
+        assert!(server.start().is_ok());
+        //Started is published even if no one listens, without dead letters
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(
+            events,
+            vec![ServerEvent::Routed(
+                String::new(),
+                String::from("$SYS/server/started"),
+                0
+            )]
+        );
+        subscribe(&mut server, &cap_path, "monitor", &["$SYS/#", "deadletter"]).unwrap();
+        subscribe(&mut server, &cap_path, "logger", &["#"]).unwrap();
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &["sensors/#"]).unwrap();
+        let system_events = |server: &mut OctoServer| -> Vec<SystemEvent> {
+            let client: &mut OctoClient = server.clients.get_mut("monitor").unwrap();
+            client
+                .pending
+                .drain(..)
+                .map(|pending| {
+                    let (message, _) = OctopipesMessage::decode(&pending.data).unwrap();
+                    assert_eq!(message.origin, None);
+                    let remote: String = message.remote.unwrap();
+                    protocol::decode_system_event(&remote, &message.data).unwrap()
+                })
+                .collect()
+        };
+        assert_eq!(
+            system_events(&mut server),
+            vec![
+                SystemEvent::Subscribed(
+                    String::from("monitor"),
+                    vec![
+                        String::from("$SYS/#"),
+                        String::from("deadletter"),
+                        String::from("monitor")
+                    ]
+                ),
+                SystemEvent::Subscribed(
+                    String::from("logger"),
+                    vec![String::from("#"), String::from("logger")]
+                ),
+                SystemEvent::Subscribed(
+                    String::from("foo"),
+                    vec![String::from("sensors/#"), String::from("foo")]
+                ),
+            ]
+        );
+        //Wildcards in the first level don't match the system group
+        assert!(server.clients["logger"].pending.is_empty());
+        //Clients can't send to the system group
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("$SYS/server/stopping")),
+            0,
+            0,
+            Vec::new(),
+        );
+        pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(
+            events,
+            vec![ServerEvent::AccessDenied(
+                String::from("foo"),
+                Access::Send(String::from("$SYS/server/stopping"))
+            )]
+        );
+        assert!(system_events(&mut server).is_empty());
+        //Unsubscribed
+        let unsubscription: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            None,
+            0,
+            0,
+            protocol::encode_unsubscription(),
+        );
+        pipes::pipe_write(&cap_path, &unsubscription.encode(), Duration::from_secs(1)).unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(
+            system_events(&mut server),
+            vec![SystemEvent::Unsubscribed(
+                String::from("foo"),
+                String::from("unsubscribed")
+            )]
+        );
+        //Overflowed, announced in the same poll
+        let (bar_tx, _) = subscribe(&mut server, &cap_path, "bar", &[]).unwrap();
+        system_events(&mut server);
+        server
+            .clients
+            .get_mut("logger")
+            .unwrap()
+            .pending
+            .push_back(PendingMessage {
+                data: Vec::new(),
+                written: 0,
+                expiration: Instant::now() + Duration::from_secs(60),
+                queued: Instant::now(),
+                queue_group: None,
+                control: false,
+            });
+        server.set_queue_policy(1, OverflowPolicy::Disconnect);
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("bar")),
+            Some(String::from("sensors/temp")),
+            0,
+            0,
+            vec![0x48, 0x49],
+        );
+        pipes::pipe_write(&bar_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert!(events.contains(&ServerEvent::Overflowed(String::from("logger"))));
+        assert_eq!(
+            system_events(&mut server),
+            vec![SystemEvent::Unsubscribed(
+                String::from("logger"),
+                String::from("overflowed")
+            )]
+        );
+        //Stopping
+        server.set_queue_policy(16, OverflowPolicy::Disconnect);
+        let events: Vec<ServerEvent> = server.shutdown(Duration::from_millis(50));
+        assert!(events.contains(&ServerEvent::Routed(
+            String::new(),
+            String::from("$SYS/server/stopping"),
+            1
+        )));
+    }
+
+    #[test]
+    fn test_server_start_cleanup() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        std::fs::create_dir_all(&client_dir).unwrap();
+        let dir: &Path = Path::new(&client_dir);
+        //Leftovers of a crashed run
+        let foo_rx: String = dir.join("foo_rx.fifo").display().to_string();
+        let foo_tx: String = dir.join("foo_tx.fifo").display().to_string();
+        assert!(pipes::pipe_create(&foo_rx).is_ok());
+        assert!(pipes::pipe_create(&foo_tx).is_ok());
+        //Files planted with the name of a client pipe
+        let bar_rx: String = dir.join("bar_rx.fifo").display().to_string();
+        std::fs::write(&bar_rx, "data").unwrap();
+        let target: String = tmpdir.path().join("target").display().to_string();
+        std::fs::write(&target, "data").unwrap();
+        let bar_tx: String = dir.join("bar_tx.fifo").display().to_string();
+        std::os::unix::fs::symlink(&target, &bar_tx).unwrap();
+        //These must be kept
+        let other_pipe: String = dir.join("other.fifo").display().to_string();
+        assert!(pipes::pipe_create(&other_pipe).is_ok());
+        let regular_file: String = dir.join("other.txt").display().to_string();
+        std::fs::write(&regular_file, "data").unwrap();
+        let other_dir: String = dir.join("baz_rx.fifo").display().to_string();
+        std::fs::create_dir(&other_dir).unwrap();
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path, client_dir).unwrap();
+        assert_eq!(
+            server.start().unwrap(),
+            vec![
+                bar_rx.clone(),
+                bar_tx.clone(),
+                foo_rx.clone(),
+                foo_tx.clone()
+            ]
+        );
+        assert!(!Path::new(&foo_rx).exists());
+        assert!(!Path::new(&foo_tx).exists());
+        assert!(std::fs::symlink_metadata(&bar_rx).is_err());
+        assert!(std::fs::symlink_metadata(&bar_tx).is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "data");
+        assert!(pipes::pipe_exists(&other_pipe));
+        assert!(Path::new(&regular_file).is_file());
+        assert!(Path::new(&other_dir).is_dir());
+        server.stop();
+        //Client dir must be a directory
+        let (cap_path, _) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path, regular_file).unwrap();
+        assert_eq!(server.start().err().unwrap(), ServerError::BadClientDir);
+        //Client dir must not be writable by other users, unless sticky
+        let shared_dir: String = tmpdir.path().join("shared").display().to_string();
+        std::fs::create_dir_all(&shared_dir).unwrap();
+        std::fs::set_permissions(&shared_dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let (cap_path, _) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path, shared_dir.clone()).unwrap();
+        assert_eq!(server.start().err().unwrap(), ServerError::BadClientDir);
+        std::fs::set_permissions(&shared_dir, std::fs::Permissions::from_mode(0o1777)).unwrap();
+        assert!(server.start().is_ok());
+        server.stop();
+        //Client dir must not be a symlink
+        let link_dir: String = tmpdir.path().join("link").display().to_string();
+        std::os::unix::fs::symlink(&shared_dir, &link_dir).unwrap();
+        let link_dir: String = format!("{}/", link_dir);
+        let (cap_path, _) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path, link_dir).unwrap();
+        assert_eq!(server.start().err().unwrap(), ServerError::BadClientDir);
+    }
+
+    #[test]
+    fn test_server_dead_clients() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        server.set_dead_client_detection(Duration::from_millis(10), 1);
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &["BROADCAST"]).unwrap();
+        //Process which owns foo's pipes
+        let mut child: std::process::Child = std::process::Command::new("sleep")
+            .arg("10")
+            .stdin(pipes::pipe_open_read_write(&foo_tx).unwrap())
+            .spawn()
+            .unwrap();
+        let pid: u32 = child.id();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(server
+            .poll(Some(Duration::from_millis(20)))
+            .unwrap()
+            .is_empty());
+        assert_eq!(server.clients.get("foo").unwrap().process.unwrap().0, pid);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_millis(20))).unwrap(),
+            vec![ServerEvent::ClientDied(
+                String::from("foo"),
+                DeathCause::ProcessTerminated(pid)
+            )]
+        );
+        assert!(!Path::new(&foo_tx).exists());
+        //Removed RX pipe
+        assert!(pipes::pipe_delete(&bar_rx).is_ok());
+        assert_eq!(
+            server.poll(Some(Duration::from_millis(20))).unwrap(),
+            vec![ServerEvent::ClientDied(
+                String::from("bar"),
+                DeathCause::PipeRemoved
+            )]
+        );
+        //Name can be used again
+        assert!(subscribe(&mut server, &cap_path, "foo", &[]).is_ok());
+        //No reader on RX pipe
+        server.set_dead_client_detection(Duration::from_secs(0), 1);
+        let (baz_tx, _) = subscribe(&mut server, &cap_path, "baz", &["BROADCAST"]).unwrap();
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("BROADCAST")),
+            1,
+            0,
+            vec![0x01],
+        );
+        let foo_tx: String = server.clients.get("foo").unwrap().tx_pipe.clone();
+        pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        let mut events: Vec<ServerEvent> = Vec::new();
+        let t_start: Instant = Instant::now();
+        while events.len() < 3 && t_start.elapsed() < Duration::from_secs(3) {
+            events.append(&mut server.poll(Some(Duration::from_millis(100))).unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![
+                ServerEvent::Routed(String::from("foo"), String::from("BROADCAST"), 1),
+                ServerEvent::MessageExpired(String::from("baz")),
+                ServerEvent::ClientDied(String::from("baz"), DeathCause::NoReader)
+            ]
+        );
+        assert!(!Path::new(&baz_tx).exists());
+        assert_eq!(server.get_clients(), vec![String::from("foo")]);
+        assert_eq!(server.get_stats().dead_clients, 3);
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_ttl() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        server.set_default_ttl(Duration::from_millis(50));
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &[]).unwrap();
+        //Message is kept until bar reads its RX pipe
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("bar")),
+            5,
+            0,
+            vec![0x01],
+        );
+        pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_secs(1))).unwrap(),
+            vec![ServerEvent::Routed(
+                String::from("foo"),
+                String::from("bar"),
+                1
+            )]
+        );
+        assert!(server.has_pending_messages());
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        assert!(server
+            .poll(Some(Duration::from_millis(50)))
+            .unwrap()
+            .is_empty());
+        assert!(!server.has_pending_messages());
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+        assert_eq!(OctopipesMessage::decode(&buffer).unwrap().0, message);
+        drop(bar_reader);
+        //Message with TTL 0 expires after the default TTL
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("bar")),
+            0,
+            0,
+            vec![0x02],
+        );
+        pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        let mut events: Vec<ServerEvent> = Vec::new();
+        let t_start: Instant = Instant::now();
+        while events.len() < 2 && t_start.elapsed() < Duration::from_secs(1) {
+            events.append(&mut server.poll(Some(Duration::from_millis(100))).unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![
+                ServerEvent::Routed(String::from("foo"), String::from("bar"), 1),
+                ServerEvent::MessageExpired(String::from("bar"))
+            ]
+        );
+        assert!(!server.has_pending_messages());
+        let stats: ServerStats = server.get_stats();
+        assert_eq!(stats.messages_routed, 2);
+        assert_eq!(stats.messages_delivered, 1);
+        assert_eq!(stats.expired_messages, 1);
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_queues() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &[]).unwrap();
+        let message = |id: u8| -> Vec<u8> {
+            OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("foo")),
+                Some(String::from("bar")),
+                10,
+                0,
+                vec![id],
+            )
+            .encode()
+        };
+        let read_messages = |reader: &mut File| -> Vec<u8> {
+            let mut buffer: Vec<u8> = Vec::new();
+            pipes::pipe_read(reader, &mut buffer).unwrap();
+            let mut ids: Vec<u8> = Vec::new();
+            while let Ok((message, size)) = OctopipesMessage::decode(&buffer) {
+                ids.push(message.data[0]);
+                buffer.drain(..size);
+            }
+            ids
+        };
+        //Drop newest
+        server.set_queue_policy(2, OverflowPolicy::DropNewest);
+        let data: Vec<u8> = [message(1), message(2), message(3)].concat();
+        pipes::pipe_write(&foo_tx, &data, Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert!(events.contains(&ServerEvent::MessageDropped(String::from("bar"))));
+        assert_eq!(
+            server.get_queues(),
+            vec![(String::from("bar"), 2), (String::from("foo"), 0)]
+        );
+        //Drop oldest
+        server.set_queue_policy(2, OverflowPolicy::DropOldest);
+        pipes::pipe_write(&foo_tx, &message(4), Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert!(events.contains(&ServerEvent::MessageDropped(String::from("bar"))));
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        server.poll(Some(Duration::from_millis(50))).unwrap();
+        assert_eq!(read_messages(&mut bar_reader), vec![2, 4]);
+        drop(bar_reader);
+        assert_eq!(server.get_stats().dropped_messages, 2);
+        //Block: foo isn't read until there's room in bar's queue
+        server.set_queue_policy(1, OverflowPolicy::Block);
+        let data: Vec<u8> = [message(5), message(6)].concat();
+        pipes::pipe_write(&foo_tx, &data, Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_secs(1))).unwrap(),
+            vec![ServerEvent::Routed(
+                String::from("foo"),
+                String::from("bar"),
+                1
+            )]
+        );
+        assert!(server.clients.get("foo").unwrap().blocked);
+        assert!(server
+            .poll(Some(Duration::from_millis(50)))
+            .unwrap()
+            .is_empty());
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        let mut events: Vec<ServerEvent> = Vec::new();
+        for _ in 0..5 {
+            events.append(&mut server.poll(Some(Duration::from_millis(20))).unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![ServerEvent::Routed(
+                String::from("foo"),
+                String::from("bar"),
+                1
+            )]
+        );
+        assert!(!server.clients.get("foo").unwrap().blocked);
+        assert_eq!(read_messages(&mut bar_reader), vec![5, 6]);
+        drop(bar_reader);
+        //Disconnect
+        server.set_queue_policy(1, OverflowPolicy::Disconnect);
+        let data: Vec<u8> = [message(7), message(8)].concat();
+        pipes::pipe_write(&foo_tx, &data, Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert!(events.contains(&ServerEvent::Overflowed(String::from("bar"))));
+        assert_eq!(server.get_clients(), vec![String::from("foo")]);
+        assert_eq!(server.get_stats().overflow_disconnections, 1);
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_queues_partial_write() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &[]).unwrap();
+        server.set_queue_policy(1, OverflowPolicy::DropOldest);
+        let message = |id: u8, size: usize| -> Vec<u8> {
+            OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("foo")),
+                Some(String::from("bar")),
+                10,
+                0,
+                vec![id; size],
+            )
+            .encode()
+        };
+        //Bar is not read: the second message fills its pipe and is partially written
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        for id in 1..3 {
+            pipes::pipe_write(&foo_tx, &message(id, 40960), Duration::from_secs(1)).unwrap();
+            server.poll(Some(Duration::from_secs(1))).unwrap();
+        }
+        assert_eq!(server.clients.get("bar").unwrap().pending.len(), 1);
+        assert!(server.clients.get("bar").unwrap().pending[0].written > 0);
+        //The partially written message can't be dropped, so the new one is
+        pipes::pipe_write(&foo_tx, &message(3, 1), Duration::from_secs(1)).unwrap();
+        let events: Vec<ServerEvent> = server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert!(events.contains(&ServerEvent::MessageDropped(String::from("bar"))));
+        assert_eq!(server.clients.get("bar").unwrap().pending.len(), 1);
+        assert_eq!(server.get_stats().dropped_messages, 1);
+        //Bar receives the first two messages, uncorrupted
+        let mut buffer: Vec<u8> = Vec::new();
+        for _ in 0..10 {
+            pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+            server.poll(Some(Duration::from_millis(20))).unwrap();
+        }
+        pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+        let mut ids: Vec<u8> = Vec::new();
+        while let Ok((message, size)) = OctopipesMessage::decode(&buffer) {
+            ids.push(message.data[0]);
+            buffer.drain(..size);
+        }
+        assert_eq!(ids, vec![1, 2]);
+        assert!(buffer.is_empty());
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_heartbeat_partial_write() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (bar_tx, bar_rx) = subscribe(&mut server, &cap_path, "bar", &[]).unwrap();
+        //Bar is not read: the message fills its pipe and is partially written
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("bar")),
+            10,
+            0,
+            vec![0x01; 40960],
+        );
+        for _ in 0..2 {
+            pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+            server.poll(Some(Duration::from_secs(1))).unwrap();
+        }
+        assert_eq!(server.clients.get("bar").unwrap().pending.len(), 1);
+        assert!(server.clients.get("bar").unwrap().pending[0].written > 0);
+        //The PONG is queued behind the partially written message
+        let ping: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("bar")),
+            None,
+            0,
+            0,
+            protocol::encode_ping(PingType::Ping),
+        );
+        pipes::pipe_write(&bar_tx, &ping.encode(), Duration::from_secs(1)).unwrap();
+        assert!(server
+            .poll(Some(Duration::from_secs(1)))
+            .unwrap()
+            .is_empty());
+        assert_eq!(server.clients.get("bar").unwrap().pending.len(), 2);
+        let mut buffer: Vec<u8> = Vec::new();
+        for _ in 0..10 {
+            pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+            server.poll(Some(Duration::from_millis(20))).unwrap();
+        }
+        pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+        for _ in 0..2 {
+            let (received, size) = OctopipesMessage::decode(&buffer).unwrap();
+            assert_eq!(received.data, message.data);
+            buffer.drain(..size);
+        }
+        let (pong, size) = OctopipesMessage::decode(&buffer).unwrap();
+        assert_eq!(protocol::decode_ping(&pong.data).unwrap(), PingType::Pong);
+        assert_eq!(buffer.len(), size);
+        //Heartbeat frames aren't accounted as deliveries
+        assert_eq!(server.get_stats().messages_delivered, 2);
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_acl() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        server.set_acl(Acl::new(
+            AclPolicy::Deny,
+            vec![
+                AclRule {
+                    client: Some(String::from("logger")),
+                    ..AclRule::default()
+                },
+                AclRule {
+                    client: Some(String::from("sensor-*")),
+                    names: Some(vec![String::from("sensor-?")]),
+                    subscribe: Some(vec![String::from("sensors")]),
+                    send: Some(vec![String::from("collector")]),
+                },
+            ],
+        ));
+        assert!(server.start().is_ok());
+        assert!(subscribe(&mut server, &cap_path, "logger", &["sensors"]).is_ok());
+        let (sensor_tx, _) = subscribe(&mut server, &cap_path, "sensor-1", &["sensors"]).unwrap();
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "sensor-2", &["alarms"]).err(),
+            Some(CapError::AccessDenied)
+        );
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "intruder", &[]).err(),
+            Some(CapError::AccessDenied)
+        );
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "sensor-10", &["sensors"]).err(),
+            Some(CapError::AccessDenied)
+        );
+        assert_eq!(
+            server.get_clients(),
+            vec![String::from("logger"), String::from("sensor-1")]
+        );
+        //Send
+        let message = |remote: &str| -> Vec<u8> {
+            OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from("sensor-1")),
+                Some(String::from(remote)),
+                5,
+                0,
+                vec![0x01],
+            )
+            .encode()
+        };
+        let data: Vec<u8> = [message("logger"), message("collector")].concat();
+        pipes::pipe_write(&sensor_tx, &data, Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_secs(1))).unwrap(),
+            vec![
+                ServerEvent::AccessDenied(
+                    String::from("sensor-1"),
+                    Access::Send(String::from("logger"))
+                ),
+                ServerEvent::Routed(String::from("sensor-1"), String::from("collector"), 0)
+            ]
+        );
+        assert_eq!(server.get_stats().access_denied, 4);
+        assert_eq!(server.get_stats().refused_subscriptions, 3);
+        //Messages can't be sent on behalf of another client
+        let spoofed: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("logger")),
+            Some(String::from("collector")),
+            5,
+            0,
+            vec![0x01],
+        );
+        pipes::pipe_write(&sensor_tx, &spoofed.encode(), Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_secs(1))).unwrap(),
+            vec![ServerEvent::ClientFailed(
+                String::from("sensor-1"),
+                ServerError::BadOrigin
+            )]
+        );
+        //Unsubscriptions are subject to the ACL as well
+        server.set_acl(Acl::new(AclPolicy::Deny, Vec::new()));
+        let unsubscription: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("logger")),
+            None,
+            0,
+            0,
+            protocol::encode_unsubscription(),
+        );
+        pipes::pipe_write(&cap_path, &unsubscription.encode(), Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_secs(1))).unwrap(),
+            vec![ServerEvent::AccessDenied(
+                String::from("logger"),
+                Access::Claim
+            )]
+        );
+        assert_eq!(
+            server.get_clients(),
+            vec![String::from("logger"), String::from("sensor-1")]
+        );
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_pipes_ownership() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer = OctoServer::new(
+            ProtocolVersion::Version1,
+            cap_path.clone(),
+            client_dir.clone(),
+        )
+        .unwrap();
+        let (uid, gid): (u32, u32) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let mode = |path: &str| -> u32 { std::fs::metadata(path).unwrap().mode() & 0o7777 };
+        server.set_pipes_ownership(
+            Ownership {
+                mode: Some(0o622),
+                owner: None,
+                group: None,
+            },
+            Ownership {
+                mode: Some(0o640),
+                owner: None,
+                group: Some(gid),
+            },
+            Ownership {
+                mode: Some(0o750),
+                owner: None,
+                group: Some(gid),
+            },
+        );
+        assert!(server.start().is_ok());
+        assert_eq!(mode(&cap_path), 0o622);
+        assert_eq!(mode(&client_dir), 0o750);
+        let (foo_tx, foo_rx) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        for pipe in [&foo_tx, &foo_rx].iter() {
+            let metadata: std::fs::Metadata = std::fs::metadata(pipe).unwrap();
+            assert_eq!(metadata.mode() & 0o7777, 0o640);
+            assert_eq!(metadata.uid(), uid);
+            assert_eq!(metadata.gid(), gid);
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_set_root() {
+        let mut server: OctoServer = OctoServer::new(
+            ProtocolVersion::Version1,
+            String::from("/run/octopipes/cap.pipe"),
+            String::from("/run/octopipes/clients/"),
+        )
+        .unwrap();
+        assert_eq!(
+            server.set_root("/var/octopipes"),
+            Err(ServerError::OpenFailed)
+        );
+        assert_eq!(
+            server.outer_path("/run/octopipes/clients/foo_tx.fifo"),
+            String::from("/run/octopipes/clients/foo_tx.fifo")
+        );
+        assert!(server.set_root("/run/octopipes").is_ok());
+        assert_eq!(server.cap_path, String::from("/cap.pipe"));
+        assert_eq!(server.client_dir, String::from("/clients"));
+        assert_eq!(
+            server.outer_path("/clients/foo_tx.fifo"),
+            String::from("/run/octopipes/clients/foo_tx.fifo")
+        );
+        //Client dir outside the root
+        let mut server: OctoServer = OctoServer::new(
+            ProtocolVersion::Version1,
+            String::from("/run/octopipes/cap.pipe"),
+            String::from("/tmp/octopipes/clients/"),
+        )
+        .unwrap();
+        assert_eq!(
+            server.set_root("/run/octopipes"),
+            Err(ServerError::BadClientDir)
+        );
+    }
+
+    #[test]
+    fn test_server_heartbeat() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        //Ping on CAP
+        let ping: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            None,
+            0,
+            0,
+            protocol::encode_ping(PingType::Ping),
+        );
+        pipes::pipe_write(&cap_path, &ping.encode(), Duration::from_secs(1)).unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        let mut cap: File = pipes::pipe_open_read(&cap_path).unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut cap, &mut buffer).unwrap();
+        drop(cap);
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        let (pong, _) = OctopipesMessage::decode(&buffer).unwrap();
+        assert_eq!(pong.remote, Some(String::from("foo")));
+        assert_eq!(protocol::decode_ping(&pong.data).unwrap(), PingType::Pong);
+        //Heartbeat
+        let (foo_tx, foo_rx) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        assert!(subscribe(&mut server, &cap_path, "bar", &[]).is_ok());
+        server.set_heartbeat(Duration::from_millis(50), 2);
+        let mut foo_reader: File = pipes::pipe_open_read(&foo_rx).unwrap();
+        pipes::pipe_write(&foo_tx, &ping.encode(), Duration::from_secs(1)).unwrap();
+        assert!(server
+            .poll(Some(Duration::from_secs(1)))
+            .unwrap()
+            .is_empty());
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut foo_reader, &mut buffer).unwrap();
+        let (pong, _) = OctopipesMessage::decode(&buffer).unwrap();
+        assert_eq!(pong.origin, None);
+        assert_eq!(protocol::decode_ping(&pong.data).unwrap(), PingType::Pong);
+        //Foo is probed, but doesn't reply; bar doesn't take part in the heartbeat
+        let mut events: Vec<ServerEvent> = Vec::new();
+        let t_start: Instant = Instant::now();
+        while events.is_empty() && t_start.elapsed() < Duration::from_secs(2) {
+            events = server.poll(Some(Duration::from_millis(100))).unwrap();
+        }
+        assert_eq!(
+            events,
+            vec![ServerEvent::ClientDied(
+                String::from("foo"),
+                DeathCause::NoHeartbeat
+            )]
+        );
+        //Two probes have been sent
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut foo_reader, &mut buffer).unwrap();
+        let (probe, size) = OctopipesMessage::decode(&buffer).unwrap();
+        assert_eq!(protocol::decode_ping(&probe.data).unwrap(), PingType::Ping);
+        assert_eq!(buffer.len(), size * 2);
+        assert_eq!(server.get_clients(), vec![String::from("bar")]);
+        //Messages without remote which aren't pings can't be served
+        let bar_tx: String = server.clients.get("bar").unwrap().tx_pipe.clone();
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("bar")),
+            None,
+            0,
+            0,
+            vec![0x01],
+        );
+        pipes::pipe_write(&bar_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_secs(1))).unwrap(),
+            vec![ServerEvent::ClientFailed(
+                String::from("bar"),
+                ServerError::NoRecipient
+            )]
+        );
+        server.stop();
+    }
+
+    #[test]
+    fn test_server_notifier() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path, client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let notifier: Notifier = server.notifier();
+        std::thread::spawn(move || notifier.notify(2).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(
+            server.poll(None).unwrap(),
+            vec![ServerEvent::Notification(2)]
+        );
+    }
+
+    #[test]
+    fn test_server_shutdown() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let (foo_tx, foo_rx) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &["BROADCAST"]).unwrap();
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        //Message in flight
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("BROADCAST")),
+            5,
+            0,
+            vec![0x48, 0x49],
+        );
+        pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        //Foo is not reading, so it can't be notified
+        let events: Vec<ServerEvent> = server.shutdown(Duration::from_secs(1));
+        assert_eq!(
+            events,
+            vec![
+                ServerEvent::Routed(String::from("foo"), String::from("BROADCAST"), 1),
+                ServerEvent::ShutdownNotified(String::from("bar")),
+                ServerEvent::DeliveryFailed(String::from("foo"), ServerError::WriteFailed)
+            ]
+        );
+        //Bar received the message and then the notice from the server
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+        let (received, size) = OctopipesMessage::decode(&buffer).unwrap();
+        assert_eq!(received, message);
+        let (notice, _) = OctopipesMessage::decode(&buffer[size..]).unwrap();
+        assert_eq!(notice.origin, None);
+        assert_eq!(notice.remote, Some(String::from("bar")));
+        assert_eq!(
+            protocol::get_cap_message_type(&notice.data).unwrap(),
+            CapMessage::Shutdown
+        );
+        //Everything has been removed
+        assert!(server.get_clients().is_empty());
+        assert!(!Path::new(&cap_path).exists());
+        assert!(!Path::new(&foo_rx).exists());
+        assert!(!Path::new(&bar_rx).exists());
+    }
+
+    #[test]
+    fn test_server_admin() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let admin_path: String = tmpdir.path().join("admin.sock").display().to_string();
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        assert!(server.start_admin(&admin_path).is_ok());
+        subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        subscribe(&mut server, &cap_path, "bar", &["BROADCAST"]).unwrap();
+        assert_eq!(server.get_stats().subscriptions, 2);
+        assert_eq!(
+            server.get_groups(),
+            vec![
+                (String::from("BROADCAST"), vec![String::from("bar")]),
+                (String::from("bar"), vec![String::from("bar")]),
+                (String::from("foo"), vec![String::from("foo")])
+            ]
+        );
+        let mut admin: UnixStream = UnixStream::connect(&admin_path).unwrap();
+        let mut reader: BufReader<UnixStream> = BufReader::new(admin.try_clone().unwrap());
+        //Accept connection
+        assert!(server
+            .poll(Some(Duration::from_secs(1)))
+            .unwrap()
+            .is_empty());
+        //List
+        admin.write_all(b"list\n").unwrap();
+        assert!(server
+            .poll(Some(Duration::from_secs(1)))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            read_admin_reply(&mut reader),
+            "OK\nbar BROADCAST,bar\nfoo foo\n"
+        );
+        //Subscriptions
+        admin.write_all(b"json subscriptions bar\n").unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        let mut line: String = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(
+            line,
+            "{\"status\":\"ok\",\"client\":\"bar\",\"groups\":[\"BROADCAST\",\"bar\"]}\n"
+        );
+        //Kick and log level
+        admin
+            .write_all(b"kick foo\nkick baz\nloglevel 1\n")
+            .unwrap();
+        assert_eq!(
+            server.poll(Some(Duration::from_secs(1))).unwrap(),
+            vec![
+                ServerEvent::Kicked(String::from("foo")),
+                ServerEvent::LogLevelChanged(1)
+            ]
+        );
+        assert_eq!(read_admin_reply(&mut reader), "OK\n");
+        assert_eq!(read_admin_reply(&mut reader), "ERR Client not found\n");
+        assert_eq!(read_admin_reply(&mut reader), "OK\n");
+        assert_eq!(server.get_clients(), vec![String::from("bar")]);
+        //Stats
+        admin.write_all(b"stats\n").unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        let stats: String = read_admin_reply(&mut reader);
+        assert!(stats.starts_with("OK\nuptime "));
+        assert!(stats.contains("\nclients 1\n"));
+        assert!(stats.contains("\nkicked_clients 1\n"));
+        //Bad request
+        admin.write_all(b"reboot\n").unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(
+            read_admin_reply(&mut reader),
+            "ERR Unknown command 'reboot'\n"
+        );
+        //Stop removes the socket
+        server.stop();
+        assert!(!Path::new(&admin_path).exists());
+    }
+
+    #[test]
+    fn test_server_metrics() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (_, bar_rx) = subscribe(&mut server, &cap_path, "bar", &["BROADCAST"]).unwrap();
+        assert_eq!(
+            subscribe(&mut server, &cap_path, "bar", &[]).err().unwrap(),
+            CapError::NameAlreadyTaken
+        );
+        //Route a message from foo to BROADCAST
+        let mut bar_reader: File = pipes::pipe_open_read(&bar_rx).unwrap();
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from("foo")),
+            Some(String::from("BROADCAST")),
+            5,
+            0,
+            vec![0x48, 0x49],
+        );
+        let size: usize = message.encode().len();
+        pipes::pipe_write(&foo_tx, &message.encode(), Duration::from_secs(1)).unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut bar_reader, &mut buffer).unwrap();
+        let page: String = server.get_metrics();
+        let lines: Vec<&str> = page.lines().collect();
+        for line in [
+            String::from("octopipes_clients 2"),
+            String::from("octopipes_active_subscriptions 3"),
+            String::from("octopipes_group_subscribers{group=\"BROADCAST\"} 1"),
+            String::from("octopipes_queue_depth{client=\"bar\"} 0"),
+            String::from("octopipes_subscriptions_total 2"),
+            String::from("octopipes_messages_routed_total 1"),
+            String::from("octopipes_messages_delivered_total 1"),
+            format!("octopipes_bytes_routed_total {}", size),
+            String::from("octopipes_client_sent_messages_total{client=\"foo\"} 1"),
+            format!("octopipes_client_received_bytes_total{{client=\"bar\"}} {}", size),
+            String::from("octopipes_group_messages_total{group=\"BROADCAST\"} 1"),
+            String::from("octopipes_cap_requests_total{type=\"subscription\",result=\"ok\"} 2"),
+            String::from(
+                "octopipes_cap_requests_total{type=\"subscription\",result=\"name_already_taken\"} 1",
+            ),
+            String::from("octopipes_routing_latency_seconds_count 1"),
+        ]
+        .iter()
+        {
+            assert!(lines.contains(&line.as_str()), "missing '{}'", line);
+        }
+        //HTTP listener
+        assert!(server.start_metrics("127.0.0.1:0").is_ok());
+        let address: SocketAddr = server.get_metrics_address().unwrap();
+        let mut stream: TcpStream = TcpStream::connect(address).unwrap();
+        //Accept connection
+        assert!(server
+            .poll(Some(Duration::from_secs(1)))
+            .unwrap()
+            .is_empty());
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        let mut reply: String = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(reply.contains("\noctopipes_clients 2\n"));
+        //File export
+        let metrics_file: String = tmpdir.path().join("octopipes.prom").display().to_string();
+        server.set_metrics_file(Some(metrics_file.clone()));
+        server.set_metrics_interval(Duration::from_secs(60));
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        let exported: String = std::fs::read_to_string(&metrics_file).unwrap();
+        assert!(exported.contains("\noctopipes_clients 2\n"));
+        //Not rewritten before the interval has elapsed
+        std::fs::remove_file(&metrics_file).unwrap();
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        assert!(!Path::new(&metrics_file).exists());
+        //Failures are reported
+        server.set_metrics_file(Some(
+            tmpdir
+                .path()
+                .join("nodir/octopipes.prom")
+                .display()
+                .to_string(),
+        ));
+        assert_eq!(
+            server.poll(Some(Duration::from_millis(10))).unwrap(),
+            vec![ServerEvent::MetricsFailed(ServerError::MetricsFailed)]
+        );
+        //Stop closes the listener
+        server.stop();
+        assert!(server.get_metrics_address().is_none());
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn test_server_status() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        server.set_status_config(vec![("cap-pipe", cap_path.clone())]);
+        let (foo_tx, foo_rx) = subscribe(&mut server, &cap_path, "foo", &["sensors/#"]).unwrap();
+        let status: Status = server.get_status();
+        assert_eq!(status.version, String::from(env!("CARGO_PKG_VERSION")));
+        assert_eq!(status.protocol_version, 1);
+        assert_eq!(status.pid, std::process::id());
+        assert_eq!(status.config, vec![("cap-pipe", cap_path.clone())]);
+        assert_eq!(status.clients.len(), 1);
+        assert_eq!(status.clients[0].name, String::from("foo"));
+        assert_eq!(
+            status.clients[0].groups,
+            vec![String::from("sensors/#"), String::from("foo")]
+        );
+        assert_eq!(status.clients[0].tx_pipe, foo_tx);
+        assert_eq!(status.clients[0].rx_pipe, foo_rx);
+        assert!(status.counters.contains(&("subscriptions", 1)));
+        assert!(status.counters.contains(&("bytes_routed", 0)));
+        //File export
+        let status_file: String = tmpdir.path().join("status.json").display().to_string();
+        server.set_status_file(Some(status_file.clone()));
+        server.set_status_interval(Duration::from_secs(60));
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        let exported: String = std::fs::read_to_string(&status_file).unwrap();
+        assert!(exported.starts_with("{\"version\":"));
+        assert!(exported.contains("\"clients\":[{\"name\":\"foo\""));
+        //Not rewritten before the interval has elapsed
+        std::fs::remove_file(&status_file).unwrap();
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        assert!(!Path::new(&status_file).exists());
+        server.set_status_file(Some(status_file.clone()));
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        assert!(Path::new(&status_file).exists());
+        //Failures are reported
+        server.set_status_file(Some(
+            tmpdir
+                .path()
+                .join("nodir/status.json")
+                .display()
+                .to_string(),
+        ));
+        assert_eq!(
+            server.poll(Some(Duration::from_millis(10))).unwrap(),
+            vec![ServerEvent::StatusFailed(ServerError::StatusFailed)]
+        );
+        //Stop removes the status file
+        server.set_status_file(Some(status_file.clone()));
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        server.stop();
+        assert!(!Path::new(&status_file).exists());
+    }
+
+    #[test]
+    fn test_server_capture() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (cap_path, client_dir) = server_paths(&tmpdir);
+        let capture_file: String = tmpdir.path().join("traffic.cap").display().to_string();
+        let mut server: OctoServer =
+            OctoServer::new(ProtocolVersion::Version1, cap_path.clone(), client_dir).unwrap();
+        assert!(server.start().is_ok());
+        assert!(server.start_capture(&capture_file).is_ok());
+        server.set_capture_filter(CaptureFilter {
+            clients: vec![String::from("foo")],
+            groups: vec![String::from("sensors/#")],
+        });
+        let (foo_tx, _) = subscribe(&mut server, &cap_path, "foo", &[]).unwrap();
+        let (bar_tx, _) = subscribe(&mut server, &cap_path, "bar", &["sensors/#"]).unwrap();
+        let send = |server: &mut OctoServer, tx: &str, origin: &str, remote: &str| {
+            let message: OctopipesMessage = OctopipesMessage::new(
+                ProtocolVersion::Version1,
+                Some(String::from(origin)),
+                Some(String::from(remote)),
+                5,
+                protocol::OPTION_RET,
+                Vec::from(remote.as_bytes()),
+            );
+            pipes::pipe_write(tx, &message.encode(), Duration::from_secs(1)).unwrap();
+            server.poll(Some(Duration::from_secs(1))).unwrap();
+        };
+        send(&mut server, &foo_tx, "foo", "sensors/kitchen/temp");
+        send(&mut server, &foo_tx, "foo", "BROADCAST");
+        send(&mut server, &bar_tx, "bar", "sensors/kitchen/temp");
+        send(&mut server, &foo_tx, "foo", "sensors/garage/temp");
+        //Records are flushed at the end of each loop iteration
+        let records: Vec<CaptureRecord> = capture::read_file(&capture_file).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].version, 1);
+        assert_eq!(records[0].origin, String::from("foo"));
+        assert_eq!(records[0].remote, String::from("sensors/kitchen/temp"));
+        assert_eq!(records[0].ttl, 5);
+        assert_eq!(records[0].options, protocol::OPTION_RET);
+        assert_eq!(records[0].data, b"sensors/kitchen/temp".to_vec());
+        assert_eq!(records[1].remote, String::from("sensors/garage/temp"));
+        assert!(records[0].timestamp <= records[1].timestamp);
+        //Rotation
+        server.set_capture_rotation(1, 1);
+        send(&mut server, &foo_tx, "foo", "sensors/kitchen/temp");
+        assert_eq!(capture::read_file(&capture_file).unwrap().len(), 1);
+        assert_eq!(
+            capture::read_file(&format!("{}.1", capture_file))
+                .unwrap()
+                .len(),
+            2
+        );
+        //Stop closes the capture, which is kept
+        server.stop();
+        assert!(Path::new(&capture_file).exists());
+        //Files which aren't captures are refused
+        let junk_file: String = tmpdir.path().join("junk.txt").display().to_string();
+        std::fs::write(&junk_file, b"not a capture").unwrap();
+        assert_eq!(
+            server.start_capture(&junk_file).err().unwrap(),
+            ServerError::CaptureFailed
+        );
+    }
+
+    /// ### read_admin_reply
+    ///
+    /// Read a text reply from the admin socket, up to the empty line
+    fn read_admin_reply(reader: &mut BufReader<UnixStream>) -> String {
+        let mut reply: String = String::new();
+        loop {
+            let mut line: String = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\n" || line.is_empty() {
+                break;
+            }
+            reply.push_str(&line);
+        }
+        reply
+    }
+
+    /// ### server_paths
+    ///
+    /// Returns CAP path and client directory in the provided temporary directory
+    fn server_paths(tmpdir: &tempfile::TempDir) -> (String, String) {
+        (
+            tmpdir.path().join("cap.fifo").display().to_string(),
+            tmpdir.path().join("clients").display().to_string(),
+        )
+    }
+
+    /// ### subscribe
+    ///
+    /// Subscribe a client to the server through the CAP, returning the assigned pipes
+    fn subscribe(
+        server: &mut OctoServer,
+        cap_path: &str,
+        client: &str,
+        groups: &[&str],
+    ) -> Result<(String, String), CapError> {
+        let groups: Vec<String> = groups.iter().map(|group| String::from(*group)).collect();
+        let message: OctopipesMessage = OctopipesMessage::new(
+            ProtocolVersion::Version1,
+            Some(String::from(client)),
+            None,
+            0,
+            0,
+            protocol::encode_subscription(&groups),
+        );
+        pipes::pipe_write(cap_path, &message.encode(), Duration::from_secs(1)).unwrap();
+        server.poll(Some(Duration::from_secs(1))).unwrap();
+        //Read assignment
+        let mut cap: File = pipes::pipe_open_read(cap_path).unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        pipes::pipe_read(&mut cap, &mut buffer).unwrap();
+        drop(cap);
+        //Let the server restart listening on the CAP
+        server.poll(Some(Duration::from_millis(10))).unwrap();
+        let (assignment, _) = OctopipesMessage::decode(&buffer).unwrap();
+        assert_eq!(assignment.remote, Some(String::from(client)));
+        match protocol::decode_assignment(&assignment.data).unwrap() {
+            (CapError::NoError, Some(pipes)) => Ok(pipes),
+            (error, _) => Err(error),
+        }
+    }
+}
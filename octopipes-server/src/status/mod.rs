@@ -0,0 +1,147 @@
+//! ### status
+//!
+//! `status` is the module which encodes a snapshot of the server state as a JSON document,
+//! periodically written to the status file for the hosts without a metrics stack
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::admin::{json_string, json_string_array};
+
+use std::time::Duration;
+
+/// ### Status
+///
+/// `Status` is a snapshot of the state of the server
+pub struct Status {
+    pub version: String,
+    pub protocol_version: u8,
+    pub pid: u32,
+    //Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub uptime: Duration,
+    //Effective configuration, by setting key
+    pub config: Vec<(&'static str, String)>,
+    pub clients: Vec<ClientStatus>,
+    pub counters: Vec<(&'static str, u64)>,
+}
+
+/// ### ClientStatus
+///
+/// `ClientStatus` describes a subscribed client
+pub struct ClientStatus {
+    pub name: String,
+    pub groups: Vec<String>,
+    pub tx_pipe: String,
+    pub rx_pipe: String,
+    pub queued: usize,
+}
+
+impl Status {
+    /// ### to_json
+    ///
+    /// `to_json` encodes the status as a JSON document
+    pub fn to_json(&self) -> String {
+        let config: Vec<String> = self
+            .config
+            .iter()
+            .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+            .collect();
+        let clients: Vec<String> = self.clients.iter().map(|client| client.to_json()).collect();
+        let counters: Vec<String> = self
+            .counters
+            .iter()
+            .map(|(name, value)| format!("{}:{}", json_string(name), value))
+            .collect();
+        format!(
+            "{{\"version\":{},\"protocol_version\":{},\"pid\":{},\"timestamp\":{},\"uptime\":{},\"config\":{{{}}},\"clients\":[{}],\"counters\":{{{}}}}}\n",
+            json_string(&self.version),
+            self.protocol_version,
+            self.pid,
+            self.timestamp,
+            self.uptime.as_secs(),
+            config.join(","),
+            clients.join(","),
+            counters.join(",")
+        )
+    }
+}
+
+impl ClientStatus {
+    /// ### to_json
+    ///
+    /// `to_json` encodes the client as a JSON object
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"name\":{},\"groups\":{},\"tx_pipe\":{},\"rx_pipe\":{},\"queued\":{}}}",
+            json_string(&self.name),
+            json_string_array(&self.groups),
+            json_string(&self.tx_pipe),
+            json_string(&self.rx_pipe),
+            self.queued
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_status_to_json() {
+        let status: Status = Status {
+            version: String::from("0.1.0"),
+            protocol_version: 1,
+            pid: 1234,
+            timestamp: 1600000000,
+            uptime: Duration::from_millis(61500),
+            config: vec![
+                ("cap-pipe", String::from("/tmp/octopipes/cap.pipe")),
+                ("queue-size", String::from("1024")),
+            ],
+            clients: vec![ClientStatus {
+                name: String::from("foo"),
+                groups: vec![String::from("foo"), String::from("sensors/\"#\"")],
+                tx_pipe: String::from("/tmp/octopipes/clients/foo_tx.fifo"),
+                rx_pipe: String::from("/tmp/octopipes/clients/foo_rx.fifo"),
+                queued: 2,
+            }],
+            counters: vec![("subscriptions", 1), ("messages_routed", 12)],
+        };
+        assert_eq!(
+            status.to_json(),
+            "{\"version\":\"0.1.0\",\"protocol_version\":1,\"pid\":1234,\"timestamp\":1600000000,\"uptime\":61,\"config\":{\"cap-pipe\":\"/tmp/octopipes/cap.pipe\",\"queue-size\":\"1024\"},\"clients\":[{\"name\":\"foo\",\"groups\":[\"foo\",\"sensors/\\\"#\\\"\"],\"tx_pipe\":\"/tmp/octopipes/clients/foo_tx.fifo\",\"rx_pipe\":\"/tmp/octopipes/clients/foo_rx.fifo\",\"queued\":2}],\"counters\":{\"subscriptions\":1,\"messages_routed\":12}}\n"
+        );
+        //Without clients
+        let status: Status = Status {
+            clients: Vec::new(),
+            config: Vec::new(),
+            counters: Vec::new(),
+            ..status
+        };
+        assert!(status
+            .to_json()
+            .ends_with("\"config\":{},\"clients\":[],\"counters\":{}}\n"));
+    }
+}
@@ -0,0 +1,453 @@
+//! ### store
+//!
+//! `store` is the module which persists on disk the messages for the durable clients, while they're offline
+
+//
+//   Octopipes-Server
+//   Developed by Christian Visintin
+//
+// MIT License
+// Copyright (c) 2020 Christian Visintin
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//Each record of the log is the timestamp (u64), the length of the message (u32) and the message
+const RECORD_HEADER_SIZE: usize = 12;
+
+/// ### MessageStore
+///
+/// `MessageStore` keeps in the data directory an append log for each durable client, with the messages
+/// which couldn't be delivered to it, and the groups it was subscribed to the last time
+pub struct MessageStore {
+    data_dir: String,
+    //Max size of each log in bytes (0 means unbounded)
+    max_size: u64,
+    //Max age of the stored messages (0 means unbounded)
+    max_age: Duration,
+    //Clients whose log has been checked for a truncated record since the store was created
+    checked: HashSet<String>,
+}
+
+/// ### Record
+///
+/// `Record` is a message stored in the log, with the time it has been stored at (seconds since the epoch)
+struct Record {
+    timestamp: u64,
+    data: Vec<u8>,
+}
+
+impl MessageStore {
+    /// ### new
+    ///
+    /// Instantiates a new MessageStore in the provided data directory, which is created on demand
+    pub fn new(data_dir: &str) -> MessageStore {
+        MessageStore {
+            data_dir: String::from(data_dir),
+            max_size: 0,
+            max_age: Duration::from_secs(0),
+            checked: HashSet::new(),
+        }
+    }
+
+    /// ### get_data_dir
+    ///
+    /// `get_data_dir` returns the path of the data directory
+    pub fn get_data_dir(&self) -> &str {
+        &self.data_dir
+    }
+
+    /// ### set_data_dir
+    ///
+    /// `set_data_dir` changes the path of the data directory (e.g. after the root directory has changed)
+    pub fn set_data_dir(&mut self, data_dir: String) {
+        self.data_dir = data_dir;
+    }
+
+    /// ### set_limits
+    ///
+    /// `set_limits` sets the max size of each log (in bytes) and the max age of the stored messages;
+    /// 0 means unbounded. Once a log exceeds its size, the oldest messages are discarded
+    pub fn set_limits(&mut self, max_size: u64, max_age: Duration) {
+        self.max_size = max_size;
+        self.max_age = max_age;
+    }
+
+    /// ### append
+    ///
+    /// `append` stores the messages at the end of the client's log.
+    /// The first time a log is written, a truncated record at its end (e.g. the server has crashed while writing it)
+    /// is removed, so that the new records can be read.
+    /// Returns the amount of messages which have been discarded to respect the limits
+    pub fn append(&mut self, client: &str, messages: &[Vec<u8>]) -> std::io::Result<usize> {
+        std::fs::create_dir_all(&self.data_dir)?;
+        let timestamp: u64 = now();
+        let mut buffer: Vec<u8> = Vec::new();
+        for message in messages.iter() {
+            encode_record(&mut buffer, timestamp, message);
+        }
+        let log_path: PathBuf = self.log_path(client);
+        let mut log: File = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        if !self.checked.contains(client) {
+            truncate_tail(&mut log)?;
+            self.checked.insert(String::from(client));
+        }
+        //If the write fails, the log may be left with a truncated record
+        if let Err(err) = log.write_all(&buffer) {
+            self.checked.remove(client);
+            return Err(err);
+        }
+        let size: u64 = log.metadata()?.len();
+        drop(log);
+        match self.max_size {
+            0 => Ok(0),
+            max_size if size <= max_size => Ok(0),
+            _ => self.compact(client),
+        }
+    }
+
+    /// ### take
+    ///
+    /// `take` removes the client's log and returns the stored messages, in the order they've been stored,
+    /// with the amount of messages which have been discarded since they were too old
+    pub fn take(&self, client: &str) -> std::io::Result<(Vec<Vec<u8>>, usize)> {
+        let records: Vec<Record> = self.read_records(client)?;
+        let total: usize = records.len();
+        let messages: Vec<Vec<u8>> = records
+            .into_iter()
+            .filter(|record| !self.is_expired(record))
+            .map(|record| record.data)
+            .collect();
+        match std::fs::remove_file(self.log_path(client)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        let expired: usize = total - messages.len();
+        Ok((messages, expired))
+    }
+
+    /// ### count
+    ///
+    /// `count` returns the amount of messages stored for the client
+    pub fn count(&self, client: &str) -> std::io::Result<usize> {
+        Ok(self.read_records(client)?.len())
+    }
+
+    /// ### save_groups
+    ///
+    /// `save_groups` stores the groups the client is subscribed to, one per line
+    pub fn save_groups(&self, client: &str, groups: &[String]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.data_dir)?;
+        let mut content: String = groups.join("\n");
+        content.push('\n');
+        std::fs::write(self.groups_path(client), content)
+    }
+
+    /// ### load_groups
+    ///
+    /// `load_groups` returns the groups the client was subscribed to the last time, if they've been stored
+    pub fn load_groups(&self, client: &str) -> std::io::Result<Option<Vec<String>>> {
+        match std::fs::read_to_string(self.groups_path(client)) {
+            Ok(content) => Ok(Some(
+                content
+                    .lines()
+                    .filter(|group| !group.is_empty())
+                    .map(String::from)
+                    .collect(),
+            )),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// ### compact
+    ///
+    /// `compact` rewrites the client's log without the messages which are too old and,
+    /// if it is still too big, without the oldest messages. Returns the amount of discarded messages
+    fn compact(&self, client: &str) -> std::io::Result<usize> {
+        let records: Vec<Record> = self.read_records(client)?;
+        let total: usize = records.len();
+        let mut records: Vec<Record> = records
+            .into_iter()
+            .filter(|record| !self.is_expired(record))
+            .collect();
+        let mut size: u64 = records
+            .iter()
+            .map(|record| (RECORD_HEADER_SIZE + record.data.len()) as u64)
+            .sum();
+        let mut oldest: usize = 0;
+        while self.max_size > 0 && size > self.max_size && oldest < records.len() {
+            size -= (RECORD_HEADER_SIZE + records[oldest].data.len()) as u64;
+            oldest += 1;
+        }
+        records.drain(..oldest);
+        let mut buffer: Vec<u8> = Vec::with_capacity(size as usize);
+        for record in records.iter() {
+            encode_record(&mut buffer, record.timestamp, &record.data);
+        }
+        //The log is replaced at once, so that it is never left half written
+        let log_path: PathBuf = self.log_path(client);
+        let tmp_path: PathBuf = log_path.with_extension("log.tmp");
+        std::fs::write(&tmp_path, &buffer)?;
+        std::fs::rename(&tmp_path, &log_path)?;
+        Ok(total - records.len())
+    }
+
+    /// ### read_records
+    ///
+    /// `read_records` reads the records of the client's log. A truncated record at the end of the log
+    /// (e.g. the server has crashed while writing it) is ignored
+    fn read_records(&self, client: &str) -> std::io::Result<Vec<Record>> {
+        let mut buffer: Vec<u8> = Vec::new();
+        match File::open(self.log_path(client)) {
+            Ok(mut log) => {
+                log.read_to_end(&mut buffer)?;
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        }
+        Ok(decode_records(&buffer).0)
+    }
+
+    /// ### is_expired
+    ///
+    /// `is_expired` returns whether the record is older than the max age
+    fn is_expired(&self, record: &Record) -> bool {
+        self.max_age.as_secs() > 0 && record.timestamp + self.max_age.as_secs() < now()
+    }
+
+    /// ### log_path
+    ///
+    /// `log_path` returns the path of the client's log
+    fn log_path(&self, client: &str) -> PathBuf {
+        Path::new(&self.data_dir).join(format!("{}.log", client))
+    }
+
+    /// ### groups_path
+    ///
+    /// `groups_path` returns the path of the file with the client's groups
+    fn groups_path(&self, client: &str) -> PathBuf {
+        Path::new(&self.data_dir).join(format!("{}.groups", client))
+    }
+}
+
+/// ### is_valid_client
+///
+/// `is_valid_client` returns whether the client name can be used to name its files in the data directory
+pub fn is_valid_client(client: &str) -> bool {
+    !client.is_empty() && client != "." && client != ".." && !client.contains(['/', '\n', '\0'])
+}
+
+/// ### encode_record
+///
+/// `encode_record` appends a record to the buffer
+fn encode_record(buffer: &mut Vec<u8>, timestamp: u64, data: &[u8]) {
+    buffer.extend_from_slice(&timestamp.to_be_bytes());
+    buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(data);
+}
+
+/// ### decode_records
+///
+/// `decode_records` decodes the records in the buffer, up to a truncated record.
+/// Returns the records with the size of the complete ones
+fn decode_records(buffer: &[u8]) -> (Vec<Record>, usize) {
+    let mut records: Vec<Record> = Vec::new();
+    let mut offset: usize = 0;
+    while buffer.len() >= offset + RECORD_HEADER_SIZE {
+        let mut timestamp: [u8; 8] = [0; 8];
+        timestamp.copy_from_slice(&buffer[offset..offset + 8]);
+        let mut length: [u8; 4] = [0; 4];
+        length.copy_from_slice(&buffer[offset + 8..offset + RECORD_HEADER_SIZE]);
+        let start: usize = offset + RECORD_HEADER_SIZE;
+        let end: usize = start + u32::from_be_bytes(length) as usize;
+        if end > buffer.len() {
+            break;
+        }
+        records.push(Record {
+            timestamp: u64::from_be_bytes(timestamp),
+            data: buffer[start..end].to_vec(),
+        });
+        offset = end;
+    }
+    (records, offset)
+}
+
+/// ### truncate_tail
+///
+/// `truncate_tail` removes from the log a truncated record at its end
+fn truncate_tail(log: &mut File) -> std::io::Result<()> {
+    let mut buffer: Vec<u8> = Vec::new();
+    log.read_to_end(&mut buffer)?;
+    let (_, size): (Vec<Record>, usize) = decode_records(&buffer);
+    if size < buffer.len() {
+        log.set_len(size as u64)?;
+    }
+    Ok(())
+}
+
+/// ### now
+///
+/// `now` returns the seconds elapsed since the epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_store_append_take() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let data_dir: String = tmpdir.path().join("data").display().to_string();
+        let mut store: MessageStore = MessageStore::new(&data_dir);
+        assert_eq!(store.get_data_dir(), data_dir.as_str());
+        //Nothing stored
+        assert_eq!(store.count("foo").unwrap(), 0);
+        assert_eq!(store.take("foo").unwrap(), (Vec::new(), 0));
+        //Messages are returned in order
+        assert_eq!(
+            store
+                .append("foo", &[vec![0x01], vec![0x02, 0x03]])
+                .unwrap(),
+            0
+        );
+        assert_eq!(store.append("foo", &[vec![0x04]]).unwrap(), 0);
+        assert_eq!(store.append("bar", &[vec![0xff]]).unwrap(), 0);
+        assert_eq!(store.count("foo").unwrap(), 3);
+        assert_eq!(
+            store.take("foo").unwrap(),
+            (vec![vec![0x01], vec![0x02, 0x03], vec![0x04]], 0)
+        );
+        assert_eq!(store.count("foo").unwrap(), 0);
+        assert_eq!(store.take("bar").unwrap(), (vec![vec![0xff]], 0));
+        //A truncated record is ignored
+        assert_eq!(store.append("foo", &[vec![0x01]]).unwrap(), 0);
+        let mut log: File = OpenOptions::new()
+            .append(true)
+            .open(store.log_path("foo"))
+            .unwrap();
+        log.write_all(&[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x01,
+        ])
+        .unwrap();
+        assert_eq!(store.take("foo").unwrap(), (vec![vec![0x01]], 0));
+    }
+
+    #[test]
+    fn test_store_append_after_truncated_record() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let data_dir: String = tmpdir.path().display().to_string();
+        let mut store: MessageStore = MessageStore::new(&data_dir);
+        assert_eq!(store.append("foo", &[vec![0x01]]).unwrap(), 0);
+        //The server crashes while writing a record
+        let mut log: File = OpenOptions::new()
+            .append(true)
+            .open(store.log_path("foo"))
+            .unwrap();
+        log.write_all(&[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x01,
+        ])
+        .unwrap();
+        drop(log);
+        //The truncated record is removed when the log is written again after a restart
+        let mut store: MessageStore = MessageStore::new(&data_dir);
+        assert_eq!(
+            store
+                .append("foo", &[vec![0x02, 0x03], vec![0x04]])
+                .unwrap(),
+            0
+        );
+        assert_eq!(store.append("foo", &[vec![0x05]]).unwrap(), 0);
+        assert_eq!(store.count("foo").unwrap(), 4);
+        assert_eq!(
+            store.take("foo").unwrap(),
+            (
+                vec![vec![0x01], vec![0x02, 0x03], vec![0x04], vec![0x05]],
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn test_store_limits() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut store: MessageStore = MessageStore::new(tmpdir.path().to_str().unwrap());
+        //Each record takes 16 bytes; the oldest ones are discarded
+        store.set_limits(48, Duration::from_secs(0));
+        assert_eq!(
+            store
+                .append("foo", &[vec![0x01; 4], vec![0x02; 4]])
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            store
+                .append("foo", &[vec![0x03; 4], vec![0x04; 4]])
+                .unwrap(),
+            1
+        );
+        assert_eq!(store.append("foo", &[vec![0x05; 20]]).unwrap(), 2);
+        assert_eq!(
+            store.take("foo").unwrap(),
+            (vec![vec![0x04; 4], vec![0x05; 20]], 0)
+        );
+        //Messages which are too old are discarded
+        store.set_limits(0, Duration::from_secs(60));
+        let mut buffer: Vec<u8> = Vec::new();
+        encode_record(&mut buffer, now() - 120, &[0x01]);
+        encode_record(&mut buffer, now(), &[0x02]);
+        std::fs::write(store.log_path("foo"), &buffer).unwrap();
+        assert_eq!(store.take("foo").unwrap(), (vec![vec![0x02]], 1));
+    }
+
+    #[test]
+    fn test_store_groups() {
+        let tmpdir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let store: MessageStore = MessageStore::new(tmpdir.path().to_str().unwrap());
+        assert_eq!(store.load_groups("foo").unwrap(), None);
+        let groups: Vec<String> = vec![String::from("sensors/#"), String::from("foo")];
+        assert!(store.save_groups("foo", &groups).is_ok());
+        assert_eq!(store.load_groups("foo").unwrap(), Some(groups));
+        assert!(store.save_groups("foo", &[]).is_ok());
+        assert_eq!(store.load_groups("foo").unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_store_is_valid_client() {
+        assert!(is_valid_client("foo"));
+        assert!(is_valid_client("foo.bar"));
+        assert!(!is_valid_client(""));
+        assert!(!is_valid_client(".."));
+        assert!(!is_valid_client("../foo"));
+        assert!(!is_valid_client("foo/bar"));
+    }
+}